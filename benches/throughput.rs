@@ -0,0 +1,170 @@
+//! Throughput of the server over loopback, on whichever backend it was built with.
+//!
+//! Compare the backends by running it once per build:
+//!
+//! ```text
+//! cargo bench --bench throughput
+//! cargo bench --bench throughput --features io-uring
+//! ```
+//!
+//! Each scenario runs `CLIENTS` threads, each one sending requests over its own keep-alive
+//! connection for `DURATION`, and reports the requests and response bytes served per second.
+
+use ducta::http::{Request, Response};
+use ducta::ServerBuilder;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const WORKERS: usize = 2;
+const CLIENTS: usize = 32;
+const DURATION: Duration = Duration::from_secs(5);
+const LARGE_BODY_SIZE: usize = 64 * 1024;
+
+struct Scenario {
+    name: &'static str,
+    path: &'static str,
+    /// Requests written at once before reading their responses
+    pipeline: usize,
+}
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "small responses",
+        path: "/small",
+        pipeline: 1,
+    },
+    Scenario {
+        name: "small responses, pipelined by 16",
+        path: "/small",
+        pipeline: 16,
+    },
+    Scenario {
+        name: "64KB responses",
+        path: "/large",
+        pipeline: 1,
+    },
+];
+
+fn main() {
+    let backend = if cfg!(all(feature = "io-uring", target_os = "linux")) {
+        "io_uring"
+    } else {
+        "mio"
+    };
+    println!(
+        "backend: {}, {} workers, {} clients, {:?} per scenario",
+        backend, WORKERS, CLIENTS, DURATION
+    );
+
+    let large_body = vec![b'x'; LARGE_BODY_SIZE];
+    let mut server = ServerBuilder::new("127.0.0.1:0")
+        .workers(WORKERS)
+        // Connections are opened once per client, only the requests are measured
+        .max_requests_per_connection(u64::MAX)
+        .build(move |req: Request| match req.path {
+            "/large" => Response::new(200).with_body(large_body.clone()),
+            _ => Response::new(200).with_body(&b"Hello from Ducta!"[..]),
+        })
+        .expect("Failed to start the server");
+    let addr = server.local_addr();
+    std::thread::spawn(move || server.run());
+
+    for scenario in SCENARIOS {
+        let (requests, bytes) = run(addr, scenario);
+        let secs = DURATION.as_secs_f64();
+        println!(
+            "{:<36} {:>12.0} req/s {:>10.1} MB/s",
+            scenario.name,
+            requests as f64 / secs,
+            bytes as f64 / secs / 1e6
+        );
+    }
+}
+
+/// Runs `scenario` against the server, returning the number of responses and bytes received
+fn run(addr: SocketAddr, scenario: &Scenario) -> (u64, u64) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let request = format!("GET {} HTTP/1.1\r\nHost: bench\r\n\r\n", scenario.path);
+    let batch = request.repeat(scenario.pipeline).into_bytes();
+
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            let stop = stop.clone();
+            let batch = batch.clone();
+            let pipeline = scenario.pipeline;
+            std::thread::spawn(move || client(addr, &batch, pipeline, &stop))
+        })
+        .collect();
+
+    std::thread::sleep(DURATION);
+    stop.store(true, Ordering::Relaxed);
+
+    clients
+        .into_iter()
+        .map(|client| client.join().expect("Client thread panicked"))
+        .fold((0, 0), |(requests, bytes), (r, b)| (requests + r, bytes + b))
+}
+
+/// Sends `batch` and reads back `pipeline` responses until told to stop
+fn client(addr: SocketAddr, batch: &[u8], pipeline: usize, stop: &AtomicBool) -> (u64, u64) {
+    let mut stream = TcpStream::connect(addr).expect("Failed to connect");
+    stream.set_nodelay(true).unwrap();
+
+    let mut buf = vec![0; 256 * 1024];
+    let mut received = Vec::new();
+    let (mut requests, mut bytes) = (0, 0);
+
+    while !stop.load(Ordering::Relaxed) {
+        stream.write_all(batch).unwrap();
+
+        for _ in 0..pipeline {
+            let len = loop {
+                if let Some(len) = response_len(&received) {
+                    break len;
+                }
+                let n = stream.read(&mut buf).unwrap();
+                assert!(n > 0, "connection closed by the server");
+                received.extend_from_slice(&buf[..n]);
+            };
+
+            // Bodies are only ever skipped, no need to hold them whole
+            if received.len() >= len {
+                received.drain(..len);
+            } else {
+                let mut remaining = len - received.len();
+                received.clear();
+                while remaining > 0 {
+                    let chunk = remaining.min(buf.len());
+                    let n = stream.read(&mut buf[..chunk]).unwrap();
+                    assert!(n > 0, "connection closed by the server");
+                    remaining -= n;
+                }
+            }
+
+            requests += 1;
+            bytes += len as u64;
+        }
+    }
+
+    (requests, bytes)
+}
+
+/// Total length of the response at the start of `received`, once its head is complete
+fn response_len(received: &[u8]) -> Option<usize> {
+    let head_end = received.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+    let head = std::str::from_utf8(&received[..head_end]).ok()?;
+
+    let body_len = head
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse::<usize>().ok())?
+        })
+        .expect("response without Content-Length");
+
+    Some(head_end + body_len)
+}
@@ -1,15 +1,15 @@
 use ducta::{
     http::{Request, Response},
-    server::Server,
+    ServerBuilder,
 };
 
 fn main() -> std::io::Result<()> {
     println!("Starting server on 127.0.0.1:8080");
 
-    let _ = Server::new("127.0.0.1:8080", |_req: Request| {
-        Response::new(200).with_body(&b"Hello from Duca!"[..])
-    })?
-    .run();
+    let _ = ServerBuilder::new("127.0.0.1:8080")
+        .shutdown_on_signals(true)
+        .build(|_req: Request| Response::new(200).with_body(&b"Hello from Duca!"[..]))?
+        .run();
 
     Ok(())
 }
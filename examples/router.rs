@@ -0,0 +1,35 @@
+use ducta::{
+    http::{Request, Response},
+    middleware::{BearerAuth, Logger, SetHeader},
+    Router, ServerBuilder,
+};
+
+fn main() -> std::io::Result<()> {
+    println!("Starting server on 127.0.0.1:8080");
+
+    // Everything under /api requires a token
+    let api = Router::new()
+        .get("/users/:id", |req: Request| {
+            Response::new(200).with_body(format!("User {}", req.param("id").unwrap_or("")))
+        })
+        .post("/users", |req: Request| {
+            Response::new(201).with_body(req.body.to_vec())
+        })
+        .layer(BearerAuth::new("secret"));
+
+    let router = Router::new()
+        .get("/", |_req: Request| Response::new(200).with_body("Hello from Ducta!"))
+        .get("/files/*path", |req: Request| {
+            Response::new(200).with_body(format!("File {}", req.param("path").unwrap_or("")))
+        })
+        .nest("/api", api)
+        .layer(Logger)
+        .layer(SetHeader::new("Server", "ducta"));
+
+    let _ = ServerBuilder::new("127.0.0.1:8080")
+        .shutdown_on_signals(true)
+        .build(router)?
+        .run();
+
+    Ok(())
+}
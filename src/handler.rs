@@ -1,3 +1,14 @@
+mod async_handler;
+mod pending;
+mod pool;
+mod responder;
+
+pub use self::async_handler::{from_async, Async, AsyncHandler};
+pub use self::responder::{defer, deferred, Deferred, Responder};
+
+pub(crate) use self::pending::PendingResponse;
+pub(crate) use self::pool::{overloaded, HandlerPool};
+
 use crate::http::{Request, Response};
 
 pub trait Handler: Send + Sync + 'static {
@@ -13,3 +24,10 @@ where
         (self)(req)
     }
 }
+
+// Lets type-erased handlers be used wherever a Handler is expected
+impl Handler for Box<dyn Handler> {
+    fn handle(&self, req: Request) -> Response {
+        (**self).handle(req)
+    }
+}
@@ -0,0 +1,70 @@
+use super::{Handler, PendingResponse};
+use crate::http::{Request, Response};
+use std::future::Future;
+
+/// A handler producing its response asynchronously.
+///
+/// The request borrows the connection's buffers, so the handler copies what it needs out of it
+/// before returning the future. Futures are polled on the event loop of the connection, which
+/// their wakers wake up, so they must not block: they can await timers, TCP streams and blocking
+/// work run elsewhere through `rt`, or anything else waking them from another thread. A future
+/// that panics is answered with `500 Internal Server Error`, and dropped if the connection fails,
+/// such as when the client resets it.
+///
+/// Turned into a `Handler` by `from_async`.
+pub trait AsyncHandler: Send + Sync + 'static {
+    type Future: Future<Output = Response> + Send + 'static;
+
+    fn handle(&self, req: Request) -> Self::Future;
+}
+
+impl<F, Fut> AsyncHandler for F
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    type Future = Fut;
+
+    fn handle(&self, req: Request) -> Fut {
+        (self)(req)
+    }
+}
+
+/// Runs an `AsyncHandler` as a `Handler`, see `from_async`
+pub struct Async<H> {
+    handler: H,
+}
+
+/// Wraps an `AsyncHandler`, so it can be served on its own or routed like any other handler.
+///
+/// ```no_run
+/// use ducta::handler;
+/// use ducta::http::{Request, Response};
+/// use ducta::{rt, Router};
+/// use std::time::Duration;
+///
+/// let router = Router::new().get(
+///     "/slow/:name",
+///     handler::from_async(|req: Request| {
+///         let name = req.params.get("name").unwrap_or_default().to_owned();
+///         async move {
+///             rt::sleep(Duration::from_secs(1)).await;
+///             match rt::blocking(move || format!("Hello, {}!", name)).await {
+///                 Ok(greeting) => Response::new(200).with_body(greeting),
+///                 Err(e) => Response::new(503).with_body(e.to_string()),
+///             }
+///         }
+///     }),
+/// );
+/// ```
+pub fn from_async<H: AsyncHandler>(handler: H) -> Async<H> {
+    Async { handler }
+}
+
+impl<H: AsyncHandler> Handler for Async<H> {
+    fn handle(&self, req: Request) -> Response {
+        let mut resp = Response::new(500);
+        resp.deferred = Some(PendingResponse::future(self.handler.handle(req)));
+        resp
+    }
+}
@@ -0,0 +1,86 @@
+use super::responder::Answer;
+use crate::http::{reason_phrase, Response};
+use crate::net::Notifier;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+/// Post-processing applied to a response once it's ready, see `Response::map`
+pub(crate) type Then = Box<dyn FnOnce(Response) -> Response + Send>;
+
+/// The response to a request that isn't ready yet, which the connection waits for
+pub(crate) struct PendingResponse {
+    source: Source,
+    /// Applied in order to the response once it's ready
+    then: Vec<Then>,
+}
+
+enum Source {
+    /// Sent through a `Responder`, from the handler pool or any other thread
+    Responder(Answer),
+    /// Produced by an `AsyncHandler`, polled on the event loop
+    Future {
+        future: Pin<Box<dyn Future<Output = Response> + Send>>,
+        waker: Option<Waker>,
+    },
+}
+
+impl PendingResponse {
+    pub(super) fn answer(answer: Answer) -> Self {
+        PendingResponse {
+            source: Source::Responder(answer),
+            then: Vec::new(),
+        }
+    }
+
+    pub(super) fn future(future: impl Future<Output = Response> + Send + 'static) -> Self {
+        PendingResponse {
+            source: Source::Future {
+                future: Box::pin(future),
+                waker: None,
+            },
+            then: Vec::new(),
+        }
+    }
+
+    /// Applies `f` to the response once it's ready, after what was added before
+    pub(crate) fn then(&mut self, f: Then) {
+        self.then.push(f);
+    }
+
+    /// Lets the response wake up the connection woken up by `notifier` once it's ready
+    pub fn attach(&mut self, notifier: Notifier) {
+        match &mut self.source {
+            Source::Responder(answer) => answer.attach(notifier),
+            Source::Future { waker, .. } => *waker = Some(Waker::from(Arc::new(notifier))),
+        }
+    }
+
+    /// The response, once it's ready. Futures are polled until they complete, a future that
+    /// panicked getting a 500 response, and must not be polled anymore once they did.
+    pub fn poll(&mut self) -> Option<Response> {
+        let resp = match &mut self.source {
+            Source::Responder(answer) => answer.poll()?,
+            Source::Future { future, waker } => {
+                let mut cx = Context::from_waker(waker.as_ref()?);
+                match std::panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(&mut cx))) {
+                    Ok(Poll::Ready(resp)) => resp,
+                    Ok(Poll::Pending) => return None,
+                    Err(_) => internal_error(),
+                }
+            }
+        };
+
+        // A response deferred in turn passes them on to the one it stands for
+        Some(self.then.drain(..).fold(resp, |resp, f| {
+            std::panic::catch_unwind(AssertUnwindSafe(|| resp.map(f)))
+                .unwrap_or_else(|_| internal_error())
+        }))
+    }
+}
+
+fn internal_error() -> Response {
+    Response::new(500).with_body(reason_phrase(500))
+}
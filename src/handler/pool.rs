@@ -0,0 +1,120 @@
+use super::responder::{self, Responder};
+use super::{Handler, PendingResponse};
+use crate::http::{reason_phrase, OwnedRequest, Response};
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+enum Job {
+    /// Boxed, as requests are much larger than blocking work
+    Request {
+        request: Box<OwnedRequest>,
+        responder: Responder,
+    },
+    /// Work handed over by `rt::blocking`, delivering its own result
+    Blocking(Box<dyn FnOnce() + Send>),
+}
+
+/// Threads running handlers away from the event loops, so that handlers doing blocking work
+/// don't stall every other connection of their worker. They also run the work of `rt::blocking`.
+///
+/// Requests wait in a bounded queue, and are refused once it's full instead of piling up. Dropping
+/// the pool lets the threads finish the requests already queued before joining them.
+pub(crate) struct HandlerPool {
+    jobs: Option<SyncSender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl HandlerPool {
+    pub fn new(
+        handler: Arc<dyn Handler>,
+        threads: usize,
+        queue_size: usize,
+    ) -> std::io::Result<Self> {
+        let (jobs, queue) = mpsc::sync_channel::<Job>(queue_size);
+        let queue = Arc::new(Mutex::new(queue));
+
+        let mut pool = HandlerPool {
+            jobs: Some(jobs),
+            threads: Vec::with_capacity(threads),
+        };
+        for idx in 0..threads {
+            let handler = handler.clone();
+            let queue = queue.clone();
+            let thread = std::thread::Builder::new()
+                .name(format!("ducta-handler-{}", idx + 1))
+                .spawn(move || run(&*handler, &queue))?;
+            pool.threads.push(thread);
+        }
+
+        Ok(pool)
+    }
+
+    /// Queues `request` for a handler thread, its response being delivered like a deferred one.
+    /// Returns `None` if the queue is full.
+    pub fn submit(&self, request: OwnedRequest) -> Option<PendingResponse> {
+        let (responder, response) = responder::channel();
+        let job = Job::Request {
+            request: Box::new(request),
+            responder,
+        };
+
+        match self.jobs.as_ref()?.try_send(job) {
+            Ok(()) => Some(response),
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => None,
+        }
+    }
+
+    /// Queues `work` for a handler thread, sharing the queue with requests. Returns whether it
+    /// was queued, which it isn't once the queue is full.
+    pub fn run_blocking(&self, work: Box<dyn FnOnce() + Send>) -> bool {
+        self.jobs
+            .as_ref()
+            .is_some_and(|jobs| jobs.try_send(Job::Blocking(work)).is_ok())
+    }
+}
+
+impl Drop for HandlerPool {
+    fn drop(&mut self) {
+        // Closing the queue stops the threads once it's empty
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(handler: &dyn Handler, queue: &Mutex<Receiver<Job>>) {
+    loop {
+        // The lock is released as soon as a job was taken
+        let job = queue.lock().unwrap().recv();
+        let (request, responder) = match job {
+            Ok(Job::Request { request, responder }) => (request, responder),
+            // Panics are caught and handed over to the future awaiting the work
+            Ok(Job::Blocking(work)) => {
+                work();
+                continue;
+            }
+            Err(_) => return,
+        };
+
+        // The connection went away while the request was queued
+        if responder.is_cancelled() {
+            continue;
+        }
+
+        // A panicking handler leaves the thread usable, the responder it drops answers with a 500
+        let response = std::panic::catch_unwind(AssertUnwindSafe(|| request.handle(handler)));
+        if let Ok(response) = response {
+            responder.respond(response);
+        }
+    }
+}
+
+/// Answers requests refused because the queue of the pool is full
+pub(crate) fn overloaded() -> Response {
+    Response::new(503)
+        .with_body(reason_phrase(503))
+        .with_header("Retry-After", "1")
+}
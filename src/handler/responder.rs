@@ -0,0 +1,206 @@
+use super::{Handler, PendingResponse};
+use crate::http::{reason_phrase, Request, Response};
+use crate::net::Notifier;
+use std::sync::{Arc, Mutex};
+
+/// Defers the response to a request, returning the placeholder response the handler returns and
+/// the `Responder` completing it later, from any thread.
+///
+/// The connection is parked once the placeholder is returned, handling nothing more from the
+/// client until the response is sent, and moves on as soon as it is. The placeholder is never
+/// sent itself, middleware post-processes the actual response through `Response::map`.
+///
+/// ```no_run
+/// use ducta::handler;
+/// use ducta::http::{Request, Response};
+/// use ducta::Router;
+/// use std::time::Duration;
+///
+/// let router = Router::new().get("/later", |_req: Request| {
+///     let (resp, responder) = handler::defer();
+///     std::thread::spawn(move || {
+///         std::thread::sleep(Duration::from_secs(1));
+///         responder.respond(Response::new(200).with_body("done"));
+///     });
+///     resp
+/// });
+/// ```
+pub fn defer() -> (Response, Responder) {
+    let (responder, pending) = channel();
+
+    let mut resp = Response::new(500);
+    resp.deferred = Some(pending);
+    (resp, responder)
+}
+
+/// Pairs a `Responder` with the connection's end of it
+pub(crate) fn channel() -> (Responder, PendingResponse) {
+    let slot = Arc::new(Slot {
+        state: Mutex::new(SlotState {
+            response: None,
+            answered: false,
+            cancelled: false,
+            on_cancel: None,
+            notifier: None,
+        }),
+    });
+
+    let answer = Answer { slot: slot.clone() };
+    (Responder { slot }, PendingResponse::answer(answer))
+}
+
+/// Turns a handler answering through a `Responder` into a `Handler`, see `deferred`
+pub struct Deferred<F> {
+    handler: F,
+}
+
+/// Wraps a handler that answers requests through the `Responder` it's given instead of returning
+/// the response, so that it can hand the responder to another thread or a timer and answer later.
+/// The responder may also be used before the handler returns.
+///
+/// ```no_run
+/// use ducta::handler::{self, Responder};
+/// use ducta::http::{Request, Response};
+/// use ducta::Router;
+///
+/// let router = Router::new().get(
+///     "/job",
+///     handler::deferred(|_req: Request, responder: Responder| {
+///         std::thread::spawn(move || {
+///             if !responder.is_cancelled() {
+///                 responder.respond(Response::new(200).with_body("done"));
+///             }
+///         });
+///     }),
+/// );
+/// ```
+pub fn deferred<F>(handler: F) -> Deferred<F>
+where
+    F: Fn(Request, Responder) + Send + Sync + 'static,
+{
+    Deferred { handler }
+}
+
+impl<F> Handler for Deferred<F>
+where
+    F: Fn(Request, Responder) + Send + Sync + 'static,
+{
+    fn handle(&self, req: Request) -> Response {
+        let (resp, responder) = defer();
+        (self.handler)(req, responder);
+        resp
+    }
+}
+
+/// Completes a deferred response from any thread, waking the connection's event loop up.
+///
+/// Dropping it without answering, such as when the code holding it panics, answers with
+/// `500 Internal Server Error`.
+pub struct Responder {
+    slot: Arc<Slot>,
+}
+
+impl Responder {
+    /// Sends `resp` as the response to the request, unless the client went away
+    pub fn respond(self, resp: Response) {
+        self.complete(resp);
+    }
+
+    /// Whether the client went away, in which case nothing is sent anymore. Noticed once the
+    /// connection fails, such as when the client resets it, a client only shutting its side of
+    /// the connection down still expecting the response
+    pub fn is_cancelled(&self) -> bool {
+        self.slot.state.lock().unwrap().cancelled
+    }
+
+    /// Runs `f` once the client went away without getting its response, right away if it already
+    /// did. It runs on the event loop, so it should only signal whatever produces the response to
+    /// stop.
+    pub fn on_cancel(&self, f: impl FnOnce() + Send + 'static) {
+        let mut state = self.slot.state.lock().unwrap();
+        if state.cancelled {
+            drop(state);
+            f();
+        } else {
+            state.on_cancel = Some(Box::new(f));
+        }
+    }
+
+    fn complete(&self, resp: Response) {
+        let mut state = self.slot.state.lock().unwrap();
+        state.answered = true;
+        if state.cancelled {
+            return;
+        }
+
+        state.response = Some(resp);
+        state.on_cancel = None;
+        let notifier = state.notifier.clone();
+        drop(state);
+
+        if let Some(notifier) = notifier {
+            notifier.notify();
+        }
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        if !self.slot.state.lock().unwrap().answered {
+            let status = 500;
+            self.complete(Response::new(status).with_body(reason_phrase(status)));
+        }
+    }
+}
+
+/// State shared by a `Responder` and the connection waiting for it
+struct Slot {
+    state: Mutex<SlotState>,
+}
+
+struct SlotState {
+    /// Response sent through the responder, and not yet taken by the connection
+    response: Option<Response>,
+    /// The responder answered or was dropped, nothing more will be sent through it
+    answered: bool,
+    /// The connection went away before the response was taken
+    cancelled: bool,
+    on_cancel: Option<Box<dyn FnOnce() + Send>>,
+    /// Set once the response is awaited by a connection
+    notifier: Option<Notifier>,
+}
+
+/// The connection's end of a `Responder`. Dropping it before the response was taken, as the
+/// connection closes, cancels the request.
+pub(super) struct Answer {
+    slot: Arc<Slot>,
+}
+
+impl Answer {
+    /// Lets the responder wake up the connection woken up by `notifier` once it answered
+    pub fn attach(&self, notifier: Notifier) {
+        self.slot.state.lock().unwrap().notifier = Some(notifier);
+    }
+
+    /// The response, once the responder answered
+    pub fn poll(&self) -> Option<Response> {
+        self.slot.state.lock().unwrap().response.take()
+    }
+}
+
+impl Drop for Answer {
+    fn drop(&mut self) {
+        let mut state = self.slot.state.lock().unwrap();
+        if state.answered {
+            return;
+        }
+
+        state.cancelled = true;
+        let on_cancel = state.on_cancel.take();
+        drop(state);
+
+        if let Some(on_cancel) = on_cancel {
+            on_cancel();
+        }
+    }
+}
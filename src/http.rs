@@ -1,9 +1,22 @@
+mod body;
+mod chunked;
+mod forwarded;
 mod parser;
 mod request;
 mod response;
 
+pub(crate) use self::{
+    body::{file_shrank, BodyWriter},
+    forwarded::TrustedProxies,
+    request::{list_contains, OwnedRequest},
+    response::status_has_body,
+};
+#[cfg(target_os = "linux")]
+pub(crate) use self::body::sendfile;
 pub use self::{
-    parser::{parse_request, ParseStatus},
-    request::Request,
-    response::Response,
+    body::{Body, FileBody, StreamBody},
+    chunked::{ChunkedDecoder, ChunkedError, ChunkedStatus},
+    parser::{body_framing, parse_request, BodyFraming, FramingError, ParseStatus},
+    request::{ConnectionInfo, Params, Request},
+    response::{reason_phrase, Response},
 };
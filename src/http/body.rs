@@ -0,0 +1,404 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::fs::File;
+use std::io::Seek;
+use std::os::fd::RawFd;
+use std::os::unix::fs::FileExt;
+
+/// Source of a response body, pulled by the connection as the socket is able to take more data.
+///
+/// Bodies are drained incrementally, so a body never needs to be fully materialized in memory.
+pub trait Body: Send + 'static {
+    /// Total length of the body, when known up front.
+    ///
+    /// A known length is sent as `Content-Length`, otherwise the response falls back to
+    /// `Transfer-Encoding: chunked`.
+    fn size_hint(&self) -> Option<u64>;
+
+    /// Returns the next piece of the body, or `None` once the body is exhausted.
+    ///
+    /// `max` is the number of bytes the connection is willing to buffer right now, returning
+    /// more is allowed, the remainder is simply kept for later.
+    fn next_chunk(&mut self, max: usize) -> std::io::Result<Option<Bytes>>;
+
+    /// Writes up to `max` bytes of the body straight to the socket `fd`, instead of them being
+    /// copied to the connection's buffers by `next_chunk`. Returns the number of bytes written,
+    /// `0` once the body is exhausted, or `None` if the body can't be sent this way, which is the
+    /// default.
+    ///
+    /// Only used on plaintext connections and for bodies sent without chunked framing, once
+    /// everything before the body was written.
+    fn send_to(&mut self, fd: RawFd, max: usize) -> Option<std::io::Result<usize>> {
+        let _ = (fd, max);
+        None
+    }
+}
+
+/// A body fully held in memory
+impl Body for Bytes {
+    fn size_hint(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
+
+    fn next_chunk(&mut self, max: usize) -> std::io::Result<Option<Bytes>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        // Zero-copy: only moves the start of the shared buffer
+        let len = std::cmp::min(max, self.len());
+        Ok(Some(self.split_to(len)))
+    }
+}
+
+/// A body of unknown length produced by an iterator of chunks
+pub struct StreamBody<I> {
+    chunks: I,
+}
+
+impl<I> StreamBody<I>
+where
+    I: Iterator<Item = Bytes> + Send + 'static,
+{
+    pub fn new(chunks: I) -> Self {
+        StreamBody { chunks }
+    }
+}
+
+impl<I> Body for StreamBody<I>
+where
+    I: Iterator<Item = Bytes> + Send + 'static,
+{
+    fn size_hint(&self) -> Option<u64> {
+        None
+    }
+
+    fn next_chunk(&mut self, _max: usize) -> std::io::Result<Option<Bytes>> {
+        Ok(self.chunks.next())
+    }
+}
+
+/// A body read from a file, starting at its current position.
+///
+/// On Linux the file is sent with `sendfile(2)`, straight from the page cache to the socket,
+/// whenever the connection allows it.
+pub struct FileBody {
+    file: File,
+    offset: u64,
+    remaining: u64,
+}
+
+impl FileBody {
+    /// Sends the file from its current position to its end, as known from its metadata
+    pub fn new(mut file: File) -> std::io::Result<Self> {
+        let offset = file.stream_position()?;
+        let remaining = file.metadata()?.len().saturating_sub(offset);
+        Ok(FileBody::range(file, offset, remaining))
+    }
+
+    /// Opens and sends the file at `path`
+    pub fn open(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        FileBody::new(File::open(path)?)
+    }
+
+    /// Sends the `len` bytes of the file starting at `offset`
+    pub fn range(file: File, offset: u64, len: u64) -> Self {
+        FileBody {
+            file,
+            offset,
+            remaining: len,
+        }
+    }
+}
+
+impl Body for FileBody {
+    fn size_hint(&self) -> Option<u64> {
+        Some(self.remaining)
+    }
+
+    fn next_chunk(&mut self, max: usize) -> std::io::Result<Option<Bytes>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+
+        let len = std::cmp::min(max as u64, self.remaining) as usize;
+        let mut chunk = vec![0; len];
+        let n = self.file.read_at(&mut chunk, self.offset)?;
+        if n == 0 {
+            return Err(file_shrank());
+        }
+
+        chunk.truncate(n);
+        self.offset += n as u64;
+        self.remaining -= n as u64;
+        Ok(Some(Bytes::from(chunk)))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn send_to(&mut self, fd: RawFd, max: usize) -> Option<std::io::Result<usize>> {
+        Some(sendfile(fd, &self.file, &mut self.offset, &mut self.remaining, max))
+    }
+}
+
+/// Sends up to `max` of the `remaining` bytes of `file` starting at `offset` to the socket `fd`
+/// with `sendfile(2)`, advancing both. Returns `0` once nothing remains.
+#[cfg(target_os = "linux")]
+pub(crate) fn sendfile(
+    fd: RawFd,
+    file: &File,
+    offset: &mut u64,
+    remaining: &mut u64,
+    max: usize,
+) -> std::io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    if *remaining == 0 {
+        return Ok(0);
+    }
+
+    let len = std::cmp::min(max as u64, *remaining) as usize;
+    let mut file_offset = *offset as libc::off_t;
+    let n = unsafe { libc::sendfile(fd, file.as_raw_fd(), &mut file_offset, len) };
+    match n {
+        n if n < 0 => return Err(std::io::Error::last_os_error()),
+        0 => return Err(file_shrank()),
+        _ => {}
+    }
+
+    *offset += n as u64;
+    *remaining -= n as u64;
+    Ok(n as usize)
+}
+
+/// The file shrank after its length was announced, the response can't be completed
+pub(crate) fn file_shrank() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "File ended before its announced length",
+    )
+}
+
+/// Moves a response body into a connection write buffer, applying chunked framing when the body
+/// length is unknown.
+pub(crate) struct BodyWriter {
+    body: Box<dyn Body>,
+    chunked: bool,
+    /// Part of the last chunk that didn't fit in the write window
+    leftover: Bytes,
+    /// The body had no data ready during the last `fill`
+    paused: bool,
+}
+
+impl BodyWriter {
+    pub(crate) fn new(body: Box<dyn Body>, chunked: bool) -> Self {
+        BodyWriter {
+            body,
+            chunked,
+            leftover: Bytes::new(),
+            paused: false,
+        }
+    }
+
+    /// Sends the next piece of the body straight to the socket `fd` if the body supports it, see
+    /// `Body::send_to`. Returns `Some(Ok(0))` once the whole body was sent.
+    pub(crate) fn send_to(&mut self, fd: RawFd, max: usize) -> Option<std::io::Result<usize>> {
+        if self.chunked || !self.leftover.is_empty() {
+            return None;
+        }
+
+        self.body.send_to(fd, max)
+    }
+
+    /// Appends body data to `dst` until it holds `window` bytes or the body ends.
+    /// Returns `false` once the whole body, including the chunked terminator, was written.
+    ///
+    /// Bodies fed from another thread, such as event streams, fail with `WouldBlock` while they
+    /// have no data ready, which pauses the body until the connection is notified.
+    pub(crate) fn fill(&mut self, dst: &mut BytesMut, window: usize) -> std::io::Result<bool> {
+        self.paused = false;
+
+        while dst.len() < window {
+            let room = window - dst.len();
+
+            let mut chunk = if self.leftover.is_empty() {
+                match self.body.next_chunk(room) {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => {
+                        if self.chunked {
+                            // Last chunk, with an empty trailer section
+                            dst.put_slice(b"0\r\n\r\n");
+                        }
+                        return Ok(false);
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        self.paused = true;
+                        return Ok(true);
+                    }
+                    Err(e) => return Err(e),
+                }
+            } else {
+                std::mem::take(&mut self.leftover)
+            };
+
+            if chunk.is_empty() {
+                // An empty chunk would read as the end of a chunked body
+                continue;
+            }
+
+            if chunk.len() > room {
+                self.leftover = chunk.split_off(room);
+            }
+
+            if self.chunked {
+                dst.put_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+                dst.put_slice(&chunk);
+                dst.put_slice(b"\r\n");
+            } else {
+                dst.put_slice(&chunk);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Whether the last `fill` stopped because the body had no data ready
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fills `writer` into a fresh buffer of `window` bytes at a time until the body ends,
+    /// returning every window written
+    fn drain(mut writer: BodyWriter, window: usize) -> Vec<Vec<u8>> {
+        let mut windows = Vec::new();
+        loop {
+            let mut dst = BytesMut::new();
+            let more = writer.fill(&mut dst, window).unwrap();
+            assert!(
+                dst.len() <= window + 16,
+                "{} bytes in a {} window",
+                dst.len(),
+                window
+            );
+            windows.push(dst.to_vec());
+            if !more {
+                return windows;
+            }
+        }
+    }
+
+    fn stream(chunks: &'static [&'static str]) -> Box<dyn Body> {
+        Box::new(StreamBody::new(
+            chunks.iter().map(|c| Bytes::from_static(c.as_bytes())),
+        ))
+    }
+
+    #[test]
+    fn writes_known_lengths_as_is() {
+        let writer = BodyWriter::new(Box::new(Bytes::from_static(b"hello world")), false);
+        assert_eq!(drain(writer, 4).concat(), b"hello world");
+    }
+
+    #[test]
+    fn frames_unknown_lengths_in_chunks() {
+        let writer = BodyWriter::new(stream(&["Wiki", "pedia"]), true);
+        assert_eq!(
+            drain(writer, 1024).concat(),
+            b"4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn skips_empty_chunks() {
+        // An empty chunk would end the body early
+        let writer = BodyWriter::new(stream(&["", "a", "", "", "b", ""]), true);
+        assert_eq!(
+            drain(writer, 1024).concat(),
+            b"1\r\na\r\n1\r\nb\r\n0\r\n\r\n"
+        );
+
+        let writer = BodyWriter::new(stream(&["", ""]), true);
+        assert_eq!(drain(writer, 1024).concat(), b"0\r\n\r\n");
+    }
+
+    #[test]
+    fn keeps_what_exceeds_the_window_for_later() {
+        let writer = BodyWriter::new(stream(&["0123456789", "ab"]), true);
+        let windows = drain(writer, 4);
+
+        // Each window holds at most its size of data, plus the framing of a single chunk
+        assert_eq!(windows[0], b"4\r\n0123\r\n");
+        assert_eq!(windows[1], b"4\r\n4567\r\n");
+        assert_eq!(
+            windows.concat(),
+            b"4\r\n0123\r\n4\r\n4567\r\n2\r\n89\r\n2\r\nab\r\n0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn pauses_bodies_without_data_ready() {
+        /// Ready every other call
+        struct Intermittent(u8);
+
+        impl Body for Intermittent {
+            fn size_hint(&self) -> Option<u64> {
+                None
+            }
+
+            fn next_chunk(&mut self, _max: usize) -> std::io::Result<Option<Bytes>> {
+                self.0 += 1;
+                match self.0 {
+                    1 | 3 => Err(std::io::ErrorKind::WouldBlock.into()),
+                    2 => Ok(Some(Bytes::from_static(b"data"))),
+                    _ => Ok(None),
+                }
+            }
+        }
+
+        let mut writer = BodyWriter::new(Box::new(Intermittent(0)), true);
+        let mut dst = BytesMut::new();
+
+        assert!(writer.fill(&mut dst, 1024).unwrap());
+        assert!(writer.is_paused());
+        assert!(dst.is_empty());
+
+        assert!(writer.fill(&mut dst, 1024).unwrap());
+        assert!(writer.is_paused());
+        assert_eq!(&dst[..], b"4\r\ndata\r\n");
+
+        assert!(!writer.fill(&mut dst, 1024).unwrap());
+        assert!(!writer.is_paused());
+        assert_eq!(&dst[..], b"4\r\ndata\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn reads_file_ranges() {
+        let path = std::env::temp_dir().join(format!("ducta-body-{}", std::process::id()));
+        std::fs::write(&path, "0123456789").unwrap();
+
+        let body = FileBody::open(&path).unwrap();
+        assert_eq!(body.size_hint(), Some(10));
+        assert_eq!(
+            drain(BodyWriter::new(Box::new(body), false), 3).concat(),
+            b"0123456789"
+        );
+
+        let range = FileBody::range(File::open(&path).unwrap(), 2, 5);
+        assert_eq!(range.size_hint(), Some(5));
+        assert_eq!(
+            drain(BodyWriter::new(Box::new(range), false), 2).concat(),
+            b"23456"
+        );
+
+        // The file shrinking under the body fails it rather than sending less than announced
+        let mut past_end = FileBody::range(File::open(&path).unwrap(), 8, 5);
+        assert_eq!(past_end.next_chunk(16).unwrap().unwrap(), "89");
+        let error = past_end.next_chunk(16).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
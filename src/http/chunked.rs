@@ -0,0 +1,390 @@
+use bytes::BytesMut;
+
+/// Longest chunk extension accepted on a single chunk-size line
+const MAX_EXTENSION_SIZE: usize = 4096;
+/// Largest trailer section accepted after the last chunk
+const MAX_TRAILER_SIZE: usize = 8192;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChunkedStatus {
+    /// The last chunk and the trailers were decoded
+    Complete,
+    /// More encoded data is needed
+    Partial,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChunkedError {
+    /// The chunk-size line isn't valid hexadecimal or overflows
+    InvalidChunkSize,
+    /// A CRLF was expected but something else was found
+    InvalidLineEnding,
+    /// A chunk extension exceeds `MAX_EXTENSION_SIZE`
+    ExtensionTooLarge,
+    /// The trailer section exceeds `MAX_TRAILER_SIZE`
+    TrailersTooLarge,
+    /// The decoded body would exceed the decoder's maximum size
+    BodyTooLarge,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// Reading the hexadecimal chunk size
+    Size,
+    /// Skipping a chunk extension, up to the end of the chunk-size line
+    Extension,
+    /// Expecting the LF that ends the chunk-size line
+    SizeLf,
+    /// Copying chunk data, holds how many bytes of the chunk are left
+    Data(usize),
+    /// Expecting the CRLF that follows the chunk data
+    DataCr,
+    DataLf,
+    /// At the beginning of a trailer line, or of the final empty line
+    TrailerStart,
+    /// Skipping a trailer field line
+    Trailer,
+    /// Expecting the LF that ends a trailer field line
+    TrailerLf,
+    /// Expecting the LF of the final empty line
+    EndLf,
+    Done,
+}
+
+/// Incremental decoder for `Transfer-Encoding: chunked` request bodies.
+///
+/// The decoder works in place: decoded bytes are written back into the same buffer right after
+/// the body start, which is always safe since decoding never makes data grow. After each call the
+/// buffer looks like `[headers][decoded body][bytes after the body]`, so the body can be borrowed
+/// as a single contiguous slice once decoding is complete.
+///
+/// Chunk extensions are skipped. Trailer fields are validated against a size limit and discarded,
+/// as permitted by RFC 9112.
+pub struct ChunkedDecoder {
+    state: State,
+    chunk_size: usize,
+    size_digits: usize,
+    extension_len: usize,
+    trailer_len: usize,
+    decoded_len: usize,
+    max_size: usize,
+}
+
+impl ChunkedDecoder {
+    /// Creates a decoder that rejects bodies larger than `max_size` decoded bytes
+    pub fn new(max_size: usize) -> Self {
+        ChunkedDecoder {
+            state: State::Size,
+            chunk_size: 0,
+            size_digits: 0,
+            extension_len: 0,
+            trailer_len: 0,
+            decoded_len: 0,
+            max_size,
+        }
+    }
+
+    /// Number of body bytes decoded so far
+    pub fn decoded_len(&self) -> usize {
+        self.decoded_len
+    }
+
+    /// Decodes the encoded bytes available in `buf` past `body_start + decoded_len()`.
+    ///
+    /// Encoded bytes are consumed as they are decoded, so once this returns `Partial` the buffer
+    /// ends right after the decoded body, and once it returns `Complete` only the bytes following
+    /// the chunked body (e.g. a pipelined request) are left after it.
+    pub fn decode_in_place(
+        &mut self,
+        buf: &mut BytesMut,
+        body_start: usize,
+    ) -> Result<ChunkedStatus, ChunkedError> {
+        let len = buf.len();
+        let mut write = body_start + self.decoded_len;
+        let mut read = write;
+
+        while read < len && self.state != State::Done {
+            if let State::Data(remaining) = self.state {
+                // Bulk copy the chunk data down to the end of the decoded body
+                let n = std::cmp::min(remaining, len - read);
+                buf.copy_within(read..read + n, write);
+                read += n;
+                write += n;
+                self.decoded_len += n;
+
+                self.state = if remaining == n {
+                    State::DataCr
+                } else {
+                    State::Data(remaining - n)
+                };
+                continue;
+            }
+
+            let byte = buf[read];
+            read += 1;
+
+            self.state = match self.state {
+                State::Size => match byte {
+                    b'0'..=b'9' | b'a'..=b'f' | b'A'..=b'F' => {
+                        let digit = (byte as char).to_digit(16).unwrap_or(0) as usize;
+                        self.chunk_size = self
+                            .chunk_size
+                            .checked_mul(16)
+                            .and_then(|size| size.checked_add(digit))
+                            .ok_or(ChunkedError::InvalidChunkSize)?;
+                        self.size_digits += 1;
+                        State::Size
+                    }
+                    b';' | b' ' | b'\t' if self.size_digits > 0 => State::Extension,
+                    b'\r' if self.size_digits > 0 => State::SizeLf,
+                    _ => return Err(ChunkedError::InvalidChunkSize),
+                },
+                State::Extension => {
+                    self.extension_len += 1;
+                    if self.extension_len > MAX_EXTENSION_SIZE {
+                        return Err(ChunkedError::ExtensionTooLarge);
+                    }
+
+                    match byte {
+                        b'\r' => State::SizeLf,
+                        b'\n' => return Err(ChunkedError::InvalidLineEnding),
+                        _ => State::Extension,
+                    }
+                }
+                State::SizeLf => {
+                    if byte != b'\n' {
+                        return Err(ChunkedError::InvalidLineEnding);
+                    }
+
+                    let size = self.chunk_size;
+                    self.chunk_size = 0;
+                    self.size_digits = 0;
+                    self.extension_len = 0;
+
+                    if size == 0 {
+                        // Last chunk, the trailer section follows
+                        State::TrailerStart
+                    } else if size > self.max_size - self.decoded_len {
+                        return Err(ChunkedError::BodyTooLarge);
+                    } else {
+                        State::Data(size)
+                    }
+                }
+                State::DataCr => match byte {
+                    b'\r' => State::DataLf,
+                    _ => return Err(ChunkedError::InvalidLineEnding),
+                },
+                State::DataLf | State::TrailerLf => match byte {
+                    b'\n' if self.state == State::DataLf => State::Size,
+                    b'\n' => State::TrailerStart,
+                    _ => return Err(ChunkedError::InvalidLineEnding),
+                },
+                State::TrailerStart | State::Trailer => {
+                    self.trailer_len += 1;
+                    if self.trailer_len > MAX_TRAILER_SIZE {
+                        return Err(ChunkedError::TrailersTooLarge);
+                    }
+
+                    match byte {
+                        b'\r' if self.state == State::TrailerStart => State::EndLf,
+                        b'\r' => State::TrailerLf,
+                        b'\n' => return Err(ChunkedError::InvalidLineEnding),
+                        _ => State::Trailer,
+                    }
+                }
+                State::EndLf => match byte {
+                    b'\n' => State::Done,
+                    _ => return Err(ChunkedError::InvalidLineEnding),
+                },
+                State::Data(_) | State::Done => unreachable!(),
+            };
+        }
+
+        // Drop the consumed encoded bytes, moving whatever follows them next to the decoded body
+        buf.copy_within(read..len, write);
+        buf.truncate(write + (len - read));
+
+        if self.state == State::Done {
+            Ok(ChunkedStatus::Complete)
+        } else {
+            Ok(ChunkedStatus::Partial)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands for the request head the body follows in the connection buffer
+    const HEAD: &[u8] = b"POST / HTTP/1.1\r\n\r\n";
+
+    /// Decodes `input` fed in the given pieces, returning the decoded body and whatever followed
+    /// it, or the first error
+    fn decode_pieces(
+        pieces: &[&[u8]],
+        max_size: usize,
+    ) -> Result<(Vec<u8>, Vec<u8>), ChunkedError> {
+        let mut decoder = ChunkedDecoder::new(max_size);
+        let mut buf = BytesMut::from(HEAD);
+        let mut status = ChunkedStatus::Partial;
+
+        for piece in pieces {
+            buf.extend_from_slice(piece);
+            status = decoder.decode_in_place(&mut buf, HEAD.len())?;
+            if status == ChunkedStatus::Partial {
+                // Everything received was consumed
+                assert_eq!(buf.len(), HEAD.len() + decoder.decoded_len());
+            }
+        }
+
+        assert_eq!(status, ChunkedStatus::Complete, "incomplete body");
+        assert!(buf.starts_with(HEAD));
+        let body = buf[HEAD.len()..HEAD.len() + decoder.decoded_len()].to_vec();
+        let rest = buf[HEAD.len() + decoder.decoded_len()..].to_vec();
+        Ok((body, rest))
+    }
+
+    /// Decodes `input` whole, one byte at a time and split in two at every byte boundary,
+    /// checking every way gives the same result. Inputs long enough to hit the size limits are
+    /// only split in two every few bytes, feeding them byte by byte already covers every boundary
+    fn decode(input: &[u8], max_size: usize) -> Result<(Vec<u8>, Vec<u8>), ChunkedError> {
+        let whole = decode_pieces(&[input], max_size);
+
+        let step = if input.len() > 1024 { 97 } else { 1 };
+        for split in (0..=input.len()).step_by(step) {
+            let (first, second) = input.split_at(split);
+            assert_eq!(
+                decode_pieces(&[first, second], max_size),
+                whole,
+                "split at {}",
+                split
+            );
+        }
+        let bytes: Vec<&[u8]> = input.chunks(1).collect();
+        assert_eq!(decode_pieces(&bytes, max_size), whole, "byte by byte");
+
+        whole
+    }
+
+    fn body(input: &str) -> String {
+        let (body, rest) = decode(input.as_bytes(), 1024).unwrap();
+        assert!(rest.is_empty(), "left {:?}", rest);
+        String::from_utf8(body).unwrap()
+    }
+
+    fn error(input: &str) -> ChunkedError {
+        decode(input.as_bytes(), 1024).unwrap_err()
+    }
+
+    #[test]
+    fn decodes_chunks() {
+        assert_eq!(body("4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n"), "Wikipedia");
+        assert_eq!(
+            body("a\r\n0123456789\r\nA\r\n0123456789\r\n0\r\n\r\n").len(),
+            20
+        );
+        assert_eq!(body("0\r\n\r\n"), "");
+        // Leading zeros don't count towards any limit
+        assert_eq!(body("0000000000000000000004\r\nWiki\r\n0\r\n\r\n"), "Wiki");
+    }
+
+    #[test]
+    fn keeps_what_follows_the_body() {
+        let input = b"4\r\nWiki\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let (body, rest) = decode(input, 1024).unwrap();
+        assert_eq!(body, b"Wiki");
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn skips_extensions_and_trailers() {
+        assert_eq!(
+            body("4;name=value\r\nWiki\r\n5 ; a=\"b;c\"\r\npedia\r\n0;last\r\n\r\n"),
+            "Wikipedia"
+        );
+        assert_eq!(
+            body("4\r\nWiki\r\n0\r\nExpires: never\r\nX-Checksum: 1234\r\n\r\n"),
+            "Wiki"
+        );
+
+        let extension = format!("1;{}\r\nx\r\n0\r\n\r\n", "e".repeat(MAX_EXTENSION_SIZE - 1));
+        assert_eq!(body(&extension), "x");
+    }
+
+    #[test]
+    fn rejects_invalid_chunk_sizes() {
+        for input in [
+            "x\r\n",
+            "\r\n",
+            ";ext\r\n",
+            "+4\r\nWiki\r\n",
+            "-1\r\n",
+            "0x4\r\n",
+            " 4\r\n",
+            "4\n",
+        ] {
+            assert_eq!(error(input), ChunkedError::InvalidChunkSize, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_overflowing_chunk_sizes() {
+        let digits = usize::BITS as usize / 4;
+        let overflow = format!("1{}\r\n", "0".repeat(digits));
+        assert_eq!(error(&overflow), ChunkedError::InvalidChunkSize);
+        assert_eq!(
+            error(&"f".repeat(digits + 1)),
+            ChunkedError::InvalidChunkSize
+        );
+
+        // The largest size fits, but not in the body limit
+        assert_eq!(
+            error(&format!("{}\r\n", "f".repeat(digits))),
+            ChunkedError::BodyTooLarge
+        );
+    }
+
+    #[test]
+    fn rejects_bad_line_endings() {
+        for input in [
+            "4\rWiki\r\n0\r\n\r\n",
+            "4;ext\nWiki\r\n0\r\n\r\n",
+            "4\r\nWikiX\r\n0\r\n\r\n",
+            "4\r\nWiki\n0\r\n\r\n",
+            "4\r\nWiki\rX0\r\n\r\n",
+            "0\r\n\n",
+            "0\r\n\rX",
+            "0\r\nExpires: never\n\r\n",
+            "0\r\nExpires: never\rX\r\n",
+        ] {
+            assert_eq!(error(input), ChunkedError::InvalidLineEnding, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn limits_extensions_and_trailers() {
+        let extension = format!("1;{}\r\nx\r\n0\r\n\r\n", "e".repeat(MAX_EXTENSION_SIZE));
+        assert_eq!(error(&extension), ChunkedError::ExtensionTooLarge);
+
+        let trailer = format!("0\r\nX-Big: {}\r\n\r\n", "t".repeat(MAX_TRAILER_SIZE));
+        assert_eq!(error(&trailer), ChunkedError::TrailersTooLarge);
+
+        // Lines add up
+        let line = format!("X-Line: {}\r\n", "t".repeat(100));
+        let trailers = format!("0\r\n{}\r\n", line.repeat(MAX_TRAILER_SIZE / 100));
+        assert_eq!(error(&trailers), ChunkedError::TrailersTooLarge);
+    }
+
+    #[test]
+    fn limits_the_decoded_body_size() {
+        let exact = decode(b"4\r\nWiki\r\n4\r\npedi\r\n0\r\n\r\n", 8).unwrap();
+        assert_eq!(exact.0, b"Wikipedi");
+
+        assert_eq!(decode(b"9\r\n", 8).unwrap_err(), ChunkedError::BodyTooLarge);
+        assert_eq!(
+            decode(b"4\r\nWiki\r\n5\r\n", 8).unwrap_err(),
+            ChunkedError::BodyTooLarge
+        );
+    }
+}
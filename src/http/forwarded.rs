@@ -0,0 +1,172 @@
+use crate::http::Request;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+/// Addresses and networks of the proxies whose `Forwarded` and `X-Forwarded-For` headers are
+/// believed, see `Request::client_addr`
+#[derive(Debug)]
+pub(crate) struct TrustedProxies {
+    networks: Vec<Network>,
+}
+
+#[derive(Debug)]
+struct Network {
+    /// Masked address bits, IPv4 addresses taking the top 32 bits
+    bits: u128,
+    mask: u128,
+    /// Size of the addresses of the network, telling IPv4 and IPv6 apart
+    width: u8,
+}
+
+impl TrustedProxies {
+    /// Parses single addresses (`10.0.0.1`, `::1`) and CIDR networks (`10.0.0.0/8`,
+    /// `fd00::/8`). Returns `None` when none was given, so no header is ever looked at
+    pub fn parse<S: AsRef<str>>(proxies: &[S]) -> std::io::Result<Option<Arc<Self>>> {
+        if proxies.is_empty() {
+            return Ok(None);
+        }
+
+        let networks = proxies
+            .iter()
+            .map(|proxy| {
+                let proxy = proxy.as_ref();
+                Network::parse(proxy).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Invalid trusted proxy {:?}", proxy),
+                    )
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+
+        Ok(Some(Arc::new(TrustedProxies { networks })))
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let (bits, width) = address_bits(addr);
+        self.networks
+            .iter()
+            .any(|network| network.width == width && bits & network.mask == network.bits)
+    }
+}
+
+impl Network {
+    fn parse(network: &str) -> Option<Self> {
+        let (addr, prefix) = match network.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (network, None),
+        };
+
+        let (bits, width) = address_bits(addr.trim().parse().ok()?);
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse()
+                .ok()
+                .filter(|&prefix| prefix <= width)?,
+            None => width,
+        };
+
+        let mask = match prefix {
+            0 => 0,
+            prefix => u128::MAX << (128 - prefix as u32),
+        };
+        Some(Network {
+            bits: bits & mask,
+            mask,
+            width,
+        })
+    }
+}
+
+/// Bits of `addr` aligned to the top of a `u128`, and the width of its family. IPv4-mapped IPv6
+/// addresses, which dual-stack listeners see IPv4 clients as, count as IPv4
+fn address_bits(addr: IpAddr) -> (u128, u8) {
+    match addr.to_canonical() {
+        IpAddr::V4(addr) => ((u32::from(addr) as u128) << 96, 32),
+        IpAddr::V6(addr) => (u128::from(addr), 128),
+    }
+}
+
+/// Resolves the address of the client that sent `req`, see `Request::client_addr`
+pub(crate) fn client_addr(req: &Request) -> IpAddr {
+    let info = req.connection;
+    let trusted = |addr| {
+        info.trusted_proxies
+            .as_deref()
+            .is_some_and(|trusted| trusted.contains(addr))
+    };
+
+    // Unless a PROXY protocol header told who connected
+    let local_peer = info.trust_unix_socket_peer
+        && info
+            .proxy
+            .as_ref()
+            .is_none_or(|header| header.source.is_none());
+
+    let mut client = info.peer_addr.ip();
+    if !local_peer && !trusted(client) {
+        return client;
+    }
+
+    // Each proxy appends the address it got the request from, so the chain is walked back from
+    // the peer for as long as the hops are trusted to tell the truth. A hop that can't be read,
+    // such as an obfuscated or `unknown` one, stops at the proxy that reported it
+    for hop in forwarded_for(req).into_iter().rev() {
+        match hop {
+            Some(addr) => client = addr,
+            None => break,
+        }
+
+        if !trusted(client) {
+            break;
+        }
+    }
+    client
+}
+
+/// Addresses listed by the `for` parameters of the `Forwarded` headers of `req`, or by its
+/// `X-Forwarded-For` headers if it has none, from the farthest hop to the closest one
+fn forwarded_for(req: &Request) -> Vec<Option<IpAddr>> {
+    let values = |name: &'static str| {
+        req.headers
+            .iter()
+            .filter(|h| h.name.eq_ignore_ascii_case(name))
+            .flat_map(|h| h.value.split(|&b| b == b','))
+            .map(|hop| std::str::from_utf8(hop).ok())
+    };
+
+    if req.get_header("Forwarded").is_some() {
+        values("Forwarded")
+            .map(|element| {
+                element?
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node))
+            })
+            .collect()
+    } else {
+        values("X-Forwarded-For")
+            .map(|hop| hop.and_then(parse_node))
+            .collect()
+    }
+}
+
+/// Parses a hop, which may carry a port and be quoted (`"[2001:db8::1]:4711"`)
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    node.strip_prefix('[')?
+        .strip_suffix(']')?
+        .parse::<Ipv6Addr>()
+        .ok()
+        .map(IpAddr::V6)
+}
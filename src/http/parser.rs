@@ -1,4 +1,4 @@
-use crate::http::request::Request;
+use crate::http::request::{Params, Request, UNKNOWN_CONNECTION};
 
 pub enum ParseStatus<'a> {
     Complete(Request<'a>, usize), // The request and the total length of headers
@@ -6,6 +6,28 @@ pub enum ParseStatus<'a> {
     Error(httparse::Error),
 }
 
+/// How the body of a request is delimited on the wire.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BodyFraming {
+    /// The request carries no body
+    Empty,
+    /// The body is exactly this many bytes long, as announced by `Content-Length`
+    ContentLength(usize),
+    /// The body is sent with `Transfer-Encoding: chunked` and must be decoded
+    Chunked,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FramingError {
+    /// `Content-Length` is not a valid number, or several disagreeing values were sent
+    InvalidContentLength,
+    /// Both `Content-Length` and `Transfer-Encoding` were sent, which could be used to smuggle
+    /// requests past intermediaries that disagree on which one wins
+    ConflictingFraming,
+    /// A transfer coding other than a single `chunked` was requested
+    UnsupportedTransferEncoding,
+}
+
 pub fn parse_request<'a>(buffer: &'a [u8], headers_storage: &'a mut [httparse::Header<'a>]) -> ParseStatus<'a> {
     let mut req = httparse::Request::new(headers_storage);
 
@@ -17,6 +39,10 @@ pub fn parse_request<'a>(buffer: &'a [u8], headers_storage: &'a mut [httparse::H
                     path: req.path.unwrap_or(""),
                     version: req.version.unwrap_or(1),
                     headers: req.headers,
+                    body: &[],
+                    params: Params::default(),
+                    connection: &UNKNOWN_CONNECTION,
+                    sequence: 1,
                 },
                 amt,
             )
@@ -26,3 +52,226 @@ pub fn parse_request<'a>(buffer: &'a [u8], headers_storage: &'a mut [httparse::H
     }
 }
 
+/// Inspects the headers of a parsed request to find out how its body is framed.
+///
+/// Repeated `Content-Length` headers are accepted only when they all carry the same value,
+/// anything else is rejected since it would make the body boundaries ambiguous. For the same
+/// reason a request carrying both `Content-Length` and `Transfer-Encoding` is rejected.
+pub fn body_framing(req: &Request) -> Result<BodyFraming, FramingError> {
+    let mut content_length: Option<usize> = None;
+    let mut transfer_codings = 0;
+    let mut chunked = false;
+
+    for header in req.headers {
+        if header.name.eq_ignore_ascii_case("Transfer-Encoding") {
+            let value = std::str::from_utf8(header.value)
+                .map_err(|_| FramingError::UnsupportedTransferEncoding)?;
+
+            for coding in value.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                transfer_codings += 1;
+                chunked = coding.eq_ignore_ascii_case("chunked");
+            }
+            continue;
+        }
+
+        if !header.name.eq_ignore_ascii_case("Content-Length") {
+            continue;
+        }
+
+        let value = std::str::from_utf8(header.value)
+            .map_err(|_| FramingError::InvalidContentLength)?
+            .trim();
+
+        // usize::from_str accepts a leading '+', which is not valid in this header
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(FramingError::InvalidContentLength);
+        }
+
+        let len: usize = value
+            .parse()
+            .map_err(|_| FramingError::InvalidContentLength)?;
+
+        match content_length {
+            Some(previous) if previous != len => return Err(FramingError::InvalidContentLength),
+            _ => content_length = Some(len),
+        }
+    }
+
+    if transfer_codings > 0 {
+        if content_length.is_some() {
+            return Err(FramingError::ConflictingFraming);
+        }
+
+        // Only a lone `chunked` coding is supported, other codings would need to be decoded too
+        return if transfer_codings == 1 && chunked {
+            Ok(BodyFraming::Chunked)
+        } else {
+            Err(FramingError::UnsupportedTransferEncoding)
+        };
+    }
+
+    match content_length {
+        Some(0) | None => Ok(BodyFraming::Empty),
+        Some(len) => Ok(BodyFraming::ContentLength(len)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a request carrying the given header lines and finds out how its body is framed
+    fn framing(headers: &[&str]) -> Result<BodyFraming, FramingError> {
+        let mut head = String::from("POST / HTTP/1.1\r\nHost: x\r\n");
+        for header in headers {
+            head += header;
+            head += "\r\n";
+        }
+        head += "\r\n";
+
+        let mut storage = [httparse::EMPTY_HEADER; 16];
+        match parse_request(head.as_bytes(), &mut storage) {
+            ParseStatus::Complete(req, _) => body_framing(&req),
+            _ => panic!("invalid request head {:?}", head),
+        }
+    }
+
+    #[test]
+    fn waits_for_the_whole_head() {
+        let head = b"POST /upload?x=1 HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello";
+        let head_len = head.len() - 5;
+
+        for split in 0..head_len {
+            let mut storage = [httparse::EMPTY_HEADER; 16];
+            let status = parse_request(&head[..split], &mut storage);
+            assert!(matches!(status, ParseStatus::Partial), "split at {}", split);
+        }
+
+        for end in head_len..=head.len() {
+            let mut storage = [httparse::EMPTY_HEADER; 16];
+            let ParseStatus::Complete(req, len) = parse_request(&head[..end], &mut storage) else {
+                panic!("incomplete at {}", end);
+            };
+            assert_eq!(len, head_len);
+            assert_eq!(
+                (req.method, req.path, req.version),
+                ("POST", "/upload?x=1", 1)
+            );
+            assert_eq!(body_framing(&req), Ok(BodyFraming::ContentLength(5)));
+        }
+    }
+
+    #[test]
+    fn frames_bodies_by_content_length() {
+        assert_eq!(framing(&[]), Ok(BodyFraming::Empty));
+        assert_eq!(framing(&["Content-Length: 0"]), Ok(BodyFraming::Empty));
+        assert_eq!(
+            framing(&["Content-Length: 42"]),
+            Ok(BodyFraming::ContentLength(42))
+        );
+        assert_eq!(
+            framing(&["content-length: 007"]),
+            Ok(BodyFraming::ContentLength(7))
+        );
+
+        // Repeated with the same value
+        let repeated = framing(&["Content-Length: 5", "Content-Length: 5"]);
+        assert_eq!(repeated, Ok(BodyFraming::ContentLength(5)));
+    }
+
+    #[test]
+    fn rejects_ambiguous_content_lengths() {
+        for value in [
+            "+5",
+            "-5",
+            "5a",
+            "0x5",
+            "5 5",
+            "5, 5",
+            "1e3",
+            "99999999999999999999999",
+        ] {
+            let header = format!("Content-Length: {}", value);
+            assert_eq!(
+                framing(&[&header]),
+                Err(FramingError::InvalidContentLength),
+                "{}",
+                value
+            );
+        }
+
+        let mismatched = framing(&["Content-Length: 5", "Content-Length: 6"]);
+        assert_eq!(mismatched, Err(FramingError::InvalidContentLength));
+        let mismatched = framing(&["Content-Length: 0", "Content-Length: 6"]);
+        assert_eq!(mismatched, Err(FramingError::InvalidContentLength));
+    }
+
+    #[test]
+    fn frames_chunked_bodies() {
+        assert_eq!(
+            framing(&["Transfer-Encoding: chunked"]),
+            Ok(BodyFraming::Chunked)
+        );
+        assert_eq!(
+            framing(&["transfer-encoding: Chunked"]),
+            Ok(BodyFraming::Chunked)
+        );
+        assert_eq!(
+            framing(&["Transfer-Encoding: chunked, "]),
+            Ok(BodyFraming::Chunked)
+        );
+    }
+
+    #[test]
+    fn rejects_content_length_with_transfer_encoding() {
+        for headers in [
+            ["Content-Length: 5", "Transfer-Encoding: chunked"],
+            ["Transfer-Encoding: chunked", "Content-Length: 5"],
+            ["Transfer-Encoding: chunked", "Content-Length: 0"],
+            ["Transfer-Encoding: gzip", "Content-Length: 5"],
+        ] {
+            assert_eq!(
+                framing(&headers),
+                Err(FramingError::ConflictingFraming),
+                "{:?}",
+                headers
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_transfer_codings() {
+        for headers in [
+            &["Transfer-Encoding: gzip"][..],
+            &["Transfer-Encoding: identity"],
+            &["Transfer-Encoding: gzip, chunked"],
+            &["Transfer-Encoding: chunked, gzip"],
+            &["Transfer-Encoding: chunked, chunked"],
+            &["Transfer-Encoding: chunked", "Transfer-Encoding: chunked"],
+            &["Transfer-Encoding: gzip", "Transfer-Encoding: chunked"],
+        ] {
+            let expected = Err(FramingError::UnsupportedTransferEncoding);
+            assert_eq!(framing(headers), expected, "{:?}", headers);
+        }
+    }
+
+    #[test]
+    fn rejects_non_utf8_framing_headers() {
+        let head = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunk\xffed\r\n\r\n";
+        let mut storage = [httparse::EMPTY_HEADER; 4];
+        let ParseStatus::Complete(req, _) = parse_request(head, &mut storage) else {
+            panic!("invalid request head");
+        };
+        assert_eq!(
+            body_framing(&req),
+            Err(FramingError::UnsupportedTransferEncoding)
+        );
+
+        let head = b"POST / HTTP/1.1\r\nContent-Length: 5\xff\r\n\r\n";
+        let mut storage = [httparse::EMPTY_HEADER; 4];
+        let ParseStatus::Complete(req, _) = parse_request(head, &mut storage) else {
+            panic!("invalid request head");
+        };
+        assert_eq!(body_framing(&req), Err(FramingError::InvalidContentLength));
+    }
+}
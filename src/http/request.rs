@@ -1,8 +1,25 @@
+
+use super::forwarded::{self, TrustedProxies};
+use crate::handler::Handler;
+use crate::http::Response;
+use crate::proxy::ProxyHeader;
+use bytes::Bytes;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 pub struct Request<'a> {
     pub method: &'a str,
     pub path: &'a str,
     pub version: u8,
     pub headers: &'a [httparse::Header<'a>],
+    pub body: &'a [u8],
+    /// Path parameters captured by the `Router` that dispatched the request
+    pub params: Params<'a>,
+    /// Connection the request was received on
+    pub connection: &'a ConnectionInfo,
+    /// Position of the request among those received on its connection, starting at 1
+    pub sequence: u64,
 }
 
 impl<'a> Request<'a> {
@@ -11,4 +28,204 @@ impl<'a> Request<'a> {
             .find(|h| h.name.eq_ignore_ascii_case(name))
             .map(|h| h.value)
     }
+
+    /// Whether any `Connection` header of the request lists `option`
+    pub fn has_connection_option(&self, option: &str) -> bool {
+        self.headers
+            .iter()
+            .filter(|h| h.name.eq_ignore_ascii_case("Connection"))
+            .any(|h| list_contains(h.value, option))
+    }
+
+    /// The path without its query string
+    pub fn path_only(&self) -> &'a str {
+        self.path.split_once('?').map_or(self.path, |(path, _)| path)
+    }
+
+    /// The query string, without the leading `?`
+    pub fn query(&self) -> Option<&'a str> {
+        self.path.split_once('?').map(|(_, query)| query)
+    }
+
+    /// Value of the path parameter `name`, shorthand for `self.params.get(name)`
+    pub fn param(&self, name: &str) -> Option<&'a str> {
+        self.params.get(name)
+    }
+
+    /// Whether the client expects the connection to stay open after this request.
+    ///
+    /// HTTP/1.1 connections are persistent unless `Connection: close` is sent, while HTTP/1.0
+    /// ones are closed unless `Connection: keep-alive` is sent.
+    pub fn keep_alive(&self) -> bool {
+        if self.version == 0 {
+            self.has_connection_option("keep-alive")
+        } else {
+            !self.has_connection_option("close")
+        }
+    }
+
+    /// Address of the client that sent the request.
+    ///
+    /// This is the peer address of the connection, unless the peer is one of the trusted proxies
+    /// set through `ServerBuilder::trusted_proxies`. The hops listed by the `Forwarded` header, or
+    /// `X-Forwarded-For` without it, are then walked back until one that isn't a trusted proxy,
+    /// which is the client. Headers sent by untrusted peers are ignored, as anyone can forge them.
+    ///
+    /// Peers connected through a Unix domain socket have no address of their own, and are only
+    /// trusted if enabled through `ServerBuilder::trust_unix_socket_peers`.
+    pub fn client_addr(&self) -> IpAddr {
+        forwarded::client_addr(self)
+    }
+}
+
+/// Identity and addresses of the connection a request was received on
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    /// Unique among the connections accepted by the process, starting at 1
+    pub id: u64,
+    /// Address of the client, or of the proxy in front of the server
+    pub peer_addr: SocketAddr,
+    /// Address the connection was accepted on
+    pub local_addr: SocketAddr,
+    /// The connection was accepted on a Unix domain socket, whose ends have no IP address: both
+    /// addresses are then unspecified, unless a PROXY protocol header gave them
+    pub unix_socket: bool,
+    /// PROXY protocol header the connection started with, whose addresses replaced the peer and
+    /// local ones, see `ServerBuilder::proxy_protocol`
+    pub proxy: Option<Arc<ProxyHeader>>,
+    pub(crate) trusted_proxies: Option<Arc<TrustedProxies>>,
+    /// Whether the peer is trusted for being connected through a Unix domain socket
+    pub(crate) trust_unix_socket_peer: bool,
+}
+
+/// Given to requests parsed apart from any connection, such as by `parse_request`
+pub(crate) static UNKNOWN_CONNECTION: ConnectionInfo = ConnectionInfo {
+    id: 0,
+    peer_addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)),
+    local_addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)),
+    unix_socket: false,
+    proxy: None,
+    trusted_proxies: None,
+    trust_unix_socket_peer: false,
+};
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+impl ConnectionInfo {
+    /// Describes a newly accepted connection, giving it the next id
+    pub(crate) fn new(
+        peer_addr: SocketAddr,
+        local_addr: SocketAddr,
+        unix_socket: bool,
+        trusted_proxies: Option<Arc<TrustedProxies>>,
+        trust_unix_socket_peers: bool,
+    ) -> Self {
+        ConnectionInfo {
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            peer_addr,
+            local_addr,
+            unix_socket,
+            proxy: None,
+            trusted_proxies,
+            trust_unix_socket_peer: unix_socket && trust_unix_socket_peers,
+        }
+    }
+}
+
+/// A request detached from the connection's buffers, so that it can be handled on another thread
+/// or once the buffers moved on
+pub(crate) struct OwnedRequest {
+    pub method: String,
+    pub path: String,
+    pub version: u8,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Bytes,
+    pub connection: ConnectionInfo,
+    pub sequence: u64,
+}
+
+impl OwnedRequest {
+    /// Hands the request to `handler`, as a `Request` borrowing from it
+    pub fn handle<H: Handler + ?Sized>(&self, handler: &H) -> Response {
+        let headers: Vec<httparse::Header> = self
+            .headers
+            .iter()
+            .map(|(name, value)| httparse::Header { name, value })
+            .collect();
+
+        handler.handle(Request {
+            method: &self.method,
+            path: &self.path,
+            version: self.version,
+            headers: &headers,
+            body: &self.body,
+            params: Params::default(),
+            connection: &self.connection,
+            sequence: self.sequence,
+        })
+    }
+}
+
+impl From<&Request<'_>> for OwnedRequest {
+    fn from(req: &Request) -> Self {
+        OwnedRequest {
+            method: req.method.to_owned(),
+            path: req.path.to_owned(),
+            version: req.version,
+            headers: req
+                .headers
+                .iter()
+                .map(|h| (h.name.to_owned(), h.value.to_vec()))
+                .collect(),
+            body: Bytes::copy_from_slice(req.body),
+            connection: req.connection.clone(),
+            sequence: req.sequence,
+        }
+    }
+}
+
+/// Path parameters captured while routing a request.
+///
+/// Values are slices of the request path, left percent-encoded as they were received. A trailing
+/// `*rest` wildcard captures the remainder of the path, slashes included.
+#[derive(Clone, Default, Debug)]
+pub struct Params<'a> {
+    entries: Vec<(Arc<str>, &'a str)>,
+}
+
+impl<'a> Params<'a> {
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| &**n == name)
+            .map(|(_, v)| *v)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over the `(name, value)` pairs, in the order they appear in the route
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &'a str)> {
+        self.entries.iter().map(|(n, v)| (&**n, *v))
+    }
+
+    pub(crate) fn push(&mut self, name: Arc<str>, value: &'a str) {
+        self.entries.push((name, value));
+    }
+
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.entries.truncate(len);
+    }
+}
+
+/// Whether the comma separated header value contains `token`, ignoring case
+pub(crate) fn list_contains(value: &[u8], token: &str) -> bool {
+    value
+        .split(|&b| b == b',')
+        .any(|item| item.trim_ascii().eq_ignore_ascii_case(token.as_bytes()))
 }
@@ -1,32 +1,103 @@
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::http::body::{Body, StreamBody};
+use crate::handler::PendingResponse;
+use crate::http::request::list_contains;
+use crate::sse::EventStream;
+use crate::websocket::WebSocketHandler;
 
 pub struct Response {
     pub status: u16,
-    pub body: Vec<u8>,
+    pub body: Box<dyn Body>,
     pub headers: Vec<(String, String)>,
+    /// Takes the connection over once this `101` response is sent, see `websocket::upgrade`
+    pub(crate) websocket: Option<Box<dyn WebSocketHandler>>,
+    /// Feeds the body of this response, see `sse::stream`
+    pub(crate) event_stream: Option<EventStream>,
+    /// Stands for the response a `Responder` sends later, see `handler::defer`
+    pub(crate) deferred: Option<PendingResponse>,
 }
 
 impl Response {
     pub fn new(status: u16) -> Self {
         Self {
             status,
-            body: Vec::new(),
+            body: Box::new(Bytes::new()),
             headers: Vec::new(),
+            websocket: None,
+            event_stream: None,
+            deferred: None,
         }
     }
 
-    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
-        self.body = body.into();
+    pub fn with_body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = Box::new(body.into());
+        self.event_stream = None;
         self
     }
 
-    /// Converts the struct into raw bytes to be sent over the wire
-    pub fn encode(&self, dst: &mut BytesMut) {
-        let status_text = match self.status {
-            200 => "OK",
-            404 => "Not Found",
-            _ => "Internal Server Error",
-        };
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Whether any `Connection` header of the response lists `option`
+    pub fn has_connection_option(&self, option: &str) -> bool {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case("Connection"))
+            .any(|(_, v)| list_contains(v.as_bytes(), option))
+    }
+
+    /// Applies `f` to the response. A deferred response, see `is_deferred`, only stands for the
+    /// one produced later, which `f` is applied to once it's ready instead, so middleware
+    /// post-processes responses through this to handle both alike.
+    pub fn map(mut self, f: impl FnOnce(Response) -> Response + Send + 'static) -> Self {
+        match self.deferred.as_mut() {
+            Some(pending) => {
+                pending.then(Box::new(f));
+                self
+            }
+            None => f(self),
+        }
+    }
+
+    /// Whether this stands for a response produced later, by a `Responder` or an `AsyncHandler`.
+    /// Its status and headers are never sent, only those of the actual response.
+    pub fn is_deferred(&self) -> bool {
+        self.deferred.is_some()
+    }
+
+    /// Streams the body from any `Body` source, such as a `FileBody`
+    pub fn with_streaming_body(mut self, body: impl Body) -> Self {
+        self.body = Box::new(body);
+        self.event_stream = None;
+        self
+    }
+
+    /// Streams the body from an iterator of chunks, the response is sent chunked
+    pub fn with_stream<I>(self, chunks: I) -> Self
+    where
+        I: Iterator<Item = Bytes> + Send + 'static,
+    {
+        self.with_streaming_body(StreamBody::new(chunks))
+    }
+
+    /// Converts the status line and headers into raw bytes to be sent over the wire.
+    /// The body is left in place, to be streamed by the connection afterwards.
+    ///
+    /// Returns whether the body must be sent with chunked framing, which is the case when its
+    /// length isn't known up front. When `allow_chunked` is false (HTTP/1.0 clients) such a body
+    /// is sent without framing instead, and delimited by closing the connection.
+    pub fn encode_head(&self, dst: &mut BytesMut, allow_chunked: bool) -> bool {
+        let status_text = reason_phrase(self.status);
 
         // Write status line
         dst.put_slice(b"HTTP/1.1 ");
@@ -35,17 +106,29 @@ impl Response {
         dst.put_slice(status_text.as_bytes());
         dst.put_slice(b"\r\n");
 
-        // Write content-length
-        dst.put_slice(b"Content-Length: ");
-        dst.put_slice(self.body.len().to_string().as_bytes());
-        dst.put_slice(b"\r\n");
+        // Write content-length, or fall back to chunked framing. Statuses that never carry a body
+        // get neither
+        let chunked = match self.body.size_hint() {
+            _ if !status_has_body(self.status) => false,
+            Some(len) => {
+                dst.put_slice(b"Content-Length: ");
+                dst.put_slice(len.to_string().as_bytes());
+                dst.put_slice(b"\r\n");
+                false
+            }
+            None if allow_chunked => {
+                dst.put_slice(b"Transfer-Encoding: chunked\r\n");
+                true
+            }
+            None => false,
+        };
 
-        // Default content-type if not provided
-        if !self
+        // Default content-type if not provided, responses that never carry a body have no content
+        let has_content_type = self
             .headers
             .iter()
-            .any(|(n, _)| n.eq_ignore_ascii_case("Content-Type"))
-        {
+            .any(|(n, _)| n.eq_ignore_ascii_case("Content-Type"));
+        if status_has_body(self.status) && !has_content_type {
             dst.put_slice(b"Content-Type: text/plain\r\n");
         }
 
@@ -57,8 +140,93 @@ impl Response {
             dst.put_slice(b"\r\n");
         }
 
-        // End headers & body
+        // End headers
         dst.put_slice(b"\r\n");
-        dst.put_slice(&self.body);
+
+        chunked
+    }
+}
+
+/// Whether responses with this status may carry a body, 1xx, 204 and 304 responses never do
+pub(crate) fn status_has_body(status: u16) -> bool {
+    !matches!(status, 100..=199 | 204 | 304)
+}
+
+/// Standard reason phrase of a status code, empty for unknown codes
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(resp: &Response) -> String {
+        let mut dst = BytesMut::new();
+        resp.encode_head(&mut dst, true);
+        String::from_utf8(dst.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn defaults_the_content_type_of_responses_with_content() {
+        assert_eq!(
+            head(&Response::new(200).with_body("hi")),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nContent-Type: text/plain\r\n\r\n"
+        );
+        assert_eq!(
+            head(&Response::new(200).with_header("Content-Type", "text/html")),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nContent-Type: text/html\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn leaves_responses_without_content_untyped() {
+        for (status, line) in [
+            (101, "101 Switching Protocols"),
+            (204, "204 No Content"),
+            (304, "304 Not Modified"),
+        ] {
+            assert_eq!(
+                head(&Response::new(status).with_header("ETag", "\"v1\"")),
+                format!("HTTP/1.1 {}\r\nETag: \"v1\"\r\n\r\n", line)
+            );
+        }
     }
 }
@@ -0,0 +1,54 @@
+mod frame;
+mod hpack;
+mod session;
+
+pub(crate) use self::{
+    frame::{HEADER_LEN as FRAME_HEADER_LEN, MAX_FRAME_SIZE, PREFACE},
+    session::Session,
+};
+
+use crate::http::{list_contains, Request};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+
+/// Protocol identifier negotiated through ALPN for HTTP/2 over TLS
+pub(crate) const ALPN_PROTOCOL: &[u8] = b"h2";
+
+/// The settings carried by an HTTP/1.1 request asking to upgrade to HTTP/2 over cleartext, if it
+/// is a valid one
+pub(crate) fn upgrade_settings(req: &Request) -> Option<Vec<u8>> {
+    let upgrade = req
+        .headers
+        .iter()
+        .filter(|h| h.name.eq_ignore_ascii_case("Upgrade"))
+        .any(|h| list_contains(h.value, "h2c"));
+    if req.version != 1
+        || !upgrade
+        || !req.has_connection_option("upgrade")
+        || !req.has_connection_option("HTTP2-Settings")
+    {
+        return None;
+    }
+
+    // Exactly one HTTP2-Settings header, holding a SETTINGS payload
+    let mut values = req
+        .headers
+        .iter()
+        .filter(|h| h.name.eq_ignore_ascii_case("HTTP2-Settings"));
+    let value = values.next()?.value;
+    if values.next().is_some() {
+        return None;
+    }
+
+    // Some clients pad the value even though the encoding shouldn't be
+    let value = value.trim_ascii();
+    let value = value
+        .strip_suffix(b"==")
+        .or(value.strip_suffix(b"="))
+        .unwrap_or(value);
+    let settings = BASE64URL.decode(value).ok()?;
+    if !settings.len().is_multiple_of(6) {
+        return None;
+    }
+    Some(settings)
+}
@@ -0,0 +1,138 @@
+use bytes::{BufMut, BytesMut};
+
+/// Sent by clients before anything else on an HTTP/2 connection
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+pub const HEADER_LEN: usize = 9;
+/// Largest frame payload accepted, which is the smallest maximum allowed and the default one
+pub const MAX_FRAME_SIZE: usize = 16384;
+/// Initial flow control window of the connection and of every stream
+pub const DEFAULT_WINDOW: i64 = 65535;
+pub const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const PRIORITY: u8 = 0x2;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PUSH_PROMISE: u8 = 0x5;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+pub const FLAG_END_STREAM: u8 = 0x1;
+pub const FLAG_ACK: u8 = 0x1;
+pub const FLAG_END_HEADERS: u8 = 0x4;
+pub const FLAG_PADDED: u8 = 0x8;
+pub const FLAG_PRIORITY: u8 = 0x20;
+
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// Error codes of RST_STREAM and GOAWAY frames
+pub mod error_code {
+    pub const NO_ERROR: u32 = 0x0;
+    pub const PROTOCOL_ERROR: u32 = 0x1;
+    pub const INTERNAL_ERROR: u32 = 0x2;
+    pub const FLOW_CONTROL_ERROR: u32 = 0x3;
+    pub const STREAM_CLOSED: u32 = 0x5;
+    pub const FRAME_SIZE_ERROR: u32 = 0x6;
+    pub const REFUSED_STREAM: u32 = 0x7;
+    pub const COMPRESSION_ERROR: u32 = 0x9;
+    pub const ENHANCE_YOUR_CALM: u32 = 0xb;
+}
+
+/// The fixed size header starting every frame
+#[derive(Clone, Copy, Debug)]
+pub struct FrameHeader {
+    pub len: usize,
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+}
+
+impl FrameHeader {
+    /// Parses the header at the start of `buf`, which must hold at least HEADER_LEN bytes
+    pub fn parse(buf: &[u8]) -> Self {
+        FrameHeader {
+            len: (buf[0] as usize) << 16 | (buf[1] as usize) << 8 | buf[2] as usize,
+            kind: buf[3],
+            flags: buf[4],
+            // The reserved bit is ignored
+            stream_id: u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]) & 0x7FFF_FFFF,
+        }
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+pub fn put_header(dst: &mut BytesMut, len: usize, kind: u8, flags: u8, stream_id: u32) {
+    dst.put_slice(&(len as u32).to_be_bytes()[1..]);
+    dst.put_u8(kind);
+    dst.put_u8(flags);
+    dst.put_u32(stream_id);
+}
+
+pub fn put_settings(dst: &mut BytesMut, settings: &[(u16, u32)]) {
+    put_header(dst, settings.len() * 6, SETTINGS, 0, 0);
+    for &(id, value) in settings {
+        dst.put_u16(id);
+        dst.put_u32(value);
+    }
+}
+
+pub fn put_window_update(dst: &mut BytesMut, stream_id: u32, increment: u32) {
+    put_header(dst, 4, WINDOW_UPDATE, 0, stream_id);
+    dst.put_u32(increment);
+}
+
+pub fn put_rst_stream(dst: &mut BytesMut, stream_id: u32, code: u32) {
+    put_header(dst, 4, RST_STREAM, 0, stream_id);
+    dst.put_u32(code);
+}
+
+pub fn put_goaway(dst: &mut BytesMut, last_stream_id: u32, code: u32) {
+    put_header(dst, 8, GOAWAY, 0, 0);
+    dst.put_u32(last_stream_id);
+    dst.put_u32(code);
+}
+
+/// Splits a header block over a HEADERS frame and as many CONTINUATION frames as needed
+pub fn put_headers(dst: &mut BytesMut, stream_id: u32, block: &[u8], end_stream: bool, max: usize) {
+    let mut chunks = block.chunks(max).peekable();
+    let mut kind = HEADERS;
+    let mut flags = if end_stream { FLAG_END_STREAM } else { 0 };
+
+    loop {
+        let chunk = chunks.next().unwrap_or_default();
+        let last = chunks.peek().is_none();
+        if last {
+            flags |= FLAG_END_HEADERS;
+        }
+
+        put_header(dst, chunk.len(), kind, flags, stream_id);
+        dst.put_slice(chunk);
+        if last {
+            return;
+        }
+
+        kind = CONTINUATION;
+        flags = 0;
+    }
+}
+
+/// Strips the padding of a DATA or HEADERS frame, failing if it's longer than the payload
+pub fn unpad(header: &FrameHeader, payload: &[u8]) -> Option<std::ops::Range<usize>> {
+    if !header.has(FLAG_PADDED) {
+        return Some(0..payload.len());
+    }
+
+    let (&pad_len, rest) = payload.split_first()?;
+    let end = rest.len().checked_sub(pad_len as usize)?;
+    Some(1..1 + end)
+}
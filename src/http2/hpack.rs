@@ -0,0 +1,299 @@
+mod huffman;
+
+use std::collections::VecDeque;
+
+/// Size of the dynamic table the decoder allows, the default of SETTINGS_HEADER_TABLE_SIZE
+pub const TABLE_SIZE: usize = 4096;
+/// Accounted for every entry on top of its name and value, and for every field of a header list
+pub const ENTRY_OVERHEAD: usize = 32;
+
+/// RFC 7541 appendix A, indexed from 1
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// A malformed header block, which leaves the decoder out of sync with the client and fails the
+/// whole connection
+#[derive(Debug)]
+pub struct DecodeError;
+
+/// A decoded header field
+pub struct Field {
+    pub name: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+/// Decodes header blocks, keeping the dynamic table the client's encoder builds in sync
+pub struct Decoder {
+    /// Most recent entry first
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size: TABLE_SIZE,
+        }
+    }
+
+    /// Decodes a complete header block. Fields are only collected while the list stays within
+    /// `max_list_size`, the rest of the block being decoded for its table updates alone, so
+    /// `Ok(None)` means the list was too large.
+    pub fn decode(
+        &mut self,
+        mut block: &[u8],
+        max_list_size: usize,
+    ) -> Result<Option<Vec<Field>>, DecodeError> {
+        let mut fields = Some(Vec::new());
+        let mut list_size = 0;
+        let mut first = true;
+
+        while let Some(&byte) = block.first() {
+            let field = if byte & 0x80 != 0 {
+                // Indexed field
+                let index = decode_int(&mut block, 7)?;
+                let (name, value) = self.get(index)?;
+                (name.to_vec(), value.to_vec())
+            } else if byte & 0x40 != 0 {
+                // Literal field, added to the table
+                let (name, value) = self.decode_literal(&mut block, 6)?;
+                self.insert(name.clone(), value.clone());
+                (name, value)
+            } else if byte & 0x20 != 0 {
+                // Table size update, only allowed at the start of a block
+                let size = decode_int(&mut block, 5)?;
+                if !first || size > TABLE_SIZE {
+                    return Err(DecodeError);
+                }
+                self.max_size = size;
+                self.evict(0);
+                continue;
+            } else {
+                // Literal field, without indexing or never indexed
+                self.decode_literal(&mut block, 4)?
+            };
+            first = false;
+
+            list_size += field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+            if list_size > max_list_size {
+                fields = None;
+            }
+            if let Some(fields) = fields.as_mut() {
+                fields.push(Field {
+                    name: field.0,
+                    value: field.1,
+                });
+            }
+        }
+
+        Ok(fields)
+    }
+
+    fn get(&self, index: usize) -> Result<(&[u8], &[u8]), DecodeError> {
+        match index {
+            0 => Err(DecodeError),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.as_bytes(), value.as_bytes()))
+            }
+            _ => {
+                let (name, value) = self.entries.get(index - 62).ok_or(DecodeError)?;
+                Ok((name, value))
+            }
+        }
+    }
+
+    fn decode_literal(
+        &self,
+        block: &mut &[u8],
+        prefix: u8,
+    ) -> Result<(Vec<u8>, Vec<u8>), DecodeError> {
+        let name = match decode_int(block, prefix)? {
+            0 => decode_string(block)?,
+            index => self.get(index)?.0.to_vec(),
+        };
+        let value = decode_string(block)?;
+        Ok((name, value))
+    }
+
+    fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+
+        // An entry larger than the table empties it without being added
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    /// Drops the oldest entries until `room` bytes fit in the table
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            let Some((name, value)) = self.entries.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+fn decode_int(block: &mut &[u8], prefix: u8) -> Result<usize, DecodeError> {
+    let mask = (1u16 << prefix) as u8 - 1;
+    let (&first, mut rest) = block.split_first().ok_or(DecodeError)?;
+
+    let mut value = (first & mask) as usize;
+    if value == mask as usize {
+        let mut shift = 0;
+        loop {
+            let (&byte, next) = rest.split_first().ok_or(DecodeError)?;
+            rest = next;
+            // Anything needing more than 4 continuation bytes is larger than any sane value
+            if shift > 21 {
+                return Err(DecodeError);
+            }
+            value += ((byte & 0x7F) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+
+    *block = rest;
+    Ok(value)
+}
+
+fn decode_string(block: &mut &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let huffman = block.first().is_some_and(|b| b & 0x80 != 0);
+    let len = decode_int(block, 7)?;
+    if block.len() < len {
+        return Err(DecodeError);
+    }
+
+    let (data, rest) = block.split_at(len);
+    *block = rest;
+
+    if huffman {
+        let mut decoded = Vec::with_capacity(len * 8 / 5);
+        huffman::decode(data, &mut decoded).map_err(|_| DecodeError)?;
+        Ok(decoded)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+/// Encodes header blocks without ever adding to the dynamic table, so blocks don't depend on each
+/// other and the client's table size doesn't matter
+pub fn encode_field(dst: &mut Vec<u8>, name: &str, value: &str) {
+    // Fully indexed, only worth it for the status codes of the static table
+    if let Some(index) = STATIC_TABLE
+        .iter()
+        .position(|&(n, v)| n == name && v == value && !v.is_empty())
+    {
+        encode_int(dst, 0x80, 7, index + 1);
+        return;
+    }
+
+    // Literal without indexing, the name indexed when the static table has it
+    match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+        Some(index) => encode_int(dst, 0x00, 4, index + 1),
+        None => {
+            encode_int(dst, 0x00, 4, 0);
+            encode_string(dst, name.as_bytes());
+        }
+    }
+    encode_string(dst, value.as_bytes());
+}
+
+fn encode_int(dst: &mut Vec<u8>, flags: u8, prefix: u8, mut value: usize) {
+    let mask = ((1u16 << prefix) - 1) as usize;
+    if value < mask {
+        dst.push(flags | value as u8);
+        return;
+    }
+
+    dst.push(flags | mask as u8);
+    value -= mask;
+    while value >= 0x80 {
+        dst.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    dst.push(value as u8);
+}
+
+/// Huffman encodes strings when that makes them shorter
+fn encode_string(dst: &mut Vec<u8>, data: &[u8]) {
+    let huffman_len = huffman::encoded_len(data);
+    if huffman_len < data.len() {
+        encode_int(dst, 0x80, 7, huffman_len);
+        huffman::encode(data, dst);
+    } else {
+        encode_int(dst, 0x00, 7, data.len());
+        dst.extend_from_slice(data);
+    }
+}
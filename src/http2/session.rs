@@ -0,0 +1,992 @@
+use super::frame::{self, error_code, FrameHeader, DEFAULT_WINDOW, HEADER_LEN, MAX_WINDOW};
+use super::hpack::{self, Decoder, Field};
+use crate::handler::{overloaded, Handler, HandlerPool, PendingResponse};
+use crate::http::{self, Body, ConnectionInfo, OwnedRequest, Response};
+use crate::net::{ConnectionLimits, Notifier};
+use crate::sse::EventStream;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::sync::Arc;
+
+/// Streams a client may have open at once, further ones are refused
+const MAX_CONCURRENT_STREAMS: usize = 100;
+
+/// Headers that only make sense for a single HTTP/1.x connection, forbidden in HTTP/2
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The HTTP/2 side of a connection: demultiplexes the frames received into requests, hands them
+/// to the handler as they complete, and interleaves the responses of every stream.
+///
+/// Input is read from and output written to the connection's buffers, the session doesn't do any
+/// I/O itself. Errors are answered as the protocol requires, with RST_STREAM for the ones
+/// affecting a single stream and GOAWAY for the ones failing the whole connection.
+pub(crate) struct Session {
+    decoder: Decoder,
+    notifier: Notifier,
+    /// Runs the handler away from the event loop, if enabled
+    handler_pool: Option<Arc<HandlerPool>>,
+    /// Connection the session runs on, handed to every request
+    connection: ConnectionInfo,
+    /// Number of requests dispatched on the connection, including one answered before an h2c
+    /// upgrade
+    requests: u64,
+    max_header_list_size: usize,
+    max_body_size: usize,
+    preface_received: bool,
+    /// The client's first SETTINGS frame was received, which must follow its preface
+    settings_received: bool,
+    streams: BTreeMap<u32, Stream>,
+    /// Highest stream id opened by the client
+    last_stream_id: u32,
+    /// Header block whose CONTINUATION frames are still expected
+    partial_headers: Option<PartialHeaders>,
+    /// Largest frame payload the client accepts
+    max_frame_size: usize,
+    /// Send window of new streams, as set by the client
+    initial_window: i64,
+    /// Connection level flow control windows
+    send_window: i64,
+    recv_window: i64,
+    /// Stream whose turn it is to send data, so streams don't starve each other
+    next_stream: u32,
+    /// No new stream is accepted, the connection closes once the open ones are done
+    going_away: bool,
+    goaway_sent: bool,
+    /// The connection failed, nothing is read or sent past the GOAWAY frame
+    failed: bool,
+}
+
+struct PartialHeaders {
+    stream_id: u32,
+    block: Vec<u8>,
+    end_stream: bool,
+}
+
+struct Stream {
+    /// Request being received, taken once it's complete or was answered early
+    request: Option<PendingRequest>,
+    /// The client sent END_STREAM
+    remote_closed: bool,
+    /// Response being produced by the handler pool or a `Responder`, and whether the request was
+    /// a HEAD
+    handler: Option<(PendingResponse, bool)>,
+    response: Option<OutgoingBody>,
+    send_window: i64,
+    recv_window: i64,
+}
+
+struct PendingRequest {
+    method: String,
+    path: String,
+    authority: Option<Vec<u8>>,
+    headers: Vec<(String, Vec<u8>)>,
+    content_length: Option<usize>,
+    body: BytesMut,
+}
+
+/// Body of a response whose headers were sent
+struct OutgoingBody {
+    body: Box<dyn Body>,
+    /// Part of the last chunk that didn't fit in the windows
+    leftover: Bytes,
+    /// The body had no data ready, see `BodyWriter::fill`
+    paused: bool,
+    event_stream: Option<EventStream>,
+}
+
+/// A request that can't be served, answered with RST_STREAM
+struct Malformed;
+
+impl Session {
+    /// Starts a session, queueing the server's SETTINGS frame which must come first
+    pub fn new(
+        limits: &ConnectionLimits,
+        notifier: Notifier,
+        handler_pool: Option<Arc<HandlerPool>>,
+        connection: ConnectionInfo,
+        requests: u64,
+        out: &mut BytesMut,
+    ) -> Self {
+        frame::put_settings(
+            out,
+            &[
+                (
+                    frame::SETTINGS_MAX_CONCURRENT_STREAMS,
+                    MAX_CONCURRENT_STREAMS as u32,
+                ),
+                (
+                    frame::SETTINGS_MAX_HEADER_LIST_SIZE,
+                    limits.max_header_size as u32,
+                ),
+            ],
+        );
+
+        Session {
+            decoder: Decoder::new(),
+            notifier,
+            handler_pool,
+            connection,
+            requests,
+            max_header_list_size: limits.max_header_size,
+            max_body_size: limits.max_body_size,
+            preface_received: false,
+            settings_received: false,
+            streams: BTreeMap::new(),
+            last_stream_id: 0,
+            partial_headers: None,
+            max_frame_size: frame::MAX_FRAME_SIZE,
+            initial_window: DEFAULT_WINDOW,
+            send_window: DEFAULT_WINDOW,
+            recv_window: DEFAULT_WINDOW,
+            next_stream: 0,
+            going_away: false,
+            goaway_sent: false,
+            failed: false,
+        }
+    }
+
+    /// Takes over a connection upgraded from HTTP/1.1 with the client's `settings`, answering the
+    /// request that asked for it on stream 1
+    pub fn upgrade(&mut self, settings: &[u8], resp: Response, head: bool, out: &mut BytesMut) {
+        if let Err(code) = self.apply_settings(settings) {
+            self.fail(code, out);
+            return;
+        }
+
+        self.last_stream_id = 1;
+        self.streams.insert(1, self.new_stream(None, true));
+        self.respond(1, resp, head, out);
+    }
+
+    /// Handles the complete frames at the start of `input`, writing replies to `out`, until it
+    /// holds `window` bytes. Returns whether frames were left for once `out` drained, so that a
+    /// client sending PINGs or SETTINGS without reading the replies can't make it grow without
+    /// bounds.
+    pub fn receive<H: Handler>(
+        &mut self,
+        input: &mut BytesMut,
+        out: &mut BytesMut,
+        window: usize,
+        handler: &H,
+    ) -> bool {
+        if !self.preface_received && !self.failed {
+            let len = input.len().min(frame::PREFACE.len());
+            if input[..len] != frame::PREFACE[..len] {
+                self.fail(error_code::PROTOCOL_ERROR, out);
+            } else if len == frame::PREFACE.len() {
+                input.advance(len);
+                self.preface_received = true;
+            }
+        }
+
+        while self.preface_received && !self.failed && input.len() >= HEADER_LEN {
+            if out.len() >= window {
+                return true;
+            }
+
+            let header = FrameHeader::parse(input);
+            if header.len > frame::MAX_FRAME_SIZE {
+                self.fail(error_code::FRAME_SIZE_ERROR, out);
+                break;
+            }
+            if input.len() < HEADER_LEN + header.len {
+                break;
+            }
+
+            let frame = input.split_to(HEADER_LEN + header.len);
+            if let Err(code) = self.on_frame(header, &frame[HEADER_LEN..], out, handler) {
+                self.fail(code, out);
+            }
+        }
+
+        if self.failed {
+            input.clear();
+        }
+        false
+    }
+
+    /// Moves response data to `out` until it holds `window` bytes, as far as flow control allows
+    pub fn fill(&mut self, out: &mut BytesMut, window: usize) {
+        loop {
+            let ready: Vec<u32> = self
+                .streams
+                .range(self.next_stream..)
+                .chain(self.streams.range(..self.next_stream))
+                .filter(|(_, stream)| stream.response.as_ref().is_some_and(|r| !r.paused))
+                .map(|(&id, _)| id)
+                .collect();
+
+            let mut progress = false;
+            for id in ready {
+                if out.len() >= window {
+                    return;
+                }
+
+                progress |= self.send_data(id, out, window);
+                self.next_stream = id + 1;
+            }
+
+            if !progress {
+                return;
+            }
+        }
+    }
+
+    /// Stops accepting streams as the server goes away, the open ones finishing first. Event
+    /// streams end after the events already queued.
+    pub fn shutdown(&mut self, out: &mut BytesMut) {
+        self.going_away = true;
+        if !self.goaway_sent && !self.failed {
+            self.goaway_sent = true;
+            frame::put_goaway(out, self.last_stream_id, error_code::NO_ERROR);
+        }
+
+        for response in self
+            .streams
+            .values_mut()
+            .filter_map(|s| s.response.as_mut())
+        {
+            if let Some(stream) = response.event_stream.as_ref() {
+                stream.finish();
+                response.paused = false;
+            }
+        }
+    }
+
+    /// Sends the responses delivered by the handler pool or `Responder`s, and lets paused bodies
+    /// be polled again, after the connection was notified
+    pub fn wake(&mut self, out: &mut BytesMut) {
+        let ready: Vec<(u32, Response, bool)> = self
+            .streams
+            .iter_mut()
+            .filter_map(|(&id, stream)| {
+                let (response, head) = stream.handler.as_mut()?;
+                Some((id, response.poll()?, *head))
+            })
+            .collect();
+        for (id, resp, head) in ready {
+            if let Some(stream) = self.streams.get_mut(&id) {
+                stream.handler = None;
+            }
+            self.answer(id, resp, head, out);
+        }
+
+        for response in self
+            .streams
+            .values_mut()
+            .filter_map(|s| s.response.as_mut())
+        {
+            response.paused = false;
+        }
+    }
+
+    /// Queues a comment on every idle event stream
+    pub fn heartbeat(&mut self) {
+        for response in self
+            .streams
+            .values_mut()
+            .filter_map(|s| s.response.as_mut())
+        {
+            if let Some(stream) = response.event_stream.as_ref().filter(|_| response.paused) {
+                stream.heartbeat();
+                response.paused = false;
+            }
+        }
+    }
+
+    /// Whether no stream is open
+    pub fn is_idle(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Whether every open stream waits, either for a response from the handler pool or a
+    /// `Responder` or, as event streams do, for events
+    pub fn is_paused(&self) -> bool {
+        !self.streams.is_empty()
+            && self.streams.values().all(|s| {
+                s.remote_closed
+                    && (s.handler.is_some() || s.response.as_ref().is_some_and(|r| r.paused))
+            })
+    }
+
+    /// Whether the connection must be closed once the output was sent
+    pub fn is_done(&self) -> bool {
+        self.failed || (self.going_away && self.streams.is_empty())
+    }
+
+    fn on_frame<H: Handler>(
+        &mut self,
+        header: FrameHeader,
+        payload: &[u8],
+        out: &mut BytesMut,
+        handler: &H,
+    ) -> Result<(), u32> {
+        if !self.settings_received
+            && (header.kind != frame::SETTINGS || header.has(frame::FLAG_ACK))
+        {
+            return Err(error_code::PROTOCOL_ERROR);
+        }
+
+        // Nothing may come between a header block's frames
+        if let Some(partial) = self.partial_headers.as_ref() {
+            if header.kind != frame::CONTINUATION || header.stream_id != partial.stream_id {
+                return Err(error_code::PROTOCOL_ERROR);
+            }
+        }
+
+        match header.kind {
+            frame::DATA => self.on_data(header, payload, out, handler),
+            frame::HEADERS => self.on_headers(header, payload, out, handler),
+            frame::CONTINUATION => self.on_continuation(header, payload, out, handler),
+            frame::PRIORITY => {
+                if header.stream_id == 0 {
+                    return Err(error_code::PROTOCOL_ERROR);
+                }
+                if payload.len() != 5 {
+                    self.reset(header.stream_id, error_code::FRAME_SIZE_ERROR, out);
+                }
+                Ok(())
+            }
+            frame::RST_STREAM => {
+                if header.stream_id == 0 || header.stream_id > self.last_stream_id {
+                    return Err(error_code::PROTOCOL_ERROR);
+                }
+                if payload.len() != 4 {
+                    return Err(error_code::FRAME_SIZE_ERROR);
+                }
+                self.streams.remove(&header.stream_id);
+                Ok(())
+            }
+            frame::SETTINGS => self.on_settings(header, payload, out),
+            frame::PING => {
+                if header.stream_id != 0 {
+                    return Err(error_code::PROTOCOL_ERROR);
+                }
+                if payload.len() != 8 {
+                    return Err(error_code::FRAME_SIZE_ERROR);
+                }
+                if !header.has(frame::FLAG_ACK) {
+                    frame::put_header(out, 8, frame::PING, frame::FLAG_ACK, 0);
+                    out.put_slice(payload);
+                }
+                Ok(())
+            }
+            frame::GOAWAY => {
+                if header.stream_id != 0 {
+                    return Err(error_code::PROTOCOL_ERROR);
+                }
+                if payload.len() < 8 {
+                    return Err(error_code::FRAME_SIZE_ERROR);
+                }
+                // The client won't open streams anymore, the open ones may still complete
+                self.going_away = true;
+                Ok(())
+            }
+            frame::WINDOW_UPDATE => self.on_window_update(header, payload, out),
+            // Servers never receive pushes
+            frame::PUSH_PROMISE => Err(error_code::PROTOCOL_ERROR),
+            // Unknown frame types are ignored
+            _ => Ok(()),
+        }
+    }
+
+    fn on_data<H: Handler>(
+        &mut self,
+        header: FrameHeader,
+        payload: &[u8],
+        out: &mut BytesMut,
+        handler: &H,
+    ) -> Result<(), u32> {
+        let id = header.stream_id;
+        if id == 0 || id > self.last_stream_id {
+            return Err(error_code::PROTOCOL_ERROR);
+        }
+        let data = &payload[frame::unpad(&header, payload).ok_or(error_code::PROTOCOL_ERROR)?];
+
+        // The whole frame counts against flow control, padding included, and is given back right
+        // away: the body size limit is what bounds the memory used by a stream
+        self.recv_window -= payload.len() as i64;
+        if self.recv_window < 0 {
+            return Err(error_code::FLOW_CONTROL_ERROR);
+        }
+        if self.recv_window <= DEFAULT_WINDOW / 2 {
+            frame::put_window_update(out, 0, (DEFAULT_WINDOW - self.recv_window) as u32);
+            self.recv_window = DEFAULT_WINDOW;
+        }
+
+        // Frames still in flight when the stream was reset are ignored
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return Ok(());
+        };
+        if stream.remote_closed {
+            self.reset(id, error_code::STREAM_CLOSED, out);
+            return Ok(());
+        }
+
+        stream.recv_window -= payload.len() as i64;
+        if stream.recv_window < 0 {
+            self.reset(id, error_code::FLOW_CONTROL_ERROR, out);
+            return Ok(());
+        }
+
+        let mut too_large = false;
+        if let Some(request) = stream.request.as_mut() {
+            too_large = request.body.len() + data.len() > self.max_body_size;
+            if !too_large {
+                request.body.extend_from_slice(data);
+            }
+        }
+
+        let end_stream = header.has(frame::FLAG_END_STREAM);
+        if end_stream {
+            stream.remote_closed = true;
+        } else if stream.recv_window <= DEFAULT_WINDOW / 2 {
+            frame::put_window_update(out, id, (DEFAULT_WINDOW - stream.recv_window) as u32);
+            stream.recv_window = DEFAULT_WINDOW;
+        }
+
+        if too_large {
+            stream.request = None;
+            self.respond(id, error(413), false, out);
+        } else if end_stream {
+            self.dispatch(id, out, handler);
+        }
+        Ok(())
+    }
+
+    fn on_headers<H: Handler>(
+        &mut self,
+        header: FrameHeader,
+        payload: &[u8],
+        out: &mut BytesMut,
+        handler: &H,
+    ) -> Result<(), u32> {
+        let id = header.stream_id;
+        if id == 0 || id.is_multiple_of(2) {
+            return Err(error_code::PROTOCOL_ERROR);
+        }
+
+        let mut block =
+            &payload[frame::unpad(&header, payload).ok_or(error_code::PROTOCOL_ERROR)?];
+        if header.has(frame::FLAG_PRIORITY) {
+            // Priorities are advisory, and ignored
+            block = block.get(5..).ok_or(error_code::PROTOCOL_ERROR)?;
+        }
+
+        let end_stream = header.has(frame::FLAG_END_STREAM);
+        if header.has(frame::FLAG_END_HEADERS) {
+            return self.on_header_block(id, block, end_stream, out, handler);
+        }
+
+        self.partial_headers = Some(PartialHeaders {
+            stream_id: id,
+            block: block.to_vec(),
+            end_stream,
+        });
+        Ok(())
+    }
+
+    fn on_continuation<H: Handler>(
+        &mut self,
+        header: FrameHeader,
+        payload: &[u8],
+        out: &mut BytesMut,
+        handler: &H,
+    ) -> Result<(), u32> {
+        let Some(partial) = self.partial_headers.as_mut() else {
+            return Err(error_code::PROTOCOL_ERROR);
+        };
+
+        // A block is never smaller than the header list it encodes, give up on oversized ones
+        // before buffering them whole
+        partial.block.extend_from_slice(payload);
+        if partial.block.len() > self.max_header_list_size {
+            return Err(error_code::ENHANCE_YOUR_CALM);
+        }
+
+        if header.has(frame::FLAG_END_HEADERS) {
+            let partial = self.partial_headers.take().unwrap();
+            return self.on_header_block(
+                partial.stream_id,
+                &partial.block,
+                partial.end_stream,
+                out,
+                handler,
+            );
+        }
+        Ok(())
+    }
+
+    fn on_header_block<H: Handler>(
+        &mut self,
+        id: u32,
+        block: &[u8],
+        end_stream: bool,
+        out: &mut BytesMut,
+        handler: &H,
+    ) -> Result<(), u32> {
+        // Decoded whatever happens to the stream, to keep the table in sync with the client
+        let fields = self
+            .decoder
+            .decode(block, self.max_header_list_size)
+            .map_err(|_| error_code::COMPRESSION_ERROR)?;
+
+        if let Some(stream) = self.streams.get_mut(&id) {
+            // Trailers, which are ignored, and must end the stream
+            if stream.remote_closed || !end_stream {
+                self.reset(id, error_code::PROTOCOL_ERROR, out);
+            } else {
+                stream.remote_closed = true;
+                self.dispatch(id, out, handler);
+            }
+            return Ok(());
+        }
+
+        // A stream that was closed already
+        if id <= self.last_stream_id {
+            return Ok(());
+        }
+        self.last_stream_id = id;
+
+        if self.going_away || self.streams.len() >= MAX_CONCURRENT_STREAMS {
+            frame::put_rst_stream(out, id, error_code::REFUSED_STREAM);
+            return Ok(());
+        }
+
+        let Some(fields) = fields else {
+            self.streams.insert(id, self.new_stream(None, end_stream));
+            self.respond(id, error(431), false, out);
+            return Ok(());
+        };
+
+        let Ok(request) = parse_request(fields) else {
+            frame::put_rst_stream(out, id, error_code::PROTOCOL_ERROR);
+            return Ok(());
+        };
+
+        self.streams
+            .insert(id, self.new_stream(Some(request), end_stream));
+        if end_stream {
+            self.dispatch(id, out, handler);
+        }
+        Ok(())
+    }
+
+    fn on_settings(
+        &mut self,
+        header: FrameHeader,
+        payload: &[u8],
+        out: &mut BytesMut,
+    ) -> Result<(), u32> {
+        if header.stream_id != 0 {
+            return Err(error_code::PROTOCOL_ERROR);
+        }
+
+        if header.has(frame::FLAG_ACK) {
+            if !payload.is_empty() {
+                return Err(error_code::FRAME_SIZE_ERROR);
+            }
+            return Ok(());
+        }
+
+        self.apply_settings(payload)?;
+        self.settings_received = true;
+        frame::put_header(out, 0, frame::SETTINGS, frame::FLAG_ACK, 0);
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), u32> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(error_code::FRAME_SIZE_ERROR);
+        }
+
+        for setting in payload.chunks_exact(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+
+            match id {
+                frame::SETTINGS_ENABLE_PUSH if value > 1 => {
+                    return Err(error_code::PROTOCOL_ERROR);
+                }
+                frame::SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > MAX_WINDOW {
+                        return Err(error_code::FLOW_CONTROL_ERROR);
+                    }
+
+                    // Applies to the open streams as well
+                    let delta = value as i64 - self.initial_window;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > MAX_WINDOW {
+                            return Err(error_code::FLOW_CONTROL_ERROR);
+                        }
+                    }
+                    self.initial_window = value as i64;
+                }
+                frame::SETTINGS_MAX_FRAME_SIZE => {
+                    if !(frame::MAX_FRAME_SIZE as u32..=0xFF_FFFF).contains(&value) {
+                        return Err(error_code::PROTOCOL_ERROR);
+                    }
+                    self.max_frame_size = value as usize;
+                }
+                // Our encoder never uses the dynamic table, and the other settings don't affect
+                // what a server sends
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_window_update(
+        &mut self,
+        header: FrameHeader,
+        payload: &[u8],
+        out: &mut BytesMut,
+    ) -> Result<(), u32> {
+        if payload.len() != 4 {
+            return Err(error_code::FRAME_SIZE_ERROR);
+        }
+        let increment = (u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]])
+            & 0x7FFF_FFFF) as i64;
+
+        let id = header.stream_id;
+        if id == 0 {
+            self.send_window += increment;
+            if increment == 0 {
+                return Err(error_code::PROTOCOL_ERROR);
+            }
+            if self.send_window > MAX_WINDOW {
+                return Err(error_code::FLOW_CONTROL_ERROR);
+            }
+            return Ok(());
+        }
+
+        if id > self.last_stream_id {
+            return Err(error_code::PROTOCOL_ERROR);
+        }
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return Ok(());
+        };
+
+        stream.send_window += increment;
+        if increment == 0 {
+            self.reset(id, error_code::PROTOCOL_ERROR, out);
+        } else if stream.send_window > MAX_WINDOW {
+            self.reset(id, error_code::FLOW_CONTROL_ERROR, out);
+        }
+        Ok(())
+    }
+
+    fn new_stream(&self, request: Option<PendingRequest>, remote_closed: bool) -> Stream {
+        Stream {
+            request,
+            remote_closed,
+            handler: None,
+            response: None,
+            send_window: self.initial_window,
+            recv_window: DEFAULT_WINDOW,
+        }
+    }
+
+    /// Hands the request of stream `id` to the handler, once it was fully received
+    fn dispatch<H: Handler>(&mut self, id: u32, out: &mut BytesMut, handler: &H) {
+        let Some(request) = self.streams.get_mut(&id).and_then(|s| s.request.take()) else {
+            return;
+        };
+
+        if request
+            .content_length
+            .is_some_and(|len| len != request.body.len())
+        {
+            self.reset(id, error_code::PROTOCOL_ERROR, out);
+            return;
+        }
+
+        let mut headers = request.headers;
+
+        // `:authority` replaces `Host`, which handlers written for HTTP/1.1 look for
+        if let Some(authority) = request.authority {
+            if !headers.iter().any(|(name, _)| name == "host") {
+                headers.push(("host".to_owned(), authority));
+            }
+        }
+
+        self.requests += 1;
+        let request = OwnedRequest {
+            method: request.method,
+            path: request.path,
+            version: 2,
+            headers,
+            body: request.body.freeze(),
+            connection: self.connection.clone(),
+            sequence: self.requests,
+        };
+        let head = request.method == "HEAD";
+
+        match self.handler_pool.as_ref() {
+            None => {
+                let resp = request.handle(handler);
+                self.answer(id, resp, head, out);
+            }
+            Some(pool) => match pool.submit(request) {
+                Some(response) => self.wait_for(id, response, head, out),
+                None => self.respond(id, overloaded(), head, out),
+            },
+        }
+    }
+
+    /// Sends `resp` on stream `id`, or waits for the response it stands for if it was deferred
+    fn answer(&mut self, id: u32, mut resp: Response, head: bool, out: &mut BytesMut) {
+        match resp.deferred.take() {
+            Some(response) => self.wait_for(id, response, head, out),
+            None => self.respond(id, resp, head, out),
+        }
+    }
+
+    /// Answers stream `id` with `response` once it's ready, found by `wake` if it isn't yet
+    fn wait_for(&mut self, id: u32, mut response: PendingResponse, head: bool, out: &mut BytesMut) {
+        response.attach(self.notifier.clone());
+        if let Some(resp) = response.poll() {
+            self.answer(id, resp, head, out);
+        } else if let Some(stream) = self.streams.get_mut(&id) {
+            stream.handler = Some((response, head));
+        }
+    }
+
+    /// Sends the headers of `resp` on stream `id`, its body following as `fill` is called
+    fn respond(&mut self, id: u32, mut resp: Response, head: bool, out: &mut BytesMut) {
+        // Protocol switches don't exist in HTTP/2, a WebSocket handshake can't be answered here
+        if resp.status < 200 {
+            resp = error(501);
+        }
+
+        let has_body = !head && http::status_has_body(resp.status);
+        let block = encode_head(&resp);
+        let end_stream = !has_body || resp.body.size_hint() == Some(0);
+        frame::put_headers(out, id, &block, end_stream, self.max_frame_size);
+
+        if end_stream {
+            self.stream_sent(id, out);
+            return;
+        }
+
+        let event_stream = resp.event_stream.take();
+        if let Some(stream) = event_stream.as_ref() {
+            stream.attach(self.notifier.clone());
+            if self.going_away {
+                stream.finish();
+            }
+        }
+
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.response = Some(OutgoingBody {
+                body: resp.body,
+                leftover: Bytes::new(),
+                paused: false,
+                event_stream,
+            });
+        }
+    }
+
+    /// Sends the next DATA frame of stream `id`. Returns whether anything was sent.
+    fn send_data(&mut self, id: u32, out: &mut BytesMut, window: usize) -> bool {
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return false;
+        };
+        let Some(response) = stream.response.as_mut() else {
+            return false;
+        };
+
+        let room = (window - out.len())
+            .min(self.max_frame_size)
+            .min(self.send_window.max(0) as usize)
+            .min(stream.send_window.max(0) as usize);
+        if room == 0 {
+            return false;
+        }
+
+        let mut chunk = if response.leftover.is_empty() {
+            match response.body.next_chunk(room) {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => {
+                    frame::put_header(out, 0, frame::DATA, frame::FLAG_END_STREAM, id);
+                    self.stream_sent(id, out);
+                    return true;
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    response.paused = true;
+                    return false;
+                }
+                Err(e) => {
+                    eprintln!("Failed to read the response body of stream {}: {}", id, e);
+                    self.reset(id, error_code::INTERNAL_ERROR, out);
+                    return true;
+                }
+            }
+        } else {
+            std::mem::take(&mut response.leftover)
+        };
+
+        if chunk.len() > room {
+            response.leftover = chunk.split_off(room);
+        }
+
+        // Bodies of known length end with their last chunk, which may use up the windows
+        let end_stream = response.leftover.is_empty() && response.body.size_hint() == Some(0);
+        let flags = if end_stream {
+            frame::FLAG_END_STREAM
+        } else {
+            0
+        };
+
+        frame::put_header(out, chunk.len(), frame::DATA, flags, id);
+        out.put_slice(&chunk);
+        self.send_window -= chunk.len() as i64;
+        stream.send_window -= chunk.len() as i64;
+
+        if end_stream {
+            self.stream_sent(id, out);
+        }
+        true
+    }
+
+    /// Closes stream `id` once its response was sent. A client still sending its request is told
+    /// to stop, the response not depending on the rest of it.
+    fn stream_sent(&mut self, id: u32, out: &mut BytesMut) {
+        if let Some(stream) = self.streams.remove(&id) {
+            if !stream.remote_closed {
+                frame::put_rst_stream(out, id, error_code::NO_ERROR);
+            }
+        }
+    }
+
+    fn reset(&mut self, id: u32, code: u32, out: &mut BytesMut) {
+        self.streams.remove(&id);
+        frame::put_rst_stream(out, id, code);
+    }
+
+    /// Fails the connection with `code`, closing it once the GOAWAY frame was sent
+    fn fail(&mut self, code: u32, out: &mut BytesMut) {
+        if !self.goaway_sent {
+            self.goaway_sent = true;
+            frame::put_goaway(out, self.last_stream_id, code);
+        }
+
+        self.failed = true;
+        self.going_away = true;
+        self.streams.clear();
+        self.partial_headers = None;
+    }
+}
+
+/// Checks the fields of a request header block, splitting the pseudo-headers off
+fn parse_request(fields: Vec<Field>) -> Result<PendingRequest, Malformed> {
+    let mut method = None;
+    let mut path = None;
+    let mut scheme = false;
+    let mut authority = None;
+    let mut headers = Vec::with_capacity(fields.len());
+    let mut content_length = None;
+
+    for field in fields {
+        let Ok(name) = String::from_utf8(field.name) else {
+            return Err(Malformed);
+        };
+        if name.is_empty() || name.bytes().any(|b| b.is_ascii_uppercase()) {
+            return Err(Malformed);
+        }
+
+        if name.starts_with(':') {
+            // Pseudo-headers come first, once each
+            if !headers.is_empty() {
+                return Err(Malformed);
+            }
+            let value = String::from_utf8(field.value).map_err(|_| Malformed)?;
+            let slot = match name.as_str() {
+                ":method" => &mut method,
+                ":path" => &mut path,
+                ":scheme" if !scheme => {
+                    scheme = true;
+                    continue;
+                }
+                ":authority" if authority.is_none() => {
+                    authority = Some(value.into_bytes());
+                    continue;
+                }
+                _ => return Err(Malformed),
+            };
+            if slot.replace(value).is_some() {
+                return Err(Malformed);
+            }
+            continue;
+        }
+
+        if CONNECTION_HEADERS.contains(&name.as_str())
+            || (name == "te" && field.value != b"trailers")
+        {
+            return Err(Malformed);
+        }
+        if name == "content-length" {
+            let len = std::str::from_utf8(&field.value)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .ok_or(Malformed)?;
+            if content_length
+                .replace(len)
+                .is_some_and(|previous| previous != len)
+            {
+                return Err(Malformed);
+            }
+        }
+
+        headers.push((name, field.value));
+    }
+
+    match (method, path) {
+        (Some(method), Some(path)) if scheme && !path.is_empty() => Ok(PendingRequest {
+            method,
+            path,
+            authority,
+            headers,
+            content_length,
+            body: BytesMut::new(),
+        }),
+        _ => Err(Malformed),
+    }
+}
+
+/// Encodes the header block of a response, with the same defaults as HTTP/1.x responses
+fn encode_head(resp: &Response) -> Vec<u8> {
+    let mut block = Vec::new();
+    hpack::encode_field(&mut block, ":status", &resp.status.to_string());
+
+    if http::status_has_body(resp.status) {
+        if let Some(len) = resp.body.size_hint() {
+            hpack::encode_field(&mut block, "content-length", &len.to_string());
+        }
+        if resp.get_header("Content-Type").is_none() {
+            hpack::encode_field(&mut block, "content-type", "text/plain");
+        }
+    }
+
+    for (name, value) in &resp.headers {
+        let name = name.to_ascii_lowercase();
+        if !CONNECTION_HEADERS.contains(&name.as_str()) {
+            hpack::encode_field(&mut block, &name, value);
+        }
+    }
+
+    block
+}
+
+fn error(status: u16) -> Response {
+    Response::new(status).with_body(http::reason_phrase(status))
+}
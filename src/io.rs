@@ -1,3 +1,5 @@
 pub mod buffer_pool;
+pub mod timer_wheel;
 
-pub use self::buffer_pool::{BufferPool, BUFFER_STANDARD_SIZE};
+pub use self::buffer_pool::{BufferPool, BUFFER_DANGER_SIZE, BUFFER_STANDARD_SIZE};
+pub use self::timer_wheel::TimerWheel;
@@ -2,7 +2,7 @@ use bytes::BytesMut;
 use std::collections::VecDeque;
 
 pub const BUFFER_STANDARD_SIZE: usize = 4096; // 4kb
-const BUFFER_DANGER_SIZE: usize = 65536; // 64KB
+pub const BUFFER_DANGER_SIZE: usize = 65536; // 64KB
 
 pub struct BufferPool {
     /// Pool of buffers
@@ -10,17 +10,24 @@ pub struct BufferPool {
 
     /// Size of each buffer
     buffer_size: usize,
+
+    /// Buffers that grew to this capacity are replaced instead of being pooled again
+    danger_size: usize,
 }
 
 impl BufferPool {
     /// Initialize the buffer pool with a fixed number of buffers
-    pub fn new(initial_capacitiy: usize, buffer_size: usize) -> Self {
+    pub fn new(initial_capacitiy: usize, buffer_size: usize, danger_size: usize) -> Self {
         let mut pool = VecDeque::with_capacity(initial_capacitiy);
         for _ in 0..initial_capacitiy {
             pool.push_back(BytesMut::with_capacity(buffer_size));
         }
 
-        BufferPool { pool, buffer_size }
+        BufferPool {
+            pool,
+            buffer_size,
+            danger_size,
+        }
     }
 
     /// Take a buffer from the pool. If none is available, allocate a new one and return it
@@ -35,7 +42,7 @@ impl BufferPool {
     pub fn return_buffer(&mut self, mut buf: BytesMut) {
         buf.clear(); // Resets the length to 0, but keeps the allocated memory
 
-        if buf.capacity() < BUFFER_DANGER_SIZE {
+        if buf.capacity() < self.danger_size {
             self.pool.push_back(buf);
         } else {
             self.pool.push_back(BytesMut::with_capacity(self.buffer_size));
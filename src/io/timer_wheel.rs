@@ -0,0 +1,196 @@
+use std::time::{Duration, Instant};
+
+struct Timer {
+    key: usize,
+    id: u64,
+    tick: u64,
+}
+
+/// Hashed timer wheel.
+///
+/// Time is split in ticks of `resolution`, and each timer lands in the slot of the tick its
+/// deadline falls in, modulo the number of slots. Deadlines further away than a full revolution
+/// simply stay in their slot for more than one revolution. Scheduling is O(1) and expiring costs
+/// one slot visit per elapsed tick.
+///
+/// Timers can't be cancelled, instead every timer gets a unique id and owners are expected to
+/// ignore expirations whose id they no longer care about.
+pub struct TimerWheel {
+    slots: Vec<Vec<Timer>>,
+    resolution: Duration,
+    origin: Instant,
+    /// Next tick to be processed by `expire`
+    current_tick: u64,
+    next_id: u64,
+    len: usize,
+}
+
+impl TimerWheel {
+    pub fn new(slots: usize, resolution: Duration) -> Self {
+        TimerWheel {
+            slots: (0..slots.max(1)).map(|_| Vec::new()).collect(),
+            resolution,
+            origin: Instant::now(),
+            current_tick: 0,
+            next_id: 0,
+            len: 0,
+        }
+    }
+
+    /// Schedules a timer for `key` firing at `deadline`, rounded up to the wheel resolution.
+    /// Returns the unique id of the timer.
+    pub fn schedule(&mut self, key: usize, deadline: Instant) -> u64 {
+        let elapsed = deadline.saturating_duration_since(self.origin).as_nanos();
+        let resolution = self.resolution.as_nanos().max(1);
+        let tick = std::cmp::max(elapsed.div_ceil(resolution) as u64, self.current_tick);
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let slot = (tick % self.slots.len() as u64) as usize;
+        self.slots[slot].push(Timer { key, id, tick });
+        self.len += 1;
+
+        id
+    }
+
+    /// Moves every timer whose deadline is not after `now` into `expired`, as `(key, id)` pairs
+    pub fn expire(&mut self, now: Instant, expired: &mut Vec<(usize, u64)>) {
+        let now_tick = (now.saturating_duration_since(self.origin).as_nanos()
+            / self.resolution.as_nanos().max(1)) as u64;
+
+        if now_tick < self.current_tick {
+            return;
+        }
+
+        // A single revolution visits every slot, no need to go around more than once
+        let slots = self.slots.len() as u64;
+        let last_tick = std::cmp::min(now_tick, self.current_tick + slots - 1);
+
+        if self.len > 0 {
+            for tick in self.current_tick..=last_tick {
+                let slot = &mut self.slots[(tick % slots) as usize];
+
+                let mut idx = 0;
+                while idx < slot.len() {
+                    if slot[idx].tick <= now_tick {
+                        let timer = slot.swap_remove(idx);
+                        expired.push((timer.key, timer.id));
+                        self.len -= 1;
+                    } else {
+                        idx += 1;
+                    }
+                }
+            }
+        }
+
+        self.current_tick = now_tick + 1;
+    }
+
+    /// How long until the next tick should be processed, or `None` when no timer is pending
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let since_origin = (self.resolution.as_nanos() as u64).saturating_mul(self.current_tick);
+        let next_tick = self.origin + Duration::from_nanos(since_origin);
+        Some(next_tick.saturating_duration_since(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(10);
+
+    fn expire(wheel: &mut TimerWheel, now: Instant) -> Vec<(usize, u64)> {
+        let mut expired = Vec::new();
+        wheel.expire(now, &mut expired);
+        expired.sort();
+        expired
+    }
+
+    #[test]
+    fn expires_timers_once_their_deadline_passed() {
+        let mut wheel = TimerWheel::new(8, TICK);
+        let origin = wheel.origin;
+
+        let first = wheel.schedule(1, origin + TICK * 2);
+        let second = wheel.schedule(2, origin + TICK * 5);
+        assert_ne!(first, second);
+
+        assert_eq!(expire(&mut wheel, origin + TICK), []);
+        assert_eq!(expire(&mut wheel, origin + TICK * 2), [(1, first)]);
+        assert_eq!(expire(&mut wheel, origin + TICK * 4), []);
+        // Every elapsed tick is visited, however late `expire` is called
+        assert_eq!(expire(&mut wheel, origin + TICK * 7), [(2, second)]);
+        assert_eq!(wheel.next_timeout(origin + TICK * 7), None);
+    }
+
+    #[test]
+    fn rounds_deadlines_up_to_the_next_tick() {
+        let mut wheel = TimerWheel::new(8, TICK);
+        let origin = wheel.origin;
+
+        let id = wheel.schedule(1, origin + TICK + Duration::from_millis(1));
+        assert_eq!(
+            expire(&mut wheel, origin + TICK + Duration::from_millis(5)),
+            []
+        );
+        assert_eq!(expire(&mut wheel, origin + TICK * 2), [(1, id)]);
+    }
+
+    #[test]
+    fn keeps_timers_further_than_a_revolution() {
+        let mut wheel = TimerWheel::new(4, TICK);
+        let origin = wheel.origin;
+
+        // Shares its slot with the ticks of earlier revolutions
+        let id = wheel.schedule(1, origin + TICK * 9);
+        for tick in 0..9 {
+            assert_eq!(expire(&mut wheel, origin + TICK * tick), []);
+        }
+        assert_eq!(expire(&mut wheel, origin + TICK * 9), [(1, id)]);
+    }
+
+    #[test]
+    fn expires_past_deadlines_on_the_next_tick() {
+        let mut wheel = TimerWheel::new(8, TICK);
+        let origin = wheel.origin;
+        assert_eq!(expire(&mut wheel, origin + TICK * 3), []);
+
+        // The tick it falls in was already processed
+        let id = wheel.schedule(1, origin);
+        assert_eq!(expire(&mut wheel, origin + TICK * 3), []);
+        assert_eq!(wheel.next_timeout(origin + TICK * 4), Some(Duration::ZERO));
+        assert_eq!(expire(&mut wheel, origin + TICK * 4), [(1, id)]);
+    }
+
+    #[test]
+    fn leaves_superseded_timers_to_their_owner() {
+        let mut wheel = TimerWheel::new(8, TICK);
+        let origin = wheel.origin;
+
+        // Rescheduling a key doesn't cancel its previous timer, the owner tells them apart by id
+        let stale = wheel.schedule(1, origin + TICK);
+        let current = wheel.schedule(1, origin + TICK * 3);
+        assert_eq!(expire(&mut wheel, origin + TICK), [(1, stale)]);
+        assert_eq!(expire(&mut wheel, origin + TICK * 3), [(1, current)]);
+    }
+
+    #[test]
+    fn waits_until_the_next_tick_while_timers_are_pending() {
+        let mut wheel = TimerWheel::new(8, TICK);
+        let origin = wheel.origin;
+        assert_eq!(wheel.next_timeout(origin), None);
+
+        wheel.schedule(1, origin + TICK * 5);
+        assert_eq!(wheel.next_timeout(origin), Some(Duration::ZERO));
+
+        expire(&mut wheel, origin + TICK * 2);
+        assert_eq!(wheel.next_timeout(origin + TICK * 2), Some(TICK));
+        assert_eq!(wheel.next_timeout(origin + TICK * 4), Some(Duration::ZERO));
+    }
+}
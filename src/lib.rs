@@ -1,7 +1,24 @@
+// Listeners, sockets and response bodies rely on Unix domain sockets, SO_REUSEPORT and file
+// descriptors throughout
+#[cfg(not(unix))]
+compile_error!("ducta only supports Unix platforms");
+
 pub mod handler;
 pub mod http;
+pub mod middleware;
+pub mod proxy;
+pub mod router;
+pub mod rt;
 pub mod server;
+pub mod sse;
+pub mod static_files;
+pub mod websocket;
 mod io;
+mod http2;
 mod net;
 
-pub use self::server::Server;
+pub use self::router::Router;
+pub use self::server::{Server, ServerBuilder, ShutdownHandle};
+pub use self::static_files::StaticFiles;
+#[cfg(feature = "tls")]
+pub use self::server::TlsConfig;
@@ -0,0 +1,94 @@
+mod bearer_auth;
+mod logger;
+mod set_header;
+
+pub use self::{bearer_auth::BearerAuth, logger::Logger, set_header::SetHeader};
+
+use crate::handler::Handler;
+use crate::http::{Request, Response};
+use std::sync::Arc;
+
+/// Behavior wrapped around a handler, such as logging, authentication or header injection.
+///
+/// A middleware gets the request before the handler does, and decides whether to pass it on by
+/// calling `next.run`, possibly after inspecting it, or to answer it on its own. The response
+/// returned by `next.run` can then be post-processed before being returned, through
+/// `Response::map` for deferred and asynchronous responses to be post-processed once ready.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, req: Request, next: Next) -> Response;
+}
+
+// Automatically implement Middleware for any function that matches the signature
+impl<F> Middleware for F
+where
+    F: Fn(Request, Next) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, req: Request, next: Next) -> Response {
+        (self)(req, next)
+    }
+}
+
+/// The rest of a middleware stack, down to the handler
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Middleware>],
+    handler: &'a dyn Fn(Request) -> Response,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        layers: &'a [Arc<dyn Middleware>],
+        handler: &'a dyn Fn(Request) -> Response,
+    ) -> Self {
+        Next { layers, handler }
+    }
+
+    /// Passes the request to the next layer, or to the handler once every layer ran
+    pub fn run(self, req: Request) -> Response {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.handle(
+                req,
+                Next {
+                    layers,
+                    handler: self.handler,
+                },
+            ),
+            None => (self.handler)(req),
+        }
+    }
+}
+
+/// A handler wrapped in a stack of middleware.
+///
+/// Layers run in the order they were added, the first one seeing the request first and the
+/// response last. Used to wrap the handler given to the server, `Router::layer` does the same
+/// for a router.
+pub struct Layered<H> {
+    handler: H,
+    layers: Vec<Arc<dyn Middleware>>,
+}
+
+impl<H: Handler> Layered<H> {
+    pub fn new(handler: H) -> Self {
+        Layered {
+            handler,
+            layers: Vec::new(),
+        }
+    }
+
+    /// Adds `middleware` below the layers already in the stack
+    pub fn layer(mut self, middleware: impl Middleware) -> Self {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+
+    /// Wraps `handler` in an existing stack of layers
+    pub(crate) fn with_layers(handler: H, layers: Vec<Arc<dyn Middleware>>) -> Self {
+        Layered { handler, layers }
+    }
+}
+
+impl<H: Handler> Handler for Layered<H> {
+    fn handle(&self, req: Request) -> Response {
+        Next::new(&self.layers, &|req| self.handler.handle(req)).run(req)
+    }
+}
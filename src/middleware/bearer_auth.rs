@@ -0,0 +1,65 @@
+use super::{Middleware, Next};
+use crate::http::{reason_phrase, Request, Response};
+
+/// Rejects requests that don't carry `Authorization: Bearer <token>` with one of the accepted
+/// tokens, answering them with `401 Unauthorized` without reaching the handler.
+pub struct BearerAuth {
+    tokens: Vec<String>,
+}
+
+impl BearerAuth {
+    pub fn new(token: impl Into<String>) -> Self {
+        BearerAuth {
+            tokens: vec![token.into()],
+        }
+    }
+
+    /// Accepts `token` as well
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.tokens.push(token.into());
+        self
+    }
+
+    fn is_authorized(&self, req: &Request) -> bool {
+        let Some(value) = req.get_header("Authorization") else {
+            return false;
+        };
+
+        // The scheme is case-insensitive, the token isn't
+        let Some((scheme, token)) = value.trim_ascii().split_at_checked(6) else {
+            return false;
+        };
+        if !scheme.eq_ignore_ascii_case(b"Bearer") || !token.starts_with(b" ") {
+            return false;
+        }
+
+        // Every token is checked, so that timing doesn't tell which one was close
+        let token = token.trim_ascii();
+        self.tokens.iter().fold(false, |found, t| {
+            found | constant_time_eq(t.as_bytes(), token)
+        })
+    }
+}
+
+/// Compares `a` and `b` in a time that only depends on their lengths, so that timing doesn't tell
+/// how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+impl Middleware for BearerAuth {
+    fn handle(&self, req: Request, next: Next) -> Response {
+        if !self.is_authorized(&req) {
+            return Response::new(401)
+                .with_header("WWW-Authenticate", "Bearer")
+                .with_body(reason_phrase(401));
+        }
+
+        next.run(req)
+    }
+}
@@ -0,0 +1,38 @@
+use super::{Middleware, Next};
+use crate::http::{Request, Response};
+use std::time::Instant;
+
+/// Logs the method, target, status and handling time of every request to stderr.
+///
+/// The time covers the layers below the logger and the handler, not the sending of the response.
+/// Deferred and asynchronous responses are logged once ready, requests cancelled before aren't.
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle(&self, req: Request, next: Next) -> Response {
+        let (method, path) = (req.method, req.path);
+        let start = Instant::now();
+
+        let resp = next.run(req);
+        if !resp.is_deferred() {
+            log(method, path, &resp, start);
+            return resp;
+        }
+
+        let (method, path) = (method.to_owned(), path.to_owned());
+        resp.map(move |resp| {
+            log(&method, &path, &resp, start);
+            resp
+        })
+    }
+}
+
+fn log(method: &str, path: &str, resp: &Response, start: Instant) {
+    eprintln!(
+        "{} {} -> {} ({:?})",
+        method,
+        path,
+        resp.status,
+        start.elapsed()
+    );
+}
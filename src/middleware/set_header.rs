@@ -0,0 +1,30 @@
+use super::{Middleware, Next};
+use crate::http::{Request, Response};
+
+/// Adds a header to every response that doesn't already set it
+#[derive(Clone)]
+pub struct SetHeader {
+    name: String,
+    value: String,
+}
+
+impl SetHeader {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        SetHeader {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl Middleware for SetHeader {
+    fn handle(&self, req: Request, next: Next) -> Response {
+        let header = self.clone();
+        next.run(req).map(move |resp| {
+            if resp.get_header(&header.name).is_some() {
+                return resp;
+            }
+            resp.with_header(header.name, header.value)
+        })
+    }
+}
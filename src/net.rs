@@ -1,3 +1,14 @@
+pub mod backend;
 pub mod connection;
+pub mod listener;
+pub mod notify;
+pub mod socket;
+pub mod stream;
 
-pub use self::connection::{ConnectionState, Connection};
+pub use self::connection::{
+    ArmedTimeout, ConnectionLimits, ConnectionState, Connection, TimeoutKind, Timeouts,
+};
+pub use self::listener::{Listener, MioListener};
+pub use self::notify::{Notifications, Notifier};
+pub use self::socket::Socket;
+pub use self::stream::Stream;
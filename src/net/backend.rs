@@ -0,0 +1,181 @@
+mod mio;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+
+use self::mio::MioWorker;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use self::uring::UringWorker;
+
+use crate::handler::{Handler, HandlerPool};
+use crate::io::TimerWheel;
+use crate::net::{ArmedTimeout, Connection, ConnectionState, Listener, TimeoutKind, Timeouts};
+use crate::server::ServerConfig;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub(crate) const TIMER_WHEEL_SLOTS: usize = 512;
+pub(crate) const TIMER_RESOLUTION: Duration = Duration::from_millis(100);
+
+/// The event loop a worker runs on, picked when it's created.
+///
+/// With the `io-uring` feature enabled on Linux, workers run on io_uring unless the server
+/// terminates TLS, which only the mio backend supports, or the kernel doesn't provide what the
+/// io_uring backend needs. They fall back to mio readiness events in both cases.
+pub(crate) enum Worker<H: Handler> {
+    Mio(Box<MioWorker<H>>),
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    Uring(Box<UringWorker<H>>),
+}
+
+impl<H: Handler> Backend<H> for Worker<H> {
+    fn new(
+        listener: Listener,
+        handler: Arc<H>,
+        handler_pool: Option<Arc<HandlerPool>>,
+        config: &ServerConfig,
+    ) -> std::io::Result<Self> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if uring::supports(config) {
+            let worker =
+                UringWorker::new(listener.try_clone()?, handler.clone(), handler_pool.clone(), config);
+            match worker {
+                Ok(worker) => return Ok(Worker::Uring(Box::new(worker))),
+                Err(e) if uring::is_unsupported(&e) => {
+                    eprintln!("io_uring unavailable ({}), falling back to mio", e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Worker::Mio(Box::new(MioWorker::new(
+            listener,
+            handler,
+            handler_pool,
+            config,
+        )?)))
+    }
+
+    fn waker(&self) -> Arc<dyn Wake> {
+        match self {
+            Worker::Mio(worker) => worker.waker(),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Worker::Uring(worker) => worker.waker(),
+        }
+    }
+
+    fn run(&mut self, should_stop: &AtomicBool) -> std::io::Result<()> {
+        match self {
+            Worker::Mio(worker) => worker.run(should_stop),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Worker::Uring(worker) => worker.run(should_stop),
+        }
+    }
+}
+
+/// Interrupts a worker blocked waiting for I/O, from any thread
+pub(crate) trait Wake: Send + Sync {
+    fn wake(&self) -> std::io::Result<()>;
+}
+
+/// An event loop serving the connections of one listener.
+///
+/// Backends only differ in how they wait for and perform I/O, the HTTP handling itself is left to
+/// `Connection`, so they all serve the same handlers the same way.
+pub(crate) trait Backend<H: Handler>: Sized + Send + 'static {
+    /// Creates a worker serving `listener`, running handlers on `handler_pool` when there's one
+    fn new(
+        listener: Listener,
+        handler: Arc<H>,
+        handler_pool: Option<Arc<HandlerPool>>,
+        config: &ServerConfig,
+    ) -> std::io::Result<Self>;
+
+    /// Waker used to interrupt this worker from other threads
+    fn waker(&self) -> Arc<dyn Wake>;
+
+    /// Runs the event loop until `should_stop` is set and the worker is woken up, then stops
+    /// accepting connections and returns once the open ones are done or the drain timeout
+    /// expired
+    fn run(&mut self, should_stop: &AtomicBool) -> std::io::Result<()>;
+}
+
+/// Keeps the connection deadline in sync with the phase it's in, scheduling a new timer when the
+/// phase changed and recording activity otherwise.
+pub(crate) fn update_timeout(
+    timer_wheel: &mut TimerWheel,
+    timeouts: &Timeouts,
+    key: usize,
+    conn: &mut Connection,
+    now: Instant,
+) {
+    let kind = conn.timeout_kind();
+
+    match (conn.timeout.as_mut(), kind) {
+        (Some(armed), Some(kind)) if armed.kind == kind => armed.last_activity = now,
+        (_, None) => conn.timeout = None,
+        (_, Some(kind)) => {
+            // Any previous timer is left in the wheel, and ignored once it expires
+            let id = timer_wheel.schedule(key, now + timeouts.get(kind));
+            conn.timeout = Some(ArmedTimeout {
+                kind,
+                id,
+                started: now,
+                last_activity: now,
+            });
+        }
+    }
+}
+
+/// How long a worker may wait for I/O: until the next timer tick if any deadline is pending, and
+/// no later than the end of the drain if shutdown started and it's not over yet
+pub(crate) fn next_wakeup(
+    timer_wheel: &TimerWheel,
+    drain_deadline: Option<Instant>,
+    now: Instant,
+) -> Option<Duration> {
+    let timer = timer_wheel.next_timeout(now);
+
+    match drain_deadline.filter(|deadline| *deadline > now) {
+        Some(deadline) => {
+            let remaining = deadline - now;
+            Some(timer.map_or(remaining, |timer| timer.min(remaining)))
+        }
+        None => timer,
+    }
+}
+
+/// Checks the timer `id` that expired for the connection at `key`, returning the phase that
+/// timed out if the connection must be told about it.
+///
+/// Stale timers, left behind by a phase change, are ignored, while timers of phases extended by
+/// activity are pushed back when there was some since they were scheduled.
+pub(crate) fn expired_timeout(
+    timer_wheel: &mut TimerWheel,
+    timeouts: &Timeouts,
+    key: usize,
+    id: u64,
+    conn: &mut Connection,
+    now: Instant,
+) -> Option<TimeoutKind> {
+    let armed = conn.timeout.filter(|armed| armed.id == id)?;
+    if *conn.state() == ConnectionState::Closed {
+        return None;
+    }
+
+    let since = if armed.kind.extended_by_activity() {
+        armed.last_activity
+    } else {
+        armed.started
+    };
+    let deadline = since + timeouts.get(armed.kind);
+    if deadline > now {
+        // There was activity since the timer was scheduled, push it back
+        let id = timer_wheel.schedule(key, deadline);
+        conn.timeout = Some(ArmedTimeout { id, ..armed });
+        return None;
+    }
+
+    conn.timeout = None;
+    Some(armed.kind)
+}
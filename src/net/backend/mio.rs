@@ -0,0 +1,398 @@
+use super::{
+    expired_timeout, next_wakeup, update_timeout, Backend, Wake, TIMER_RESOLUTION,
+    TIMER_WHEEL_SLOTS,
+};
+use crate::handler::{Handler, HandlerPool};
+use crate::http::{ConnectionInfo, TrustedProxies};
+use crate::io::{BufferPool, TimerWheel};
+use crate::net::{
+    Connection, ConnectionLimits, ConnectionState, Listener, MioListener, Notifications, Socket,
+    Stream, Timeouts,
+};
+use crate::proxy::ProxyProtocol;
+use crate::rt;
+use crate::server::ServerConfig;
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token, Waker};
+use slab::Slab;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const LISTENER_TOKEN: Token = Token(0);
+const WAKER_TOKEN: Token = Token(1);
+const RT_IO_TOKEN: Token = Token(2);
+const SLAB_OFFSET: usize = 3;
+
+impl Wake for Waker {
+    fn wake(&self) -> std::io::Result<()> {
+        Waker::wake(self)
+    }
+}
+
+/// A single readiness based event loop, owning its own listener, connections and buffers.
+///
+/// Workers share nothing but the handler, so the server scales by running one of them per thread.
+pub struct MioWorker<H: Handler> {
+    poll: Poll,
+    events: Events,
+    listener: MioListener,
+    connections: Slab<Connection>,
+    buffer_pool: BufferPool,
+    handler: Arc<H>,
+    /// Runs the handler away from the event loop, if enabled
+    handler_pool: Option<Arc<HandlerPool>>,
+    /// Proxies whose forwarding headers are believed by `Request::client_addr`
+    trusted_proxies: Option<Arc<TrustedProxies>>,
+    /// Whether the forwarding headers of Unix domain socket peers are believed
+    trust_unix_socket_peers: bool,
+    /// Whether connections start with a PROXY protocol header
+    proxy_protocol: ProxyProtocol,
+    waker: Arc<Waker>,
+    /// Connections woken up from other threads, through the waker
+    notifications: Arc<Notifications>,
+    max_connections: usize,
+    limits: ConnectionLimits,
+    timeouts: Timeouts,
+    timer_wheel: TimerWheel,
+    drain_timeout: Duration,
+    /// Set once shutdown started, connections left open past it are closed
+    drain_deadline: Option<Instant>,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl<H: Handler> Backend<H> for MioWorker<H> {
+    fn new(
+        listener: Listener,
+        handler: Arc<H>,
+        handler_pool: Option<Arc<HandlerPool>>,
+        config: &ServerConfig,
+    ) -> std::io::Result<Self> {
+        let mut listener = MioListener::from(listener);
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER_TOKEN)?);
+
+        // Register the listener to know when new clients connect
+        poll.registry()
+            .register(&mut listener, LISTENER_TOKEN, Interest::READABLE)?;
+
+        Ok(Self {
+            poll,
+            events: Events::with_capacity(config.events_capacity),
+            listener,
+            connections: Slab::with_capacity(config.connections_capacity),
+            buffer_pool: BufferPool::new(
+                config.pooled_buffers,
+                config.buffer_size,
+                config.max_pooled_buffer_size,
+            ),
+            handler,
+            handler_pool,
+            trusted_proxies: config.trusted_proxies.clone(),
+            trust_unix_socket_peers: config.trust_unix_socket_peers,
+            proxy_protocol: config.proxy_protocol,
+            notifications: Notifications::new(waker.clone()),
+            waker,
+            max_connections: config.max_connections,
+            limits: config.limits,
+            timeouts: config.timeouts,
+            timer_wheel: TimerWheel::new(TIMER_WHEEL_SLOTS, TIMER_RESOLUTION),
+            drain_timeout: config.drain_timeout,
+            drain_deadline: None,
+            #[cfg(feature = "tls")]
+            tls: config.tls.clone(),
+        })
+    }
+
+    fn waker(&self) -> Arc<dyn Wake> {
+        self.waker.clone()
+    }
+
+    fn run(&mut self, should_stop: &AtomicBool) -> std::io::Result<()> {
+        let mut to_remove: Vec<Token> = Vec::new();
+        let mut expired: Vec<(usize, u64)> = Vec::new();
+        let mut notified: Vec<usize> = Vec::new();
+        let runtime = rt::enter(self.handler_pool.clone())?;
+        self.poll.registry().register(
+            &mut SourceFd(&runtime.io_fd()),
+            RT_IO_TOKEN,
+            Interest::READABLE,
+        )?;
+
+        loop {
+            to_remove.clear();
+            let mut stop_requested = false;
+            let mut woken = false;
+
+            // Wake up in time for the next timer tick or the end of the drain
+            rt::schedule_sleeps(&mut self.timer_wheel);
+            let poll_timeout = next_wakeup(&self.timer_wheel, self.drain_deadline, Instant::now());
+            match self.poll.poll(&mut self.events, poll_timeout) {
+                Ok(()) => {}
+                // A signal landed on this thread, the signal handler will wake us if needed
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+
+            let now = Instant::now();
+
+            for event in self.events.iter() {
+                match event.token() {
+                    LISTENER_TOKEN => {
+                        // Accept as many connections as possible
+                        loop {
+                            match self.listener.accept() {
+                                Ok((stream, peer_addr)) => {
+                                    if self.connections.len() >= self.max_connections {
+                                        // Shed the connection right away, leaving it pending
+                                        // would keep it from being noticed again
+                                        drop(stream);
+                                        continue;
+                                    }
+
+                                    let local_addr = match stream.local_addr() {
+                                        Ok(addr) => addr,
+                                        Err(e) => {
+                                            eprintln!("Failed to open connection: {}", e);
+                                            continue;
+                                        }
+                                    };
+
+                                    let unix_socket = stream.is_unix();
+                                    let mut stream = match self.open_stream(stream) {
+                                        Ok(stream) => stream,
+                                        Err(e) => {
+                                            eprintln!("Failed to open connection: {}", e);
+                                            continue;
+                                        }
+                                    };
+
+                                    let entry = self.connections.vacant_entry();
+                                    let token = Token(entry.key() + SLAB_OFFSET);
+
+                                    if let Err(e) = self.poll.registry().register(
+                                        stream.socket(),
+                                        token,
+                                        Interest::READABLE,
+                                    ) {
+                                        eprintln!("Failed to register connection: {}", e);
+                                        continue;
+                                    }
+
+                                    let key = entry.key();
+                                    let conn = entry.insert(Connection::new(
+                                        stream,
+                                        self.buffer_pool.checkout(),
+                                        self.buffer_pool.checkout(),
+                                        self.limits,
+                                        self.timeouts,
+                                        self.notifications.notifier(key),
+                                        self.handler_pool.clone(),
+                                        self.proxy_protocol,
+                                        ConnectionInfo::new(
+                                            peer_addr,
+                                            local_addr,
+                                            unix_socket,
+                                            self.trusted_proxies.clone(),
+                                            self.trust_unix_socket_peers,
+                                        ),
+                                    ));
+                                    update_timeout(
+                                        &mut self.timer_wheel,
+                                        &self.timeouts,
+                                        key,
+                                        conn,
+                                        now,
+                                    );
+                                }
+                                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                                    // No more connections to accept right now
+                                    break;
+                                }
+                                Err(e) => {
+                                    eprintln!("Accept error: {}", e);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    WAKER_TOKEN => {
+                        woken = true;
+                        stop_requested = should_stop.load(Ordering::SeqCst);
+                    }
+                    RT_IO_TOKEN => {
+                        // The futures woken are picked up along with the other notifications
+                        rt::dispatch_io()?;
+                        woken = true;
+                    }
+                    token => {
+                        let conn_idx = usize::from(token) - SLAB_OFFSET;
+                        if let Some(conn) = self.connections.get_mut(conn_idx) {
+                            if let Some(new_interest) = conn.process(event, &*self.handler) {
+                                self.poll.registry().reregister(
+                                    conn.socket(),
+                                    token,
+                                    new_interest,
+                                )?;
+                            }
+
+                            if *conn.state() == ConnectionState::Closed {
+                                to_remove.push(token);
+                            } else {
+                                update_timeout(
+                                    &mut self.timer_wheel,
+                                    &self.timeouts,
+                                    conn_idx,
+                                    conn,
+                                    now,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Fire expired deadlines
+            expired.clear();
+            self.timer_wheel.expire(now, &mut expired);
+            for &(conn_idx, id) in &expired {
+                if rt::expire_sleep(conn_idx, id) {
+                    continue;
+                }
+                let Some(conn) = self.connections.get_mut(conn_idx) else {
+                    continue;
+                };
+
+                let Some(kind) = expired_timeout(
+                    &mut self.timer_wheel,
+                    &self.timeouts,
+                    conn_idx,
+                    id,
+                    conn,
+                    now,
+                ) else {
+                    continue;
+                };
+
+                let token = Token(conn_idx + SLAB_OFFSET);
+                if let Some(new_interest) = conn.on_timeout(kind) {
+                    self.poll
+                        .registry()
+                        .reregister(conn.socket(), token, new_interest)?;
+                }
+
+                if *conn.state() == ConnectionState::Closed {
+                    to_remove.push(token);
+                } else {
+                    update_timeout(&mut self.timer_wheel, &self.timeouts, conn_idx, conn, now);
+                }
+            }
+
+            // Pick up work queued for connections from other threads
+            if woken {
+                notified.clear();
+                self.notifications.take(&mut notified);
+                for &conn_idx in &notified {
+                    let Some(conn) = self.connections.get_mut(conn_idx) else {
+                        continue;
+                    };
+                    // Already waiting to be removed
+                    if *conn.state() == ConnectionState::Closed {
+                        continue;
+                    }
+
+                    let token = Token(conn_idx + SLAB_OFFSET);
+                    if let Some(new_interest) = conn.on_notify() {
+                        self.poll
+                            .registry()
+                            .reregister(conn.socket(), token, new_interest)?;
+                    }
+
+                    if *conn.state() == ConnectionState::Closed {
+                        to_remove.push(token);
+                    } else {
+                        update_timeout(&mut self.timer_wheel, &self.timeouts, conn_idx, conn, now);
+                    }
+                }
+            }
+
+            // Clean up closed connections
+            for token in &to_remove {
+                self.remove_connection(usize::from(*token) - SLAB_OFFSET);
+            }
+
+            if stop_requested && self.drain_deadline.is_none() {
+                self.start_drain(now)?;
+            }
+
+            if let Some(deadline) = self.drain_deadline {
+                if !self.connections.is_empty() && now >= deadline {
+                    eprintln!(
+                        "Drain deadline reached, closing {} connections",
+                        self.connections.len()
+                    );
+                    let remaining: Vec<usize> = self.connections.iter().map(|(k, _)| k).collect();
+                    for conn_idx in remaining {
+                        self.remove_connection(conn_idx);
+                    }
+                }
+
+                if self.connections.is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl<H: Handler> MioWorker<H> {
+    /// Stops accepting new connections and asks the open ones to finish, the idle ones being
+    /// closed right away
+    fn start_drain(&mut self, now: Instant) -> std::io::Result<()> {
+        self.drain_deadline = Some(now + self.drain_timeout);
+        self.poll.registry().deregister(&mut self.listener)?;
+
+        let mut idle = Vec::new();
+        for (conn_idx, conn) in self.connections.iter_mut() {
+            if let Some(new_interest) = conn.shutdown() {
+                let token = Token(conn_idx + SLAB_OFFSET);
+                self.poll
+                    .registry()
+                    .reregister(conn.socket(), token, new_interest)?;
+            }
+
+            if *conn.state() == ConnectionState::Closed {
+                idle.push(conn_idx);
+            }
+        }
+
+        for conn_idx in idle {
+            self.remove_connection(conn_idx);
+        }
+
+        Ok(())
+    }
+
+    fn remove_connection(&mut self, conn_idx: usize) {
+        let mut conn = self.connections.remove(conn_idx);
+
+        if let Err(e) = self.poll.registry().deregister(conn.socket()) {
+            eprintln!("Failed to deregister connection: {}", e);
+        }
+
+        // Return buffer to the buffer pool
+        let (buf1, buf2) = conn.get_buffers();
+        self.buffer_pool.return_buffer(buf1);
+        self.buffer_pool.return_buffer(buf2);
+    }
+
+    /// Wraps an accepted socket in the transport configured for the server
+    fn open_stream(&self, socket: Socket) -> std::io::Result<Stream> {
+        #[cfg(feature = "tls")]
+        return Stream::new(socket, self.tls.as_ref());
+
+        #[cfg(not(feature = "tls"))]
+        Ok(Stream::Plain(socket))
+    }
+}
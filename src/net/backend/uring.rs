@@ -0,0 +1,949 @@
+use super::{
+    expired_timeout, next_wakeup, update_timeout, Backend, Wake, TIMER_RESOLUTION,
+    TIMER_WHEEL_SLOTS,
+};
+use crate::handler::{Handler, HandlerPool};
+use crate::http::{ConnectionInfo, TrustedProxies};
+use crate::io::{BufferPool, TimerWheel};
+use crate::net::{
+    Connection, ConnectionLimits, ConnectionState, Listener, Notifications, Stream, Timeouts,
+};
+use crate::proxy::ProxyProtocol;
+use crate::rt;
+use crate::server::ServerConfig;
+use bytes::BytesMut;
+use io_uring::types::{BufRingEntry, Fd, SubmitArgs, Timespec};
+use io_uring::{cqueue, opcode, squeue, IoUring};
+use slab::Slab;
+use std::alloc::{alloc_zeroed, dealloc, Layout};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Number of submission queue entries
+const RING_ENTRIES: u32 = 1024;
+/// Number of buffers in the provided buffer ring, a power of two
+const BUF_RING_ENTRIES: u16 = 512;
+const BUF_GROUP: u16 = 0;
+
+// Operation kinds, stored in the low bits of the user_data of each submission, the slab key of
+// the connection being stored in the remaining ones
+const OP_ACCEPT: u64 = 0;
+const OP_WAKE: u64 = 1;
+const OP_RECV: u64 = 2;
+const OP_SEND: u64 = 3;
+const OP_CANCEL: u64 = 4;
+const OP_RT_IO: u64 = 5;
+const OP_BITS: u64 = 3;
+
+fn user_data(key: usize, op: u64) -> u64 {
+    ((key as u64) << OP_BITS) | op
+}
+
+/// Whether a server with this configuration can run on io_uring, TLS sessions being only
+/// handled by the mio backend
+pub(crate) fn supports(config: &ServerConfig) -> bool {
+    #[cfg(feature = "tls")]
+    if config.tls.is_some() {
+        return false;
+    }
+
+    let _ = config;
+    true
+}
+
+/// Whether setting a worker up failed because the kernel lacks io_uring or one of the features
+/// used, such as provided buffer rings (Linux 5.19), or because io_uring is disabled
+pub(crate) fn is_unsupported(e: &std::io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::ENOSYS | libc::EPERM | libc::EINVAL | libc::EOPNOTSUPP)
+    )
+}
+
+/// Waker backed by an eventfd, which the worker keeps a read submitted on
+struct EventFd(OwnedFd);
+
+impl EventFd {
+    fn new() -> std::io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(EventFd(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+}
+
+impl Wake for EventFd {
+    fn wake(&self) -> std::io::Result<()> {
+        let value: u64 = 1;
+        let written = unsafe {
+            libc::write(
+                self.0.as_raw_fd(),
+                &value as *const u64 as *const libc::c_void,
+                std::mem::size_of::<u64>(),
+            )
+        };
+
+        match written {
+            n if n >= 0 => Ok(()),
+            _ => match std::io::Error::last_os_error() {
+                // The counter is saturated, the worker has a wake up pending anyway
+                e if e.kind() == std::io::ErrorKind::WouldBlock => Ok(()),
+                e => Err(e),
+            },
+        }
+    }
+}
+
+/// Ring of buffers provided to the kernel, which picks one of them for each receive instead of
+/// the receive naming its destination up front. A connection waiting for input thus holds no
+/// buffer of its own, buffers are only in use between a completion and its processing.
+///
+/// The buffers come from the worker's `BufferPool`, and are handed back to the ring once the
+/// received data was copied to the connection.
+struct BufRing {
+    entries: *mut BufRingEntry,
+    layout: Layout,
+    /// Local copy of the ring tail, published to the kernel after each update
+    tail: u16,
+    buffers: Vec<BytesMut>,
+}
+
+// The ring memory is only ever touched by the worker owning it, and by the kernel
+unsafe impl Send for BufRing {}
+
+impl BufRing {
+    fn new(ring: &IoUring, buffer_pool: &mut BufferPool) -> std::io::Result<Self> {
+        // The kernel requires the ring to be page aligned
+        let layout = Layout::from_size_align(
+            BUF_RING_ENTRIES as usize * std::mem::size_of::<BufRingEntry>(),
+            4096,
+        )
+        .map_err(std::io::Error::other)?;
+
+        let entries = unsafe { alloc_zeroed(layout) } as *mut BufRingEntry;
+        if entries.is_null() {
+            return Err(std::io::ErrorKind::OutOfMemory.into());
+        }
+
+        let mut buf_ring = BufRing {
+            entries,
+            layout,
+            tail: 0,
+            buffers: (0..BUF_RING_ENTRIES).map(|_| buffer_pool.checkout()).collect(),
+        };
+
+        unsafe {
+            ring.submitter()
+                .register_buf_ring_with_flags(entries as u64, BUF_RING_ENTRIES, BUF_GROUP, 0)?;
+        }
+
+        for bid in 0..BUF_RING_ENTRIES {
+            buf_ring.recycle(bid);
+        }
+
+        Ok(buf_ring)
+    }
+
+    /// The first `len` bytes the kernel received into buffer `bid`
+    fn get(&self, bid: u16, len: usize) -> &[u8] {
+        let buffer = &self.buffers[bid as usize];
+        unsafe { std::slice::from_raw_parts(buffer.as_ptr(), len.min(buffer.capacity())) }
+    }
+
+    /// Hands buffer `bid` back to the kernel
+    fn recycle(&mut self, bid: u16) {
+        let buffer = &mut self.buffers[bid as usize];
+        let idx = self.tail & (BUF_RING_ENTRIES - 1);
+
+        unsafe {
+            let entry = &mut *self.entries.add(idx as usize);
+            entry.set_addr(buffer.as_mut_ptr() as u64);
+            entry.set_len(buffer.capacity() as u32);
+            entry.set_bid(bid);
+
+            // Publish the entry, the tail overlaps the reserved field of the first entry
+            self.tail = self.tail.wrapping_add(1);
+            let tail = BufRingEntry::tail(self.entries) as *const AtomicU16;
+            (*tail).store(self.tail, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for BufRing {
+    fn drop(&mut self) {
+        // The ring is registered with the IoUring, which is dropped first
+        unsafe { dealloc(self.entries as *mut u8, self.layout) };
+    }
+}
+
+/// A connection along with the operations it has in flight. Its buffers and socket must stay
+/// alive until the kernel is done with them, so a connection is only removed once it has none.
+struct Slot {
+    conn: Connection,
+    fd: RawFd,
+    recv_pending: bool,
+    send_pending: bool,
+    closing: bool,
+}
+
+/// A single completion based event loop on io_uring, owning its own listener, connections and
+/// buffers.
+///
+/// New connections come from a multishot accept, input is received into buffers picked by the
+/// kernel from a provided buffer ring, and output is sent straight from the connection's
+/// buffers, see `Connection::pending_output`. When a response is expected to be the last output
+/// before reading again, its send is linked to the next receive so both are submitted at once.
+pub struct UringWorker<H: Handler> {
+    // Dropped first, so the kernel releases the buffer ring before its memory is freed
+    ring: IoUring,
+    buf_ring: BufRing,
+    listener: Listener,
+    connections: Slab<Slot>,
+    buffer_pool: BufferPool,
+    handler: Arc<H>,
+    /// Runs the handler away from the event loop, if enabled
+    handler_pool: Option<Arc<HandlerPool>>,
+    /// Proxies whose forwarding headers are believed by `Request::client_addr`
+    trusted_proxies: Option<Arc<TrustedProxies>>,
+    /// Whether the forwarding headers of Unix domain socket peers are believed
+    trust_unix_socket_peers: bool,
+    /// Whether connections start with a PROXY protocol header
+    proxy_protocol: ProxyProtocol,
+    waker: Arc<EventFd>,
+    /// Connections woken up from other threads, through the waker
+    notifications: Arc<Notifications>,
+    /// Destination of the read submitted on the waker's eventfd
+    wake_buf: Box<[u8; 8]>,
+    max_connections: usize,
+    limits: ConnectionLimits,
+    timeouts: Timeouts,
+    timer_wheel: TimerWheel,
+    drain_timeout: Duration,
+    /// Set once shutdown started, connections left open past it are closed
+    drain_deadline: Option<Instant>,
+    completions: Vec<(u64, i32, u32)>,
+}
+
+impl<H: Handler> Backend<H> for UringWorker<H> {
+    fn new(
+        listener: Listener,
+        handler: Arc<H>,
+        handler_pool: Option<Arc<HandlerPool>>,
+        config: &ServerConfig,
+    ) -> std::io::Result<Self> {
+        let ring = IoUring::new(RING_ENTRIES)?;
+        let mut buffer_pool = BufferPool::new(
+            config.pooled_buffers,
+            config.buffer_size,
+            config.max_pooled_buffer_size,
+        );
+        let buf_ring = BufRing::new(&ring, &mut buffer_pool)?;
+        let waker = Arc::new(EventFd::new()?);
+
+        let mut worker = Self {
+            ring,
+            buf_ring,
+            listener,
+            connections: Slab::with_capacity(config.connections_capacity),
+            buffer_pool,
+            handler,
+            handler_pool,
+            trusted_proxies: config.trusted_proxies.clone(),
+            trust_unix_socket_peers: config.trust_unix_socket_peers,
+            proxy_protocol: config.proxy_protocol,
+            notifications: Notifications::new(waker.clone()),
+            waker,
+            wake_buf: Box::new([0; 8]),
+            max_connections: config.max_connections,
+            limits: config.limits,
+            timeouts: config.timeouts,
+            timer_wheel: TimerWheel::new(TIMER_WHEEL_SLOTS, TIMER_RESOLUTION),
+            drain_timeout: config.drain_timeout,
+            drain_deadline: None,
+            completions: Vec::with_capacity(config.events_capacity),
+        };
+
+        worker.submit_accept()?;
+        worker.submit_wake_read()?;
+        Ok(worker)
+    }
+
+    fn waker(&self) -> Arc<dyn Wake> {
+        self.waker.clone()
+    }
+
+    fn run(&mut self, should_stop: &AtomicBool) -> std::io::Result<()> {
+        let runtime = rt::enter(self.handler_pool.clone())?;
+        self.submit_rt_io_poll(runtime.io_fd())?;
+
+        loop {
+            // Wake up in time for the next timer tick or the end of the drain
+            rt::schedule_sleeps(&mut self.timer_wheel);
+            let timeout = next_wakeup(&self.timer_wheel, self.drain_deadline, Instant::now());
+            let waited = match timeout {
+                Some(timeout) => {
+                    let timespec = Timespec::from(timeout);
+                    let args = SubmitArgs::new().timespec(&timespec);
+                    self.ring.submitter().submit_with_args(1, &args)
+                }
+                None => self.ring.submit_and_wait(1),
+            };
+
+            match waited {
+                Ok(_) => {}
+                // The timeout elapsed, or a signal landed on this thread
+                Err(ref e) if e.raw_os_error() == Some(libc::ETIME) => {}
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+
+            let now = Instant::now();
+
+            self.completions.clear();
+            self.completions.extend(
+                self.ring
+                    .completion()
+                    .map(|cqe| (cqe.user_data(), cqe.result(), cqe.flags())),
+            );
+
+            for idx in 0..self.completions.len() {
+                let (user_data, result, flags) = self.completions[idx];
+                let key = (user_data >> OP_BITS) as usize;
+
+                match user_data & ((1 << OP_BITS) - 1) {
+                    OP_ACCEPT => self.on_accept(result, flags, now)?,
+                    OP_WAKE => {
+                        self.notify_connections(now)?;
+
+                        // The eventfd isn't read again once stopping, so this only happens once
+                        if should_stop.load(Ordering::SeqCst) {
+                            self.start_drain(now)?;
+                        } else {
+                            self.submit_wake_read()?;
+                        }
+                    }
+                    OP_RECV => self.on_recv(key, result, flags, now)?,
+                    OP_SEND => self.on_send(key, result, now)?,
+                    OP_RT_IO => {
+                        rt::dispatch_io()?;
+                        self.notify_connections(now)?;
+                        self.submit_rt_io_poll(runtime.io_fd())?;
+                    }
+                    _ => {}
+                }
+            }
+
+            // Fire expired deadlines
+            let mut expired = Vec::new();
+            self.timer_wheel.expire(now, &mut expired);
+            for (key, id) in expired {
+                if rt::expire_sleep(key, id) {
+                    continue;
+                }
+                let Some(slot) = self.connections.get_mut(key) else {
+                    continue;
+                };
+                let Some(kind) = expired_timeout(
+                    &mut self.timer_wheel,
+                    &self.timeouts,
+                    key,
+                    id,
+                    &mut slot.conn,
+                    now,
+                ) else {
+                    continue;
+                };
+
+                slot.conn.on_timeout(kind);
+                self.after_io(key, now)?;
+            }
+
+            if let Some(deadline) = self.drain_deadline {
+                let remaining: Vec<usize> = self
+                    .connections
+                    .iter()
+                    .filter(|(_, slot)| !slot.closing)
+                    .map(|(key, _)| key)
+                    .collect();
+                if !remaining.is_empty() && now >= deadline {
+                    eprintln!(
+                        "Drain deadline reached, closing {} connections",
+                        remaining.len()
+                    );
+                    for key in remaining {
+                        self.close(key);
+                    }
+                }
+
+                // Closed connections are only gone once their operations completed
+                if self.connections.is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl<H: Handler> UringWorker<H> {
+    fn on_accept(&mut self, result: i32, flags: u32, now: Instant) -> std::io::Result<()> {
+        // The multishot accept stops on errors, among others, and must then be submitted again
+        // unless it was cancelled by shutdown
+        if self.drain_deadline.is_some() {
+            if result >= 0 {
+                drop(unsafe { OwnedFd::from_raw_fd(result) });
+            }
+            return Ok(());
+        }
+        if !cqueue::more(flags) {
+            self.submit_accept()?;
+        }
+
+        if result < 0 {
+            eprintln!("Accept error: {}", std::io::Error::from_raw_os_error(-result));
+            return Ok(());
+        }
+
+        let socket = unsafe { self.listener.accepted(result) };
+        if self.connections.len() >= self.max_connections {
+            // Shed the connection right away
+            drop(socket);
+            return Ok(());
+        }
+
+        // Multishot accepts don't report the peer address, and a connection reset in the meantime
+        // has none left
+        let (Ok(peer_addr), Ok(local_addr)) = (socket.peer_addr(), socket.local_addr()) else {
+            return Ok(());
+        };
+
+        let unix_socket = socket.is_unix();
+        let entry = self.connections.vacant_entry();
+        let key = entry.key();
+        let conn = Connection::new(
+            Stream::Plain(socket),
+            self.buffer_pool.checkout(),
+            self.buffer_pool.checkout(),
+            self.limits,
+            self.timeouts,
+            self.notifications.notifier(key),
+            self.handler_pool.clone(),
+            self.proxy_protocol,
+            ConnectionInfo::new(
+                peer_addr,
+                local_addr,
+                unix_socket,
+                self.trusted_proxies.clone(),
+                self.trust_unix_socket_peers,
+            ),
+        );
+        entry.insert(Slot {
+            conn,
+            fd: result,
+            recv_pending: false,
+            send_pending: false,
+            closing: false,
+        });
+
+        self.after_io(key, now)
+    }
+
+    fn on_recv(&mut self, key: usize, result: i32, flags: u32, now: Instant) -> std::io::Result<()> {
+        let slot = &mut self.connections[key];
+        slot.recv_pending = false;
+
+        if let Some(bid) = cqueue::buffer_select(flags) {
+            if result > 0 {
+                let data = self.buf_ring.get(bid, result as usize);
+                slot.conn.receive(data, &*self.handler);
+            }
+            self.buf_ring.recycle(bid);
+        }
+
+        match result {
+            n if n > 0 => {}
+            // No buffer was available, or the send linked to this receive failed, in both cases
+            // the receive is simply submitted again if still needed
+            n if n == -libc::ENOBUFS || n == -libc::ECANCELED => {}
+            // Clean close, the requests received are still answered
+            0 => slot.conn.end_of_input(&*self.handler),
+            // Socket error
+            _ => slot.conn.close(),
+        }
+
+        self.after_io(key, now)
+    }
+
+    fn on_send(&mut self, key: usize, result: i32, now: Instant) -> std::io::Result<()> {
+        let slot = &mut self.connections[key];
+        slot.send_pending = false;
+
+        if result < 0 {
+            slot.conn.close();
+        } else {
+            slot.conn.consume_output(result as usize);
+        }
+
+        self.after_io(key, now)
+    }
+
+    /// Submits whatever the connection is waiting for next, or releases it once closed
+    fn after_io(&mut self, key: usize, now: Instant) -> std::io::Result<()> {
+        self.drive(key)?;
+
+        let slot = &mut self.connections[key];
+        if *slot.conn.state() == ConnectionState::Closed {
+            self.close(key);
+        } else {
+            update_timeout(&mut self.timer_wheel, &self.timeouts, key, &mut slot.conn, now);
+        }
+
+        Ok(())
+    }
+
+    fn drive(&mut self, key: usize) -> std::io::Result<()> {
+        let slot = &mut self.connections[key];
+
+        let has_output = matches!(
+            slot.conn.state(),
+            ConnectionState::Writing | ConnectionState::Upgraded | ConnectionState::Http2
+        );
+        if has_output && !slot.send_pending {
+            let (buf, len, complete) = match slot.conn.pending_output(&*self.handler) {
+                Ok(output) => (output.as_ptr(), output.len(), slot.conn.is_output_complete()),
+                Err(_) => return Ok(()),
+            };
+
+            if len > 0 {
+                let send = opcode::Send::new(Fd(slot.fd), buf, len as u32)
+                    .flags(libc::MSG_NOSIGNAL)
+                    .build()
+                    .user_data(user_data(key, OP_SEND));
+                slot.send_pending = true;
+
+                // The connection reads again once this output is sent, so queue the receive
+                // right behind it
+                if complete && !slot.recv_pending && slot.conn.wants_input() {
+                    slot.recv_pending = true;
+                    let recv = recv_entry(key, slot.fd);
+                    return self.push(&[send.flags(squeue::Flags::IO_LINK), recv]);
+                }
+
+                return self.push(&[send]);
+            }
+        }
+
+        let slot = &mut self.connections[key];
+        let waiting_input = matches!(
+            slot.conn.state(),
+            ConnectionState::Reading
+                | ConnectionState::Draining
+                | ConnectionState::Upgraded
+                | ConnectionState::Http2
+                | ConnectionState::WaitingForHandler
+        );
+        // Input piling up behind a pending handler or response is capped by the read limit,
+        // receiving resumes once the connection made room
+        if waiting_input && !slot.recv_pending && slot.conn.wants_input() {
+            slot.recv_pending = true;
+            let recv = recv_entry(key, slot.fd);
+            return self.push(&[recv]);
+        }
+
+        Ok(())
+    }
+
+    /// Lets the connections woken up from other threads pick up the work queued for them
+    fn notify_connections(&mut self, now: Instant) -> std::io::Result<()> {
+        let mut keys = Vec::new();
+        self.notifications.take(&mut keys);
+
+        for key in keys {
+            match self.connections.get_mut(key) {
+                Some(slot) if !slot.closing => {
+                    slot.conn.on_notify();
+                    self.after_io(key, now)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops accepting new connections and asks the open ones to finish, the idle ones being
+    /// closed right away
+    fn start_drain(&mut self, now: Instant) -> std::io::Result<()> {
+        self.drain_deadline = Some(now + self.drain_timeout);
+
+        let cancel = opcode::AsyncCancel::new(OP_ACCEPT)
+            .build()
+            .user_data(OP_CANCEL);
+        self.push(&[cancel])?;
+
+        let keys: Vec<usize> = self.connections.iter().map(|(key, _)| key).collect();
+        for key in keys {
+            self.connections[key].conn.shutdown();
+            self.after_io(key, now)?;
+        }
+
+        Ok(())
+    }
+
+    /// Closes the connection, removing it once the kernel is done with it
+    fn close(&mut self, key: usize) {
+        let slot = &mut self.connections[key];
+        slot.conn.close();
+
+        if !slot.closing {
+            // Makes any operation still in flight complete right away
+            slot.closing = true;
+            let _ = slot.conn.socket().shutdown(std::net::Shutdown::Both);
+        }
+
+        if slot.recv_pending || slot.send_pending {
+            return;
+        }
+
+        let slot = self.connections.remove(key);
+        let (buf1, buf2) = slot.conn.get_buffers();
+        self.buffer_pool.return_buffer(buf1);
+        self.buffer_pool.return_buffer(buf2);
+    }
+
+    fn submit_accept(&mut self) -> std::io::Result<()> {
+        let accept = opcode::AcceptMulti::new(Fd(self.listener.as_raw_fd()))
+            .flags(libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC)
+            .build()
+            .user_data(OP_ACCEPT);
+        self.push(&[accept])
+    }
+
+    fn submit_wake_read(&mut self) -> std::io::Result<()> {
+        let read = opcode::Read::new(
+            Fd(self.waker.0.as_raw_fd()),
+            self.wake_buf.as_mut_ptr(),
+            self.wake_buf.len() as u32,
+        )
+        .build()
+        .user_data(OP_WAKE);
+        self.push(&[read])
+    }
+
+    /// Waits for the sockets of the runtime to be ready, see `rt::dispatch_io`
+    fn submit_rt_io_poll(&mut self, fd: RawFd) -> std::io::Result<()> {
+        let poll = opcode::PollAdd::new(Fd(fd), libc::POLLIN as u32)
+            .build()
+            .user_data(OP_RT_IO);
+        self.push(&[poll])
+    }
+
+    /// Queues `entries` in a single batch, so linked entries are submitted together
+    fn push(&mut self, entries: &[squeue::Entry]) -> std::io::Result<()> {
+        let free = {
+            let submission = self.ring.submission();
+            submission.capacity() - submission.len()
+        };
+        if free < entries.len() {
+            self.ring.submit()?;
+        }
+
+        for entry in entries {
+            // Safety: every buffer referenced by an entry outlives its operation, see `Slot`
+            unsafe { self.ring.submission().push(entry) }
+                .map_err(|_| std::io::Error::other("Submission queue full"))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn recv_entry(key: usize, fd: RawFd) -> squeue::Entry {
+    opcode::Recv::new(Fd(fd), std::ptr::null_mut(), 0)
+        .buf_group(BUF_GROUP)
+        .build()
+        .flags(squeue::Flags::BUFFER_SELECT)
+        .user_data(user_data(key, OP_RECV))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Request, Response};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::thread::JoinHandle;
+
+    struct TestWorker {
+        addr: SocketAddr,
+        should_stop: Arc<AtomicBool>,
+        waker: Arc<dyn Wake>,
+        thread: JoinHandle<std::io::Result<()>>,
+    }
+
+    impl TestWorker {
+        /// Runs a worker serving `handler` on its own thread, or `None` if the kernel doesn't
+        /// support what it needs
+        fn start<H: Handler>(config: ServerConfig, handler: H) -> Option<TestWorker> {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.set_nonblocking(true).unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let mut worker =
+                match UringWorker::new(Listener::Tcp(listener), Arc::new(handler), None, &config) {
+                    Ok(worker) => worker,
+                    Err(e) if is_unsupported(&e) => {
+                        eprintln!("io_uring unavailable ({}), skipping", e);
+                        return None;
+                    }
+                    Err(e) => panic!("{}", e),
+                };
+
+            let should_stop = Arc::new(AtomicBool::new(false));
+            let stop = should_stop.clone();
+            Some(TestWorker {
+                addr,
+                should_stop,
+                waker: worker.waker(),
+                thread: std::thread::spawn(move || worker.run(&stop)),
+            })
+        }
+
+        fn connect(&self) -> TcpStream {
+            let stream = TcpStream::connect(self.addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream
+        }
+
+        fn stop(self) {
+            self.should_stop.store(true, Ordering::SeqCst);
+            self.waker.wake().unwrap();
+            self.thread.join().unwrap().unwrap();
+        }
+    }
+
+    /// Answers with the path and the length of the body, or with `len` bytes for `/bytes/<len>`
+    fn respond(req: Request) -> Response {
+        if let Some(len) = req.path.strip_prefix("/bytes/") {
+            return Response::new(200).with_body(vec![b'x'; len.parse().unwrap()]);
+        }
+        if req.path == "/stream" {
+            let chunks = ["one", "", "two"].into_iter().map(bytes::Bytes::from);
+            return Response::new(200).with_stream(chunks);
+        }
+        Response::new(200).with_body(format!("{} {}", req.path, req.body.len()))
+    }
+
+    /// Reads a response with a `Content-Length` body, returning its head and body
+    fn read_response(stream: &mut TcpStream) -> (String, Vec<u8>) {
+        let mut head = Vec::new();
+        let mut byte = [0];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+
+        let len = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .map_or(0, |len| len.parse().unwrap());
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).unwrap();
+        (head, body)
+    }
+
+    fn read_body(stream: &mut TcpStream) -> String {
+        String::from_utf8(read_response(stream).1).unwrap()
+    }
+
+    #[test]
+    fn answers_pipelined_requests_in_order() {
+        let Some(worker) = TestWorker::start(ServerConfig::default(), respond) else {
+            return;
+        };
+        let mut stream = worker.connect();
+
+        let requests: String = (0..20)
+            .map(|i| format!("GET /{} HTTP/1.1\r\nHost: x\r\n\r\n", i))
+            .collect();
+        stream.write_all(requests.as_bytes()).unwrap();
+        for i in 0..20 {
+            assert_eq!(read_body(&mut stream), format!("/{} 0", i));
+        }
+
+        // The connection stays open for more
+        stream
+            .write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello")
+            .unwrap();
+        assert_eq!(read_body(&mut stream), "/upload 5");
+
+        drop(stream);
+        worker.stop();
+    }
+
+    #[test]
+    fn receives_requests_spread_over_many_buffers() {
+        let config = ServerConfig {
+            buffer_size: 1024,
+            max_pooled_buffer_size: 4096,
+            ..ServerConfig::default()
+        };
+        let Some(worker) = TestWorker::start(config, respond) else {
+            return;
+        };
+        let mut stream = worker.connect();
+
+        let body = vec![b'b'; 512 * 1024];
+        write!(
+            stream,
+            "POST /upload HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(&body).unwrap();
+        assert_eq!(read_body(&mut stream), format!("/upload {}", body.len()));
+
+        drop(stream);
+        worker.stop();
+    }
+
+    #[test]
+    fn sends_large_and_streaming_responses() {
+        let Some(worker) = TestWorker::start(ServerConfig::default(), respond) else {
+            return;
+        };
+        let mut stream = worker.connect();
+
+        // Larger than the socket buffers, sent over several completions
+        let len = 8 * 1024 * 1024;
+        write!(stream, "GET /bytes/{} HTTP/1.1\r\n\r\n", len).unwrap();
+        let (_, body) = read_response(&mut stream);
+        assert_eq!(body.len(), len);
+        assert!(body.iter().all(|&b| b == b'x'));
+
+        stream.write_all(b"GET /stream HTTP/1.1\r\n\r\n").unwrap();
+        let mut received = Vec::new();
+        let end = b"0\r\n\r\n";
+        let mut byte = [0];
+        while !received.ends_with(end) {
+            stream.read_exact(&mut byte).unwrap();
+            received.push(byte[0]);
+        }
+        let received = String::from_utf8(received).unwrap();
+        assert!(
+            received.ends_with("\r\n\r\n3\r\none\r\n3\r\ntwo\r\n0\r\n\r\n"),
+            "{}",
+            received
+        );
+
+        // Still in sync with the client
+        stream.write_all(b"GET /after HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(read_body(&mut stream), "/after 0");
+
+        drop(stream);
+        worker.stop();
+    }
+
+    #[test]
+    fn closes_connections_when_asked_or_refused() {
+        let Some(worker) = TestWorker::start(ServerConfig::default(), respond) else {
+            return;
+        };
+
+        let mut stream = worker.connect();
+        stream
+            .write_all(b"GET /last HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        assert_eq!(read_body(&mut stream), "/last 0");
+        assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+        drop(stream);
+
+        let mut stream = worker.connect();
+        stream.write_all(b"GET / HTTP/3.0\r\n\r\n").unwrap();
+        let (head, _) = read_response(&mut stream);
+        assert!(head.starts_with("HTTP/1.1 505 "), "{}", head);
+
+        drop(stream);
+        worker.stop();
+    }
+
+    #[test]
+    fn sheds_connections_past_the_limit() {
+        let config = ServerConfig {
+            max_connections: 1,
+            connections_capacity: 1,
+            ..ServerConfig::default()
+        };
+        let Some(worker) = TestWorker::start(config, respond) else {
+            return;
+        };
+
+        let mut first = worker.connect();
+        first.write_all(b"GET /first HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(read_body(&mut first), "/first 0");
+
+        let mut second = worker.connect();
+        let _ = second.write_all(b"GET /second HTTP/1.1\r\n\r\n");
+        assert!(matches!(second.read(&mut [0; 16]), Ok(0) | Err(_)));
+
+        // The open connection is unaffected
+        first.write_all(b"GET /again HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(read_body(&mut first), "/again 0");
+
+        drop(first);
+        worker.stop();
+    }
+
+    #[test]
+    fn expires_deadlines() {
+        let mut config = ServerConfig::default();
+        config.timeouts.header_read = Duration::from_millis(200);
+        config.timeouts.linger = Duration::from_millis(100);
+        let Some(worker) = TestWorker::start(config, respond) else {
+            return;
+        };
+
+        let mut stream = worker.connect();
+        stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        let (head, _) = read_response(&mut stream);
+        assert!(head.starts_with("HTTP/1.1 408 "), "{}", head);
+        assert!(matches!(stream.read(&mut [0; 16]), Ok(0) | Err(_)));
+
+        worker.stop();
+    }
+
+    #[test]
+    fn finishes_open_requests_on_shutdown() {
+        let Some(worker) = TestWorker::start(ServerConfig::default(), respond) else {
+            return;
+        };
+
+        let mut idle = worker.connect();
+        idle.write_all(b"GET /idle HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(read_body(&mut idle), "/idle 0");
+
+        // Half way through its request when shutdown starts
+        let mut busy = worker.connect();
+        busy.write_all(b"POST /busy HTTP/1.1\r\nContent-Length: 4\r\n\r\nha")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+
+        worker.should_stop.store(true, Ordering::SeqCst);
+        worker.waker.wake().unwrap();
+
+        assert_eq!(idle.read(&mut [0; 16]).unwrap(), 0);
+        busy.write_all(b"lf").unwrap();
+        let (head, body) = read_response(&mut busy);
+        assert!(head.contains("\r\nConnection: close\r\n"), "{}", head);
+        assert_eq!(body, b"/busy 4");
+
+        drop(busy);
+        worker.thread.join().unwrap().unwrap();
+    }
+}
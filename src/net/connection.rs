@@ -1,118 +1,479 @@
-use bytes::BufMut;
 use bytes::BytesMut;
 use mio::event::Event;
 use mio::Interest;
 
-use crate::handler::Handler;
-use crate::http::{self, ParseStatus};
+use super::stream::Stream;
+use super::Socket;
+use super::Notifier;
+use crate::handler::{overloaded, Handler, HandlerPool, PendingResponse};
+use crate::http2;
+use crate::proxy::{self, HeaderStatus, ProxyProtocol};
+use crate::http::{
+    self, BodyFraming, BodyWriter, ChunkedDecoder, ChunkedError, ChunkedStatus, FramingError,
+    ConnectionInfo, OwnedRequest, ParseStatus, Request, Response,
+};
+use crate::sse::EventStream;
+use crate::websocket::{self, Session};
 
-use mio::net::TcpStream;
 use std::io::ErrorKind;
 use std::io::Read;
 use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-const MAX_REQUEST_SIZE: usize = 8192;
+/// How many encoded bytes may be read past the decoded body of a chunked request at once
+const CHUNKED_READ_WINDOW: usize = 16384; // 16KB
+/// How many bytes of a response body may be buffered for writing at once
+const WRITE_WINDOW: usize = 32768; // 32KB
+/// How many bytes of a file body may be handed to sendfile(2) at once
+const SENDFILE_WINDOW: usize = 1024 * 1024; // 1MB
+
+/// Size limits enforced while reading requests
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectionLimits {
+    /// Largest request head (request line and headers) accepted, in bytes
+    pub max_header_size: usize,
+    /// Largest number of headers accepted in a request
+    pub max_headers: usize,
+    /// Longest request target accepted, in bytes
+    pub max_uri_length: usize,
+    /// Largest (decoded) request body accepted, in bytes, which is also the largest WebSocket
+    /// message accepted
+    pub max_body_size: usize,
+    /// Number of requests served on a connection before it's closed
+    pub max_requests: u64,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits {
+            max_header_size: 8192,
+            max_headers: 64,
+            max_uri_length: 4096,
+            max_body_size: 1024 * 1024, // 1MB
+            max_requests: 1000,
+        }
+    }
+}
+
+/// How long a connection may stay in each phase before being timed out
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    /// Time allowed to receive a complete request head, from its first byte (or from the
+    /// connection being accepted). Not extended by progress, so slow clients can't hold a slot
+    pub header_read: Duration,
+    /// Time allowed between two reads of a request body
+    pub body_read: Duration,
+    /// Time allowed between two writes of a response
+    pub write: Duration,
+    /// Time an idle connection is kept open waiting for its next request
+    pub keep_alive: Duration,
+    /// Time given to the client to close the connection after a final response
+    pub linger: Duration,
+    /// Time an event stream may stay idle before a comment is sent to keep it open
+    pub heartbeat: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            header_read: Duration::from_secs(10),
+            body_read: Duration::from_secs(30),
+            write: Duration::from_secs(30),
+            keep_alive: Duration::from_secs(5),
+            linger: Duration::from_secs(2),
+            heartbeat: Duration::from_secs(15),
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn get(&self, kind: TimeoutKind) -> Duration {
+        match kind {
+            TimeoutKind::HeaderRead => self.header_read,
+            TimeoutKind::BodyRead => self.body_read,
+            TimeoutKind::Write => self.write,
+            TimeoutKind::KeepAlive => self.keep_alive,
+            TimeoutKind::Linger => self.linger,
+            TimeoutKind::Heartbeat => self.heartbeat,
+        }
+    }
+}
+
+/// The phase a connection deadline applies to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutKind {
+    HeaderRead,
+    BodyRead,
+    Write,
+    KeepAlive,
+    Linger,
+    Heartbeat,
+}
+
+impl TimeoutKind {
+    /// Whether any activity during the phase pushes the deadline back
+    pub fn extended_by_activity(self) -> bool {
+        matches!(self, TimeoutKind::BodyRead | TimeoutKind::Write)
+    }
+}
+
+/// Deadline currently scheduled for a connection
+#[derive(Clone, Copy, Debug)]
+pub struct ArmedTimeout {
+    pub kind: TimeoutKind,
+    /// Id of the timer wheel entry backing this deadline
+    pub id: u64,
+    pub started: Instant,
+    pub last_activity: Instant,
+}
 
 #[derive(PartialEq)]
 pub enum ConnectionState {
     Reading,
     Writing,
+    /// The final response was sent, discarding input until the client closes the connection
+    Draining,
+    /// Taken over by the WebSocket protocol, exchanging frames in both directions
+    Upgraded,
+    /// Speaking HTTP/2, with requests multiplexed over streams
+    Http2,
+    /// Waiting for the response of a request handed to the handler pool, or deferred by its
+    /// handler. Input is only read to notice the client going away
+    WaitingForHandler,
     Closed,
 }
 
+/// What's needed to answer a request once its handler returned
+struct Reply {
+    keep_alive: bool,
+    version: u8,
+    head: bool,
+    /// Settings of the h2c upgrade the request asked for, accepted along with the response
+    h2c_settings: Option<Vec<u8>>,
+}
+
+/// A request whose response is produced by the handler pool or a `Responder`
+struct PendingHandler {
+    response: PendingResponse,
+    reply: Reply,
+}
+
+/// Outcome of handing a request to the handler
+enum Handled {
+    Ready(Response),
+    Pending(PendingResponse),
+}
+
 pub struct Connection {
     pub read_buffer: BytesMut,
     pub write_buffer: BytesMut,
+    /// Output taken out of the write_buffer by a completion based backend while the kernel sends
+    /// it, so that what the connection keeps queueing meanwhile can't move it
+    sending: BytesMut,
     state: ConnectionState,
-    socket: TcpStream,
+    socket: Stream,
+    /// Interest the socket is currently registered with
+    interest: Interest,
+    limits: ConnectionLimits,
+    timeouts: Timeouts,
+    /// Total size (headers + body) of the request currently being read, known once its headers
+    /// have been parsed but the body has not fully arrived yet
+    pending_request_len: Option<usize>,
+    /// Decoder state of the chunked body currently being read, if any
+    chunked_decoder: Option<ChunkedDecoder>,
+    /// Body of the response being written, pulled into the write_buffer as the socket drains
+    response_body: Option<BodyWriter>,
+    /// Close the connection once the write_buffer is drained instead of reading again
+    close_after_write: bool,
+    /// HTTP/2 frames were left in the read_buffer as the write_buffer was full, they are handled
+    /// once it drained
+    frames_blocked: bool,
+    /// The client shut its side of the connection down, nothing follows the requests received
+    read_closed: bool,
+    /// The server is shutting down, the next response is the last one
+    shutting_down: bool,
+    /// Number of requests handled so far on this connection
+    requests_handled: u64,
+    /// Identity and addresses of the connection, handed to every request
+    info: ConnectionInfo,
+    /// Whether a PROXY protocol header is still expected, before anything else
+    proxy_protocol: ProxyProtocol,
+    /// WebSocket session the connection was upgraded to, or is about to be once the handshake
+    /// response is sent
+    websocket: Option<Session>,
+    /// HTTP/2 session the connection switched to, through its preface, ALPN or an h2c upgrade
+    http2: Option<Box<http2::Session>>,
+    /// Event stream feeding the response_body, if it's one
+    event_stream: Option<EventStream>,
+    /// Wakes the worker up when the connection has work queued from another thread
+    notifier: Notifier,
+    /// Runs the handler away from the event loop, if enabled
+    handler_pool: Option<Arc<HandlerPool>>,
+    /// Request handed to the handler_pool or deferred, answered once its response is ready.
+    /// Dropping it along with the connection cancels the request
+    pending_handler: Option<PendingHandler>,
+    /// Deadline of the current phase, managed by the worker's timer wheel
+    pub timeout: Option<ArmedTimeout>,
 }
 
 impl Connection {
-    pub fn new(socket: TcpStream, read_buffer: BytesMut, write_buffer: BytesMut) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        mut socket: Stream,
+        read_buffer: BytesMut,
+        write_buffer: BytesMut,
+        limits: ConnectionLimits,
+        timeouts: Timeouts,
+        notifier: Notifier,
+        handler_pool: Option<Arc<HandlerPool>>,
+        proxy_protocol: ProxyProtocol,
+        info: ConnectionInfo,
+    ) -> Self {
         // Set TCP_NODELAY to reduce latency
-        let _ = socket.set_nodelay(true);
+        let _ = socket.socket().set_nodelay(true);
 
         Connection {
             state: ConnectionState::Reading,
             read_buffer,
             write_buffer,
+            sending: BytesMut::new(),
             socket,
+            interest: Interest::READABLE,
+            limits,
+            timeouts,
+            pending_request_len: None,
+            chunked_decoder: None,
+            response_body: None,
+            close_after_write: false,
+            frames_blocked: false,
+            read_closed: false,
+            shutting_down: false,
+            requests_handled: 0,
+            info,
+            proxy_protocol,
+            websocket: None,
+            http2: None,
+            event_stream: None,
+            notifier,
+            handler_pool,
+            pending_handler: None,
+            timeout: None,
         }
     }
 
     /// The state machine coordinator.
     /// Dispatches I/O tasks based on current state and event.
+    ///
+    /// Returns the interest the socket must be registered with, if it changed.
     pub fn process<H: Handler>(&mut self, event: &Event, handler: &H) -> Option<Interest> {
         let mut changed_interest = false;
-        // Handle reads
-        if event.is_readable() && self.state == ConnectionState::Reading {
-            match self.read() {
-                // Clean close: peer shut down the connection
-                Ok(0) => {
-                    self.state = ConnectionState::Closed;
-                    return None;
+        let mut readable = event.is_readable();
+        let mut writable = event.is_writable();
+
+        loop {
+            // Handle reads
+            if readable && self.state == ConnectionState::Reading {
+                loop {
+                    let read_limit = self.read_limit();
+
+                    match self.read() {
+                        // Clean close: peer shut down the connection, possibly right after
+                        // sending requests that are still answered
+                        Ok(_) if self.read_closed => {
+                            self.end_of_input(handler);
+                            if self.state == ConnectionState::Closed {
+                                return None;
+                            }
+                            changed_interest = true;
+                        }
+
+                        // Data arrived, Try to parse it into an HTTP Request.
+                        Ok(_n) => {
+                            let reached_limit = self.read_buffer.len() >= read_limit;
+                            self.handle_requests(handler);
+                            changed_interest = true; // State changed to Writing
+
+                            // `read` stops once the buffer reaches the read limit, without
+                            // draining the socket. If the parsed headers announced a body the
+                            // limit grows, so keep reading since no new event will be delivered
+                            // for pending bytes.
+                            if reached_limit
+                                && self.state == ConnectionState::Reading
+                                && self.read_limit() > self.read_buffer.len()
+                            {
+                                continue;
+                            }
+                        }
+
+                        Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+
+                        Err(_) => {
+                            // Fatal socket error
+                            self.state = ConnectionState::Closed;
+                            return None;
+                        }
+                    }
+
+                    break;
                 }
+            }
 
-                // Data arrived, Try to parse it into an HTTP Request.
-                Ok(_n) => {
-                    self.handle_request(handler);
-                    changed_interest = true; // State changed to Writing
+            // Buffers what the client sends while the response isn't ready. A client shutting its
+            // side down still gets the response, one that went away is noticed as it's written
+            if readable && self.state == ConnectionState::WaitingForHandler && !self.read_closed {
+                match self.read() {
+                    Ok(_) => {}
+                    Err(_) => {
+                        self.state = ConnectionState::Closed;
+                        return None;
+                    }
                 }
+            }
 
-                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+            // Nothing is written to a paused event stream, so clients leaving it are only
+            // noticed as the socket reports the connection closed
+            if readable
+                && self.is_body_paused()
+                && (event.is_read_closed() || event.is_error())
+            {
+                self.state = ConnectionState::Closed;
+                return None;
+            }
 
-                Err(_) => {
-                    // Fatal socket error
-                    self.state = ConnectionState::Closed;
+            if readable
+                && matches!(
+                    self.state,
+                    ConnectionState::Upgraded | ConnectionState::Http2
+                )
+            {
+                self.read_frames(handler);
+                if self.state == ConnectionState::Closed {
                     return None;
                 }
+
+                // Replies such as pongs are sent right away, the socket is most likely writable
+                writable |= !self.write_buffer.is_empty();
             }
-        }
 
-        // Handle writes
-        if event.is_writable() && self.state == ConnectionState::Writing {
-            match self.write() {
-                Ok(_) => {
-                    // If it was a partial write, state remains Writing.
-                    if self.state == ConnectionState::Reading {
-                        changed_interest = true;
+            // TLS records may be waiting for the socket, such as handshake messages
+            if writable && self.state != ConnectionState::Writing && self.socket.wants_write() {
+                match self.socket.flush() {
+                    Ok(()) => {}
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(_) => {
+                        self.state = ConnectionState::Closed;
+                        return None;
                     }
                 }
+            }
+
+            // Handle writes
+            if writable
+                && matches!(
+                    self.state,
+                    ConnectionState::Writing | ConnectionState::Upgraded | ConnectionState::Http2
+                )
+            {
+                let frames_blocked = self.frames_blocked;
+                match self.write(handler) {
+                    Ok(_) => {
+                        // If it was a partial write, state remains Writing.
+                        if matches!(
+                            self.state,
+                            ConnectionState::Reading | ConnectionState::Draining
+                        ) {
+                            changed_interest = true;
+                        }
+
+                        // Input already decrypted by a TLS session won't be signaled by the
+                        // socket, so read it right away. Neither will input left in the socket
+                        // while frames waited for the output to drain
+                        if matches!(
+                            self.state,
+                            ConnectionState::Reading
+                                | ConnectionState::Upgraded
+                                | ConnectionState::Http2
+                        ) && (self.socket.has_buffered_input()
+                            || (frames_blocked && !self.frames_blocked))
+                        {
+                            readable = true;
+                            writable = false;
+                            continue;
+                        }
+                    }
 
-                Err(_) => self.state = ConnectionState::Closed,
+                    Err(_) => self.state = ConnectionState::Closed,
+                }
             }
+
+            break;
         }
 
-        if changed_interest {
-            match self.state {
-                ConnectionState::Reading => Some(Interest::READABLE),
-                ConnectionState::Writing => Some(Interest::WRITABLE),
-                _ => None,
-            }
+        // Discard anything the client sends after the final response
+        if event.is_readable() && self.state == ConnectionState::Draining {
+            self.drain();
+        }
+
+        let interest = self.interest()?;
+        if changed_interest || interest != self.interest {
+            self.interest = interest;
+            Some(interest)
         } else {
             None
         }
     }
 
+    /// Interest matching the current state, plus writability while a TLS session has records
+    /// to send
+    fn interest(&self) -> Option<Interest> {
+        let interest = match self.state {
+            ConnectionState::Reading
+            | ConnectionState::Draining
+            | ConnectionState::WaitingForHandler => Interest::READABLE,
+            ConnectionState::Writing if self.is_body_paused() => Interest::READABLE,
+            ConnectionState::Writing => Interest::WRITABLE,
+            ConnectionState::Upgraded | ConnectionState::Http2 if !self.write_buffer.is_empty() => {
+                Interest::READABLE | Interest::WRITABLE
+            }
+            ConnectionState::Upgraded | ConnectionState::Http2 => Interest::READABLE,
+            ConnectionState::Closed => return None,
+        };
+
+        if self.socket.wants_write() {
+            Some(interest | Interest::WRITABLE)
+        } else {
+            Some(interest)
+        }
+    }
+
     /// Reads data from the socket directly into the pooled BytesMut.
     /// Returns the number of bytes read in this call.
+    ///
+    /// Reading stops early once the buffer holds `read_limit` bytes, it's up to
+    /// `handle_request` to decide whether the buffered data is acceptable.
     pub fn read(&mut self) -> std::io::Result<usize> {
+        if self.proxy_protocol != ProxyProtocol::Disabled
+            && self.socket.is_tls()
+            && !self.read_proxy_header()?
+        {
+            return Ok(0);
+        }
+
         let mut bytes_read_this_turn = 0;
+        let read_limit = self.read_limit();
 
         loop {
             // Hard limit check
             // Prevents malicious clients from causing Out-Of-Memory (OOM) via Slowloris.
-            if self.read_buffer.len() >= MAX_REQUEST_SIZE {
-                self.state = ConnectionState::Closed;
-                return Err(std::io::Error::new(
-                    ErrorKind::InvalidData,
-                    "Request header too large",
-                ));
+            if self.read_buffer.len() >= read_limit {
+                break;
             }
 
-            // Ensure there is space to read.
-            if self.read_buffer.remaining_mut() < 1024 {
-                let space_left = MAX_REQUEST_SIZE - self.read_buffer.len();
+            // Ensure there is space to read. Note that `BufMut::remaining_mut` can't be used here,
+            // as BytesMut reports it as unbounded since it grows on demand.
+            if self.read_buffer.capacity() - self.read_buffer.len() < 1024 {
+                let space_left = read_limit - self.read_buffer.len();
                 if space_left == 0 {
                     break;
                 }
@@ -131,8 +492,8 @@ impl Connection {
 
                 match self.socket.read(slice) {
                     Ok(0) => {
-                        // Clean EOF: Client closed the connection
-                        self.state = ConnectionState::Closed;
+                        // Clean EOF: Client closed the connection, or only its side of it
+                        self.read_closed = true;
                         return Ok(bytes_read_this_turn);
                     }
                     Ok(n) => n,
@@ -161,13 +522,64 @@ impl Connection {
         Ok(bytes_read_this_turn)
     }
 
-    /// Writes the contents of write_buffer to the socket.
+    /// Writes the contents of write_buffer to the socket, refilling it from the response body
+    /// so that it never holds more than WRITE_WINDOW bytes of body at once. Once a response is
+    /// fully sent, the next pipelined requests already buffered are handled right away.
     /// Returns the number of bytes written in this call.
-    pub fn write(&mut self) -> std::io::Result<usize> {
+    pub fn write<H: Handler>(&mut self, handler: &H) -> std::io::Result<usize> {
         let mut bytes_written_this_turn = 0;
 
-        // Drain the buffer
-        while !self.write_buffer.is_empty() {
+        loop {
+            // Once everything before the body was written, file bodies go straight from the page
+            // cache to the socket
+            if self.write_buffer.is_empty() {
+                match self.send_body_directly() {
+                    None => {}
+                    Some(Ok(0)) => self.response_body = None,
+                    Some(Ok(n)) => {
+                        bytes_written_this_turn += n;
+                        continue;
+                    }
+                    Some(Err(ref e)) if e.kind() == ErrorKind::WouldBlock => {
+                        return Ok(bytes_written_this_turn);
+                    }
+                    Some(Err(e)) => {
+                        self.state = ConnectionState::Closed;
+                        return Err(e);
+                    }
+                }
+            }
+
+            if let Err(e) = self.fill_write_buffer() {
+                // The body can't be completed, and the response is already on its way
+                self.state = ConnectionState::Closed;
+                return Err(e);
+            }
+
+            if self.write_buffer.is_empty() {
+                // Records still held by a TLS session belong to the response as well
+                match self.socket.flush() {
+                    Ok(()) => {}
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                        return Ok(bytes_written_this_turn);
+                    }
+                    Err(e) => {
+                        self.state = ConnectionState::Closed;
+                        return Err(e);
+                    }
+                }
+
+                // Waiting for the body to be notified of new data
+                if self.is_body_paused() {
+                    break;
+                }
+
+                if !self.output_sent(handler) {
+                    break;
+                }
+                continue;
+            }
+
             match self.socket.write(&self.write_buffer) {
                 Ok(0) => {
                     // A write of 0 usually means the connection was dropped by the peer
@@ -193,59 +605,996 @@ impl Connection {
             }
         }
 
-        // If the loop finishes, the buffer is empty.
-        self.state = ConnectionState::Reading;
-
         Ok(bytes_written_this_turn)
     }
 
+    /// Moves on once everything queued was sent: either closes our side of the connection after
+    /// a final response, or answers the requests still waiting in the read_buffer.
+    /// Returns whether more output was queued.
+    fn output_sent<H: Handler>(&mut self, handler: &H) -> bool {
+        // Nothing is left to drain once the client shut its side down
+        if self.close_after_write && self.read_closed {
+            self.state = ConnectionState::Closed;
+            return false;
+        }
+
+        if self.close_after_write {
+            // Lingering close: closing right away with unread data would make the kernel reset
+            // the connection, possibly destroying the response before the client reads it. So
+            // only close our side and drain whatever the client still sends.
+            self.socket.shutdown_write();
+            self.state = ConnectionState::Draining;
+            return false;
+        }
+
+        // Streams are answered as their requests complete, not once the output drained, only
+        // the frames left waiting for room in the write_buffer are handled now
+        if self.state == ConnectionState::Http2 {
+            if !self.frames_blocked {
+                return false;
+            }
+            self.receive_frames(handler);
+            return !self.write_buffer.is_empty();
+        }
+
+        if self.websocket.is_some() {
+            if self.state == ConnectionState::Upgraded {
+                return false;
+            }
+
+            // The handshake response is out, the protocol switches right after it
+            self.start_websocket(handler);
+            return true;
+        }
+
+        self.handle_requests(handler);
+        self.state == ConnectionState::Writing
+    }
+
+    /// Appends data received by a completion based backend, which reads on its own instead of
+    /// going through `read`, and handles the requests it completes.
+    ///
+    /// Data arriving while a response is being written is kept for later, like pipelined
+    /// requests, and anything received while draining is discarded. The backend stops receiving
+    /// once the read limit is reached, see `wants_input`.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn receive<H: Handler>(&mut self, data: &[u8], handler: &H) {
+        match self.state {
+            ConnectionState::Reading => {
+                self.read_buffer.extend_from_slice(data);
+                self.handle_requests(handler);
+            }
+            ConnectionState::Writing | ConnectionState::WaitingForHandler => {
+                self.read_buffer.extend_from_slice(data)
+            }
+            ConnectionState::Upgraded | ConnectionState::Http2 => {
+                self.read_buffer.extend_from_slice(data);
+                self.receive_frames(handler);
+            }
+            ConnectionState::Draining | ConnectionState::Closed => {}
+        }
+    }
+
+    /// Output waiting to be sent, for completion based backends. Refills the write_buffer from
+    /// the response body and moves on to the next response like `write` does, the returned slice
+    /// is only empty once there's nothing left to send, or while an event stream waits for events.
+    ///
+    /// The output is taken out of the write_buffer, it stays put until the transport reports how
+    /// much of it was sent through `consume_output`, whatever the connection queues meanwhile.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn pending_output<H: Handler>(&mut self, handler: &H) -> std::io::Result<&[u8]> {
+        loop {
+            if !self.sending.is_empty() {
+                return Ok(&self.sending);
+            }
+
+            if let Err(e) = self.fill_write_buffer() {
+                self.state = ConnectionState::Closed;
+                return Err(e);
+            }
+
+            if !self.write_buffer.is_empty() {
+                self.sending = self.write_buffer.split();
+                return Ok(&self.sending);
+            }
+
+            if self.is_body_paused() || !self.output_sent(handler) {
+                return Ok(&[]);
+            }
+        }
+    }
+
+    /// Drops the first `n` bytes of the output returned by `pending_output`, once the transport
+    /// sent them
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn consume_output(&mut self, n: usize) {
+        let _ = self.sending.split_to(n);
+        if self.sending.is_empty() {
+            // Leaves the write_buffer sole owner of the memory, so it's reused as it grows again
+            self.sending = BytesMut::new();
+        }
+    }
+
+    /// Whether the output currently buffered completes everything there is to send, so the
+    /// connection is expected to read again once it was sent
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn is_output_complete(&self) -> bool {
+        self.response_body.is_none() && !self.close_after_write
+    }
+
+    /// Whether the client may still send input and the read_buffer has room for it. Completion
+    /// based backends stop submitting receives past the read limit, as `read` stops reading, so
+    /// clients pipelining requests behind a slow handler can't make the buffer grow without bounds
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn wants_input(&self) -> bool {
+        // Input received while draining is discarded
+        !self.read_closed
+            && (self.state == ConnectionState::Draining
+                || self.read_buffer.len() < self.read_limit())
+    }
+
+    /// Handles the client shutting its side of the connection down. It may have gone away, or
+    /// only be done sending requests, so the ones received are still answered before closing.
+    pub fn end_of_input<H: Handler>(&mut self, handler: &H) {
+        self.read_closed = true;
+        match self.state {
+            ConnectionState::Reading => {
+                self.handle_requests(handler);
+                // Such as a PROXY protocol header cut short
+                if self.state == ConnectionState::Reading {
+                    self.state = ConnectionState::Closed;
+                }
+            }
+            // Closed once answered, or as soon as writing fails
+            ConnectionState::Writing | ConnectionState::WaitingForHandler => {}
+            _ => self.state = ConnectionState::Closed,
+        }
+    }
+
+    /// Writes the next piece of the response body to the socket without copying it, if both the
+    /// body and the transport allow it, see `Body::send_to`
+    fn send_body_directly(&mut self) -> Option<std::io::Result<usize>> {
+        let fd = self.socket.plain_fd()?;
+        self.response_body.as_mut()?.send_to(fd, SENDFILE_WINDOW)
+    }
+
+    /// Whether everything was sent and the response body is waiting for more data, as event
+    /// streams do between events
+    fn is_body_paused(&self) -> bool {
+        self.state == ConnectionState::Writing
+            && !self.has_output()
+            && self.response_body.as_ref().is_some_and(BodyWriter::is_paused)
+    }
+
+    /// Whether output is queued in the write_buffer, or still being sent from `sending`
+    fn has_output(&self) -> bool {
+        !self.write_buffer.is_empty() || !self.sending.is_empty()
+    }
+
+    /// Pulls the body of the response being sent into the write_buffer, up to WRITE_WINDOW bytes.
+    /// Upgraded connections pull the messages queued by their senders instead, and HTTP/2 ones
+    /// the response bodies of their streams.
+    fn fill_write_buffer(&mut self) -> std::io::Result<()> {
+        if let Some(body) = self.response_body.as_mut() {
+            if !body.fill(&mut self.write_buffer, WRITE_WINDOW)? {
+                self.response_body = None;
+                self.event_stream = None;
+            }
+        }
+
+        if self.state == ConnectionState::Upgraded {
+            if let Some(session) = self.websocket.as_mut() {
+                session.fill(&mut self.write_buffer, WRITE_WINDOW);
+            }
+        }
+
+        if self.state == ConnectionState::Http2 {
+            if let Some(session) = self.http2.as_mut() {
+                session.fill(&mut self.write_buffer, WRITE_WINDOW);
+
+                // Closed like after a final response once the last stream is done
+                if session.is_done() {
+                    self.close_after_write = true;
+                    self.state = ConnectionState::Writing;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switches to the WebSocket protocol once the handshake response was sent, handling the
+    /// frames the client may have sent right behind its request
+    fn start_websocket<H: Handler>(&mut self, handler: &H) {
+        let Some(session) = self.websocket.as_mut() else {
+            return;
+        };
+
+        self.state = ConnectionState::Upgraded;
+        session.open(&mut self.write_buffer);
+        if self.shutting_down {
+            session.shutdown(&mut self.write_buffer);
+        }
+
+        self.receive_frames(handler);
+    }
+
+    /// Switches to HTTP/2 once its session was set up, handling the frames the client sent right
+    /// behind its preface
+    fn start_http2<H: Handler>(&mut self, handler: &H) {
+        self.state = ConnectionState::Http2;
+        self.receive_frames(handler);
+
+        // The requests already received are still answered
+        if self.shutting_down && self.state == ConnectionState::Http2 {
+            if let Some(session) = self.http2.as_mut() {
+                session.shutdown(&mut self.write_buffer);
+            }
+            let _ = self.fill_write_buffer();
+        }
+    }
+
+    /// Sets up an HTTP/2 session, queueing the server's preface
+    fn new_http2_session(&mut self) {
+        let session = http2::Session::new(
+            &self.limits,
+            self.notifier.clone(),
+            self.handler_pool.clone(),
+            self.info.clone(),
+            self.requests_handled,
+            &mut self.write_buffer,
+        );
+        self.http2 = Some(Box::new(session));
+    }
+
+    /// Reads and handles the frames sent to an upgraded or HTTP/2 connection, until the socket is
+    /// drained
+    fn read_frames<H: Handler>(&mut self, handler: &H) {
+        loop {
+            let read_limit = self.read_limit();
+
+            match self.read() {
+                Ok(_) if self.read_closed => {
+                    self.state = ConnectionState::Closed;
+                    return;
+                }
+                Ok(_) => {
+                    let reached_limit = self.read_buffer.len() >= read_limit;
+                    self.receive_frames(handler);
+
+                    // Like for requests, reading stopped at the limit without draining the socket
+                    if reached_limit
+                        && matches!(
+                            self.state,
+                            ConnectionState::Upgraded | ConnectionState::Http2
+                        )
+                        && self.read_buffer.len() < self.read_limit()
+                    {
+                        continue;
+                    }
+                }
+                Err(_) => {
+                    self.state = ConnectionState::Closed;
+                    return;
+                }
+            }
+
+            break;
+        }
+    }
+
+    /// Hands the complete frames of the read_buffer to the WebSocket or HTTP/2 session. Once the
+    /// closing handshake is over, or the HTTP/2 session went away, the connection is closed after
+    /// sending what's left, like after a final response.
+    fn receive_frames<H: Handler>(&mut self, handler: &H) {
+        if let Some(session) = self.http2.as_mut() {
+            self.frames_blocked = session.receive(
+                &mut self.read_buffer,
+                &mut self.write_buffer,
+                WRITE_WINDOW,
+                handler,
+            );
+
+            // Window updates may let blocked responses move on
+            let _ = self.fill_write_buffer();
+            return;
+        }
+
+        let Some(session) = self.websocket.as_mut() else {
+            return;
+        };
+
+        session.receive(&mut self.read_buffer, &mut self.write_buffer);
+        if session.is_done() {
+            self.close_after_write = true;
+            self.state = ConnectionState::Writing;
+        }
+    }
+
+    /// Reads and discards incoming data until the client closes its side of the connection.
+    fn drain(&mut self) {
+        self.read_buffer.clear();
+        if self.read_buffer.capacity() == 0 {
+            self.read_buffer.reserve(1024);
+        }
+
+        loop {
+            let n = unsafe {
+                // Read into the spare capacity, the data is never committed
+                let ptr = self.read_buffer.as_mut_ptr();
+                let slice = std::slice::from_raw_parts_mut(ptr, self.read_buffer.capacity());
+
+                self.socket.read(slice)
+            };
+
+            match n {
+                Ok(0) => break,
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(_) => break,
+            }
+        }
+
+        self.state = ConnectionState::Closed;
+    }
+
     /// Attempts to parse and process an HTTP request from the internal read buffer.
     ///
     /// This method implements a zero-copy approach:
     /// 1. It uses `httparse` to find the boundaries of the request without copying data.
-    /// 2. If a full request is found, it "consumes" those bytes from the `read_buffer` 
+    /// 2. The body, framed by `Content-Length` or decoded in place when chunked, is handed to the
+    ///    handler as a slice of the `read_buffer`. If it hasn't fully arrived yet the connection
+    ///    stays in `Reading`.
+    /// 3. Once a full request is found, it "consumes" those bytes from the `read_buffer`
     ///    using `split_to`, which is an O(1) operation that simply moves a pointer.
-    /// 3. It then generates a response and transitions the connection state to `Writing`.
+    /// 4. It then generates a response and transitions the connection state to `Writing`.
+    ///
+    /// Only the first request of the `read_buffer` is consumed, see `handle_requests` for
+    /// pipelining. Returns whether a request was handled.
     ///
-    /// # Pipelining Support
-    /// If the `read_buffer` contains more than one request (Pipelining), this method 
-    /// only consumes the first request. The remaining bytes stay in the buffer to 
-    /// be processed in the next cycle.
-    pub fn handle_request<H: Handler>(&mut self, handler:&H) {
+    /// Connections starting with the HTTP/2 preface, or that negotiated HTTP/2 through ALPN, get an
+    /// HTTP/2 session instead, as do HTTP/1.1 requests asking for an h2c upgrade.
+    pub fn handle_request<H: Handler>(&mut self, handler:&H) -> bool {
+        if self.requests_handled == 0 {
+            if self.socket.alpn_protocol() == Some(http2::ALPN_PROTOCOL)
+                || self.read_buffer.starts_with(http2::PREFACE)
+            {
+                self.new_http2_session();
+                return false;
+            }
+
+            // Wait for the rest of the preface, which would otherwise parse as a request line
+            if http2::PREFACE.starts_with(&self.read_buffer) {
+                return false;
+            }
+        }
+
         // Storage for headers (httparse needs a place to put references)
-        let mut header_storage = [httparse::EMPTY_HEADER; 64];
+        let mut header_storage = vec![httparse::EMPTY_HEADER; self.limits.max_headers];
 
         // Attempt to parse the read_buffer
         match http::parse_request(&self.read_buffer, &mut header_storage) {
-            ParseStatus::Complete(req, amt) => {
-                // full HTTP Request
+            ParseStatus::Complete(mut req, header_len) => {
+                if header_len > self.limits.max_header_size {
+                    // Request header too large
+                    self.respond_with_error(431);
+                    return false;
+                }
+
+                if req.path.len() > self.limits.max_uri_length {
+                    // Request target too long
+                    self.respond_with_error(414);
+                    return false;
+                }
+
+                // full HTTP headers, check whether the body is complete as well
+                let body_len = match http::body_framing(&req) {
+                    Ok(BodyFraming::Empty) => 0,
+                    Ok(BodyFraming::ContentLength(len)) => len,
+                    Ok(BodyFraming::Chunked) => {
+                        // Decoding rewrites the read_buffer, which `req` borrows from, so chunked
+                        // requests are handled apart
+                        return self.handle_chunked_request(handler, header_len);
+                    }
+                    Err(FramingError::UnsupportedTransferEncoding) => {
+                        self.respond_with_error(501);
+                        return false;
+                    }
+                    Err(_) => {
+                        // Ambiguous body boundaries
+                        self.respond_with_error(400);
+                        return false;
+                    }
+                };
 
-                // For now, ignore the request and just generate a hardcoded response
-                let resp = handler.handle(req);
+                if body_len > self.limits.max_body_size {
+                    self.respond_with_error(413);
+                    return false;
+                }
+
+                let request_len = header_len + body_len;
+                if self.read_buffer.len() < request_len {
+                    // The body wasn't fully read, stay in the Reading state waiting for the rest
+                    self.pending_request_len = Some(request_len);
+                    return false;
+                }
+                self.pending_request_len = None;
 
-                // Encode the response into the write_buffer
-                resp.encode(&mut self.write_buffer);
+                req.body = &self.read_buffer[header_len..request_len];
+                let reply = Reply {
+                    keep_alive: req.keep_alive(),
+                    version: req.version,
+                    head: req.method == "HEAD",
+                    // Cleartext only, TLS connections negotiate HTTP/2 through ALPN instead
+                    h2c_settings: (!self.socket.is_tls() && !self.shutting_down)
+                        .then(|| http2::upgrade_settings(&req))
+                        .flatten(),
+                };
+
+                let handled = self.call_handler(handler, req);
 
                 // Consume the parsed bytes from the read_buffer
                 // If there's extra data (like a second request), it stays in read_buffer
-                let _ = self.read_buffer.split_to(amt);
+                let _ = self.read_buffer.split_to(request_len);
 
-                // Transition state to start sending the data
-                self.state = ConnectionState::Writing;
+                self.reply(handled, reply);
+                true
             }
             ParseStatus::Partial => {
                 // The HTTP request wasn't fully read, so stay in the Reading state wating for the
-                // next event
+                // next event, unless the headers already exceed their limit
+                if self.read_buffer.len() >= self.limits.max_header_size {
+                    // Still missing the end of the request line means the target is to blame
+                    if self.read_buffer.contains(&b'\n') {
+                        self.respond_with_error(431);
+                    } else {
+                        self.respond_with_error(414);
+                    }
+                }
+                false
             }
-            ParseStatus::Error(_) => {
-                // Malformed HTTP, close the connection
+            ParseStatus::Error(e) => {
+                match e {
+                    httparse::Error::TooManyHeaders => self.respond_with_error(431),
+                    httparse::Error::Version => self.respond_with_error(505),
+                    // Malformed HTTP
+                    _ => self.respond_with_error(400),
+                }
+                false
+            }
+        }
+    }
+
+    /// Accepts an h2c upgrade, the response to the request that asked for it being sent on the
+    /// first stream of the HTTP/2 session
+    fn upgrade_to_http2(&mut self, settings: &[u8], resp: Response, head: bool) {
+        self.requests_handled += 1;
+
+        Response::new(101)
+            .with_header("Connection", "Upgrade")
+            .with_header("Upgrade", "h2c")
+            .encode_head(&mut self.write_buffer, false);
+
+        self.new_http2_session();
+        if let Some(session) = self.http2.as_mut() {
+            session.upgrade(settings, resp, head, &mut self.write_buffer);
+        }
+    }
+
+    /// Handles every complete request already buffered (HTTP pipelining), queueing their
+    /// responses in order into the write_buffer.
+    ///
+    /// Parsing stops once the queued output reaches WRITE_WINDOW or a streaming body can't be
+    /// fully buffered, the remaining requests stay in the `read_buffer` and are handled by `write`
+    /// as the output drains. It also stops after a response that closes the connection.
+    /// The connection ends up `Writing` if anything was queued, `WaitingForHandler` if a request
+    /// is still being handled by the handler pool, `Reading` otherwise. Requests after one being
+    /// handled by the pool wait for its response to be queued first.
+    pub fn handle_requests<H: Handler>(&mut self, handler: &H) {
+        if self.proxy_protocol != ProxyProtocol::Disabled
+            && !self.socket.is_tls()
+            && !self.take_proxy_header()
+        {
+            return;
+        }
+
+        loop {
+            if self.fill_write_buffer().is_err() {
+                self.state = ConnectionState::Closed;
+                return;
+            }
+
+            if !self.finish_handler()
+                || self.response_body.is_some()
+                || self.close_after_write
+                || self.websocket.is_some()
+                || self.http2.is_some()
+                || self.write_buffer.len() >= WRITE_WINDOW
+                || !self.handle_request(handler)
+            {
+                break;
+            }
+        }
+
+        if self.http2.is_some() {
+            self.start_http2(handler);
+            return;
+        }
+
+        // An error response may have been queued without a request being handled
+        if self.fill_write_buffer().is_err() {
+            self.state = ConnectionState::Closed;
+            return;
+        }
+
+        if self.write_buffer.is_empty() && self.response_body.is_none() {
+            self.state = if self.pending_handler.is_some() {
+                ConnectionState::WaitingForHandler
+            } else if self.read_closed {
+                // Every request received was answered
+                ConnectionState::Closed
+            } else {
+                ConnectionState::Reading
+            };
+        } else {
+            self.state = ConnectionState::Writing;
+        }
+    }
+
+    /// Takes the PROXY protocol header off the start of the read_buffer, where plain connections
+    /// buffer it along with the requests following it. Returns whether requests can be handled.
+    fn take_proxy_header(&mut self) -> bool {
+        let input = std::mem::take(&mut self.read_buffer);
+        let header_len = self.accept_proxy_header(&input);
+        self.read_buffer = input;
+
+        match header_len {
+            Some(len) => {
+                let _ = self.read_buffer.split_to(len);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Takes the PROXY protocol header off the socket of a TLS connection, before the session
+    /// gets to see any of it. The header is peeked first so that nothing past it is consumed.
+    /// Returns whether the connection can move on to TLS.
+    fn read_proxy_header(&mut self) -> std::io::Result<bool> {
+        let mut input = vec![0; self.limits.max_header_size];
+        let n = match self.socket.socket().peek(&mut input) {
+            Ok(0) => {
+                self.state = ConnectionState::Closed;
+                return Ok(false);
+            }
+            Ok(n) => n,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(e) => {
+                self.state = ConnectionState::Closed;
+                return Err(e);
+            }
+        };
+
+        let Some(len) = self.accept_proxy_header(&input[..n]) else {
+            return Ok(false);
+        };
+        // Already received, so it can't block
+        self.socket.socket().read_exact(&mut input[..len])?;
+        Ok(true)
+    }
+
+    /// Parses the PROXY protocol header expected at the start of `input`, whose addresses replace
+    /// the ones of the connection. Returns its length, 0 if an optional header wasn't sent, or
+    /// `None` while it's incomplete. Connections lacking a valid header are closed.
+    fn accept_proxy_header(&mut self, input: &[u8]) -> Option<usize> {
+        let len = match proxy::parse_header(input) {
+            HeaderStatus::Complete(header, len) => {
+                self.info.peer_addr = header.source.unwrap_or(self.info.peer_addr);
+                self.info.local_addr = header.destination.unwrap_or(self.info.local_addr);
+                self.info.proxy = Some(Arc::new(header));
+                len
+            }
+            HeaderStatus::Missing if self.proxy_protocol == ProxyProtocol::Optional => 0,
+            HeaderStatus::Partial if input.len() < self.limits.max_header_size => return None,
+            _ => {
                 self.state = ConnectionState::Closed;
+                return None;
+            }
+        };
+
+        self.proxy_protocol = ProxyProtocol::Disabled;
+        Some(len)
+    }
+
+    /// Decodes the chunked body of the request whose headers end at `header_len`, processing the
+    /// request once the last chunk has arrived.
+    fn handle_chunked_request<H: Handler>(&mut self, handler: &H, header_len: usize) -> bool {
+        let decoder = self
+            .chunked_decoder
+            .get_or_insert_with(|| ChunkedDecoder::new(self.limits.max_body_size));
+
+        match decoder.decode_in_place(&mut self.read_buffer, header_len) {
+            Ok(ChunkedStatus::Complete) => {}
+            Ok(ChunkedStatus::Partial) => {
+                // Allow reading more encoded data, stay in the Reading state waiting for it
+                self.pending_request_len =
+                    Some(header_len + decoder.decoded_len() + CHUNKED_READ_WINDOW);
+                return false;
+            }
+            Err(ChunkedError::BodyTooLarge) => {
+                self.respond_with_error(413);
+                return false;
+            }
+            Err(_) => {
+                // Malformed chunked encoding
+                self.respond_with_error(400);
+                return false;
             }
         }
+
+        let request_len = header_len + decoder.decoded_len();
+        self.chunked_decoder = None;
+        self.pending_request_len = None;
+
+        // Parse the headers again, the decoded body now sits right after them
+        let mut header_storage = vec![httparse::EMPTY_HEADER; self.limits.max_headers];
+        let ParseStatus::Complete(mut req, _) =
+            http::parse_request(&self.read_buffer[..request_len], &mut header_storage)
+        else {
+            self.respond_with_error(400);
+            return false;
+        };
+
+        req.body = &self.read_buffer[header_len..request_len];
+        let reply = Reply {
+            keep_alive: req.keep_alive(),
+            version: req.version,
+            head: req.method == "HEAD",
+            h2c_settings: None,
+        };
+        let handled = self.call_handler(handler, req);
+
+        let _ = self.read_buffer.split_to(request_len);
+        self.reply(handled, reply);
+        true
+    }
+
+    /// Hands `req` to the handler pool if there's one, copying it out of the read_buffer, or to
+    /// the handler right away otherwise. Requests refused by a full pool get a 503 response.
+    fn call_handler<H: Handler>(&self, handler: &H, req: Request) -> Handled {
+        let req = Request {
+            connection: &self.info,
+            sequence: self.requests_handled + 1,
+            ..req
+        };
+
+        let Some(pool) = self.handler_pool.as_ref() else {
+            return Handled::Ready(handler.handle(req));
+        };
+
+        match pool.submit(OwnedRequest::from(&req)) {
+            Some(response) => Handled::Pending(response),
+            None => Handled::Ready(overloaded()),
+        }
+    }
+
+    /// Queues the response to a request, or keeps what's needed to send it once the handler pool
+    /// or a `Responder` delivers it
+    fn reply(&mut self, handled: Handled, reply: Reply) {
+        let mut response = match handled {
+            Handled::Ready(mut resp) => match resp.deferred.take() {
+                Some(response) => response,
+                None => return self.send_reply(resp, reply),
+            },
+            Handled::Pending(response) => response,
+        };
+
+        // Picked up by `finish_handler` if it's already there
+        response.attach(self.notifier.clone());
+        self.pending_handler = Some(PendingHandler { response, reply });
+    }
+
+    fn send_reply(&mut self, resp: Response, reply: Reply) {
+        match reply.h2c_settings {
+            Some(settings) => self.upgrade_to_http2(&settings, resp, reply.head),
+            None => self.queue_response(resp, reply.keep_alive, reply.version, reply.head),
+        }
+    }
+
+    /// Queues the response of the request handed to the handler pool, if it's ready. Returns
+    /// whether no handler is left to wait for.
+    fn finish_handler(&mut self) -> bool {
+        // A response deferred from a handler thread waits in turn, and must be polled once to be
+        // woken up
+        loop {
+            let Some(pending) = self.pending_handler.as_mut() else {
+                return true;
+            };
+            let Some(resp) = pending.response.poll() else {
+                return false;
+            };
+
+            if let Some(pending) = self.pending_handler.take() {
+                self.reply(Handled::Ready(resp), pending.reply);
+            }
+        }
+    }
+
+    /// Encodes the response head into the write_buffer, leaving the body to be streamed by
+    /// `write` as the socket accepts data.
+    ///
+    /// Also decides whether the connection persists after this response, which it doesn't when
+    /// either side asked to close it, the request limit was reached, or an HTTP/1.0 client gets a
+    /// body of unknown length (it can only be delimited by closing). The `Connection` and
+    /// `Keep-Alive` headers are set to match.
+    fn queue_response(&mut self, mut resp: Response, keep_alive: bool, version: u8, head: bool) {
+        self.requests_handled += 1;
+
+        // Accepted WebSocket handshakes keep the connection, whatever the request limit
+        if let Some(handler) = resp.websocket.take().filter(|_| resp.status == 101) {
+            resp.encode_head(&mut self.write_buffer, false);
+            self.websocket = Some(Session::new(
+                handler,
+                self.notifier.clone(),
+                self.limits.max_body_size,
+            ));
+            return;
+        }
+
+        // Responses to HEAD keep the headers describing the body, but never send it
+        let has_body = !head && http::status_has_body(resp.status);
+
+        let http10 = version == 0;
+        let close = !keep_alive
+            || resp.has_connection_option("close")
+            || self.requests_handled >= self.limits.max_requests
+            || self.shutting_down
+            || (http10 && has_body && resp.body.size_hint().is_none());
+
+        if close {
+            resp.headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Connection"));
+            resp.headers.push(("Connection".into(), "close".into()));
+            self.close_after_write = true;
+        } else if http10 {
+            // Persistence is opt-in for HTTP/1.0, confirm it
+            if resp.get_header("Connection").is_none() {
+                resp.headers.push(("Connection".into(), "keep-alive".into()));
+            }
+            let remaining = self.limits.max_requests - self.requests_handled;
+            resp.headers.push((
+                "Keep-Alive".into(),
+                format!(
+                    "timeout={}, max={}",
+                    self.timeouts.keep_alive.as_secs(),
+                    remaining
+                ),
+            ));
+        }
+
+        let chunked = resp.encode_head(&mut self.write_buffer, !http10);
+        if has_body {
+            self.response_body = Some(BodyWriter::new(resp.body, chunked));
+
+            if let Some(stream) = resp.event_stream.take() {
+                stream.attach(self.notifier.clone());
+                if self.shutting_down {
+                    stream.finish();
+                }
+                self.event_stream = Some(stream);
+            }
+        }
+    }
+
+    /// The phase whose deadline currently applies to the connection, if any
+    pub fn timeout_kind(&self) -> Option<TimeoutKind> {
+        match self.state {
+            ConnectionState::Writing if self.is_body_paused() => Some(TimeoutKind::Heartbeat),
+            ConnectionState::Writing => Some(TimeoutKind::Write),
+            ConnectionState::Draining => Some(TimeoutKind::Linger),
+            // Handlers are given as long as they need
+            ConnectionState::WaitingForHandler | ConnectionState::Closed => None,
+            // Waiting for the client to answer the close frame sent
+            ConnectionState::Upgraded if self.websocket.as_ref().is_some_and(Session::is_closing) => {
+                Some(TimeoutKind::Linger)
+            }
+            ConnectionState::Upgraded if self.has_output() => Some(TimeoutKind::Write),
+            // Idle WebSocket connections are kept open, it's up to the handler to close them
+            ConnectionState::Upgraded => None,
+            ConnectionState::Http2 => {
+                let session = self.http2.as_ref()?;
+                if self.has_output() {
+                    Some(TimeoutKind::Write)
+                } else if session.is_idle() {
+                    Some(TimeoutKind::KeepAlive)
+                } else if session.is_paused() {
+                    Some(TimeoutKind::Heartbeat)
+                } else {
+                    // Waiting for request bodies, or for the client to open its windows
+                    Some(TimeoutKind::BodyRead)
+                }
+            }
+            ConnectionState::Reading if self.pending_request_len.is_some() => {
+                Some(TimeoutKind::BodyRead)
+            }
+            ConnectionState::Reading
+                if self.requests_handled > 0 && self.read_buffer.is_empty() =>
+            {
+                Some(TimeoutKind::KeepAlive)
+            }
+            ConnectionState::Reading => Some(TimeoutKind::HeaderRead),
+        }
+    }
+
+    /// Handles an expired deadline. Clients that were in the middle of sending a request get a
+    /// 408 response before the connection is closed, idle event streams get a heartbeat, idle
+    /// HTTP/2 connections are shut down gracefully, and anything else is closed right away.
+    /// Returns the new interest if the connection has something to write.
+    pub fn on_timeout(&mut self, kind: TimeoutKind) -> Option<Interest> {
+        if kind == TimeoutKind::Heartbeat {
+            if let Some(stream) = self.event_stream.as_ref() {
+                stream.heartbeat();
+            }
+            if let Some(session) = self.http2.as_mut() {
+                session.heartbeat();
+            }
+            return self.on_notify();
+        }
+
+        if self.state == ConnectionState::Http2 {
+            if kind == TimeoutKind::KeepAlive {
+                return self.shutdown_http2();
+            }
+
+            self.state = ConnectionState::Closed;
+            return None;
+        }
+
+        let request_started = match kind {
+            TimeoutKind::HeaderRead => !self.read_buffer.is_empty(),
+            TimeoutKind::BodyRead => true,
+            TimeoutKind::Write
+            | TimeoutKind::KeepAlive
+            | TimeoutKind::Linger
+            | TimeoutKind::Heartbeat => false,
+        };
+
+        if !request_started {
+            self.state = ConnectionState::Closed;
+            return None;
+        }
+
+        self.respond_with_error(408);
+
+        self.interest = Interest::WRITABLE;
+        Some(Interest::WRITABLE)
+    }
+
+    /// Starts a graceful shutdown of the connection. Idle connections are closed right away,
+    /// while a connection receiving a request or sending a response finishes it and closes
+    /// afterwards, the response being sent with `Connection: close` unless it was already queued.
+    /// Pipelined requests past the current one are left unanswered, event streams end after the
+    /// events already queued, WebSocket connections start their closing handshake, and HTTP/2
+    /// connections stop accepting streams, closing once the open ones are done.
+    ///
+    /// Returns the new interest if it changed.
+    pub fn shutdown(&mut self) -> Option<Interest> {
+        self.shutting_down = true;
+
+        match self.state {
+            ConnectionState::Reading if self.read_buffer.is_empty() => {
+                self.state = ConnectionState::Closed;
+            }
+            ConnectionState::Writing => {
+                self.close_after_write = true;
+                if let Some(stream) = self.event_stream.as_ref() {
+                    stream.finish();
+                    return self.on_notify();
+                }
+            }
+            ConnectionState::Upgraded => {
+                if let Some(session) = self.websocket.as_mut() {
+                    session.shutdown(&mut self.write_buffer);
+                }
+            }
+            ConnectionState::Http2 => return self.shutdown_http2(),
+            // The response is sent with `Connection: close` once the handler returns
+            ConnectionState::Reading
+            | ConnectionState::Draining
+            | ConnectionState::WaitingForHandler
+            | ConnectionState::Closed => {}
+        }
+
+        self.refresh_interest()
+    }
+
+    /// Sends GOAWAY, letting the open streams complete before the connection is closed
+    fn shutdown_http2(&mut self) -> Option<Interest> {
+        if let Some(session) = self.http2.as_mut() {
+            session.shutdown(&mut self.write_buffer);
+        }
+        if self.fill_write_buffer().is_err() {
+            self.state = ConnectionState::Closed;
+        }
+
+        self.refresh_interest()
+    }
+
+    /// Picks up the work queued from another thread through the connection's `Notifier`, such as
+    /// messages sent to a WebSocket, events to an event stream or responses returned by the
+    /// handler pool. Returns the new interest if it changed.
+    pub fn on_notify(&mut self) -> Option<Interest> {
+        if self.state == ConnectionState::WaitingForHandler && self.finish_handler() {
+            self.state = match self.fill_write_buffer() {
+                Ok(()) => ConnectionState::Writing,
+                Err(_) => ConnectionState::Closed,
+            };
+            return self.refresh_interest();
+        }
+
+        if let Some(session) = self.http2.as_mut() {
+            session.wake(&mut self.write_buffer);
+        }
+
+        let streaming = self.state == ConnectionState::Writing && self.event_stream.is_some();
+        if (streaming
+            || matches!(
+                self.state,
+                ConnectionState::Upgraded | ConnectionState::Http2
+            ))
+            && self.fill_write_buffer().is_err()
+        {
+            self.state = ConnectionState::Closed;
+        }
+
+        self.refresh_interest()
+    }
+
+    /// The interest matching the current state, if it differs from the registered one
+    fn refresh_interest(&mut self) -> Option<Interest> {
+        let interest = self.interest()?;
+        if interest == self.interest {
+            return None;
+        }
+
+        self.interest = interest;
+        Some(interest)
+    }
+
+    /// Abandons the request being read and answers it with an error response, after which the
+    /// connection is closed gracefully.
+    fn respond_with_error(&mut self, status: u16) {
+        self.read_buffer.clear();
+        self.pending_request_len = None;
+        self.chunked_decoder = None;
+
+        let resp = Response::new(status).with_body(http::reason_phrase(status));
+        self.queue_response(resp, false, 1, false);
+        self.state = ConnectionState::Writing;
+    }
+
+    /// How many bytes may be buffered for the request currently being read.
+    ///
+    /// While the headers are still being read only `max_header_size` bytes are allowed, once they
+    /// are parsed the limit grows to fit the body announced by them.
+    fn read_limit(&self) -> usize {
+        if self.state == ConnectionState::Upgraded {
+            return self.limits.max_body_size + websocket::MAX_FRAME_HEADER_LEN;
+        }
+        if self.state == ConnectionState::Http2 {
+            return http2::MAX_FRAME_SIZE + http2::FRAME_HEADER_LEN;
+        }
+
+        self.pending_request_len.unwrap_or(self.limits.max_header_size)
+    }
+
+    pub fn socket(&mut self) -> &mut Socket {
+        self.socket.socket()
     }
 
-    pub fn socket(&mut self) -> &mut TcpStream {
-        &mut self.socket
+    /// Marks the connection as closed, the worker then releases it
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn close(&mut self) {
+        self.state = ConnectionState::Closed;
     }
 
     pub fn state(&self) -> &ConnectionState {
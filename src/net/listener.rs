@@ -0,0 +1,110 @@
+use super::Socket;
+use mio::event::Source;
+use mio::{Interest, Registry, Token};
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, RawFd};
+
+/// A bound, non-blocking listening socket, handed to a worker
+pub enum Listener {
+    Tcp(std::net::TcpListener),
+    Unix(std::os::unix::net::UnixListener),
+}
+
+impl Listener {
+    pub fn try_clone(&self) -> std::io::Result<Self> {
+        match self {
+            Listener::Tcp(listener) => listener.try_clone().map(Listener::Tcp),
+            Listener::Unix(listener) => listener.try_clone().map(Listener::Unix),
+        }
+    }
+
+    /// Takes the socket of a connection accepted on this listener by io_uring
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open socket accepted on this listener, owned by nothing else
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub unsafe fn accepted(&self, fd: RawFd) -> Socket {
+        use std::os::fd::FromRawFd;
+
+        match self {
+            Listener::Tcp(_) => Socket::Tcp(mio::net::TcpStream::from_raw_fd(fd)),
+            Listener::Unix(_) => Socket::Unix(mio::net::UnixStream::from_raw_fd(fd)),
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// A `Listener` registered with a mio poll
+pub enum MioListener {
+    Tcp(mio::net::TcpListener),
+    Unix(mio::net::UnixListener),
+}
+
+impl MioListener {
+    pub fn accept(&self) -> std::io::Result<(Socket, SocketAddr)> {
+        match self {
+            MioListener::Tcp(listener) => listener
+                .accept()
+                .map(|(socket, addr)| (Socket::Tcp(socket), addr)),
+            MioListener::Unix(listener) => {
+                let (socket, _) = listener.accept()?;
+                let socket = Socket::Unix(socket);
+                let addr = socket.peer_addr()?;
+                Ok((socket, addr))
+            }
+        }
+    }
+}
+
+impl From<Listener> for MioListener {
+    fn from(listener: Listener) -> Self {
+        match listener {
+            Listener::Tcp(listener) => MioListener::Tcp(mio::net::TcpListener::from_std(listener)),
+            Listener::Unix(listener) => {
+                MioListener::Unix(mio::net::UnixListener::from_std(listener))
+            }
+        }
+    }
+}
+
+impl Source for MioListener {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> std::io::Result<()> {
+        match self {
+            MioListener::Tcp(listener) => listener.register(registry, token, interests),
+            MioListener::Unix(listener) => listener.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> std::io::Result<()> {
+        match self {
+            MioListener::Tcp(listener) => listener.reregister(registry, token, interests),
+            MioListener::Unix(listener) => listener.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        match self {
+            MioListener::Tcp(listener) => listener.deregister(registry),
+            MioListener::Unix(listener) => listener.deregister(registry),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use super::backend::Wake;
+use std::sync::{Arc, Mutex};
+
+/// Connections of a worker that other threads asked it to look at, such as WebSocket connections
+/// with messages pushed to them. Shared by the worker and the `Notifier` of each connection.
+pub struct Notifications {
+    /// Slab keys of the notified connections, a key may have been reused by a newer connection
+    /// by the time it's handled, which then simply finds nothing to do
+    pending: Mutex<Vec<usize>>,
+    waker: Arc<dyn Wake>,
+}
+
+impl Notifications {
+    pub(crate) fn new(waker: Arc<dyn Wake>) -> Arc<Self> {
+        Arc::new(Notifications {
+            pending: Mutex::new(Vec::new()),
+            waker,
+        })
+    }
+
+    /// Notifier of the connection at `key`
+    pub(crate) fn notifier(self: &Arc<Self>, key: usize) -> Notifier {
+        Notifier {
+            key,
+            notifications: self.clone(),
+        }
+    }
+
+    /// Moves the keys of the connections notified since the last call into `keys`
+    pub(crate) fn take(&self, keys: &mut Vec<usize>) {
+        keys.append(&mut self.pending.lock().unwrap());
+    }
+}
+
+/// Wakes the worker owning a connection from any thread, for it to call `Connection::on_notify`
+#[derive(Clone)]
+pub struct Notifier {
+    key: usize,
+    notifications: Arc<Notifications>,
+}
+
+impl Notifier {
+    pub fn notify(&self) {
+        self.notifications.pending.lock().unwrap().push(self.key);
+
+        if let Err(e) = self.notifications.waker.wake() {
+            eprintln!("Failed to wake event loop: {}", e);
+        }
+    }
+}
+
+/// Lets futures polled by a connection wake it up, see `AsyncHandler`
+impl std::task::Wake for Notifier {
+    fn wake(self: Arc<Self>) {
+        self.notify();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.notify();
+    }
+}
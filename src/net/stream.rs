@@ -0,0 +1,216 @@
+use super::Socket;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::fd::{AsRawFd, RawFd};
+
+#[cfg(feature = "tls")]
+use std::io::ErrorKind;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+/// Transport of a connection, either the plain socket or a TLS session running over it.
+///
+/// Both behave like a non-blocking socket: `read` and `write` move plaintext and fail with
+/// `WouldBlock` when they can't make progress. A TLS session may however hold data of its own,
+/// ciphertext waiting for the socket to accept it (`wants_write`) and decrypted input that was
+/// already pulled from the socket (`has_buffered_input`), which the connection has to account for
+/// since the socket won't signal readiness for either of them.
+pub enum Stream {
+    Plain(Socket),
+    #[cfg(feature = "tls")]
+    Tls(Box<TlsStream>),
+}
+
+impl Stream {
+    /// Wraps a freshly accepted socket, starting a TLS session over it when `tls` is set
+    #[cfg(feature = "tls")]
+    pub fn new(
+        socket: Socket,
+        tls: Option<&Arc<rustls::ServerConfig>>,
+    ) -> std::io::Result<Self> {
+        match tls {
+            Some(config) => Ok(Stream::Tls(Box::new(TlsStream::new(socket, config.clone())?))),
+            None => Ok(Stream::Plain(socket)),
+        }
+    }
+
+    /// The underlying socket, as registered with the poll
+    pub fn socket(&mut self) -> &mut Socket {
+        match self {
+            Stream::Plain(socket) => socket,
+            #[cfg(feature = "tls")]
+            Stream::Tls(tls) => &mut tls.socket,
+        }
+    }
+
+    /// The socket, when data can be written to it directly instead of going through `write`,
+    /// which a TLS session doesn't allow
+    pub fn plain_fd(&self) -> Option<RawFd> {
+        match self {
+            Stream::Plain(socket) => Some(socket.as_raw_fd()),
+            #[cfg(feature = "tls")]
+            Stream::Tls(_) => None,
+        }
+    }
+
+    /// Whether the connection runs over TLS
+    pub fn is_tls(&self) -> bool {
+        !matches!(self, Stream::Plain(_))
+    }
+
+    /// Protocol negotiated through ALPN during the TLS handshake, if any
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        match self {
+            Stream::Plain(_) => None,
+            #[cfg(feature = "tls")]
+            Stream::Tls(tls) => tls.session.alpn_protocol(),
+        }
+    }
+
+    /// Whether output is pending that only the socket becoming writable can flush
+    pub fn wants_write(&self) -> bool {
+        match self {
+            Stream::Plain(_) => false,
+            #[cfg(feature = "tls")]
+            Stream::Tls(tls) => tls.session.wants_write(),
+        }
+    }
+
+    /// Whether input was already pulled from the socket but not returned by `read` yet
+    pub fn has_buffered_input(&self) -> bool {
+        match self {
+            Stream::Plain(_) => false,
+            #[cfg(feature = "tls")]
+            Stream::Tls(tls) => tls.buffered_plaintext > 0,
+        }
+    }
+
+    /// Closes the sending side of the connection, after notifying the peer for TLS sessions
+    pub fn shutdown_write(&mut self) {
+        #[cfg(feature = "tls")]
+        if let Stream::Tls(tls) = self {
+            tls.session.send_close_notify();
+            // Best effort, the alert is only a courtesy before closing
+            let _ = tls.flush();
+        }
+
+        let _ = self.socket().shutdown(Shutdown::Write);
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(tls) => tls.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            // Writes go straight to the kernel
+            Stream::Plain(_) => Ok(()),
+            #[cfg(feature = "tls")]
+            Stream::Tls(tls) => tls.flush(),
+        }
+    }
+}
+
+/// A rustls server session over a non-blocking socket.
+///
+/// The handshake needs no special handling from the connection: it's driven by the same `read`
+/// and `flush` calls that move application data, until it completes and plaintext starts flowing.
+#[cfg(feature = "tls")]
+pub struct TlsStream {
+    socket: Socket,
+    session: rustls::ServerConnection,
+    /// Decrypted bytes held by the session, as of the last processed packets
+    buffered_plaintext: usize,
+}
+
+#[cfg(feature = "tls")]
+impl TlsStream {
+    fn new(socket: Socket, config: Arc<rustls::ServerConfig>) -> std::io::Result<Self> {
+        let session = rustls::ServerConnection::new(config).map_err(std::io::Error::other)?;
+
+        Ok(TlsStream {
+            socket,
+            session,
+            buffered_plaintext: 0,
+        })
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            // Hand out what was already decrypted before touching the socket
+            match self.session.reader().read(buf) {
+                Ok(n) => {
+                    self.buffered_plaintext = self.buffered_plaintext.saturating_sub(n);
+                    return Ok(n);
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+
+            if self.session.read_tls(&mut self.socket)? == 0 {
+                // The peer closed the socket without a close_notify alert
+                return Ok(0);
+            }
+
+            let state = match self.session.process_new_packets() {
+                Ok(state) => state,
+                Err(e) => {
+                    // Try to let the peer know why the session is being torn down
+                    let _ = self.flush();
+                    return Err(std::io::Error::new(ErrorKind::InvalidData, e));
+                }
+            };
+            self.buffered_plaintext = state.plaintext_bytes_to_read();
+
+            // Answer handshake messages right away, whatever the connection is waiting for
+            match self.flush() {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // The session buffers a bounded amount of plaintext, encrypting it as it goes
+        let n = self.session.writer().write(buf)?;
+
+        match self.flush() {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock && n > 0 => {}
+            Err(e) => return Err(e),
+        }
+
+        if n == 0 {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        Ok(n)
+    }
+
+    /// Writes pending ciphertext to the socket, failing with `WouldBlock` if it can't take it all
+    fn flush(&mut self) -> std::io::Result<()> {
+        while self.session.wants_write() {
+            if self.session.write_tls(&mut self.socket)? == 0 {
+                return Err(ErrorKind::WriteZero.into());
+            }
+        }
+
+        Ok(())
+    }
+}
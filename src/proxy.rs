@@ -0,0 +1,202 @@
+//! PROXY protocol support, for servers behind load balancers such as HAProxy or AWS NLBs that
+//! announce the client they forward each connection for in a header sent before anything else.
+//!
+//! Enabled through `ServerBuilder::proxy_protocol`, after which the addresses of the header replace
+//! the peer and local addresses of `ConnectionInfo`, the whole header being kept in its `proxy`
+//! field.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Signature starting every version 2 header
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest version 1 header, CRLF included
+const V1_MAX_LEN: usize = 107;
+
+/// Type of the TLV carrying the protocol negotiated through ALPN
+pub const TLV_ALPN: u8 = 0x01;
+/// Type of the TLV carrying the host name sent through SNI
+pub const TLV_AUTHORITY: u8 = 0x02;
+/// Type of the TLV carrying an identifier of the connection unique to the proxy
+pub const TLV_UNIQUE_ID: u8 = 0x05;
+/// Type of the TLV describing the TLS session terminated by the proxy
+pub const TLV_SSL: u8 = 0x20;
+/// Type of the TLV carrying AWS specific information, such as the VPC endpoint id
+pub const TLV_AWS: u8 = 0xEA;
+
+/// Whether connections start with a PROXY protocol header
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    /// Connections start with HTTP (or TLS) right away
+    #[default]
+    Disabled,
+    /// Connections may start with a header, which is used when present. Any client reaching the
+    /// listener can then claim to be whoever it wants
+    Optional,
+    /// Connections must start with a valid header, and are closed otherwise
+    Required,
+}
+
+/// A PROXY protocol header, as sent by the proxy before forwarding the connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// Version of the protocol, 1 for the text format and 2 for the binary one
+    pub version: u8,
+    /// Address of the client connected to the proxy, unless the proxy didn't tell, such as for
+    /// its own health checks (`LOCAL`), `UNKNOWN` connections or non-IP families
+    pub source: Option<SocketAddr>,
+    /// Address the client connected to on the proxy, known along with the `source`
+    pub destination: Option<SocketAddr>,
+    /// Type-length-value fields of a version 2 header, in the order they were sent
+    pub tlvs: Vec<(u8, Vec<u8>)>,
+}
+
+impl ProxyHeader {
+    /// Value of the first TLV of type `kind`, see the `TLV_*` constants
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, value)| &value[..])
+    }
+}
+
+pub(crate) enum HeaderStatus {
+    /// The header and its length
+    Complete(ProxyHeader, usize),
+    /// Not enough data yet
+    Partial,
+    /// The connection doesn't start with a header
+    Missing,
+    Invalid,
+}
+
+/// Parses the PROXY protocol header at the start of `input`, in either version
+pub(crate) fn parse_header(input: &[u8]) -> HeaderStatus {
+    if input.starts_with(V2_SIGNATURE) {
+        parse_v2(input)
+    } else if input.starts_with(b"PROXY ") {
+        parse_v1(input)
+    } else if V2_SIGNATURE.starts_with(input) || b"PROXY ".starts_with(input) {
+        HeaderStatus::Partial
+    } else {
+        HeaderStatus::Missing
+    }
+}
+
+/// Parses a text header, such as `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`
+fn parse_v1(input: &[u8]) -> HeaderStatus {
+    let window = &input[..input.len().min(V1_MAX_LEN)];
+    let Some(end) = window.windows(2).position(|w| w == b"\r\n") else {
+        if input.len() < V1_MAX_LEN {
+            return HeaderStatus::Partial;
+        }
+        return HeaderStatus::Invalid;
+    };
+
+    let Ok(line) = std::str::from_utf8(&input[..end]) else {
+        return HeaderStatus::Invalid;
+    };
+    let fields: Vec<&str> = line.split(' ').collect();
+
+    let addresses = match fields[1..] {
+        // Whatever follows is to be ignored
+        ["UNKNOWN", ..] => None,
+        [family @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] => {
+            let parse_ip = |ip: &str| match family {
+                "TCP4" => ip.parse::<Ipv4Addr>().ok().map(IpAddr::V4),
+                _ => ip.parse::<Ipv6Addr>().ok().map(IpAddr::V6),
+            };
+            let addr = |ip, port: &str| Some(SocketAddr::new(parse_ip(ip)?, port.parse().ok()?));
+
+            match (
+                addr(source, source_port),
+                addr(destination, destination_port),
+            ) {
+                (Some(source), Some(destination)) => Some((source, destination)),
+                _ => return HeaderStatus::Invalid,
+            }
+        }
+        _ => return HeaderStatus::Invalid,
+    };
+
+    let header = ProxyHeader {
+        version: 1,
+        source: addresses.map(|(source, _)| source),
+        destination: addresses.map(|(_, destination)| destination),
+        tlvs: Vec::new(),
+    };
+    HeaderStatus::Complete(header, end + 2)
+}
+
+/// Parses a binary header: the signature, the version and command, the address family and
+/// transport, the length of what follows, then the addresses and TLVs
+fn parse_v2(input: &[u8]) -> HeaderStatus {
+    let Some(fixed) = input.get(..16) else {
+        return HeaderStatus::Partial;
+    };
+
+    let (version, command, family) = (fixed[12] >> 4, fixed[12] & 0x0F, fixed[13]);
+    if version != 2 || command > 1 {
+        return HeaderStatus::Invalid;
+    }
+
+    let header_len = 16 + u16::from_be_bytes([fixed[14], fixed[15]]) as usize;
+    let Some(payload) = input.get(16..header_len) else {
+        return HeaderStatus::Partial;
+    };
+
+    // The addresses of a LOCAL connection, opened by the proxy itself, are ignored
+    let addresses_len = match family >> 4 {
+        0x1 => 12,
+        0x2 => 36,
+        0x3 => 216,
+        _ => 0,
+    };
+    let Some(addresses) = payload.get(..addresses_len) else {
+        return HeaderStatus::Invalid;
+    };
+
+    let (source, destination) = match (command, addresses_len) {
+        (1, 12) => {
+            let ip = |at: usize| IpAddr::from(<[u8; 4]>::try_from(&addresses[at..at + 4]).unwrap());
+            let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+            (
+                Some(SocketAddr::new(ip(0), port(8))),
+                Some(SocketAddr::new(ip(4), port(10))),
+            )
+        }
+        (1, 36) => {
+            let ip =
+                |at: usize| IpAddr::from(<[u8; 16]>::try_from(&addresses[at..at + 16]).unwrap());
+            let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+            (
+                Some(SocketAddr::new(ip(0), port(32))),
+                Some(SocketAddr::new(ip(16), port(34))),
+            )
+        }
+        _ => (None, None),
+    };
+
+    let mut tlvs = Vec::new();
+    let mut rest = &payload[addresses_len..];
+    while !rest.is_empty() {
+        let Some(&[kind, len_high, len_low]) = rest.get(..3) else {
+            return HeaderStatus::Invalid;
+        };
+        let len = u16::from_be_bytes([len_high, len_low]) as usize;
+        let Some(value) = rest.get(3..3 + len) else {
+            return HeaderStatus::Invalid;
+        };
+
+        tlvs.push((kind, value.to_vec()));
+        rest = &rest[3 + len..];
+    }
+
+    let header = ProxyHeader {
+        version: 2,
+        source,
+        destination,
+        tlvs,
+    };
+    HeaderStatus::Complete(header, header_len)
+}
@@ -0,0 +1,528 @@
+use crate::handler::Handler;
+use crate::http::{reason_phrase, Params, Request, Response};
+use crate::middleware::{Layered, Middleware, Next};
+use std::sync::Arc;
+
+/// Dispatches requests to handlers by path and method.
+///
+/// Route patterns are split into `/` separated segments, each of them being either:
+/// - a literal, matched as is, such as `users`
+/// - a `:name` parameter, capturing any non-empty segment
+/// - a trailing `*name` wildcard, capturing the rest of the path, slashes included
+///
+/// Literals take precedence over parameters, which take precedence over wildcards, so
+/// `/users/me` wins over `/users/:id` no matter the order routes were added in. Captured values
+/// are exposed through `Request::params`.
+///
+/// When a path matches but the method doesn't, the router answers `405 Method Not Allowed` with
+/// an `Allow` header. `HEAD` requests fall back to the `GET` handler, the connection leaves the
+/// body out, and `OPTIONS` requests are answered with the allowed methods unless a handler was
+/// registered for them.
+///
+/// Routes are meant to be set up once at startup, adding a route that conflicts with an existing
+/// one panics.
+#[derive(Default)]
+pub struct Router {
+    root: Node,
+    layers: Vec<Arc<dyn Middleware>>,
+}
+
+#[derive(Default)]
+struct Node {
+    /// Children matching a literal segment
+    statics: Vec<(String, Node)>,
+    /// Child matching any single segment
+    param: Option<(Arc<str>, Box<Node>)>,
+    /// Routes matching whatever is left of the path
+    wildcard: Option<(Arc<str>, Endpoint)>,
+    /// Routes ending at this node
+    endpoint: Option<Endpoint>,
+}
+
+/// Handlers of a single route pattern, by method
+#[derive(Default)]
+struct Endpoint {
+    methods: Vec<(String, Box<dyn Handler>)>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    /// Routes `method` requests matching `pattern` to `handler`
+    pub fn route(mut self, method: &str, pattern: &str, handler: impl Handler) -> Self {
+        let endpoint = self.root.insert(pattern);
+        endpoint.add(method, Box::new(handler), pattern);
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route("GET", pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route("POST", pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route("PUT", pattern, handler)
+    }
+
+    pub fn patch(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route("PATCH", pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route("DELETE", pattern, handler)
+    }
+
+    /// Mounts every route of `router` under `prefix`, so `/users` nested at `/api` answers
+    /// `/api/users`. The prefix may contain parameters, but no wildcard.
+    ///
+    /// Layers of `router` keep applying to its own routes only, while layers of this router
+    /// apply to the nested routes as well.
+    pub fn nest(mut self, prefix: &str, mut router: Router) -> Self {
+        if !router.layers.is_empty() {
+            let layers = std::mem::take(&mut router.layers);
+            router.root.for_each_endpoint(&mut |endpoint| endpoint.wrap(&layers));
+        }
+
+        let mut node = &mut self.root;
+        for segment in segments(prefix) {
+            node = node.child(segment, prefix);
+        }
+        node.merge(router.root, prefix);
+        self
+    }
+
+    /// Runs `middleware` around every request reaching this router, including the ones answered
+    /// by the router itself such as 404s. Layers run in the order they were added.
+    pub fn layer(mut self, middleware: impl Middleware) -> Self {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+
+    fn lookup<'a>(&self, path: &'a str, params: &mut Params<'a>) -> Option<&Endpoint> {
+        let path = path.strip_prefix('/')?;
+        let path = if path.is_empty() { None } else { Some(path) };
+        self.root.lookup(path, params)
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, req: Request) -> Response {
+        if self.layers.is_empty() {
+            return self.dispatch(req);
+        }
+
+        Next::new(&self.layers, &|req| self.dispatch(req)).run(req)
+    }
+}
+
+impl Router {
+    fn dispatch(&self, mut req: Request) -> Response {
+        let mut params = Params::default();
+        let Some(endpoint) = self.lookup(req.path_only(), &mut params) else {
+            return Response::new(404).with_body(reason_phrase(404));
+        };
+        req.params = params;
+
+        if let Some(handler) = endpoint.get(req.method) {
+            return handler.handle(req);
+        }
+
+        match (req.method, endpoint.get("GET")) {
+            ("HEAD", Some(handler)) => handler.handle(req),
+            ("OPTIONS", _) => Response::new(204).with_header("Allow", endpoint.allow()),
+            _ => Response::new(405)
+                .with_header("Allow", endpoint.allow())
+                .with_body(reason_phrase(405)),
+        }
+    }
+}
+
+impl Node {
+    /// Walks down `pattern`, creating the missing nodes, and returns the endpoint it ends at
+    fn insert(&mut self, pattern: &str) -> &mut Endpoint {
+        let mut node = self;
+        let mut segments = segments(pattern).peekable();
+
+        while let Some(segment) = segments.next() {
+            if let Some(name) = segment.strip_prefix('*') {
+                assert!(
+                    segments.peek().is_none(),
+                    "Wildcard must be the last segment of route {:?}",
+                    pattern
+                );
+                let (current, endpoint) = node
+                    .wildcard
+                    .get_or_insert_with(|| (parameter_name(name, pattern), Endpoint::default()));
+                check_name(current, name, pattern);
+                return endpoint;
+            }
+
+            node = node.child(segment, pattern);
+        }
+
+        node.endpoint.get_or_insert_with(Endpoint::default)
+    }
+
+    /// Child node for a literal or `:name` segment, created if missing
+    fn child(&mut self, segment: &str, pattern: &str) -> &mut Node {
+        if let Some(name) = segment.strip_prefix(':') {
+            let (current, child) = self
+                .param
+                .get_or_insert_with(|| (parameter_name(name, pattern), Box::default()));
+            check_name(current, name, pattern);
+            return child;
+        }
+
+        assert!(
+            !segment.starts_with('*'),
+            "Wildcard not allowed in prefix {:?}",
+            pattern
+        );
+
+        match self.statics.iter().position(|(s, _)| s == segment) {
+            Some(idx) => &mut self.statics[idx].1,
+            None => {
+                self.statics.push((segment.to_owned(), Node::default()));
+                &mut self.statics.last_mut().unwrap().1
+            }
+        }
+    }
+
+    /// Moves every route of `other` into this node
+    fn merge(&mut self, other: Node, prefix: &str) {
+        if let Some(endpoint) = other.endpoint {
+            self.endpoint
+                .get_or_insert_with(Endpoint::default)
+                .merge(endpoint, prefix);
+        }
+
+        for (segment, child) in other.statics {
+            self.child(&segment, prefix).merge(child, prefix);
+        }
+
+        if let Some((name, child)) = other.param {
+            let (current, node) = self
+                .param
+                .get_or_insert_with(|| (name.clone(), Box::default()));
+            check_name(current, &name, prefix);
+            node.merge(*child, prefix);
+        }
+
+        if let Some((name, endpoint)) = other.wildcard {
+            let (current, existing) = self
+                .wildcard
+                .get_or_insert_with(|| (name.clone(), Endpoint::default()));
+            check_name(current, &name, prefix);
+            existing.merge(endpoint, prefix);
+        }
+    }
+
+    fn for_each_endpoint(&mut self, f: &mut impl FnMut(&mut Endpoint)) {
+        if let Some(endpoint) = self.endpoint.as_mut() {
+            f(endpoint);
+        }
+        if let Some((_, endpoint)) = self.wildcard.as_mut() {
+            f(endpoint);
+        }
+        for (_, child) in &mut self.statics {
+            child.for_each_endpoint(f);
+        }
+        if let Some((_, child)) = self.param.as_mut() {
+            child.for_each_endpoint(f);
+        }
+    }
+
+    /// Finds the endpoint matching `path`, which is `None` once every segment was consumed,
+    /// backtracking to less specific routes when a more specific one leads nowhere
+    fn lookup<'a>(&self, path: Option<&'a str>, params: &mut Params<'a>) -> Option<&Endpoint> {
+        let Some(path) = path else {
+            return self
+                .endpoint
+                .as_ref()
+                .or_else(|| self.wildcard_match("", params));
+        };
+
+        let (segment, rest) = match path.split_once('/') {
+            Some((segment, rest)) => (segment, Some(rest)),
+            None => (path, None),
+        };
+
+        if let Some((_, child)) = self.statics.iter().find(|(s, _)| s == segment) {
+            if let Some(endpoint) = child.lookup(rest, params) {
+                return Some(endpoint);
+            }
+        }
+
+        if let Some((name, child)) = &self.param {
+            if !segment.is_empty() {
+                let len = params.len();
+                params.push(name.clone(), segment);
+                if let Some(endpoint) = child.lookup(rest, params) {
+                    return Some(endpoint);
+                }
+                params.truncate(len);
+            }
+        }
+
+        self.wildcard_match(path, params)
+    }
+
+    fn wildcard_match<'a>(&self, rest: &'a str, params: &mut Params<'a>) -> Option<&Endpoint> {
+        let (name, endpoint) = self.wildcard.as_ref()?;
+        params.push(name.clone(), rest);
+        Some(endpoint)
+    }
+}
+
+impl Endpoint {
+    fn add(&mut self, method: &str, handler: Box<dyn Handler>, pattern: &str) {
+        assert!(
+            self.get(method).is_none(),
+            "Duplicate {} route for {:?}",
+            method,
+            pattern
+        );
+        self.methods.push((method.to_owned(), handler));
+    }
+
+    /// Wraps every handler of the endpoint in `layers`
+    fn wrap(&mut self, layers: &[Arc<dyn Middleware>]) {
+        self.methods = std::mem::take(&mut self.methods)
+            .into_iter()
+            .map(|(method, handler)| {
+                let layered = Layered::with_layers(handler, layers.to_vec());
+                (method, Box::new(layered) as Box<dyn Handler>)
+            })
+            .collect();
+    }
+
+    fn merge(&mut self, other: Endpoint, prefix: &str) {
+        for (method, handler) in other.methods {
+            self.add(&method, handler, prefix);
+        }
+    }
+
+    fn get(&self, method: &str) -> Option<&dyn Handler> {
+        self.methods
+            .iter()
+            .find(|(m, _)| m == method)
+            .map(|(_, handler)| &**handler)
+    }
+
+    /// Value of the `Allow` header, including the methods the router answers on its own
+    fn allow(&self) -> String {
+        let mut methods: Vec<&str> = self.methods.iter().map(|(m, _)| m.as_str()).collect();
+        if self.get("GET").is_some() && self.get("HEAD").is_none() {
+            methods.push("HEAD");
+        }
+        if self.get("OPTIONS").is_none() {
+            methods.push("OPTIONS");
+        }
+        methods.join(", ")
+    }
+}
+
+/// Segments of a route pattern, `/` has none while `/users/` ends with an empty one
+fn segments(pattern: &str) -> impl Iterator<Item = &str> {
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+    pattern.split('/').filter(move |_| !pattern.is_empty())
+}
+
+fn parameter_name(name: &str, pattern: &str) -> Arc<str> {
+    assert!(!name.is_empty(), "Unnamed parameter in route {:?}", pattern);
+    Arc::from(name)
+}
+
+/// Two routes sharing a parameter position must name it the same way
+fn check_name(current: &str, name: &str, pattern: &str) {
+    assert!(
+        current == name,
+        "Parameter {:?} of route {:?} conflicts with existing parameter {:?}",
+        name,
+        pattern,
+        current
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{parse_request, ParseStatus};
+
+    /// Answers with `name` followed by the captured parameters
+    fn named(name: &'static str) -> impl Handler {
+        move |req: Request| {
+            let mut body = name.to_owned();
+            for (param, value) in req.params.iter() {
+                body += &format!(" {}={}", param, value);
+            }
+            Response::new(200).with_body(body)
+        }
+    }
+
+    /// Status, `Allow` header and body of the answer of `router` to a `method` request for `path`
+    fn call(router: &Router, method: &str, path: &str) -> (u16, Option<String>, String) {
+        let raw = format!("{} {} HTTP/1.1\r\nHost: x\r\n\r\n", method, path);
+        let mut headers = [httparse::EMPTY_HEADER; 4];
+        let ParseStatus::Complete(req, _) = parse_request(raw.as_bytes(), &mut headers) else {
+            panic!("invalid request {:?}", raw);
+        };
+
+        let mut resp = router.handle(req);
+        let mut body = Vec::new();
+        while let Some(chunk) = resp.body.next_chunk(usize::MAX).unwrap() {
+            body.extend_from_slice(&chunk);
+        }
+        let allow = resp.get_header("Allow").map(str::to_owned);
+        (resp.status, allow, String::from_utf8(body).unwrap())
+    }
+
+    fn body(router: &Router, path: &str) -> String {
+        let (status, _, body) = call(router, "GET", path);
+        assert_eq!(status, 200, "GET {}", path);
+        body
+    }
+
+    #[test]
+    fn prefers_literals_to_parameters_to_wildcards() {
+        // Added from the least to the most specific, order doesn't matter
+        let router = Router::new()
+            .get("/users/*rest", named("wildcard"))
+            .get("/users/:id", named("param"))
+            .get("/users/me", named("static"))
+            .get("/", named("root"));
+
+        assert_eq!(body(&router, "/"), "root");
+        assert_eq!(body(&router, "/users/me"), "static");
+        assert_eq!(body(&router, "/users/42"), "param id=42");
+        assert_eq!(body(&router, "/users/42?full=1"), "param id=42");
+        assert_eq!(body(&router, "/users/42/posts"), "wildcard rest=42/posts");
+        // Wildcards match empty rests too
+        assert_eq!(body(&router, "/users/"), "wildcard rest=");
+        assert_eq!(body(&router, "/users"), "wildcard rest=");
+        assert_eq!(call(&router, "GET", "/other").0, 404);
+    }
+
+    #[test]
+    fn backtracks_to_less_specific_routes() {
+        let router = Router::new()
+            .get("/files/special/info", named("static"))
+            .get("/files/:name/meta", named("param"))
+            .get("/files/*path", named("wildcard"));
+
+        assert_eq!(body(&router, "/files/special/info"), "static");
+        // The literal branch leads nowhere, the parameter one does
+        assert_eq!(body(&router, "/files/special/meta"), "param name=special");
+        // Neither does, and the parameter captured along the way is dropped
+        assert_eq!(
+            body(&router, "/files/special/other"),
+            "wildcard path=special/other"
+        );
+        assert_eq!(body(&router, "/files/a/meta/b"), "wildcard path=a/meta/b");
+    }
+
+    #[test]
+    fn captures_every_parameter() {
+        let router = Router::new().get("/orgs/:org/repos/:repo/*file", named("file"));
+
+        assert_eq!(
+            body(&router, "/orgs/rust/repos/ducta/src/lib.rs"),
+            "file org=rust repo=ducta file=src/lib.rs"
+        );
+        // Parameters never match empty segments
+        assert_eq!(call(&router, "GET", "/orgs//repos/ducta/x").0, 404);
+    }
+
+    #[test]
+    fn answers_other_methods_with_method_not_allowed() {
+        let router = Router::new()
+            .get("/items", named("list"))
+            .post("/items", named("create"))
+            .delete("/items/:id", named("delete"));
+
+        assert_eq!(call(&router, "POST", "/items").2, "create");
+        assert_eq!(
+            call(&router, "PUT", "/items"),
+            (
+                405,
+                Some("GET, POST, HEAD, OPTIONS".to_owned()),
+                "Method Not Allowed".to_owned()
+            )
+        );
+        assert_eq!(
+            call(&router, "GET", "/items/1"),
+            (
+                405,
+                Some("DELETE, OPTIONS".to_owned()),
+                "Method Not Allowed".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn answers_head_and_options_requests() {
+        let router = Router::new()
+            .get("/items", named("list"))
+            .route("OPTIONS", "/custom", named("options"))
+            .post("/custom", named("create"));
+
+        // Sent without its body by the connection
+        assert_eq!(
+            call(&router, "HEAD", "/items"),
+            (200, None, "list".to_owned())
+        );
+        assert_eq!(
+            call(&router, "OPTIONS", "/items"),
+            (204, Some("GET, HEAD, OPTIONS".to_owned()), String::new())
+        );
+        assert_eq!(call(&router, "OPTIONS", "/custom").2, "options");
+        assert_eq!(
+            call(&router, "HEAD", "/custom").1.as_deref(),
+            Some("OPTIONS, POST")
+        );
+    }
+
+    #[test]
+    fn mounts_nested_routers_under_their_prefix() {
+        let users = Router::new()
+            .get("/", named("users"))
+            .get("/:id", named("user"));
+        let router = Router::new()
+            .get("/api/health", named("health"))
+            .nest("/api/:version/users", users);
+
+        assert_eq!(body(&router, "/api/health"), "health");
+        assert_eq!(body(&router, "/api/v1/users"), "users version=v1");
+        assert_eq!(body(&router, "/api/v1/users/7"), "user version=v1 id=7");
+        assert_eq!(call(&router, "GET", "/users/7").0, 404);
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate GET route")]
+    fn refuses_duplicate_routes() {
+        let _ = Router::new()
+            .get("/items/:id", named("first"))
+            .get("/items/:id", named("second"));
+    }
+
+    #[test]
+    #[should_panic(expected = "conflicts with existing parameter")]
+    fn refuses_differently_named_parameters() {
+        let _ = Router::new()
+            .get("/items/:id", named("first"))
+            .get("/items/:name/meta", named("second"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate GET route")]
+    fn refuses_nested_routes_conflicting_with_existing_ones() {
+        let _ = Router::new()
+            .get("/api/items", named("first"))
+            .nest("/api", Router::new().get("/items", named("second")));
+    }
+}
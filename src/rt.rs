@@ -0,0 +1,408 @@
+//! Minimal runtime support for `AsyncHandler`s: timers, TCP connections, and blocking work run
+//! off the event loop.
+//!
+//! Futures are polled by the connection they answer, on its event loop, so there's no task
+//! spawning here, only leaf futures waking the connection up once they're ready. They all rely on
+//! the worker polling them: sleeps are kept in its timer wheel, the readiness of `TcpStream`s is
+//! reported along with its connections, and blocking work runs on the handler threads of its
+//! server.
+//!
+//! Regular files are always ready as far as readiness goes, so file access goes through
+//! `blocking`, as does anything else without a non-blocking interface.
+
+mod reactor;
+mod tcp;
+mod timer;
+
+pub use self::tcp::TcpStream;
+
+use self::reactor::Reactor;
+use self::timer::{Registration, Sleeps};
+use crate::handler::HandlerPool;
+use crate::io::TimerWheel;
+use std::cell::RefCell;
+use std::future::Future;
+use std::io::{Error, ErrorKind};
+use std::os::fd::RawFd;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// Runtime of the worker running on this thread, see `enter`
+    static RUNTIME: RefCell<Option<Runtime>> = const { RefCell::new(None) };
+}
+
+/// Source of the ids telling runtimes apart
+static NEXT_RUNTIME_ID: AtomicU64 = AtomicU64::new(0);
+
+struct Runtime {
+    /// Tells the sockets registered with this runtime apart from those of other workers
+    id: u64,
+    sleeps: Sleeps,
+    reactor: Reactor,
+    /// Runs the work of `blocking`, if the server has handler threads
+    handler_pool: Option<Arc<HandlerPool>>,
+}
+
+/// Makes the runtime of a worker available to the futures it polls, until dropped
+pub(crate) struct Entered {
+    io_fd: RawFd,
+}
+
+/// Sets the runtime of the worker about to run on this thread up
+pub(crate) fn enter(handler_pool: Option<Arc<HandlerPool>>) -> std::io::Result<Entered> {
+    let reactor = Reactor::new()?;
+    let io_fd = reactor.fd();
+
+    RUNTIME.with(|runtime| {
+        *runtime.borrow_mut() = Some(Runtime {
+            id: NEXT_RUNTIME_ID.fetch_add(1, Ordering::Relaxed),
+            sleeps: Sleeps::default(),
+            reactor,
+            handler_pool,
+        });
+    });
+    Ok(Entered { io_fd })
+}
+
+impl Entered {
+    /// File descriptor the worker watches for readability, calling `dispatch_io` once it is
+    pub(crate) fn io_fd(&self) -> RawFd {
+        self.io_fd
+    }
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        // Dropped outside of the borrow, in case anything dropped with it uses the runtime
+        let runtime = RUNTIME.try_with(|runtime| runtime.borrow_mut().take());
+        drop(runtime);
+    }
+}
+
+/// Runs `f` with the runtime of the worker running on this thread, if any
+fn with_runtime<R>(f: impl FnOnce(&mut Runtime) -> R) -> Option<R> {
+    RUNTIME
+        .try_with(|runtime| runtime.borrow_mut().as_mut().map(f))
+        .ok()
+        .flatten()
+}
+
+/// Schedules the sleeps registered since the last call in the timer wheel of the worker
+pub(crate) fn schedule_sleeps(timer_wheel: &mut TimerWheel) {
+    with_runtime(|runtime| runtime.sleeps.schedule(timer_wheel));
+}
+
+/// Wakes the sleep the expired timer `id` of `key` was scheduled for, if it was one. Returns
+/// whether it was, the timer belonging to a connection otherwise.
+pub(crate) fn expire_sleep(key: usize, id: u64) -> bool {
+    // Woken outside of the borrow, wakers may do anything
+    let waker = with_runtime(|runtime| runtime.sleeps.expire(key, id)).flatten();
+    waker.map(Waker::wake).is_some()
+}
+
+/// Wakes the futures waiting for the sockets that became ready
+pub(crate) fn dispatch_io() -> std::io::Result<()> {
+    // Woken outside of the borrow, wakers may do anything
+    let mut wakers = Vec::new();
+    with_runtime(|runtime| runtime.reactor.dispatch(&mut wakers)).unwrap_or(Ok(()))?;
+    wakers.into_iter().for_each(Waker::wake);
+    Ok(())
+}
+
+/// Completes once `duration` elapsed
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Completes once `deadline` is reached
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        registration: None,
+    }
+}
+
+/// Future returned by `sleep` and `sleep_until`.
+///
+/// Deadlines are kept in the timer wheel of the worker polling the future, so they are rounded up
+/// to its resolution of 100ms. Polling the future anywhere else panics.
+pub struct Sleep {
+    deadline: Instant,
+    /// Entry in the sleeps of the worker, once polled
+    registration: Option<Registration>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        let deadline = self.deadline;
+        let registered = with_runtime(|runtime| match &self.registration {
+            Some(registration) => {
+                runtime.sleeps.update(registration, cx.waker());
+                None
+            }
+            None => Some(runtime.sleeps.register(deadline, cx.waker().clone())),
+        });
+
+        match registered {
+            Some(Some(registration)) => self.registration = Some(registration),
+            Some(None) => {}
+            None => panic!("rt::sleep polled outside of a ducta worker"),
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(registration) = self.registration.take() {
+            with_runtime(|runtime| runtime.sleeps.remove(&registration));
+        }
+    }
+}
+
+/// Runs `f` on one of the handler threads of the server, see `ServerBuilder::handler_threads`,
+/// completing with its result. For blocking work such as file or database access, which would
+/// otherwise stall the event loop. A panic in `f` is resumed by the future.
+///
+/// The work is queued once the future is first polled. It fails if the server has no handler
+/// threads, and with `ErrorKind::WouldBlock` if their queue is full, as requests then get a 503.
+pub fn blocking<T, F>(f: F) -> Blocking<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(BlockingState {
+        result: None,
+        waker: None,
+    }));
+
+    let state = shared.clone();
+    let work = Box::new(move || {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+
+        let mut state = state.lock().unwrap();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            drop(state);
+            waker.wake();
+        }
+    });
+
+    Blocking {
+        shared,
+        work: Some(work),
+    }
+}
+
+/// Future returned by `blocking`
+pub struct Blocking<T> {
+    shared: Arc<Mutex<BlockingState<T>>>,
+    /// Handed to the handler threads on the first poll
+    work: Option<Box<dyn FnOnce() + Send>>,
+}
+
+struct BlockingState<T> {
+    result: Option<std::thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = std::io::Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<T>> {
+        if let Some(work) = self.work.take() {
+            self.shared.lock().unwrap().waker = Some(cx.waker().clone());
+            if let Err(e) = queue_blocking(work) {
+                return Poll::Ready(Err(e));
+            }
+        }
+
+        let mut state = self.shared.lock().unwrap();
+        match state.result.take() {
+            Some(Ok(value)) => Poll::Ready(Ok(value)),
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Hands `work` to the handler threads of the worker running on this thread
+fn queue_blocking(work: Box<dyn FnOnce() + Send>) -> std::io::Result<()> {
+    let queued = with_runtime(|runtime| {
+        let pool = runtime.handler_pool.as_ref()?;
+        Some(pool.run_blocking(work))
+    });
+
+    match queued {
+        Some(Some(true)) => Ok(()),
+        Some(Some(false)) => Err(Error::new(
+            ErrorKind::WouldBlock,
+            "Handler thread queue is full",
+        )),
+        Some(None) => Err(Error::new(
+            ErrorKind::Unsupported,
+            "rt::blocking needs handler threads, see ServerBuilder::handler_threads",
+        )),
+        None => Err(Error::new(
+            ErrorKind::Unsupported,
+            "rt::blocking polled outside of a ducta worker",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+        Pin::new(future).poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    fn sleeps() -> usize {
+        with_runtime(|runtime| runtime.sleeps.len()).unwrap()
+    }
+
+    #[test]
+    fn releases_the_entries_of_dropped_sleeps() {
+        let _runtime = enter(None).unwrap();
+
+        let mut sleep = sleep(Duration::from_secs(3600));
+        assert!(poll(&mut sleep).is_pending());
+        assert!(poll(&mut sleep).is_pending());
+        assert_eq!(sleeps(), 1);
+
+        drop(sleep);
+        assert_eq!(sleeps(), 0);
+    }
+
+    #[test]
+    fn completes_sleeps_through_the_timer_wheel() {
+        let _runtime = enter(None).unwrap();
+        let mut timer_wheel = TimerWheel::new(16, Duration::from_millis(10));
+
+        let mut sleep = sleep(Duration::from_millis(20));
+        assert!(poll(&mut sleep).is_pending());
+        schedule_sleeps(&mut timer_wheel);
+
+        let mut expired = Vec::new();
+        while expired.is_empty() {
+            std::thread::sleep(Duration::from_millis(10));
+            timer_wheel.expire(Instant::now(), &mut expired);
+        }
+        let [(key, id)] = expired[..] else {
+            panic!("{:?}", expired);
+        };
+        assert!(expire_sleep(key, id));
+        // Fired once only
+        assert!(!expire_sleep(key, id));
+        assert_eq!(poll(&mut sleep), Poll::Ready(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of a ducta worker")]
+    fn panics_when_sleeping_outside_of_a_worker() {
+        let _ = poll(&mut sleep(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn refuses_blocking_work_without_handler_threads() {
+        let error = match poll(&mut blocking(|| ())) {
+            Poll::Ready(result) => result.unwrap_err(),
+            Poll::Pending => panic!("blocking work was queued"),
+        };
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+        let _runtime = enter(None).unwrap();
+        let error = match poll(&mut blocking(|| ())) {
+            Poll::Ready(result) => result.unwrap_err(),
+            Poll::Pending => panic!("blocking work was queued"),
+        };
+        assert!(error.to_string().contains("handler_threads"), "{}", error);
+    }
+
+    /// Polls `future` until ready, dispatching the readiness of sockets in between like a worker
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let Poll::Ready(output) = poll(&mut future) {
+                return output;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+            dispatch_io().unwrap();
+        }
+    }
+
+    fn sockets() -> usize {
+        with_runtime(|runtime| runtime.reactor.len()).unwrap()
+    }
+
+    #[test]
+    fn exchanges_data_over_tcp_streams() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = std::thread::spawn(move || {
+            use std::io::{Read, Write};
+
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut buf = [0; 4];
+            socket.read_exact(&mut buf).unwrap();
+            // Leaves the reader waiting for a while
+            std::thread::sleep(Duration::from_millis(50));
+            socket
+                .write_all(&buf.map(|b| b.to_ascii_uppercase()))
+                .unwrap();
+        });
+
+        let _runtime = enter(None).unwrap();
+        let mut stream = block_on(TcpStream::connect(addr)).unwrap();
+        assert_eq!(sockets(), 1);
+
+        block_on(stream.write_all(b"ping")).unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0; 16];
+        loop {
+            match block_on(stream.read(&mut buf)).unwrap() {
+                0 => break,
+                n => received.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(received, b"PING");
+        peer.join().unwrap();
+
+        drop(stream);
+        assert_eq!(sockets(), 0);
+    }
+
+    #[test]
+    fn refuses_tcp_streams_outside_of_their_worker() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let error = block_on(TcpStream::connect(addr)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+        let runtime = enter(None).unwrap();
+        let mut stream = block_on(TcpStream::connect(addr)).unwrap();
+        drop(runtime);
+
+        let _other = enter(None).unwrap();
+        let error = block_on(stream.read(&mut [0; 16])).unwrap_err();
+        assert!(error.to_string().contains("another worker"), "{}", error);
+        // Left to the reactor it was registered with
+        drop(stream);
+        assert_eq!(sockets(), 0);
+    }
+}
@@ -0,0 +1,135 @@
+use mio::event::Source;
+use mio::{Events, Interest, Poll, Token};
+use slab::Slab;
+use std::os::fd::{AsRawFd, RawFd};
+use std::task::Waker;
+use std::time::Duration;
+
+/// Readiness of the sockets used by the futures of a worker, reported by a poll of its own. The
+/// worker watches that poll along with its connections, and has it dispatched once it's ready.
+///
+/// Sockets are registered edge triggered, so each direction is assumed ready until an operation
+/// would block, and waited for from then on.
+pub(super) struct Reactor {
+    poll: Poll,
+    events: Events,
+    sources: Slab<Readiness>,
+}
+
+struct Readiness {
+    readable: bool,
+    writable: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+#[derive(Clone, Copy)]
+pub(super) enum Direction {
+    Read,
+    Write,
+}
+
+impl Reactor {
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Reactor {
+            poll: Poll::new()?,
+            events: Events::with_capacity(256),
+            sources: Slab::new(),
+        })
+    }
+
+    /// The poll's file descriptor, readable once sockets are ready
+    pub fn fd(&self) -> RawFd {
+        self.poll.as_raw_fd()
+    }
+
+    /// Starts reporting the readiness of `source`, returning its key
+    pub fn register(&mut self, source: &mut impl Source) -> std::io::Result<usize> {
+        let entry = self.sources.vacant_entry();
+        let key = entry.key();
+        self.poll.registry().register(
+            source,
+            Token(key),
+            Interest::READABLE | Interest::WRITABLE,
+        )?;
+
+        entry.insert(Readiness {
+            readable: true,
+            writable: true,
+            read_waker: None,
+            write_waker: None,
+        });
+        Ok(key)
+    }
+
+    pub fn deregister(&mut self, key: usize, source: &mut impl Source) {
+        let _ = self.poll.registry().deregister(source);
+        self.sources.try_remove(key);
+    }
+
+    /// Whether the source may be ready in `direction`, `waker` being woken once it is otherwise
+    pub fn poll_ready(&mut self, key: usize, direction: Direction, waker: &Waker) -> bool {
+        let Some(source) = self.sources.get_mut(key) else {
+            return true;
+        };
+
+        let (ready, current) = match direction {
+            Direction::Read => (source.readable, &mut source.read_waker),
+            Direction::Write => (source.writable, &mut source.write_waker),
+        };
+        if !ready {
+            match current {
+                Some(current) => current.clone_from(waker),
+                None => *current = Some(waker.clone()),
+            }
+        }
+        ready
+    }
+
+    /// An operation in `direction` would block, the source is waited for until reported ready
+    pub fn clear_ready(&mut self, key: usize, direction: Direction) {
+        if let Some(source) = self.sources.get_mut(key) {
+            match direction {
+                Direction::Read => source.readable = false,
+                Direction::Write => source.writable = false,
+            }
+        }
+    }
+
+    /// Marks the sources reported since the last call ready, adding the wakers of the futures
+    /// waiting for them to `wakers`
+    pub fn dispatch(&mut self, wakers: &mut Vec<Waker>) -> std::io::Result<()> {
+        // The worker is only told again about sources that become ready, so drain every event
+        loop {
+            match self.poll.poll(&mut self.events, Some(Duration::ZERO)) {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            if self.events.is_empty() {
+                return Ok(());
+            }
+
+            for event in self.events.iter() {
+                let Some(source) = self.sources.get_mut(event.token().0) else {
+                    continue;
+                };
+
+                // Errors and hang ups are reported by the next operation
+                if event.is_readable() || event.is_read_closed() || event.is_error() {
+                    source.readable = true;
+                    wakers.extend(source.read_waker.take());
+                }
+                if event.is_writable() || event.is_write_closed() || event.is_error() {
+                    source.writable = true;
+                    wakers.extend(source.write_waker.take());
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+}
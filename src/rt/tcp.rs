@@ -0,0 +1,159 @@
+use super::reactor::Direction;
+use super::with_runtime;
+use std::future::poll_fn;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::task::{Context, Poll};
+
+/// A TCP connection whose reads and writes are awaited, for handlers talking to other services,
+/// such as upstream servers or databases, without holding a thread.
+///
+/// Its readiness is reported by the worker polling the future using it, so it's only usable from
+/// futures polled by the worker it was created on. Anywhere else, its operations fail with
+/// `ErrorKind::Unsupported`.
+pub struct TcpStream {
+    io: mio::net::TcpStream,
+    /// Key of the socket in the reactor of its runtime
+    key: usize,
+    /// Runtime whose reactor the socket is registered with
+    runtime: u64,
+}
+
+impl TcpStream {
+    /// Opens a connection to `addr`
+    pub async fn connect(addr: SocketAddr) -> std::io::Result<TcpStream> {
+        let mut io = mio::net::TcpStream::connect(addr)?;
+        let (key, runtime) = with_runtime(|runtime| {
+            let key = runtime.reactor.register(&mut io)?;
+            Ok((key, runtime.id))
+        })
+        .unwrap_or_else(|| Err(outside_of_a_worker()))?;
+        let stream = TcpStream { io, key, runtime };
+
+        // The connection is established or failed once the socket is writable
+        loop {
+            poll_fn(|cx| stream.poll_ready(cx, Direction::Write)).await?;
+            if let Some(e) = stream.io.take_error()? {
+                return Err(e);
+            }
+
+            match stream.io.peer_addr() {
+                Ok(_) => return Ok(stream),
+                Err(e) if e.kind() == ErrorKind::NotConnected => {
+                    stream.clear_ready(Direction::Write)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reads into `buf` once data is available, returning how much was read, or 0 once the peer
+    /// shut its side of the connection down
+    pub async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        poll_fn(|cx| self.poll_io(cx, Direction::Read, |io| io.read(buf))).await
+    }
+
+    /// Writes from `buf` once the socket has room, returning how much was written
+    pub async fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        poll_fn(|cx| self.poll_io(cx, Direction::Write, |io| io.write(buf))).await
+    }
+
+    /// Writes the whole of `buf`
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf).await? {
+                0 => return Err(ErrorKind::WriteZero.into()),
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        self.io.shutdown(how)
+    }
+
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.io.peer_addr()
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.io.local_addr()
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> std::io::Result<()> {
+        self.io.set_nodelay(nodelay)
+    }
+
+    /// Runs `op` until it doesn't block, waiting for the socket to be ready in between
+    fn poll_io<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        direction: Direction,
+        mut op: impl FnMut(&mut mio::net::TcpStream) -> std::io::Result<T>,
+    ) -> Poll<std::io::Result<T>> {
+        loop {
+            if let Err(e) = std::task::ready!(self.poll_ready(cx, direction)) {
+                return Poll::Ready(Err(e));
+            }
+
+            match op(&mut self.io) {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    if let Err(e) = self.clear_ready(direction) {
+                        return Poll::Ready(Err(e));
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    fn poll_ready(&self, cx: &mut Context<'_>, direction: Direction) -> Poll<std::io::Result<()>> {
+        match self.with_reactor(|reactor| reactor.poll_ready(self.key, direction, cx.waker())) {
+            Ok(true) => Poll::Ready(Ok(())),
+            Ok(false) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+
+    fn clear_ready(&self, direction: Direction) -> std::io::Result<()> {
+        self.with_reactor(|reactor| reactor.clear_ready(self.key, direction))
+    }
+
+    /// Runs `f` with the reactor the socket is registered with, if it belongs to this thread
+    fn with_reactor<R>(
+        &self,
+        f: impl FnOnce(&mut super::reactor::Reactor) -> R,
+    ) -> std::io::Result<R> {
+        match with_runtime(|runtime| (runtime.id == self.runtime).then(|| f(&mut runtime.reactor)))
+        {
+            Some(Some(result)) => Ok(result),
+            Some(None) => Err(Error::new(
+                ErrorKind::Unsupported,
+                "rt::TcpStream used on another worker than the one it was created on",
+            )),
+            None => Err(outside_of_a_worker()),
+        }
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        // Closing the socket unregisters it anyway, only its entry is released here
+        let (key, id) = (self.key, self.runtime);
+        let io = &mut self.io;
+        with_runtime(|runtime| {
+            if runtime.id == id {
+                runtime.reactor.deregister(key, io);
+            }
+        });
+    }
+}
+
+fn outside_of_a_worker() -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        "rt::TcpStream used outside of a ducta worker",
+    )
+}
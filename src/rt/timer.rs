@@ -0,0 +1,106 @@
+use crate::io::TimerWheel;
+use slab::Slab;
+use std::task::Waker;
+use std::time::Instant;
+
+/// Deadlines waited for by the `Sleep` futures of a worker, scheduled in its timer wheel.
+///
+/// The wheel can't cancel timers, so a sleep dropped before its deadline only releases its entry,
+/// and its timer is ignored once it expires, like the stale timers of connections.
+#[derive(Default)]
+pub(super) struct Sleeps {
+    entries: Slab<Entry>,
+    /// Entries not scheduled in the timer wheel yet
+    unscheduled: Vec<usize>,
+    next_serial: u64,
+}
+
+struct Entry {
+    /// Tells the registration apart from the ones that reused its key
+    serial: u64,
+    deadline: Instant,
+    /// Taken once the timer expired
+    waker: Option<Waker>,
+    /// Id of the timer scheduled in the wheel, if it was
+    timer: Option<u64>,
+}
+
+/// Entry of a `Sleep`, released through `Sleeps::remove`
+pub(super) struct Registration {
+    key: usize,
+    serial: u64,
+}
+
+impl Sleeps {
+    /// Wakes `waker` up once `deadline` is reached, after the next call to `schedule`
+    pub fn register(&mut self, deadline: Instant, waker: Waker) -> Registration {
+        let serial = self.next_serial;
+        self.next_serial += 1;
+
+        let key = self.entries.insert(Entry {
+            serial,
+            deadline,
+            waker: Some(waker),
+            timer: None,
+        });
+        self.unscheduled.push(key);
+        Registration { key, serial }
+    }
+
+    /// Replaces the waker of a sleep polled again
+    pub fn update(&mut self, registration: &Registration, waker: &Waker) {
+        let Some(entry) = self.entry(registration) else {
+            return;
+        };
+
+        match &mut entry.waker {
+            Some(current) => current.clone_from(waker),
+            None => {
+                // Polled again after its timer expired, which rounding up the deadline should
+                // prevent, wait for another one
+                entry.waker = Some(waker.clone());
+                entry.timer = None;
+                self.unscheduled.push(registration.key);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, registration: &Registration) {
+        if self.entry(registration).is_some() {
+            self.entries.remove(registration.key);
+        }
+    }
+
+    /// Schedules the sleeps registered since the last call in `timer_wheel`
+    pub fn schedule(&mut self, timer_wheel: &mut TimerWheel) {
+        for key in self.unscheduled.drain(..) {
+            // Removed in the meantime, possibly reused by a sleep still in the list
+            let Some(entry) = self.entries.get_mut(key) else {
+                continue;
+            };
+            if entry.timer.is_none() {
+                entry.timer = Some(timer_wheel.schedule(key, entry.deadline));
+            }
+        }
+    }
+
+    /// The waker of the sleep timer `id` was scheduled for, if it's still waited for
+    pub fn expire(&mut self, key: usize, id: u64) -> Option<Waker> {
+        let entry = self.entries.get_mut(key)?;
+        if entry.timer != Some(id) {
+            return None;
+        }
+        entry.waker.take()
+    }
+
+    fn entry(&mut self, registration: &Registration) -> Option<&mut Entry> {
+        self.entries
+            .get_mut(registration.key)
+            .filter(|entry| entry.serial == registration.serial)
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
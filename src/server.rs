@@ -1,145 +1,208 @@
-use crate::handler::Handler;
-use crate::io::{BufferPool, BUFFER_STANDARD_SIZE};
-use crate::net::{Connection, ConnectionState};
-use mio::net::TcpListener;
-use mio::{Events, Interest, Poll, Token, Waker};
-use slab::Slab;
-use std::sync::atomic::{AtomicBool, Ordering};
+mod builder;
+mod shutdown;
+#[cfg(feature = "tls")]
+mod tls;
+mod unix;
+
+pub use self::builder::ServerBuilder;
+pub use self::shutdown::ShutdownHandle;
+#[cfg(feature = "tls")]
+pub use self::tls::TlsConfig;
+
+pub(crate) use self::builder::ServerConfig;
+
+use self::unix::{SocketFile, UnixSocketOptions};
+use crate::handler::{Handler, HandlerPool};
+use crate::net::backend::{Backend, Worker};
+use crate::net::Listener;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-
-const LISTENER_TOKEN: Token = Token(0);
-const WAKER_TOKEN: Token = Token(1);
-const SLAB_OFFSET: usize = 2;
+use std::thread::JoinHandle;
 
 pub struct Server<H: Handler> {
-    poll: Poll,
-    events: Events,
-    listener: TcpListener,
-    connections: Slab<Connection>,
-    buffer_pool: BufferPool,
-    handler: H,
+    listener: Listener,
+    local_addr: SocketAddr,
+    unix_addr: Option<std::os::unix::net::SocketAddr>,
+    /// Socket file of a Unix domain socket listener, removed along with the server
+    _socket_file: Option<SocketFile>,
+    config: ServerConfig,
+    handler: Arc<H>,
+    shutdown: ShutdownHandle,
 }
 
 impl<H: Handler> Server<H> {
+    /// Creates a server with the default configuration, see `ServerBuilder` to customize it
     pub fn new(addr: &str, handler: H) -> std::io::Result<Self> {
-        let poll = Poll::new()?;
-        let events = Events::with_capacity(1024);
-        let mut listener = TcpListener::bind(addr.parse().unwrap())?;
+        ServerBuilder::new(addr).build(handler)
+    }
+
+    /// Binds the server to `addr`, see `ServerBuilder::new` for its format
+    fn bind(
+        addr: &str,
+        unix_options: &UnixSocketOptions,
+        config: ServerConfig,
+        handler: H,
+    ) -> std::io::Result<Self> {
+        let (listener, unix_addr, socket_file) = match addr.strip_prefix("unix:") {
+            Some(path) => {
+                let (listener, socket_file) = unix::bind(path, unix_options)?;
+                let unix_addr = listener.local_addr()?;
+                (Listener::Unix(listener), Some(unix_addr), socket_file)
+            }
+            None => {
+                let addr: SocketAddr = addr.parse().map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Invalid address {:?}: {}", addr, e),
+                    )
+                })?;
+                (Listener::Tcp(bind_listener(addr)?), None, None)
+            }
+        };
 
-        // Register the listener to know when new clients connect
-        poll.registry()
-            .register(&mut listener, LISTENER_TOKEN, Interest::READABLE)?;
+        let local_addr = match &listener {
+            Listener::Tcp(listener) => listener.local_addr()?,
+            Listener::Unix(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        };
 
         Ok(Self {
-            poll,
-            events,
             listener,
-            connections: Slab::with_capacity(1024),
-            buffer_pool: BufferPool::new(1024, BUFFER_STANDARD_SIZE),
-            handler,
+            local_addr,
+            unix_addr,
+            _socket_file: socket_file,
+            config,
+            handler: Arc::new(handler),
+            shutdown: ShutdownHandle::new(),
         })
     }
 
+    /// The address the server is listening on, unspecified for Unix domain sockets
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The address of the Unix domain socket the server is listening on, if it is
+    pub fn unix_addr(&self) -> Option<&std::os::unix::net::SocketAddr> {
+        self.unix_addr.as_ref()
+    }
+
+    /// A handle to shut the server down from other threads, which can be taken before running it
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Runs the server until it's shut down through a `ShutdownHandle`, or by SIGINT and SIGTERM
+    /// when enabled with `ServerBuilder::shutdown_on_signals`.
+    ///
+    /// Each worker runs its own event loop with its own backend, connections and buffer pool, and
+    /// binds its own listener to the same address through `SO_REUSEPORT`, leaving it to the kernel
+    /// to balance new connections between them. The first worker runs on the calling thread, the
+    /// remaining ones on spawned threads which are all stopped and joined before returning.
     pub fn run(&mut self) -> std::io::Result<()> {
-        // Stop flag and Waker to gracefully kill application
-        let should_stop = Arc::new(AtomicBool::new(false));
-        let r = should_stop.clone();
-        let waker = Arc::new(Waker::new(self.poll.registry(), WAKER_TOKEN)?);
-        let waker_for_signal = waker.clone();
-
-        // set signal handler to Ctrl-C
-        ctrlc::set_handler(move || {
-            eprintln!("\nShutdown signal received...");
-            r.store(true, Ordering::SeqCst);
-            waker_for_signal.wake().expect("Failed to wake event loop");
-        })
-        .expect("Error setting Ctrl-C handler");
-
-        let mut to_remove: Vec<Token> = Vec::new();
-
-        loop {
-            to_remove.clear();
-
-            self.poll.poll(&mut self.events, None)?;
-
-            for event in self.events.iter() {
-                match event.token() {
-                    LISTENER_TOKEN => {
-                        // Accept as many connections as possible
-                        loop {
-                            match self.listener.accept() {
-                                Ok((mut stream, _addr)) => {
-                                    let entry = self.connections.vacant_entry();
-                                    let token = Token(entry.key() + SLAB_OFFSET);
-
-                                    if let Err(e) = self.poll.registry().register(
-                                        &mut stream,
-                                        token,
-                                        Interest::READABLE,
-                                    ) {
-                                        eprintln!("Failed to register connection: {}", e);
-                                        continue;
-                                    }
-
-                                    entry.insert(Connection::new(
-                                        stream,
-                                        self.buffer_pool.checkout(),
-                                        self.buffer_pool.checkout(),
-                                    ));
-                                }
-                                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                                    // No more connections to accept right now
-                                    break;
-                                }
-                                Err(e) => {
-                                    eprintln!("Accept error: {}", e);
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                    WAKER_TOKEN => {
-                        if should_stop.load(Ordering::SeqCst) {
-                            eprintln!("Graceful shutdown starting...");
-                            return Ok(());
-                        }
-                    }
-                    token => {
-                        let conn_idx = usize::from(token) - SLAB_OFFSET;
-                        if let Some(conn) = self.connections.get_mut(conn_idx) {
-                            if let Some(new_interest) = conn.process(event, &self.handler) {
-                                self.poll.registry().reregister(
-                                    conn.socket(),
-                                    token,
-                                    new_interest,
-                                )?;
-                            }
-
-                            if *conn.state() == ConnectionState::Closed {
-                                to_remove.push(token);
-                            }
-                        }
-                    }
-                }
-            }
+        // Bind every listener before spawning anything, so a failure leaves nothing running
+        let mut listeners = vec![self.listener.try_clone()?];
+        for _ in 1..self.config.workers {
+            listeners.push(match &self.listener {
+                Listener::Tcp(_) => Listener::Tcp(bind_listener(self.local_addr)?),
+                Listener::Unix(listener) => Listener::Unix(listener.try_clone()?),
+            });
+        }
 
-            // Clean up closed connections
-            for token in &to_remove {
-                let conn_idx = usize::from(*token) - SLAB_OFFSET;
-                let mut conn = self.connections.remove(conn_idx);
+        // Shared by every worker, its threads are joined once the workers are dropped
+        let handler_pool = match self.config.handler_threads {
+            0 => None,
+            threads => Some(Arc::new(HandlerPool::new(
+                self.handler.clone(),
+                threads,
+                self.config.handler_queue_size,
+            )?)),
+        };
+
+        let mut workers = Vec::with_capacity(self.config.workers);
+        for listener in listeners {
+            workers.push(Worker::new(
+                listener,
+                self.handler.clone(),
+                handler_pool.clone(),
+                &self.config,
+            )?);
+        }
+        drop(handler_pool);
 
-                if let Err(e) = self.poll.registry().deregister(conn.socket()) {
-                    eprintln!("Failed to deregister connection: {}", e);
-                }
+        if self.config.shutdown_on_signals {
+            shutdown::register_signals(&self.shutdown)?;
+        }
+        self.shutdown.attach(workers.iter().map(Backend::waker).collect());
 
-                // Return buffer to the buffer pool
-                let (buf1, buf2) = conn.get_buffers();
-                self.buffer_pool.return_buffer(buf1);
-                self.buffer_pool.return_buffer(buf2);
+        let result = run_workers(workers, &self.shutdown);
 
-                // TODO this can be removed, just for visualization
-                println!("Removing connection at index: {}", conn_idx);
+        self.shutdown.detach();
+        if self.config.shutdown_on_signals {
+            shutdown::unregister_signals(&self.shutdown);
+        }
+
+        result
+    }
+}
+
+/// Runs the first worker on the calling thread and the other ones on their own threads, until
+/// they all stopped
+fn run_workers<H: Handler>(
+    mut workers: Vec<Worker<H>>,
+    shutdown: &ShutdownHandle,
+) -> std::io::Result<()> {
+    let mut main_worker = workers.remove(0);
+    let mut handles: Vec<JoinHandle<std::io::Result<()>>> = Vec::with_capacity(workers.len());
+    let mut result = Ok(());
+
+    for (idx, mut worker) in workers.into_iter().enumerate() {
+        let shutdown = shutdown.clone();
+        let spawned = std::thread::Builder::new()
+            .name(format!("ducta-worker-{}", idx + 1))
+            .spawn(move || worker.run(shutdown.flag()));
+
+        match spawned {
+            Ok(handle) => handles.push(handle),
+            Err(e) => {
+                result = Err(e);
+                break;
             }
         }
     }
+
+    if result.is_ok() {
+        result = main_worker.run(shutdown.flag());
+    }
+
+    // Whatever made the main worker return, bring the other ones down as well
+    shutdown.shutdown();
+
+    for handle in handles {
+        let worker_result = handle.join().unwrap_or_else(|_| {
+            Err(std::io::Error::other("Worker thread panicked"))
+        });
+
+        if result.is_ok() {
+            result = worker_result;
+        }
+    }
+
+    result
+}
+
+/// Binds a non-blocking listener with `SO_REUSEPORT` set, so that each worker can bind its own
+/// listener to the same address.
+fn bind_listener(addr: SocketAddr) -> std::io::Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    // Same options as mio's TcpListener::bind, plus SO_REUSEPORT
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    Ok(socket.into())
 }
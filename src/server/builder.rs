@@ -0,0 +1,355 @@
+use super::unix::UnixSocketOptions;
+use super::Server;
+use crate::handler::Handler;
+use crate::http::TrustedProxies;
+use crate::io::{BUFFER_DANGER_SIZE, BUFFER_STANDARD_SIZE};
+use crate::net::{ConnectionLimits, Timeouts};
+use crate::proxy::ProxyProtocol;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use std::sync::Arc;
+
+#[cfg(feature = "tls")]
+use super::TlsConfig;
+
+/// Settings shared by every worker of a server
+#[derive(Clone, Debug)]
+pub(crate) struct ServerConfig {
+    pub workers: usize,
+    pub events_capacity: usize,
+    pub connections_capacity: usize,
+    pub max_connections: usize,
+    pub pooled_buffers: usize,
+    pub buffer_size: usize,
+    pub max_pooled_buffer_size: usize,
+    pub limits: ConnectionLimits,
+    pub timeouts: Timeouts,
+    /// Time given to open connections to finish once shutdown starts
+    pub drain_timeout: Duration,
+    /// Shut down on SIGINT and SIGTERM
+    pub shutdown_on_signals: bool,
+    /// Threads running handlers away from the event loops, none runs them inline
+    pub handler_threads: usize,
+    /// Requests waiting for a handler thread before new ones are refused
+    pub handler_queue_size: usize,
+    /// Proxies whose forwarding headers are believed, none if no header is
+    pub trusted_proxies: Option<Arc<TrustedProxies>>,
+    /// Believe the forwarding headers of peers connected through a Unix domain socket
+    pub trust_unix_socket_peers: bool,
+    /// Whether connections start with a PROXY protocol header
+    pub proxy_protocol: ProxyProtocol,
+    /// Terminate TLS on every accepted connection
+    #[cfg(feature = "tls")]
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            workers: 1,
+            events_capacity: 1024,
+            connections_capacity: 1024,
+            max_connections: 16384,
+            pooled_buffers: 1024,
+            buffer_size: BUFFER_STANDARD_SIZE,
+            max_pooled_buffer_size: BUFFER_DANGER_SIZE,
+            limits: ConnectionLimits::default(),
+            timeouts: Timeouts::default(),
+            drain_timeout: Duration::from_secs(30),
+            shutdown_on_signals: false,
+            handler_threads: 0,
+            handler_queue_size: 1024,
+            trusted_proxies: None,
+            trust_unix_socket_peers: false,
+            proxy_protocol: ProxyProtocol::Disabled,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    fn validate(&self) -> std::io::Result<()> {
+        let checks = [
+            (self.workers > 0, "workers must be at least 1"),
+            (self.events_capacity > 0, "events_capacity must be at least 1"),
+            (self.max_connections > 0, "max_connections must be at least 1"),
+            (
+                self.connections_capacity <= self.max_connections,
+                "connections_capacity can't exceed max_connections",
+            ),
+            (self.buffer_size > 0, "buffer_size must be at least 1"),
+            (
+                self.max_pooled_buffer_size > self.buffer_size,
+                "max_pooled_buffer_size must be larger than buffer_size",
+            ),
+            (self.limits.max_header_size > 0, "max_header_size must be at least 1"),
+            (self.limits.max_headers > 0, "max_headers must be at least 1"),
+            (
+                self.limits.max_uri_length > 0
+                    && self.limits.max_uri_length <= self.limits.max_header_size,
+                "max_uri_length must be between 1 and max_header_size",
+            ),
+            (self.limits.max_requests > 0, "max_requests_per_connection must be at least 1"),
+            (!self.timeouts.header_read.is_zero(), "header_read_timeout can't be zero"),
+            (!self.timeouts.body_read.is_zero(), "body_read_timeout can't be zero"),
+            (!self.timeouts.write.is_zero(), "write_timeout can't be zero"),
+            (!self.timeouts.keep_alive.is_zero(), "keep_alive_timeout can't be zero"),
+            (!self.timeouts.linger.is_zero(), "linger_timeout can't be zero"),
+            (!self.timeouts.heartbeat.is_zero(), "heartbeat_interval can't be zero"),
+            (self.handler_queue_size > 0, "handler_queue_size must be at least 1"),
+        ];
+
+        match checks.iter().find(|(valid, _)| !valid) {
+            Some((_, reason)) => Err(Error::new(ErrorKind::InvalidInput, *reason)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Configures and creates a `Server`.
+///
+/// Every setting starts with a sensible default, and the whole configuration is validated by
+/// `build`, which reports invalid values as `ErrorKind::InvalidInput` errors.
+pub struct ServerBuilder {
+    addr: String,
+    config: ServerConfig,
+    trusted_proxies: Vec<String>,
+    unix_socket: UnixSocketOptions,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+}
+
+impl ServerBuilder {
+    /// Starts the configuration of a server listening on `addr`, either a TCP address such as
+    /// `127.0.0.1:8080` or a Unix domain socket: a path as in `unix:/run/app.sock`, or a name in
+    /// the Linux abstract namespace as in `unix:@app`
+    pub fn new(addr: &str) -> Self {
+        ServerBuilder {
+            addr: addr.to_owned(),
+            config: ServerConfig::default(),
+            trusted_proxies: Vec::new(),
+            unix_socket: UnixSocketOptions::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Number of event loops, each running on its own thread
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.config.workers = workers;
+        self
+    }
+
+    /// Maximum number of readiness events handled per poll
+    pub fn events_capacity(mut self, capacity: usize) -> Self {
+        self.config.events_capacity = capacity;
+        self
+    }
+
+    /// Number of connection slots preallocated by each worker
+    pub fn connections_capacity(mut self, capacity: usize) -> Self {
+        self.config.connections_capacity = capacity;
+        self
+    }
+
+    /// Maximum number of open connections per worker, new connections past it are closed right
+    /// after being accepted
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.config.max_connections = max;
+        self
+    }
+
+    /// Number of buffers preallocated in each worker's buffer pool
+    pub fn pooled_buffers(mut self, count: usize) -> Self {
+        self.config.pooled_buffers = count;
+        self
+    }
+
+    /// Initial capacity of each pooled buffer, in bytes
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.config.buffer_size = size;
+        self
+    }
+
+    /// Buffers that grew to this capacity are released instead of returning to the pool
+    pub fn max_pooled_buffer_size(mut self, size: usize) -> Self {
+        self.config.max_pooled_buffer_size = size;
+        self
+    }
+
+    /// Largest request head (request line and headers) accepted, in bytes
+    pub fn max_header_size(mut self, size: usize) -> Self {
+        self.config.limits.max_header_size = size;
+        self
+    }
+
+    /// Largest number of headers accepted in a request
+    pub fn max_headers(mut self, count: usize) -> Self {
+        self.config.limits.max_headers = count;
+        self
+    }
+
+    /// Longest request target accepted, in bytes
+    pub fn max_uri_length(mut self, length: usize) -> Self {
+        self.config.limits.max_uri_length = length;
+        self
+    }
+
+    /// Largest request body accepted, in bytes. Applies to the decoded size of chunked bodies
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.config.limits.max_body_size = size;
+        self
+    }
+
+    /// Number of requests served on a connection before it's closed
+    pub fn max_requests_per_connection(mut self, max: u64) -> Self {
+        self.config.limits.max_requests = max;
+        self
+    }
+
+    /// Time allowed to receive a complete request head, from its first byte. Not extended by
+    /// progress, so clients trickling bytes can't hold a connection forever
+    pub fn header_read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeouts.header_read = timeout;
+        self
+    }
+
+    /// Time allowed between two reads of a request body
+    pub fn body_read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeouts.body_read = timeout;
+        self
+    }
+
+    /// Time allowed between two writes of a response
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeouts.write = timeout;
+        self
+    }
+
+    /// Time an idle connection is kept open waiting for its next request
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeouts.keep_alive = timeout;
+        self
+    }
+
+    /// Time given to the client to close the connection after a final response, such as an
+    /// error, during which its input is discarded
+    pub fn linger_timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeouts.linger = timeout;
+        self
+    }
+
+    /// Time an event stream may stay idle before a comment is sent on it, keeping clients and
+    /// proxies from giving up on the connection, see `sse::stream`
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.config.timeouts.heartbeat = interval;
+        self
+    }
+
+    /// Time given to open connections to finish their current request once shutdown starts,
+    /// after which they are closed whatever their state. New connections are no longer accepted
+    /// and idle ones are closed right away
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.config.drain_timeout = timeout;
+        self
+    }
+
+    /// Shuts the server down on SIGINT (Ctrl-C) and SIGTERM, off by default. Every server of the
+    /// process enabling it shares the same signal handler, which fails to install if the
+    /// application already set its own through the `ctrlc` crate
+    pub fn shutdown_on_signals(mut self, enabled: bool) -> Self {
+        self.config.shutdown_on_signals = enabled;
+        self
+    }
+
+    /// Runs handlers on a pool of threads shared by every worker instead of on the event loops,
+    /// so that handlers doing blocking work (file or database access, calls to other services)
+    /// don't hold up the other connections. They also run the work of `rt::blocking`, which fails
+    /// without them. Off by default, handlers being expected to return quickly
+    pub fn handler_threads(mut self, threads: usize) -> Self {
+        self.config.handler_threads = threads;
+        self
+    }
+
+    /// Number of requests waiting for a handler thread, past which new requests are answered with
+    /// `503 Service Unavailable`. Only used along with `handler_threads`
+    pub fn handler_queue_size(mut self, size: usize) -> Self {
+        self.config.handler_queue_size = size;
+        self
+    }
+
+    /// Addresses (`10.0.0.1`) and networks (`10.0.0.0/8`) of the reverse proxies in front of the
+    /// server, whose `Forwarded` and `X-Forwarded-For` headers `Request::client_addr` believes.
+    /// None by default, the peer address being the client's
+    pub fn trusted_proxies<I, S>(mut self, proxies: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.trusted_proxies = proxies.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Believes the `Forwarded` and `X-Forwarded-For` headers of every peer connected through a
+    /// Unix domain socket, such as a web server running on the same host, as they have no address
+    /// to be listed in `trusted_proxies`. Off by default, since any local process allowed to
+    /// connect to the socket could then forge them
+    pub fn trust_unix_socket_peers(mut self, enabled: bool) -> Self {
+        self.config.trust_unix_socket_peers = enabled;
+        self
+    }
+
+    /// Expects connections to start with a PROXY protocol header, version 1 or 2, as sent by load
+    /// balancers such as HAProxy or AWS NLBs to pass on the client address. The header is read
+    /// before anything else, TLS included, and its addresses become the peer and local addresses
+    /// of the connection. Disabled by default, and only to be enabled when every connection comes
+    /// through such a proxy, since clients could otherwise send a header of their own
+    pub fn proxy_protocol(mut self, mode: ProxyProtocol) -> Self {
+        self.config.proxy_protocol = mode;
+        self
+    }
+
+    /// Permissions given to the socket file of a Unix domain socket listener, such as `0o660`.
+    /// Left to the umask by default
+    pub fn unix_socket_mode(mut self, mode: u32) -> Self {
+        self.unix_socket.mode = Some(mode);
+        self
+    }
+
+    /// User and group ids given to the socket file of a Unix domain socket listener, `None`
+    /// keeping the ones of the process. Changing the owner usually requires privileges
+    pub fn unix_socket_owner(mut self, owner: Option<u32>, group: Option<u32>) -> Self {
+        self.unix_socket.owner = owner;
+        self.unix_socket.group = group;
+        self
+    }
+
+    /// Serves HTTPS instead of plaintext HTTP, with the given certificates and ALPN protocols
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Validates the configuration and binds the server to its address
+    pub fn build<H: Handler>(self, handler: H) -> std::io::Result<Server<H>> {
+        self.config.validate()?;
+        let trusted_proxies = TrustedProxies::parse(&self.trusted_proxies)?;
+
+        #[cfg(feature = "tls")]
+        let config = ServerConfig {
+            trusted_proxies,
+            tls: self.tls.as_ref().map(TlsConfig::build).transpose()?,
+            ..self.config
+        };
+        #[cfg(not(feature = "tls"))]
+        let config = ServerConfig {
+            trusted_proxies,
+            ..self.config
+        };
+
+        Server::bind(&self.addr, &self.unix_socket, config, handler)
+    }
+}
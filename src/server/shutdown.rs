@@ -0,0 +1,109 @@
+use crate::net::backend::Wake;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Stops a `Server` from any thread.
+///
+/// Handles are cheap to clone and can be taken before the server runs. Shutting down drains the
+/// server: it stops accepting connections and lets the open ones finish, after which `run`
+/// returns. Shutdown is final, a server asked to shut down before running returns from `run`
+/// right away.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    inner: Arc<Shared>,
+}
+
+struct Shared {
+    requested: AtomicBool,
+    /// Wakers of the workers of the running server, empty while it isn't running
+    wakers: Mutex<Vec<Arc<dyn Wake>>>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new() -> Self {
+        ShutdownHandle {
+            inner: Arc::new(Shared {
+                requested: AtomicBool::new(false),
+                wakers: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Asks the server to shut down, without waiting for it to do so
+    pub fn shutdown(&self) {
+        self.inner.requested.store(true, Ordering::SeqCst);
+
+        for waker in self.inner.wakers.lock().unwrap().iter() {
+            if let Err(e) = waker.wake() {
+                eprintln!("Failed to wake event loop: {}", e);
+            }
+        }
+    }
+
+    /// Whether shutdown was requested
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.requested.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn flag(&self) -> &AtomicBool {
+        &self.inner.requested
+    }
+
+    /// Makes `shutdown` wake the workers of a server starting to run, waking them right away if
+    /// shutdown was already requested
+    pub(crate) fn attach(&self, wakers: Vec<Arc<dyn Wake>>) {
+        *self.inner.wakers.lock().unwrap() = wakers;
+
+        // Checked after the wakers are in place, so a concurrent `shutdown` is never missed
+        if self.is_shutdown() {
+            self.shutdown();
+        }
+    }
+
+    /// Forgets the wakers of workers that stopped running
+    pub(crate) fn detach(&self) {
+        self.inner.wakers.lock().unwrap().clear();
+    }
+
+    fn same_server(&self, other: &ShutdownHandle) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+/// Handles of the running servers that shut down on SIGINT and SIGTERM
+static SIGNAL_HANDLES: Mutex<Vec<ShutdownHandle>> = Mutex::new(Vec::new());
+
+/// Shuts the server behind `handle` down on SIGINT or SIGTERM, until `unregister_signals`.
+///
+/// The process-wide signal handler is installed the first time this is called, and shared by
+/// every server registered afterwards.
+pub(crate) fn register_signals(handle: &ShutdownHandle) -> std::io::Result<()> {
+    static INSTALLED: OnceLock<Result<(), String>> = OnceLock::new();
+
+    let installed = INSTALLED.get_or_init(|| {
+        ctrlc::set_handler(|| {
+            eprintln!("\nShutdown signal received...");
+            for handle in SIGNAL_HANDLES.lock().unwrap().iter() {
+                handle.shutdown();
+            }
+        })
+        .map_err(|e| e.to_string())
+    });
+
+    if let Err(e) = installed {
+        return Err(std::io::Error::other(format!(
+            "Failed to set the signal handler: {}",
+            e
+        )));
+    }
+
+    SIGNAL_HANDLES.lock().unwrap().push(handle.clone());
+    Ok(())
+}
+
+pub(crate) fn unregister_signals(handle: &ShutdownHandle) {
+    SIGNAL_HANDLES
+        .lock()
+        .unwrap()
+        .retain(|registered| !registered.same_server(handle));
+}
@@ -0,0 +1,132 @@
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Certificates and protocol settings used to terminate TLS, enabled by the `tls` feature.
+///
+/// A default certificate answers every client, and certificates added with `with_sni_cert` take
+/// over for clients asking for their hostname through SNI. Without a default certificate,
+/// handshakes for unknown hostnames are refused.
+pub struct TlsConfig {
+    provider: Arc<CryptoProvider>,
+    default_cert: Option<Arc<CertifiedKey>>,
+    sni_certs: HashMap<String, Arc<CertifiedKey>>,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl TlsConfig {
+    /// A configuration without any certificate yet, offering `h2` and `http/1.1` through ALPN
+    pub fn new() -> Self {
+        TlsConfig {
+            provider: Arc::new(rustls::crypto::ring::default_provider()),
+            default_cert: None,
+            sni_certs: HashMap::new(),
+            alpn_protocols: vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        }
+    }
+
+    /// Loads the default certificate chain and its private key from PEM files
+    pub fn from_pem_files(
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> std::io::Result<Self> {
+        let cert_chain = std::fs::read(cert_path)?;
+        let key = std::fs::read(key_path)?;
+        TlsConfig::new().with_cert(&cert_chain, &key)
+    }
+
+    /// Sets the default certificate, from a PEM encoded chain (leaf first) and private key
+    pub fn with_cert(mut self, cert_chain: &[u8], key: &[u8]) -> std::io::Result<Self> {
+        self.default_cert = Some(self.load_cert(cert_chain, key)?);
+        Ok(self)
+    }
+
+    /// Serves the PEM encoded chain and private key to clients asking for `hostname`
+    pub fn with_sni_cert(
+        mut self,
+        hostname: &str,
+        cert_chain: &[u8],
+        key: &[u8],
+    ) -> std::io::Result<Self> {
+        let cert = self.load_cert(cert_chain, key)?;
+        self.sni_certs.insert(hostname.to_ascii_lowercase(), cert);
+        Ok(self)
+    }
+
+    /// Protocols offered through ALPN, by order of preference. Clients that support ALPN but
+    /// none of these protocols are refused, an empty list disables ALPN.
+    pub fn with_alpn_protocols(mut self, protocols: &[&[u8]]) -> Self {
+        self.alpn_protocols = protocols.iter().map(|p| p.to_vec()).collect();
+        self
+    }
+
+    fn load_cert(&self, cert_chain: &[u8], key: &[u8]) -> std::io::Result<Arc<CertifiedKey>> {
+        let certs = CertificateDer::pem_slice_iter(cert_chain)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(format!("Invalid certificate PEM: {}", e)))?;
+        if certs.is_empty() {
+            return Err(invalid("No certificate found in PEM".into()));
+        }
+
+        let key = PrivateKeyDer::from_pem_slice(key)
+            .map_err(|e| invalid(format!("Invalid private key PEM: {}", e)))?;
+
+        let cert = CertifiedKey::from_der(certs, key, &self.provider)
+            .map_err(|e| invalid(format!("Invalid certificate: {}", e)))?;
+        Ok(Arc::new(cert))
+    }
+
+    /// Builds the rustls configuration shared by every connection
+    pub(crate) fn build(&self) -> std::io::Result<Arc<rustls::ServerConfig>> {
+        if self.default_cert.is_none() && self.sni_certs.is_empty() {
+            return Err(invalid("TLS configuration has no certificate".into()));
+        }
+
+        let resolver = SniResolver {
+            default_cert: self.default_cert.clone(),
+            sni_certs: self.sni_certs.clone(),
+        };
+
+        let mut config = rustls::ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid(e.to_string()))?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = self.alpn_protocols.clone();
+
+        Ok(Arc::new(config))
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig::new()
+    }
+}
+
+/// Picks the certificate matching the SNI hostname, falling back to the default one
+#[derive(Debug)]
+struct SniResolver {
+    default_cert: Option<Arc<CertifiedKey>>,
+    sni_certs: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|name| self.sni_certs.get(&name.to_ascii_lowercase()))
+            .or(self.default_cert.as_ref())
+            .cloned()
+    }
+}
+
+fn invalid(reason: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, reason)
+}
@@ -0,0 +1,99 @@
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+/// Permissions and ownership given to the socket file of a Unix domain socket listener
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UnixSocketOptions {
+    pub mode: Option<u32>,
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+}
+
+/// Socket file of a listener, removed along with the server
+#[derive(Debug)]
+pub(crate) struct SocketFile {
+    path: PathBuf,
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Binds a non-blocking Unix domain socket listener to `addr`, a path or, on Linux, `@name` for
+/// a name in the abstract namespace. The socket file left behind by a server that didn't get to
+/// remove it is replaced, while one still being listened on makes binding fail with
+/// `AddrInUse`.
+pub(crate) fn bind(
+    addr: &str,
+    options: &UnixSocketOptions,
+) -> std::io::Result<(UnixListener, Option<SocketFile>)> {
+    if let Some(name) = addr.strip_prefix('@') {
+        let listener = bind_abstract(name)?;
+        listener.set_nonblocking(true)?;
+        return Ok((listener, None));
+    }
+
+    let path = Path::new(addr);
+    remove_stale_socket(path)?;
+
+    let listener = UnixListener::bind(path)?;
+    let file = SocketFile {
+        path: path.to_owned(),
+    };
+    listener.set_nonblocking(true)?;
+
+    if let Some(mode) = options.mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    if options.owner.is_some() || options.group.is_some() {
+        std::os::unix::fs::chown(path, options.owner, options.group)?;
+    }
+
+    Ok((listener, Some(file)))
+}
+
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &str) -> std::io::Result<UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    UnixListener::bind_addr(&addr)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(_name: &str) -> std::io::Result<UnixListener> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "The abstract namespace is only available on Linux",
+    ))
+}
+
+/// Removes the socket file at `path` if nothing listens on it anymore. Files that aren't sockets
+/// are left alone
+fn remove_stale_socket(path: &Path) -> std::io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} exists and isn't a socket", path.display()),
+        ));
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => Err(Error::new(
+            ErrorKind::AddrInUse,
+            format!("{} is already listened on", path.display()),
+        )),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => std::fs::remove_file(path),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,274 @@
+use crate::http::{Body, Response};
+use crate::net::Notifier;
+use bytes::{BufMut, Bytes, BytesMut};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Comment sent on idle streams, keeping proxies from timing the connection out
+const HEARTBEAT: &[u8] = b":\n\n";
+
+/// A Server-Sent Event, made of the fields understood by `EventSource` clients
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    event: Option<String>,
+    data: String,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// An event carrying `data`, which may span several lines
+    pub fn new(data: impl Into<String>) -> Self {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// Type of the event, dispatched to the client's listeners of that name instead of
+    /// `onmessage`
+    pub fn with_event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Id the client sends back in `Last-Event-ID` when it reconnects
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Time the client waits before reconnecting once the stream is lost
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Encodes the event in the `text/event-stream` format. Line breaks are removed from the
+    /// event type and id, which can't hold them, and split `data` into several fields.
+    pub fn encode(&self, dst: &mut BytesMut) {
+        if let Some(event) = &self.event {
+            put_field(dst, "event", event);
+        }
+        if let Some(id) = &self.id {
+            put_field(dst, "id", id);
+        }
+        if let Some(retry) = self.retry {
+            put_field(dst, "retry", &retry.as_millis().to_string());
+        }
+
+        for line in self.data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+            dst.put_slice(b"data: ");
+            dst.put_slice(line.as_bytes());
+            dst.put_u8(b'\n');
+        }
+        dst.put_u8(b'\n');
+    }
+}
+
+fn put_field(dst: &mut BytesMut, name: &str, value: &str) {
+    dst.put_slice(name.as_bytes());
+    dst.put_slice(b": ");
+    for part in value.split(['\r', '\n']) {
+        dst.put_slice(part.as_bytes());
+    }
+    dst.put_u8(b'\n');
+}
+
+/// Opens an event stream, returning the `200 OK` response to send and the sender feeding it.
+///
+/// The response is written as events are sent, until every sender was dropped or `close` was
+/// called. The connection stays busy meanwhile, with a comment sent whenever the stream was idle
+/// for the server's heartbeat interval, see `ServerBuilder::heartbeat_interval`.
+///
+/// ```no_run
+/// use ducta::http::Request;
+/// use ducta::sse::{self, Event};
+/// use ducta::Router;
+/// use std::time::Duration;
+///
+/// let router = Router::new().get("/clock", |_req: Request| {
+///     let (resp, events) = sse::stream();
+///     std::thread::spawn(move || {
+///         for tick in 0.. {
+///             let event = Event::new(tick.to_string()).with_event("tick");
+///             if events.send(event).is_err() {
+///                 break; // The client went away
+///             }
+///             std::thread::sleep(Duration::from_secs(1));
+///         }
+///     });
+///     resp
+/// });
+/// ```
+pub fn stream() -> (Response, EventSender) {
+    let channel = Arc::new(Channel {
+        state: Mutex::new(ChannelState {
+            pending: BytesMut::new(),
+            closed: false,
+            senders: 1,
+            notifier: None,
+            notified: false,
+        }),
+    });
+
+    let mut resp = Response::new(200)
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache")
+        .with_streaming_body(EventBody {
+            channel: channel.clone(),
+        });
+    resp.event_stream = Some(EventStream {
+        channel: channel.clone(),
+    });
+
+    (resp, EventSender { channel })
+}
+
+/// Sends events to a stream from any thread, waking its event loop up.
+///
+/// Events are queued without limit until the connection is able to send them.
+pub struct EventSender {
+    channel: Arc<Channel>,
+}
+
+impl EventSender {
+    /// Queues `event` to be sent, failing with `NotConnected` once the stream is closed, or the
+    /// client went away
+    pub fn send(&self, event: Event) -> std::io::Result<()> {
+        let mut state = self.channel.state.lock().unwrap();
+        if state.closed {
+            return Err(std::io::ErrorKind::NotConnected.into());
+        }
+
+        event.encode(&mut state.pending);
+        self.channel.notify(state);
+        Ok(())
+    }
+
+    /// Ends the stream once the events already queued are sent
+    pub fn close(&self) {
+        let mut state = self.channel.state.lock().unwrap();
+        if !state.closed {
+            state.closed = true;
+            self.channel.notify(state);
+        }
+    }
+
+    /// Whether the stream is closed, events can't be sent anymore
+    pub fn is_closed(&self) -> bool {
+        self.channel.state.lock().unwrap().closed
+    }
+}
+
+impl Clone for EventSender {
+    fn clone(&self) -> Self {
+        self.channel.state.lock().unwrap().senders += 1;
+        EventSender {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock().unwrap();
+        state.senders -= 1;
+        // Nothing can be sent anymore, let the stream end
+        if state.senders == 0 && !state.closed {
+            state.closed = true;
+            self.channel.notify(state);
+        }
+    }
+}
+
+/// State shared by a stream and its senders
+struct Channel {
+    state: Mutex<ChannelState>,
+}
+
+struct ChannelState {
+    /// Events encoded and not yet taken by the connection
+    pending: BytesMut,
+    /// No more events are accepted, the stream ends once `pending` is sent
+    closed: bool,
+    senders: usize,
+    /// Set once the response was handed to a connection
+    notifier: Option<Notifier>,
+    /// The worker was notified and didn't look at the stream since, so it doesn't need to be again
+    notified: bool,
+}
+
+impl Channel {
+    /// Wakes the connection up to send what changed, if it's waiting for it
+    fn notify(&self, mut state: std::sync::MutexGuard<ChannelState>) {
+        if state.notified {
+            return;
+        }
+
+        let Some(notifier) = state.notifier.clone() else {
+            return;
+        };
+        state.notified = true;
+        drop(state);
+
+        notifier.notify();
+    }
+}
+
+/// Body of an event stream response, pausing the response while no event is pending
+struct EventBody {
+    channel: Arc<Channel>,
+}
+
+impl Body for EventBody {
+    fn size_hint(&self) -> Option<u64> {
+        None
+    }
+
+    fn next_chunk(&mut self, _max: usize) -> std::io::Result<Option<Bytes>> {
+        let mut state = self.channel.state.lock().unwrap();
+        state.notified = false;
+
+        if !state.pending.is_empty() {
+            Ok(Some(state.pending.split().freeze()))
+        } else if state.closed {
+            Ok(None)
+        } else {
+            Err(std::io::ErrorKind::WouldBlock.into())
+        }
+    }
+}
+
+impl Drop for EventBody {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock().unwrap();
+        state.closed = true;
+        state.pending.clear();
+    }
+}
+
+/// The connection's handle on the event stream it sends
+pub(crate) struct EventStream {
+    channel: Arc<Channel>,
+}
+
+impl EventStream {
+    /// Hands the stream over to the connection woken up by `notifier`
+    pub fn attach(&self, notifier: Notifier) {
+        self.channel.state.lock().unwrap().notifier = Some(notifier);
+    }
+
+    /// Queues a comment keeping the idle stream alive
+    pub fn heartbeat(&self) {
+        let mut state = self.channel.state.lock().unwrap();
+        if !state.closed {
+            state.pending.put_slice(HEARTBEAT);
+        }
+    }
+
+    /// Ends the stream once the events already queued are sent, as the server goes away
+    pub fn finish(&self) {
+        self.channel.state.lock().unwrap().closed = true;
+    }
+}
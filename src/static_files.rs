@@ -0,0 +1,323 @@
+mod http_date;
+mod mime;
+mod range;
+
+use self::range::{MultipartRanges, Ranges};
+use crate::handler::Handler;
+use crate::http::{reason_phrase, FileBody, Request, Response};
+use std::ffi::OsString;
+use std::fs::{File, Metadata};
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Serves the files of a directory under a URL prefix.
+///
+/// The part of the request path after the prefix is percent-decoded and looked up in the
+/// directory. Paths trying to climb out of it with `..` are rejected, and so are files that only
+/// resolve outside of it, such as through a symlink. Requests for a directory are answered with
+/// its first existing index file, `index.html` by default, after redirecting to the path with a
+/// trailing slash.
+///
+/// Responses carry a `Content-Type` guessed from the file extension, along with `ETag` and
+/// `Last-Modified` validators, so `If-None-Match` and `If-Modified-Since` requests are answered
+/// with `304 Not Modified` when the file didn't change. `Range` requests get the requested parts
+/// only, as a `multipart/byteranges` body when several are asked for. On Linux, plaintext
+/// connections send the file content with `sendfile(2)`.
+///
+/// Only `GET` and `HEAD` requests are allowed, others get `405 Method Not Allowed`.
+pub struct StaticFiles {
+    prefix: String,
+    root: PathBuf,
+    index_files: Vec<String>,
+}
+
+impl StaticFiles {
+    /// Serves the files under `root` at the paths starting with `prefix`, so `/assets` mapped to
+    /// `./public` answers `/assets/app.js` with `./public/app.js`
+    pub fn new(prefix: &str, root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref();
+
+        StaticFiles {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            // Resolved paths are compared against the root, so it has to be resolved as well
+            root: root.canonicalize().unwrap_or_else(|_| root.to_path_buf()),
+            index_files: vec!["index.html".to_string()],
+        }
+    }
+
+    /// Files looked up, in order, when a directory is requested. Directories are not served at
+    /// all if the list is empty.
+    pub fn with_index_files(mut self, names: &[&str]) -> Self {
+        self.index_files = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Finds what `path`, relative to the root, refers to, making sure it stays in the root
+    fn resolve(&self, path: &Path) -> std::io::Result<PathBuf> {
+        let resolved = self.root.join(path).canonicalize()?;
+        if !resolved.starts_with(&self.root) {
+            return Err(ErrorKind::NotFound.into());
+        }
+
+        Ok(resolved)
+    }
+
+    /// Opens the first index file of the directory at `dir`
+    fn open_index(&self, dir: &Path) -> std::io::Result<(PathBuf, File, Metadata)> {
+        for name in &self.index_files {
+            let opened = self.resolve(&dir.join(name)).and_then(|path| {
+                let (file, metadata) = open_file(&path)?;
+                Ok((path, file, metadata))
+            });
+
+            match opened {
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                result => return result,
+            }
+        }
+
+        Err(ErrorKind::NotFound.into())
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, req: Request) -> Response {
+        if req.method != "GET" && req.method != "HEAD" {
+            return error(405).with_header("Allow", "GET, HEAD");
+        }
+
+        let Some(rest) = strip_prefix(req.path_only(), &self.prefix) else {
+            return error(404);
+        };
+        let Some(relative) = decode_path(rest) else {
+            return error(400);
+        };
+
+        let path = match self.resolve(&relative) {
+            Ok(path) => path,
+            Err(e) => return io_error(&e),
+        };
+
+        let opened = if path.is_dir() {
+            if !rest.ends_with('/') {
+                let location = match req.query() {
+                    Some(query) => format!("{}/?{}", req.path_only(), query),
+                    None => format!("{}/", req.path_only()),
+                };
+                return error(301).with_header("Location", location);
+            }
+            self.open_index(&path)
+        } else {
+            open_file(&path).map(|(file, metadata)| (path, file, metadata))
+        };
+
+        match opened {
+            Ok((path, file, metadata)) => serve_file(&req, &path, file, &metadata),
+            Err(e) => io_error(&e),
+        }
+    }
+}
+
+/// Builds the response for the regular file `file`, found at `path`
+fn serve_file(req: &Request, path: &Path, file: File, metadata: &Metadata) -> Response {
+    let len = metadata.len();
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let etag = entity_tag(len, modified);
+    let content_type = mime::from_path(path);
+
+    let response = Response::new(200)
+        .with_header("Content-Type", content_type)
+        .with_header("ETag", etag.clone())
+        .with_header("Last-Modified", http_date::format(modified))
+        .with_header("Accept-Ranges", "bytes");
+
+    if is_not_modified(req, &etag, modified) {
+        return Response {
+            status: 304,
+            ..response
+        };
+    }
+
+    // Range requests only apply to GET, and only while the representation the client has parts
+    // of is still the current one
+    let range = match req.get_header("Range") {
+        Some(range) if req.method == "GET" && if_range_matches(req, &etag, modified) => {
+            String::from_utf8_lossy(range).into_owned()
+        }
+        _ => return response.with_streaming_body(FileBody::range(file, 0, len)),
+    };
+
+    match range::parse(&range, len) {
+        Ranges::Ignored => response.with_streaming_body(FileBody::range(file, 0, len)),
+        Ranges::Unsatisfiable => {
+            error(416).with_header("Content-Range", format!("bytes */{}", len))
+        }
+        Ranges::Satisfiable(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            Response {
+                status: 206,
+                ..response
+            }
+            .with_header("Content-Range", range.content_range(len))
+            .with_streaming_body(FileBody::range(file, range.start, range.len()))
+        }
+        Ranges::Satisfiable(ranges) => {
+            let boundary = boundary();
+            let mut response = Response {
+                status: 206,
+                ..response
+            };
+            response.headers.retain(|(name, _)| name != "Content-Type");
+
+            let body = MultipartRanges::new(file, &ranges, len, content_type, &boundary);
+            response
+                .with_header(
+                    "Content-Type",
+                    format!("multipart/byteranges; boundary={}", boundary),
+                )
+                .with_streaming_body(body)
+        }
+    }
+}
+
+/// Whether the client's cached copy is still current. `If-Modified-Since` is only looked at when
+/// `If-None-Match` is absent, as entity tags are the more precise validator.
+fn is_not_modified(req: &Request, etag: &str, modified: SystemTime) -> bool {
+    if let Some(if_none_match) = req.get_header("If-None-Match") {
+        let if_none_match = String::from_utf8_lossy(if_none_match);
+        return if_none_match.trim() == "*"
+            || if_none_match
+                .split(',')
+                .any(|tag| weak_etag(tag.trim()) == weak_etag(etag));
+    }
+
+    let since = req
+        .get_header("If-Modified-Since")
+        .and_then(|since| std::str::from_utf8(since).ok())
+        .and_then(http_date::parse);
+    match since {
+        // Dates only have a resolution of a second
+        Some(since) => unix_secs(modified) <= unix_secs(since),
+        None => false,
+    }
+}
+
+/// Whether the `If-Range` precondition, if any, lets the `Range` header apply. Entity tags are
+/// compared strongly and dates exactly.
+fn if_range_matches(req: &Request, etag: &str, modified: SystemTime) -> bool {
+    let Some(if_range) = req.get_header("If-Range") else {
+        return true;
+    };
+    let Ok(if_range) = std::str::from_utf8(if_range) else {
+        return false;
+    };
+    let if_range = if_range.trim();
+
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return if_range == etag;
+    }
+    http_date::parse(if_range).is_some_and(|date| unix_secs(date) == unix_secs(modified))
+}
+
+/// Entity tag of a file, which changes whenever its length or modification time does
+fn entity_tag(len: u64, modified: SystemTime) -> String {
+    let nanos = modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    format!("\"{:x}-{:x}\"", len, nanos)
+}
+
+/// The opaque part of an entity tag, without the weakness indicator
+fn weak_etag(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// A multipart boundary unlikely to appear in any file
+fn boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.subsec_nanos());
+    format!(
+        "ducta-{:08x}{:016x}",
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// The part of `path` after `prefix`, which has to end at a segment boundary
+fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix)?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+/// Percent-decodes the segments of `path` into a relative path. Returns `None` if a segment is
+/// badly encoded, is `..`, or decodes to something that isn't a single file name.
+fn decode_path(path: &str) -> Option<PathBuf> {
+    let mut decoded = PathBuf::new();
+
+    for segment in path.split('/') {
+        let segment = percent_decode(segment)?;
+        match segment.as_slice() {
+            b"" | b"." => continue,
+            b".." => return None,
+            bytes if bytes.contains(&b'/') || bytes.contains(&0) => return None,
+            _ => decoded.push(OsString::from_vec(segment)),
+        }
+    }
+
+    Some(decoded)
+}
+
+fn percent_decode(segment: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(segment.len());
+    let mut bytes = segment.bytes();
+
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            decoded.push(b);
+            continue;
+        }
+
+        let hex = [bytes.next()?, bytes.next()?];
+        let hex = std::str::from_utf8(&hex).ok()?;
+        decoded.push(u8::from_str_radix(hex, 16).ok()?);
+    }
+
+    Some(decoded)
+}
+
+/// Opens the file at `path`, which has to be a regular file
+fn open_file(path: &Path) -> std::io::Result<(File, Metadata)> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(ErrorKind::NotFound.into());
+    }
+
+    Ok((file, metadata))
+}
+
+fn io_error(e: &std::io::Error) -> Response {
+    match e.kind() {
+        ErrorKind::NotFound | ErrorKind::NotADirectory | ErrorKind::InvalidFilename => error(404),
+        ErrorKind::PermissionDenied => error(403),
+        _ => {
+            eprintln!("Failed to serve file: {}", e);
+            error(500)
+        }
+    }
+}
+
+fn error(status: u16) -> Response {
+    Response::new(status).with_body(reason_phrase(status))
+}
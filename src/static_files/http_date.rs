@@ -0,0 +1,178 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats `time` as an IMF-fixdate, such as `Sun, 06 Nov 1994 08:49:37 GMT`. Times before the
+/// Unix epoch are clamped to it.
+pub(crate) fn format(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let days = (secs / 86400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let secs_of_day = secs % 86400;
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        // The epoch was a Thursday
+        WEEKDAYS[((days + 4) % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// Parses an IMF-fixdate. The obsolete RFC 850 and asctime formats are not supported, dates in
+/// them are rejected like any other invalid date.
+pub(crate) fn parse(value: &str) -> Option<SystemTime> {
+    let mut fields = value.split_ascii_whitespace();
+
+    let weekday = fields.next()?.strip_suffix(',')?;
+    let day: u32 = fields.next()?.parse().ok()?;
+    let month = fields.next()?;
+    let year: i64 = fields.next()?.parse().ok()?;
+    let time = fields.next()?;
+    if fields.next()? != "GMT" || fields.next().is_some() || !WEEKDAYS.contains(&weekday) {
+        return None;
+    }
+
+    let month = MONTHS.iter().position(|m| *m == month)? as u32 + 1;
+    // Years take four digits, which also keeps the arithmetic below far from overflowing
+    if !(1..=31).contains(&day) || !(1970..=9999).contains(&year) {
+        return None;
+    }
+
+    let mut hms = time.split(':').map(|field| field.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (hms.next()??, hms.next()??, hms.next()??);
+    if hms.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day) as u64;
+    let secs = days
+        .checked_mul(86400)?
+        .checked_add(hours * 3600 + minutes * 60 + seconds)?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+// Conversions between days since the epoch and the proleptic Gregorian calendar, from Howard
+// Hinnant's chrono-compatible date algorithms
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_and_parses_imf_fixdates() {
+        // Example from RFC 9110
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+
+        assert_eq!(format(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(parse("Thu, 01 Jan 1970 00:00:00 GMT"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse("Fri, 31 Dec 9999 23:59:59 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(253402300799))
+        );
+    }
+
+    #[test]
+    fn round_trips_through_leap_years_and_month_ends() {
+        // Steps of a little over a day, landing on every time of day and calendar day in turn
+        let mut secs = 0;
+        while secs < 253402300799 {
+            let time = UNIX_EPOCH + Duration::from_secs(secs);
+            assert_eq!(parse(&format(time)), Some(time), "{}", format(time));
+            secs += 86400 * 37 + 3613;
+        }
+
+        assert_eq!(
+            format(UNIX_EPOCH + Duration::from_secs(951782400)),
+            "Tue, 29 Feb 2000 00:00:00 GMT"
+        );
+        // Century years are only leap years every 400 years
+        assert_eq!(
+            format(UNIX_EPOCH + Duration::from_secs(4107542399)),
+            "Sun, 28 Feb 2100 23:59:59 GMT"
+        );
+        assert_eq!(
+            format(UNIX_EPOCH + Duration::from_secs(4107542400)),
+            "Mon, 01 Mar 2100 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn clamps_times_before_the_epoch() {
+        let time = UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(format(time), "Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    #[test]
+    fn rejects_years_out_of_range() {
+        for date in [
+            "Sun, 06 Nov 500000000000 08:49:37 GMT",
+            "Sun, 06 Nov 9223372036854775807 08:49:37 GMT",
+            "Sun, 06 Nov 10000 08:49:37 GMT",
+            "Sun, 06 Nov 1969 08:49:37 GMT",
+            "Sun, 06 Nov -1994 08:49:37 GMT",
+        ] {
+            assert_eq!(parse(date), None, "{}", date);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_dates() {
+        for date in [
+            "",
+            "Sun 06 Nov 1994 08:49:37 GMT",
+            "Xyz, 06 Nov 1994 08:49:37 GMT",
+            "Sun, 00 Nov 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:49:37 GMT",
+            "Sun, 06 Nov 1994 08:60:37 GMT",
+            "Sun, 06 Nov 1994 08:49:61 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 06 Nov 1994 08:49:37:00 GMT",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Nov 1994 08:49:37 GMT extra",
+            "Sun, 06 November 1994 08:49:37 GMT",
+            // RFC 850 and asctime formats
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(parse(date), None, "{}", date);
+        }
+    }
+}
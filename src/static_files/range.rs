@@ -0,0 +1,245 @@
+use crate::http::{file_shrank, Body};
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+
+#[cfg(target_os = "linux")]
+use std::os::fd::RawFd;
+
+/// Requests asking for more ranges than this are served the whole file instead, so a client
+/// can't make the server send a file many times over in tiny pieces
+const MAX_RANGES: usize = 16;
+
+/// A satisfiable range of bytes, both ends included
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Value of the `Content-Range` header describing this range of a `total` bytes file
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+/// How a `Range` header applies to a representation
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Ranges {
+    /// The header is invalid, uses another unit than bytes or asks for too many ranges, it's
+    /// ignored and the whole representation is sent
+    Ignored,
+    /// None of the ranges overlaps the representation
+    Unsatisfiable,
+    /// The satisfiable ranges, sorted with overlapping and adjacent ones merged
+    Satisfiable(Vec<ByteRange>),
+}
+
+/// Applies the `Range` header `value` to a representation of `len` bytes
+pub(crate) fn parse(value: &str, len: u64) -> Ranges {
+    let Some((unit, specs)) = value.split_once('=') else {
+        return Ranges::Ignored;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Ranges::Ignored;
+    }
+
+    let mut ranges = Vec::new();
+    let mut count = 0;
+
+    for spec in specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+        count += 1;
+        if count > MAX_RANGES {
+            return Ranges::Ignored;
+        }
+
+        let Some((first, last)) = spec.split_once('-') else {
+            return Ranges::Ignored;
+        };
+        let (first, last) = (first.trim(), last.trim());
+
+        let range = if first.is_empty() {
+            // Suffix range, the last `last` bytes
+            let Ok(suffix) = last.parse::<u64>() else {
+                return Ranges::Ignored;
+            };
+            (suffix > 0 && len > 0).then(|| ByteRange {
+                start: len.saturating_sub(suffix),
+                end: len - 1,
+            })
+        } else {
+            let Ok(start) = first.parse::<u64>() else {
+                return Ranges::Ignored;
+            };
+            let end = match last {
+                "" => u64::MAX,
+                last => match last.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return Ranges::Ignored,
+                },
+            };
+            (start < len).then(|| ByteRange {
+                start,
+                end: end.min(len - 1),
+            })
+        };
+
+        ranges.extend(range);
+    }
+
+    if count == 0 {
+        return Ranges::Ignored;
+    }
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    Ranges::Satisfiable(merged)
+}
+
+/// A `multipart/byteranges` body, made of several ranges of the same file each preceded by its
+/// own headers
+pub(crate) struct MultipartRanges {
+    file: File,
+    parts: VecDeque<Part>,
+    remaining: u64,
+}
+
+enum Part {
+    Bytes(Bytes),
+    File { offset: u64, remaining: u64 },
+}
+
+impl MultipartRanges {
+    pub fn new(
+        file: File,
+        ranges: &[ByteRange],
+        total: u64,
+        content_type: &str,
+        boundary: &str,
+    ) -> Self {
+        let mut parts = VecDeque::with_capacity(ranges.len() * 2 + 1);
+
+        for (i, range) in ranges.iter().enumerate() {
+            // The CRLF before each delimiter belongs to it, so the first one has none
+            let head = format!(
+                "{}--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                if i == 0 { "" } else { "\r\n" },
+                boundary,
+                content_type,
+                range.content_range(total)
+            );
+            parts.push_back(Part::Bytes(Bytes::from(head)));
+            parts.push_back(Part::File {
+                offset: range.start,
+                remaining: range.len(),
+            });
+        }
+        parts.push_back(Part::Bytes(Bytes::from(format!("\r\n--{}--\r\n", boundary))));
+
+        let remaining = parts
+            .iter()
+            .map(|part| match part {
+                Part::Bytes(bytes) => bytes.len() as u64,
+                Part::File { remaining, .. } => *remaining,
+            })
+            .sum();
+
+        MultipartRanges {
+            file,
+            parts,
+            remaining,
+        }
+    }
+}
+
+impl Body for MultipartRanges {
+    fn size_hint(&self) -> Option<u64> {
+        Some(self.remaining)
+    }
+
+    fn next_chunk(&mut self, max: usize) -> std::io::Result<Option<Bytes>> {
+        let chunk = match self.parts.front_mut() {
+            None => return Ok(None),
+            Some(Part::Bytes(bytes)) => {
+                let chunk = std::mem::take(bytes);
+                self.parts.pop_front();
+                chunk
+            }
+            Some(Part::File { offset, remaining }) => {
+                let len = std::cmp::min(max as u64, *remaining) as usize;
+                let mut chunk = vec![0; len];
+                let n = self.file.read_at(&mut chunk, *offset)?;
+                if n == 0 {
+                    return Err(file_shrank());
+                }
+
+                chunk.truncate(n);
+                *offset += n as u64;
+                *remaining -= n as u64;
+                if *remaining == 0 {
+                    self.parts.pop_front();
+                }
+                Bytes::from(chunk)
+            }
+        };
+
+        self.remaining -= chunk.len() as u64;
+        Ok(Some(chunk))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn send_to(&mut self, fd: RawFd, max: usize) -> Option<std::io::Result<usize>> {
+        let result = match self.parts.front_mut() {
+            None => return Some(Ok(0)),
+            Some(Part::Bytes(bytes)) => {
+                let n = unsafe {
+                    libc::send(
+                        fd,
+                        bytes.as_ptr() as *const libc::c_void,
+                        bytes.len().min(max),
+                        libc::MSG_NOSIGNAL,
+                    )
+                };
+                if n < 0 {
+                    return Some(Err(std::io::Error::last_os_error()));
+                }
+
+                let _ = bytes.split_to(n as usize);
+                if bytes.is_empty() {
+                    self.parts.pop_front();
+                }
+                Ok(n as usize)
+            }
+            Some(Part::File { offset, remaining }) => {
+                let result = crate::http::sendfile(fd, &self.file, offset, remaining, max);
+                if *remaining == 0 {
+                    self.parts.pop_front();
+                }
+                result
+            }
+        };
+
+        if let Ok(n) = result {
+            self.remaining -= n as u64;
+        }
+        Some(result)
+    }
+}
@@ -0,0 +1,147 @@
+mod frame;
+mod handshake;
+mod session;
+
+pub use self::handshake::upgrade;
+pub(crate) use self::{frame::MAX_HEADER_LEN as MAX_FRAME_HEADER_LEN, session::Session};
+
+use self::frame::Opcode;
+use self::session::Outbox;
+use bytes::{Bytes, BytesMut};
+use std::sync::Arc;
+
+/// Status codes of close frames, as passed to `WebSocket::close` and reported to
+/// `WebSocketHandler::on_close`
+pub mod close_code {
+    pub const NORMAL: u16 = 1000;
+    /// The server is shutting down, or the client navigated away
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    /// A kind of message the endpoint can't handle was received
+    pub const UNSUPPORTED_DATA: u16 = 1003;
+    /// The close frame carried no status code, never sent
+    pub const NO_STATUS: u16 = 1005;
+    /// The connection was closed without a close frame, never sent
+    pub const ABNORMAL: u16 = 1006;
+    /// A text message wasn't valid UTF-8
+    pub const INVALID_DATA: u16 = 1007;
+    pub const POLICY_VIOLATION: u16 = 1008;
+    pub const MESSAGE_TOO_BIG: u16 = 1009;
+    pub const INTERNAL_ERROR: u16 = 1011;
+}
+
+/// A complete WebSocket message, reassembled from its fragments
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Bytes),
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Message::Text(text)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Message::Text(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(data: Vec<u8>) -> Self {
+        Message::Binary(Bytes::from(data))
+    }
+}
+
+impl From<Bytes> for Message {
+    fn from(data: Bytes) -> Self {
+        Message::Binary(data)
+    }
+}
+
+/// Handles the messages of a single WebSocket connection, see `upgrade`.
+///
+/// Callbacks run on the event loop thread of the connection, so like `Handler::handle` they
+/// must not block. Pings are answered and the closing handshake is carried out automatically.
+pub trait WebSocketHandler: Send + 'static {
+    /// Called once the handshake response was sent
+    fn on_open(&mut self, ws: &mut WebSocket) {
+        let _ = ws;
+    }
+
+    fn on_message(&mut self, ws: &mut WebSocket, message: Message);
+
+    /// Called once when the connection ends, with the code of the close frame received from the
+    /// client or sent to fail the connection, `close_code::NO_STATUS` if the client's close frame
+    /// had none, or `close_code::ABNORMAL` if the connection was lost without a closing handshake
+    fn on_close(&mut self, code: u16, reason: &str) {
+        let _ = (code, reason);
+    }
+}
+
+/// The connection a `WebSocketHandler` callback runs for
+pub struct WebSocket<'a> {
+    out: &'a mut BytesMut,
+    outbox: &'a Arc<Outbox>,
+    close_sent: &'a mut bool,
+}
+
+impl WebSocket<'_> {
+    /// Queues `message` to be sent, after the ones already queued. Ignored once the connection
+    /// started closing.
+    pub fn send(&mut self, message: impl Into<Message>) {
+        if !*self.close_sent {
+            session::encode_message(self.out, &message.into());
+        }
+    }
+
+    /// Sends a ping, the client's pong is not reported
+    pub fn ping(&mut self, data: &[u8]) {
+        if !*self.close_sent {
+            frame::encode(self.out, Opcode::Ping, &data[..data.len().min(125)]);
+        }
+    }
+
+    /// Starts the closing handshake, the connection is closed once the client answers it
+    pub fn close(&mut self, code: u16, reason: &str) {
+        if !*self.close_sent {
+            *self.close_sent = true;
+            frame::encode_close(self.out, code, reason);
+        }
+    }
+
+    /// A handle sending messages to this connection from any thread
+    pub fn sender(&self) -> WebSocketSender {
+        WebSocketSender {
+            outbox: self.outbox.clone(),
+        }
+    }
+}
+
+/// Sends messages to a WebSocket connection from any thread, waking its event loop up.
+///
+/// Messages are queued without limit until the connection is able to send them.
+#[derive(Clone)]
+pub struct WebSocketSender {
+    outbox: Arc<Outbox>,
+}
+
+impl WebSocketSender {
+    /// Queues `message` to be sent, failing with `NotConnected` once the connection is closing
+    /// or closed
+    pub fn send(&self, message: impl Into<Message>) -> std::io::Result<()> {
+        self.outbox.push(session::Outgoing::Message(message.into()))
+    }
+
+    /// Starts the closing handshake after the messages already queued are sent
+    pub fn close(&self, code: u16, reason: &str) -> std::io::Result<()> {
+        self.outbox.push(session::Outgoing::Close(code, reason.to_string()))
+    }
+
+    /// Whether the connection is closing or closed, messages can't be sent anymore
+    pub fn is_closed(&self) -> bool {
+        self.outbox.is_closed()
+    }
+}
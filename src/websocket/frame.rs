@@ -0,0 +1,164 @@
+use super::close_code;
+use bytes::{BufMut, BytesMut};
+
+/// Largest frame header: 2 bytes, an 8 bytes extended length and a 4 bytes masking key
+pub(crate) const MAX_HEADER_LEN: usize = 14;
+/// Largest payload of a control frame
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+pub(crate) struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    /// Unmasked payload
+    pub payload: BytesMut,
+}
+
+/// Takes the first frame off `buf` and unmasks its payload in place. Returns `Ok(None)` while the
+/// frame hasn't fully arrived, or the close code to fail the connection with when the frame
+/// breaks the protocol or its payload is larger than `max_payload`.
+pub(crate) fn decode(buf: &mut BytesMut, max_payload: usize) -> Result<Option<Frame>, u16> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+
+    let fin = buf[0] & 0x80 != 0;
+    // No extension was negotiated, so none of the reserved bits may be set
+    if buf[0] & 0x70 != 0 {
+        return Err(close_code::PROTOCOL_ERROR);
+    }
+    let opcode = Opcode::from_bits(buf[0] & 0x0F).ok_or(close_code::PROTOCOL_ERROR)?;
+    // Clients must mask every frame they send
+    if buf[1] & 0x80 == 0 {
+        return Err(close_code::PROTOCOL_ERROR);
+    }
+
+    let (len, len_end) = match buf[1] & 0x7F {
+        126 if buf.len() < 4 => return Ok(None),
+        126 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+        127 if buf.len() < 10 => return Ok(None),
+        127 => {
+            let len = u64::from_be_bytes(buf[2..10].try_into().unwrap());
+            if len >> 63 != 0 {
+                return Err(close_code::PROTOCOL_ERROR);
+            }
+            (len, 10)
+        }
+        len => (len as u64, 2),
+    };
+
+    if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+        return Err(close_code::PROTOCOL_ERROR);
+    }
+    if len > max_payload as u64 {
+        return Err(close_code::MESSAGE_TOO_BIG);
+    }
+
+    let header_len = len_end + 4;
+    let frame_len = header_len + len as usize;
+    if buf.len() < frame_len {
+        return Ok(None);
+    }
+
+    let mask: [u8; 4] = buf[len_end..header_len].try_into().unwrap();
+    let mut payload = buf.split_to(frame_len).split_off(header_len);
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok(Some(Frame {
+        fin,
+        opcode,
+        payload,
+    }))
+}
+
+/// Appends an unmasked, unfragmented frame to `dst`
+pub(crate) fn encode(dst: &mut BytesMut, opcode: Opcode, payload: &[u8]) {
+    dst.reserve(MAX_HEADER_LEN + payload.len());
+    dst.put_u8(0x80 | opcode.bits());
+
+    match payload.len() {
+        len if len < 126 => dst.put_u8(len as u8),
+        len if len <= u16::MAX as usize => {
+            dst.put_u8(126);
+            dst.put_u16(len as u16);
+        }
+        len => {
+            dst.put_u8(127);
+            dst.put_u64(len as u64);
+        }
+    }
+
+    dst.put_slice(payload);
+}
+
+/// Appends a close frame with `code` and as much of `reason` as fits in a control frame
+pub(crate) fn encode_close(dst: &mut BytesMut, code: u16, reason: &str) {
+    let mut end = reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let mut payload = Vec::with_capacity(2 + end);
+    payload.extend_from_slice(&code.to_be_bytes());
+    payload.extend_from_slice(&reason.as_bytes()[..end]);
+    encode(dst, Opcode::Close, &payload);
+}
+
+/// Status code and reason of a received close frame, or the close code to fail the connection
+/// with if they are invalid
+pub(crate) fn parse_close(payload: &[u8]) -> Result<(u16, &str), u16> {
+    match payload.len() {
+        0 => return Ok((close_code::NO_STATUS, "")),
+        1 => return Err(close_code::PROTOCOL_ERROR),
+        _ => {}
+    }
+
+    let code = u16::from_be_bytes([payload[0], payload[1]]);
+    // Codes that are reserved, or only meant to be reported locally, can't be sent
+    if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+        return Err(close_code::PROTOCOL_ERROR);
+    }
+
+    let reason = std::str::from_utf8(&payload[2..]).map_err(|_| close_code::INVALID_DATA)?;
+    Ok((code, reason))
+}
@@ -0,0 +1,72 @@
+use super::WebSocketHandler;
+use crate::http::{reason_phrase, Request, Response};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+/// Appended to the client's key to compute the accept value, per RFC 6455
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Answers a WebSocket opening handshake, handing the connection over to `handler` once the
+/// `101 Switching Protocols` response is sent.
+///
+/// Requests that aren't a valid handshake get `400 Bad Request`, or `426 Upgrade Required` when
+/// they don't ask for an upgrade at all or use another version of the protocol than 13. The
+/// returned response can be given more headers, such as the `Sec-WebSocket-Protocol` picked
+/// among the ones offered by the client.
+///
+/// ```no_run
+/// use ducta::http::Request;
+/// use ducta::websocket::{self, Message, WebSocket, WebSocketHandler};
+/// use ducta::Router;
+///
+/// struct Echo;
+///
+/// impl WebSocketHandler for Echo {
+///     fn on_message(&mut self, ws: &mut WebSocket, message: Message) {
+///         ws.send(message);
+///     }
+/// }
+///
+/// let router = Router::new().get("/ws", |req: Request| websocket::upgrade(&req, Echo));
+/// ```
+pub fn upgrade(req: &Request, handler: impl WebSocketHandler) -> Response {
+    let has_header = |name: &str, token: &str| {
+        req.headers
+            .iter()
+            .filter(|h| h.name.eq_ignore_ascii_case(name))
+            .any(|h| crate::http::list_contains(h.value, token))
+    };
+
+    if !has_header("Upgrade", "websocket") || !req.has_connection_option("upgrade") {
+        return error(426).with_header("Upgrade", "websocket");
+    }
+    if req.method != "GET" || req.version == 0 {
+        return error(400);
+    }
+    if req.get_header("Sec-WebSocket-Version") != Some(b"13") {
+        return error(426).with_header("Sec-WebSocket-Version", "13");
+    }
+
+    let key = match req.get_header("Sec-WebSocket-Key") {
+        Some(key) if BASE64.decode(key.trim_ascii()).is_ok_and(|nonce| nonce.len() == 16) => {
+            key.trim_ascii()
+        }
+        _ => return error(400),
+    };
+
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(key);
+    sha1.update(ACCEPT_GUID.as_bytes());
+    let accept = BASE64.encode(sha1.digest().bytes());
+
+    let mut resp = Response::new(101)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", accept);
+    resp.websocket = Some(Box::new(handler));
+    resp
+}
+
+fn error(status: u16) -> Response {
+    Response::new(status).with_body(reason_phrase(status))
+}
@@ -0,0 +1,273 @@
+use super::frame::{self, Frame, Opcode};
+use super::{close_code, Message, WebSocket, WebSocketHandler};
+use crate::net::Notifier;
+use bytes::BytesMut;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Output queued for a connection by its `WebSocketSender`s
+pub(crate) enum Outgoing {
+    Message(Message),
+    Close(u16, String),
+}
+
+/// Queue shared by a WebSocket connection and its senders
+pub(crate) struct Outbox {
+    state: Mutex<OutboxState>,
+    notifier: Notifier,
+}
+
+struct OutboxState {
+    queue: VecDeque<Outgoing>,
+    /// No more output is accepted
+    closed: bool,
+    /// The worker was notified and didn't empty the queue since, so it doesn't need to be again
+    notified: bool,
+}
+
+impl Outbox {
+    pub fn push(&self, outgoing: Outgoing) -> std::io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(std::io::ErrorKind::NotConnected.into());
+        }
+
+        state.closed = matches!(outgoing, Outgoing::Close(..));
+        state.queue.push_back(outgoing);
+        let notify = !std::mem::replace(&mut state.notified, true);
+        drop(state);
+
+        if notify {
+            self.notifier.notify();
+        }
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.queue.clear();
+    }
+}
+
+/// The WebSocket side of an upgraded connection: reassembles messages out of the frames received,
+/// hands them to the handler, and encodes what is sent back.
+///
+/// Input is read from and output written to the connection's buffers, the session doesn't do any
+/// I/O itself.
+pub(crate) struct Session {
+    handler: Box<dyn WebSocketHandler>,
+    outbox: Arc<Outbox>,
+    max_message_size: usize,
+    /// Opcode and data received so far of a fragmented message
+    partial: Option<(Opcode, BytesMut)>,
+    close_sent: bool,
+    /// The closing handshake is over or the connection failed, nothing else is read or sent
+    done: bool,
+    /// `on_close` was called
+    closed: bool,
+}
+
+impl Session {
+    pub fn new(
+        handler: Box<dyn WebSocketHandler>,
+        notifier: Notifier,
+        max_message_size: usize,
+    ) -> Self {
+        Session {
+            handler,
+            outbox: Arc::new(Outbox {
+                state: Mutex::new(OutboxState {
+                    queue: VecDeque::new(),
+                    closed: false,
+                    notified: false,
+                }),
+                notifier,
+            }),
+            max_message_size,
+            partial: None,
+            close_sent: false,
+            done: false,
+            closed: false,
+        }
+    }
+
+    pub fn open(&mut self, out: &mut BytesMut) {
+        self.run_handler(out, |handler, ws| handler.on_open(ws));
+    }
+
+    /// Handles every complete frame at the start of `input`, writing replies to `out`
+    pub fn receive(&mut self, input: &mut BytesMut, out: &mut BytesMut) {
+        while !self.done {
+            match frame::decode(input, self.max_message_size) {
+                Ok(Some(frame)) => self.on_frame(frame, out),
+                Ok(None) => break,
+                Err(code) => self.fail(code, out),
+            }
+        }
+
+        if self.done {
+            input.clear();
+        }
+    }
+
+    /// Moves output queued by senders to `out` until it holds `window` bytes
+    pub fn fill(&mut self, out: &mut BytesMut, window: usize) {
+        let mut state = self.outbox.state.lock().unwrap();
+
+        while out.len() < window {
+            match state.queue.pop_front() {
+                Some(Outgoing::Message(message)) if !self.close_sent => {
+                    encode_message(out, &message);
+                }
+                Some(Outgoing::Close(code, reason)) if !self.close_sent => {
+                    self.close_sent = true;
+                    frame::encode_close(out, code, &reason);
+                }
+                Some(_) => {}
+                None => {
+                    state.notified = false;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Starts the closing handshake as the server goes away
+    pub fn shutdown(&mut self, out: &mut BytesMut) {
+        if !self.close_sent {
+            self.close_sent = true;
+            self.outbox.close();
+            frame::encode_close(out, close_code::GOING_AWAY, "");
+        }
+    }
+
+    /// Whether a close frame was sent, the client's one being awaited unless `is_done`
+    pub fn is_closing(&self) -> bool {
+        self.close_sent
+    }
+
+    /// Whether the connection must be closed once the output was sent
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn on_frame(&mut self, frame: Frame, out: &mut BytesMut) {
+        match frame.opcode {
+            Opcode::Ping if !self.close_sent => frame::encode(out, Opcode::Pong, &frame.payload),
+            Opcode::Ping | Opcode::Pong => {}
+            Opcode::Close => self.on_close_frame(&frame.payload, out),
+            Opcode::Text | Opcode::Binary if self.partial.is_some() => {
+                // The previous message isn't complete yet
+                self.fail(close_code::PROTOCOL_ERROR, out);
+            }
+            Opcode::Text | Opcode::Binary if frame.fin => {
+                self.deliver(frame.opcode, frame.payload, out);
+            }
+            Opcode::Text | Opcode::Binary => self.partial = Some((frame.opcode, frame.payload)),
+            Opcode::Continuation => {
+                let Some((_, data)) = self.partial.as_mut() else {
+                    return self.fail(close_code::PROTOCOL_ERROR, out);
+                };
+                if data.len() + frame.payload.len() > self.max_message_size {
+                    return self.fail(close_code::MESSAGE_TOO_BIG, out);
+                }
+
+                data.extend_from_slice(&frame.payload);
+                if frame.fin {
+                    let (opcode, data) = self.partial.take().unwrap();
+                    self.deliver(opcode, data, out);
+                }
+            }
+        }
+    }
+
+    fn deliver(&mut self, opcode: Opcode, data: BytesMut, out: &mut BytesMut) {
+        // Messages still in flight after a close frame was sent are dropped
+        if self.close_sent {
+            return;
+        }
+
+        let message = match opcode {
+            Opcode::Text => match String::from_utf8(Vec::from(data)) {
+                Ok(text) => Message::Text(text),
+                Err(_) => return self.fail(close_code::INVALID_DATA, out),
+            },
+            _ => Message::Binary(data.freeze()),
+        };
+
+        self.run_handler(out, |handler, ws| handler.on_message(ws, message));
+    }
+
+    fn on_close_frame(&mut self, payload: &[u8], out: &mut BytesMut) {
+        let (code, reason) = match frame::parse_close(payload) {
+            Ok(close) => close,
+            Err(code) => return self.fail(code, out),
+        };
+
+        // Echo the client's status code back, completing the handshake
+        if !self.close_sent {
+            self.close_sent = true;
+            match code {
+                close_code::NO_STATUS => frame::encode(out, Opcode::Close, &[]),
+                code => frame::encode_close(out, code, ""),
+            }
+        }
+
+        self.done = true;
+        self.report_close(code, reason);
+    }
+
+    /// Fails the connection with `code`, closing it without waiting for the client's close frame
+    fn fail(&mut self, code: u16, out: &mut BytesMut) {
+        if !self.close_sent {
+            self.close_sent = true;
+            frame::encode_close(out, code, "");
+        }
+
+        self.done = true;
+        self.report_close(code, "");
+    }
+
+    fn report_close(&mut self, code: u16, reason: &str) {
+        if !self.closed {
+            self.closed = true;
+            self.outbox.close();
+            self.handler.on_close(code, reason);
+        }
+    }
+
+    fn run_handler(
+        &mut self,
+        out: &mut BytesMut,
+        f: impl FnOnce(&mut dyn WebSocketHandler, &mut WebSocket),
+    ) {
+        let mut ws = WebSocket {
+            out,
+            outbox: &self.outbox,
+            close_sent: &mut self.close_sent,
+        };
+        f(&mut *self.handler, &mut ws);
+
+        if self.close_sent {
+            self.outbox.close();
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.report_close(close_code::ABNORMAL, "");
+    }
+}
+
+pub(crate) fn encode_message(out: &mut BytesMut, message: &Message) {
+    match message {
+        Message::Text(text) => frame::encode(out, Opcode::Text, text.as_bytes()),
+        Message::Binary(data) => frame::encode(out, Opcode::Binary, data),
+    }
+}
@@ -0,0 +1,234 @@
+mod common;
+
+use common::{get, read_response, reset, RunningServer};
+use ducta::handler;
+use ducta::http::{Request, Response};
+use ducta::{rt, Router, ServerBuilder};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Sends a message once dropped, along with the future holding it
+struct DropSignal(Sender<()>);
+
+impl Drop for DropSignal {
+    fn drop(&mut self) {
+        let _ = self.0.send(());
+    }
+}
+
+/// Starts a server whose `/forever` futures report through the returned receiver that they were
+/// dropped
+fn start(builder: ServerBuilder) -> (RunningServer, Receiver<()>) {
+    let (dropped, dropped_rx) = mpsc::channel();
+    let dropped = Mutex::new(dropped);
+
+    let router = Router::new()
+        .get(
+            "/sleep/:millis",
+            handler::from_async(|req: Request| {
+                let millis: u64 = req.params.get("millis").unwrap().parse().unwrap();
+                async move {
+                    rt::sleep(Duration::from_millis(millis)).await;
+                    Response::new(200).with_body(format!("slept {}", millis))
+                }
+            }),
+        )
+        .post(
+            "/blocking",
+            handler::from_async(|req: Request| {
+                let body = req.body.to_vec();
+                async move {
+                    match rt::blocking(move || body.len()).await {
+                        Ok(len) => Response::new(200).with_body(format!("{} bytes", len)),
+                        Err(e) => Response::new(503).with_body(e.to_string()),
+                    }
+                }
+            }),
+        )
+        .get(
+            "/upstream/:port",
+            handler::from_async(|req: Request| {
+                let port: u16 = req.params.get("port").unwrap().parse().unwrap();
+                async move {
+                    let mut upstream =
+                        match rt::TcpStream::connect(([127, 0, 0, 1], port).into()).await {
+                            Ok(upstream) => upstream,
+                            Err(e) => return Response::new(502).with_body(e.to_string()),
+                        };
+
+                    let mut reply = Vec::new();
+                    let mut buf = [0; 64];
+                    let exchanged = async {
+                        upstream.write_all(b"ping").await?;
+                        loop {
+                            match upstream.read(&mut buf).await? {
+                                0 => return Ok::<_, std::io::Error>(()),
+                                n => reply.extend_from_slice(&buf[..n]),
+                            }
+                        }
+                    };
+                    match exchanged.await {
+                        Ok(()) => Response::new(200).with_body(reply),
+                        Err(e) => Response::new(502).with_body(e.to_string()),
+                    }
+                }
+            }),
+        )
+        .get(
+            "/ready",
+            handler::from_async(|_req: Request| async { Response::new(200).with_body("ready") }),
+        )
+        .get(
+            "/panic",
+            handler::from_async(|_req: Request| async {
+                rt::sleep(Duration::from_millis(10)).await;
+                panic!("future panicked")
+            }),
+        )
+        .get(
+            "/forever",
+            handler::from_async(move |_req: Request| {
+                let signal = DropSignal(dropped.lock().unwrap().clone());
+                async move {
+                    let _signal = signal;
+                    rt::sleep(Duration::from_secs(3600)).await;
+                    Response::new(200)
+                }
+            }),
+        );
+
+    let server = RunningServer::start(builder, router);
+    (server, dropped_rx)
+}
+
+#[test]
+fn awaits_timers_without_blocking_the_event_loop() {
+    let (server, _dropped) = start(ServerBuilder::new("127.0.0.1:0"));
+    let started = Instant::now();
+
+    let mut sleeping = server.connect();
+    sleeping
+        .write_all(b"GET /sleep/200 HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+
+    let mut other = server.connect();
+    assert!(get(&mut other, "/sleep/10").ends_with("slept 10"));
+    assert!(started.elapsed() < Duration::from_millis(200));
+
+    assert!(read_response(&mut sleeping).ends_with("slept 200"));
+    assert!(started.elapsed() >= Duration::from_millis(200));
+
+    // Futures ready right away are answered like any other response
+    assert!(get(&mut sleeping, "/ready").ends_with("\r\n\r\nready"));
+
+    drop((sleeping, other));
+    server.stop();
+}
+
+#[test]
+fn awaits_tcp_streams_without_blocking_the_event_loop() {
+    let (server, _dropped) = start(ServerBuilder::new("127.0.0.1:0"));
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = upstream.local_addr().unwrap().port();
+    let upstream = std::thread::spawn(move || {
+        let (mut socket, _) = upstream.accept().unwrap();
+        let mut request = [0; 4];
+        socket.read_exact(&mut request).unwrap();
+        assert_eq!(&request, b"ping");
+        std::thread::sleep(Duration::from_millis(200));
+        socket.write_all(b"pong").unwrap();
+    });
+    let started = Instant::now();
+
+    let mut waiting = server.connect();
+    write!(
+        waiting,
+        "GET /upstream/{} HTTP/1.1\r\nHost: x\r\n\r\n",
+        port
+    )
+    .unwrap();
+
+    let mut other = server.connect();
+    assert!(get(&mut other, "/ready").ends_with("\r\n\r\nready"));
+    assert!(started.elapsed() < Duration::from_millis(200));
+
+    let response = read_response(&mut waiting);
+    assert!(response.ends_with("\r\n\r\npong"), "{}", response);
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    upstream.join().unwrap();
+
+    drop((waiting, other));
+    server.stop();
+}
+
+#[test]
+fn awaits_blocking_work() {
+    let (server, _dropped) = start(ServerBuilder::new("127.0.0.1:0").handler_threads(2));
+    let mut stream = server.connect();
+
+    stream
+        .write_all(b"POST /blocking HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello")
+        .unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\n5 bytes"));
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn refuses_blocking_work_without_handler_threads() {
+    let (server, _dropped) = start(ServerBuilder::new("127.0.0.1:0"));
+    let mut stream = server.connect();
+
+    stream
+        .write_all(b"POST /blocking HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello")
+        .unwrap();
+    let response = read_response(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 503 "), "{}", response);
+    assert!(
+        response.ends_with("see ServerBuilder::handler_threads"),
+        "{}",
+        response
+    );
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn answers_panicking_futures_with_500() {
+    let (server, _dropped) = start(ServerBuilder::new("127.0.0.1:0"));
+    let mut stream = server.connect();
+
+    let response = get(&mut stream, "/panic");
+    assert!(
+        response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"),
+        "{}",
+        response
+    );
+
+    // The event loop survived the panic
+    assert!(get(&mut stream, "/ready").ends_with("\r\n\r\nready"));
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn drops_futures_once_the_client_went_away() {
+    let (server, dropped) = start(ServerBuilder::new("127.0.0.1:0"));
+    let mut stream = server.connect();
+
+    stream
+        .write_all(b"GET /forever HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+    assert!(dropped.recv_timeout(Duration::from_millis(200)).is_err());
+
+    reset(stream);
+    dropped.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    server.stop();
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use common::{get, RunningServer};
+use ducta::http::{Request, Response};
+use ducta::ServerBuilder;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+fn hello(_req: Request) -> Response {
+    Response::new(200).with_body("hello")
+}
+
+/// The reason `builder` is refused for
+fn rejection(builder: ServerBuilder) -> String {
+    let error = builder
+        .build(hello)
+        .err()
+        .expect("invalid configuration accepted");
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    error.to_string()
+}
+
+fn builder() -> ServerBuilder {
+    ServerBuilder::new("127.0.0.1:0")
+}
+
+#[test]
+fn rejects_empty_counts() {
+    let cases = [
+        (builder().workers(0), "workers must be at least 1"),
+        (
+            builder().events_capacity(0),
+            "events_capacity must be at least 1",
+        ),
+        (
+            builder().max_connections(0),
+            "max_connections must be at least 1",
+        ),
+        (builder().buffer_size(0), "buffer_size must be at least 1"),
+        (
+            builder().max_header_size(0),
+            "max_header_size must be at least 1",
+        ),
+        (builder().max_headers(0), "max_headers must be at least 1"),
+        (
+            builder().max_requests_per_connection(0),
+            "max_requests_per_connection must be at least 1",
+        ),
+        (
+            builder().handler_queue_size(0),
+            "handler_queue_size must be at least 1",
+        ),
+    ];
+    for (builder, reason) in cases {
+        assert_eq!(rejection(builder), reason);
+    }
+}
+
+#[test]
+fn rejects_inconsistent_sizes() {
+    let cases = [
+        (
+            builder().max_connections(16).connections_capacity(32),
+            "connections_capacity can't exceed max_connections",
+        ),
+        (
+            builder().buffer_size(4096).max_pooled_buffer_size(4096),
+            "max_pooled_buffer_size must be larger than buffer_size",
+        ),
+        (
+            builder().max_uri_length(0),
+            "max_uri_length must be between 1 and max_header_size",
+        ),
+        (
+            builder().max_header_size(1024).max_uri_length(1025),
+            "max_uri_length must be between 1 and max_header_size",
+        ),
+    ];
+    for (builder, reason) in cases {
+        assert_eq!(rejection(builder), reason);
+    }
+}
+
+#[test]
+fn rejects_zero_timeouts() {
+    let cases = [
+        (
+            builder().header_read_timeout(Duration::ZERO),
+            "header_read_timeout can't be zero",
+        ),
+        (
+            builder().body_read_timeout(Duration::ZERO),
+            "body_read_timeout can't be zero",
+        ),
+        (
+            builder().write_timeout(Duration::ZERO),
+            "write_timeout can't be zero",
+        ),
+        (
+            builder().keep_alive_timeout(Duration::ZERO),
+            "keep_alive_timeout can't be zero",
+        ),
+        (
+            builder().linger_timeout(Duration::ZERO),
+            "linger_timeout can't be zero",
+        ),
+        (
+            builder().heartbeat_interval(Duration::ZERO),
+            "heartbeat_interval can't be zero",
+        ),
+    ];
+    for (builder, reason) in cases {
+        assert_eq!(rejection(builder), reason);
+    }
+}
+
+#[test]
+fn rejects_malformed_trusted_proxies() {
+    for proxy in ["proxy.local", "10.0.0.0/33", "10.0.0.1/", "::1/129"] {
+        rejection(builder().trusted_proxies([proxy]));
+    }
+}
+
+#[test]
+fn accepts_the_smallest_valid_configuration() {
+    let builder = builder()
+        .workers(1)
+        .events_capacity(1)
+        .max_connections(1)
+        .connections_capacity(1)
+        .pooled_buffers(0)
+        .buffer_size(1)
+        .max_pooled_buffer_size(2)
+        .max_header_size(64)
+        .max_headers(1)
+        .max_uri_length(1)
+        .max_body_size(0)
+        .max_requests_per_connection(1)
+        .trusted_proxies(["10.0.0.1", "10.0.0.0/8", "::1", "fd00::/8"]);
+    let server = RunningServer::start(builder, hello);
+
+    let response = get(&mut server.connect(), "/");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello"));
+
+    server.stop();
+}
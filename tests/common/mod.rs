@@ -0,0 +1,159 @@
+//! Harness shared by the integration tests: servers running on a thread of their own, and the
+//! client side helpers reading what they answer.
+
+// Each test binary compiles its own copy and only uses part of it
+#![allow(dead_code)]
+
+use ducta::handler::Handler;
+use ducta::{ServerBuilder, ShutdownHandle};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub struct RunningServer {
+    pub addr: SocketAddr,
+    /// Address of the Unix domain socket, for servers listening on one
+    pub unix_addr: Option<std::os::unix::net::SocketAddr>,
+    pub handle: ShutdownHandle,
+    thread: JoinHandle<std::io::Result<()>>,
+}
+
+impl RunningServer {
+    /// Builds a server serving `handler` and runs it on its own thread
+    pub fn start<H: Handler>(builder: ServerBuilder, handler: H) -> RunningServer {
+        let mut server = builder.build(handler).unwrap();
+        RunningServer {
+            addr: server.local_addr(),
+            unix_addr: server.unix_addr().cloned(),
+            handle: server.shutdown_handle(),
+            thread: std::thread::spawn(move || server.run()),
+        }
+    }
+
+    pub fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    pub fn connect_unix(&self) -> UnixStream {
+        let stream = UnixStream::connect_addr(self.unix_addr.as_ref().unwrap()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    /// Shuts the server down and waits for `run` to return
+    pub fn stop(self) {
+        self.handle.shutdown();
+        self.thread.join().unwrap().unwrap();
+    }
+}
+
+/// Reads a response head byte by byte, leaving whatever follows it unread
+pub fn read_head(stream: &mut impl Read) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+/// Reads a single response with a `Content-Length` body
+pub fn read_response(stream: &mut impl Read) -> String {
+    let head = read_head(stream);
+    let len: usize = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .unwrap()
+        .parse()
+        .unwrap();
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).unwrap();
+
+    head + &String::from_utf8(body).unwrap()
+}
+
+/// Reads a single response with a `Content-Length` body, returning its body
+pub fn read_body(stream: &mut impl Read) -> String {
+    let response = read_response(stream);
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    body.to_owned()
+}
+
+pub fn get(stream: &mut (impl Read + Write), path: &str) -> String {
+    write!(stream, "GET {} HTTP/1.1\r\nHost: x\r\n\r\n", path).unwrap();
+    read_response(stream)
+}
+
+/// Reads from `stream` until what was received ends with `end`
+pub fn read_until(stream: &mut impl Read, end: &str) -> String {
+    let mut received = Vec::new();
+    let mut byte = [0];
+    while !received.ends_with(end.as_bytes()) {
+        let n = stream.read(&mut byte).unwrap();
+        assert_eq!(
+            n,
+            1,
+            "connection closed after {:?}",
+            String::from_utf8_lossy(&received)
+        );
+        received.push(byte[0]);
+    }
+    String::from_utf8(received).unwrap()
+}
+
+pub fn read_until_closed(stream: &mut impl Read) -> String {
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    String::from_utf8(received).unwrap()
+}
+
+/// Whether the server closed the connection without answering
+pub fn is_closed(stream: &mut impl Read) -> bool {
+    let mut buf = [0; 64];
+    matches!(stream.read(&mut buf), Ok(0) | Err(_))
+}
+
+/// A fresh directory for `name`, removed beforehand in case a previous run left it behind
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ducta-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Client configuration trusting only `cert` and offering `alpn`
+#[cfg(feature = "tls")]
+pub fn tls_client(
+    cert: &rustls::pki_types::CertificateDer<'static>,
+    alpn: &[&[u8]],
+) -> std::sync::Arc<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert.clone()).unwrap();
+
+    let provider = std::sync::Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+    std::sync::Arc::new(config)
+}
+
+/// Closes `stream` with a reset rather than a clean shutdown, like a client that went away
+/// without the server getting to answer, as a clean one may only end the client's side
+pub fn reset(stream: TcpStream) {
+    socket2::SockRef::from(&stream)
+        .set_linger(Some(Duration::ZERO))
+        .unwrap();
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::{read_body, RunningServer};
+use ducta::http::{Request, Response};
+use ducta::ServerBuilder;
+use std::io::{ErrorKind, Write};
+use std::net::TcpStream;
+
+/// Starts a server answering every request with its connection's id, peer and local addresses,
+/// its sequence number and its client address
+fn start(builder: ServerBuilder) -> RunningServer {
+    let handler = |req: Request| {
+        let info = req.connection;
+        Response::new(200).with_body(format!(
+            "{} {} {} {} {}",
+            info.id,
+            info.peer_addr,
+            info.local_addr,
+            req.sequence,
+            req.client_addr()
+        ))
+    };
+
+    RunningServer::start(builder, handler)
+}
+
+/// Reads a single response, returning its body split into its fields
+fn read_fields(stream: &mut TcpStream) -> Vec<String> {
+    read_body(stream).split(' ').map(str::to_owned).collect()
+}
+
+/// Sends a request with the extra `headers`, returning the client address it resolved to
+fn client_addr(stream: &mut TcpStream, headers: &str) -> String {
+    write!(stream, "GET / HTTP/1.1\r\nHost: x\r\n{}\r\n", headers).unwrap();
+    read_fields(stream).swap_remove(4)
+}
+
+fn exposes_connection_info(builder: ServerBuilder) {
+    let server = start(builder);
+    let mut stream = server.connect();
+
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\nGET / HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+    let first = read_fields(&mut stream);
+    let second = read_fields(&mut stream);
+
+    assert_eq!(first[1], stream.local_addr().unwrap().to_string());
+    assert_eq!(first[2], server.addr.to_string());
+    assert_eq!((&*first[3], &*second[3]), ("1", "2"));
+    assert_eq!(first[4], "127.0.0.1");
+
+    // The id is kept by the connection, and differs from the next one
+    assert_eq!(first[0], second[0]);
+    let mut other = server.connect();
+    write!(other, "GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    let other_fields = read_fields(&mut other);
+    assert_ne!(other_fields[0], first[0]);
+    assert_eq!(other_fields[3], "1");
+
+    drop((stream, other));
+    server.stop();
+}
+
+#[test]
+fn exposes_addresses_ids_and_sequence_numbers() {
+    exposes_connection_info(ServerBuilder::new("127.0.0.1:0"));
+}
+
+#[test]
+fn exposes_connection_info_to_pooled_handlers() {
+    exposes_connection_info(ServerBuilder::new("127.0.0.1:0").handler_threads(2));
+}
+
+#[test]
+fn ignores_forwarding_headers_by_default() {
+    let server = start(ServerBuilder::new("127.0.0.1:0"));
+    let mut stream = server.connect();
+
+    let forged = "X-Forwarded-For: 203.0.113.7\r\nForwarded: for=198.51.100.1\r\n";
+    assert_eq!(client_addr(&mut stream, forged), "127.0.0.1");
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn resolves_clients_behind_trusted_proxies() {
+    let builder = ServerBuilder::new("127.0.0.1:0").trusted_proxies(["127.0.0.1", "10.0.0.0/8"]);
+    let server = start(builder);
+    let mut stream = server.connect();
+
+    // Walked back up to the first hop that isn't trusted
+    let headers = "X-Forwarded-For: 198.51.100.1, 203.0.113.7\r\nX-Forwarded-For: 10.1.2.3\r\n";
+    assert_eq!(client_addr(&mut stream, headers), "203.0.113.7");
+
+    let headers = "X-Forwarded-For: 10.0.0.2, 10.0.0.1\r\n";
+    assert_eq!(client_addr(&mut stream, headers), "10.0.0.2");
+
+    // Forwarded wins over X-Forwarded-For
+    let headers = "X-Forwarded-For: 203.0.113.7\r\n\
+                   Forwarded: for=192.0.2.60;proto=http, for=\"[2001:db8::17]:4711\"\r\n";
+    assert_eq!(client_addr(&mut stream, headers), "2001:db8::17");
+
+    let headers = "Forwarded: for=192.0.2.43:80;by=10.0.0.1, for=10.0.0.5\r\n";
+    assert_eq!(client_addr(&mut stream, headers), "192.0.2.43");
+
+    // Unreadable hops stop at the proxy that reported them
+    let headers = "Forwarded: for=192.0.2.60, for=unknown, for=10.0.0.5\r\n";
+    assert_eq!(client_addr(&mut stream, headers), "10.0.0.5");
+
+    assert_eq!(client_addr(&mut stream, ""), "127.0.0.1");
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn rejects_invalid_trusted_proxies() {
+    for proxy in ["10.0.0.0/33", "::1/129", "localhost", "10.0.0.0/"] {
+        let err = ServerBuilder::new("127.0.0.1:0")
+            .trusted_proxies([proxy])
+            .build(|_req: Request| Response::new(200))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", proxy);
+    }
+}
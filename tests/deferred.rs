@@ -0,0 +1,124 @@
+mod common;
+
+use common::{get, read_response, reset, RunningServer};
+use ducta::handler::{self, Responder};
+use ducta::http::{Request, Response};
+use ducta::{Router, ServerBuilder};
+use std::io::Write;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Starts a server whose `/held` handlers hand their responders over through the returned
+/// receiver
+fn start() -> (RunningServer, Receiver<Responder>) {
+    let (held, held_rx) = mpsc::channel();
+    let held = Mutex::new(held);
+
+    let router = Router::new()
+        .get(
+            "/held",
+            handler::deferred(move |_req: Request, responder: Responder| {
+                let _ = held.lock().unwrap().send(responder);
+            }),
+        )
+        .get(
+            "/later",
+            handler::deferred(|req: Request, responder: Responder| {
+                let body = format!("later {}", req.path);
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(50));
+                    responder.respond(Response::new(200).with_body(body));
+                });
+            }),
+        )
+        .get(
+            "/now",
+            handler::deferred(|_req: Request, responder: Responder| {
+                responder.respond(Response::new(200).with_body("now"));
+            }),
+        )
+        .get("/dropped", |_req: Request| handler::defer().0)
+        .get("/plain", |_req: Request| {
+            Response::new(200).with_body("plain")
+        });
+
+    let server = RunningServer::start(ServerBuilder::new("127.0.0.1:0"), router);
+    (server, held_rx)
+}
+
+#[test]
+fn sends_responses_completed_later() {
+    let (server, _held) = start();
+    let mut stream = server.connect();
+
+    let response = get(&mut stream, "/later");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("later /later"), "{}", response);
+
+    // Answered before the handler returned
+    assert!(get(&mut stream, "/now").ends_with("\r\n\r\nnow"));
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn keeps_pipelined_requests_waiting() {
+    let (server, held) = start();
+    let mut stream = server.connect();
+
+    stream
+        .write_all(b"GET /held HTTP/1.1\r\nHost: x\r\n\r\nGET /plain HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+    let responder = held.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    // Other connections are served meanwhile
+    let mut other = server.connect();
+    assert!(get(&mut other, "/plain").ends_with("\r\n\r\nplain"));
+
+    responder.respond(Response::new(200).with_body("held"));
+    assert!(read_response(&mut stream).ends_with("\r\n\r\nheld"));
+    assert!(read_response(&mut stream).ends_with("\r\n\r\nplain"));
+
+    drop((stream, other));
+    server.stop();
+}
+
+#[test]
+fn answers_dropped_responders_with_500() {
+    let (server, _held) = start();
+    let mut stream = server.connect();
+
+    let response = get(&mut stream, "/dropped");
+    assert!(
+        response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"),
+        "{}",
+        response
+    );
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn cancels_responses_once_the_client_went_away() {
+    let (server, held) = start();
+    let mut stream = server.connect();
+
+    stream
+        .write_all(b"GET /held HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+    let responder = held.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(!responder.is_cancelled());
+
+    let (cancelled, cancelled_rx) = mpsc::channel();
+    responder.on_cancel(move || cancelled.send(()).unwrap());
+    reset(stream);
+
+    cancelled_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(responder.is_cancelled());
+    responder.respond(Response::new(200));
+
+    server.stop();
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use common::{is_closed, read_response, RunningServer};
+use ducta::http::{Request, Response};
+use ducta::ServerBuilder;
+use std::io::Write;
+use std::time::Duration;
+
+fn start() -> RunningServer {
+    let builder = ServerBuilder::new("127.0.0.1:0")
+        .max_header_size(1024)
+        .max_headers(8)
+        .max_uri_length(128)
+        .max_body_size(64)
+        // Refused connections are closed once it's over, the client never closing them
+        .linger_timeout(Duration::from_millis(100));
+    RunningServer::start(builder, |_req: Request| {
+        Response::new(200).with_body("handled")
+    })
+}
+
+/// Sends `request` on a fresh connection, checking the server answered it with `status` and
+/// closed the connection
+fn assert_refused(server: &RunningServer, request: &[u8], status: u16, reason: &str) {
+    let mut stream = server.connect();
+    stream.write_all(request).unwrap();
+
+    let response = read_response(&mut stream);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(
+        head.starts_with(&format!("HTTP/1.1 {} {}\r\n", status, reason)),
+        "{}",
+        head
+    );
+    assert!(
+        head.lines().any(|line| line == "Connection: close"),
+        "{}",
+        head
+    );
+    assert_eq!(body, reason);
+    assert!(is_closed(&mut stream));
+}
+
+#[test]
+fn answers_malformed_requests_with_bad_request() {
+    let server = start();
+    let requests: [&[u8]; 5] = [
+        b"GET / HTTP/1.1\r\nBad Header: x\r\n\r\n",
+        b"GET\r\n\r\n",
+        b"GET / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab",
+        b"POST / HTTP/1.1\r\nContent-Length: 2\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+    ];
+    for request in requests {
+        assert_refused(&server, request, 400, "Bad Request");
+    }
+    server.stop();
+}
+
+#[test]
+fn answers_large_bodies_with_content_too_large() {
+    let server = start();
+    let body = "b".repeat(65);
+
+    let request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", body.len());
+    assert_refused(&server, request.as_bytes(), 413, "Content Too Large");
+
+    // Only known once enough chunks were decoded
+    let request = format!(
+        "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n20\r\n{}\r\n21\r\n{}\r\n0\r\n\r\n",
+        &body[..32],
+        &body[32..]
+    );
+    assert_refused(&server, request.as_bytes(), 413, "Content Too Large");
+
+    server.stop();
+}
+
+#[test]
+fn answers_long_targets_with_uri_too_long() {
+    let server = start();
+
+    // Within the header limit
+    let request = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(128));
+    assert_refused(&server, request.as_bytes(), 414, "URI Too Long");
+
+    // Past the header limit before the request line even ended
+    let request = format!("GET /{}", "a".repeat(1024));
+    assert_refused(&server, request.as_bytes(), 414, "URI Too Long");
+
+    server.stop();
+}
+
+#[test]
+fn answers_large_heads_with_header_fields_too_large() {
+    let server = start();
+
+    let request = format!("GET / HTTP/1.1\r\nX-Large: {}\r\n\r\n", "a".repeat(1024));
+    assert_refused(
+        &server,
+        request.as_bytes(),
+        431,
+        "Request Header Fields Too Large",
+    );
+
+    let headers: String = (0..9).map(|i| format!("X-Header-{}: x\r\n", i)).collect();
+    let request = format!("GET / HTTP/1.1\r\n{}\r\n", headers);
+    assert_refused(
+        &server,
+        request.as_bytes(),
+        431,
+        "Request Header Fields Too Large",
+    );
+
+    server.stop();
+}
+
+#[test]
+fn answers_other_versions_with_version_not_supported() {
+    let server = start();
+    assert_refused(
+        &server,
+        b"GET / HTTP/2.0\r\nHost: x\r\n\r\n",
+        505,
+        "HTTP Version Not Supported",
+    );
+    server.stop();
+}
+
+#[test]
+fn answers_other_transfer_codings_with_not_implemented() {
+    let server = start();
+    assert_refused(
+        &server,
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n",
+        501,
+        "Not Implemented",
+    );
+    server.stop();
+}
+
+#[test]
+fn keeps_serving_after_requests_within_the_limits() {
+    let server = start();
+    let mut stream = server.connect();
+
+    let headers: String = (0..7).map(|i| format!("X-Header-{}: x\r\n", i)).collect();
+    write!(
+        stream,
+        "POST /{} HTTP/1.1\r\n{}Content-Length: 64\r\n\r\n{}",
+        "a".repeat(127),
+        headers,
+        "b".repeat(64)
+    )
+    .unwrap();
+    let response = read_response(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\nhandled"));
+
+    server.stop();
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use common::{get, read_response, RunningServer};
+use ducta::http::{Request, Response};
+use ducta::{Router, ServerBuilder};
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Holds the handlers of `/blocked` until opened
+#[derive(Clone, Default)]
+struct Gate(Arc<(Mutex<bool>, Condvar)>);
+
+impl Gate {
+    fn wait(&self) {
+        let (open, opened) = &*self.0;
+        let mut open = open.lock().unwrap();
+        while !*open {
+            open = opened.wait(open).unwrap();
+        }
+    }
+
+    fn open(&self) {
+        let (open, opened) = &*self.0;
+        *open.lock().unwrap() = true;
+        opened.notify_all();
+    }
+}
+
+/// Starts a server whose `/blocked` handlers report they started through the returned receiver,
+/// then wait for the gate to open
+fn start(builder: ServerBuilder, gate: &Gate) -> (RunningServer, Receiver<()>) {
+    let (started, started_rx) = mpsc::channel();
+    let started = Mutex::new(started);
+    let gate = gate.clone();
+
+    let router = Router::new()
+        .get("/blocked", move |_req: Request| {
+            let _ = started.lock().unwrap().send(());
+            gate.wait();
+            Response::new(200).with_body("unblocked")
+        })
+        .get("/panic", |_req: Request| -> Response {
+            panic!("handler panicked")
+        })
+        .post("/echo", |req: Request| {
+            Response::new(200).with_body(req.body.to_vec())
+        });
+
+    (RunningServer::start(builder, router), started_rx)
+}
+
+#[test]
+fn keeps_serving_while_handlers_block() {
+    let gate = Gate::default();
+    let (server, started) = start(ServerBuilder::new("127.0.0.1:0").handler_threads(2), &gate);
+
+    let mut blocked = server.connect();
+    blocked
+        .write_all(b"GET /blocked HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+    started.recv().unwrap();
+
+    // Served by the other handler thread, the worker isn't held up
+    let mut other = server.connect();
+    other
+        .write_all(b"POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello")
+        .unwrap();
+    assert!(read_response(&mut other).ends_with("\r\n\r\nhello"));
+
+    gate.open();
+    let response = read_response(&mut blocked);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("unblocked"), "{}", response);
+
+    // The connection is kept alive after a response from the pool
+    assert!(get(&mut blocked, "/blocked").ends_with("unblocked"));
+
+    drop((blocked, other));
+    server.stop();
+}
+
+#[test]
+fn refuses_requests_once_the_queue_is_full() {
+    let gate = Gate::default();
+    let builder = ServerBuilder::new("127.0.0.1:0")
+        .handler_threads(1)
+        .handler_queue_size(1);
+    let (server, started) = start(builder, &gate);
+
+    // Taken by the only handler thread
+    let mut running = server.connect();
+    running
+        .write_all(b"GET /blocked HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+    started.recv().unwrap();
+
+    // Fills the queue
+    let mut queued = server.connect();
+    queued
+        .write_all(b"GET /blocked HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+
+    let mut refused = server.connect();
+    let response = get(&mut refused, "/blocked");
+    assert!(
+        response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.contains("Retry-After: 1\r\n"), "{}", response);
+
+    gate.open();
+    assert!(read_response(&mut running).ends_with("unblocked"));
+    assert!(read_response(&mut queued).ends_with("unblocked"));
+
+    drop((running, queued, refused));
+    server.stop();
+}
+
+#[test]
+fn bounds_what_is_buffered_behind_a_blocked_handler() {
+    let gate = Gate::default();
+    let (server, started) = start(ServerBuilder::new("127.0.0.1:0").handler_threads(1), &gate);
+    let mut stream = server.connect();
+    stream
+        .write_all(b"GET /blocked HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+    started.recv().unwrap();
+
+    // Once the server stops reading, writes stall as soon as the socket buffers are full
+    stream
+        .set_write_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let junk = vec![b'x'; 64 * 1024];
+    let mut sent = 0;
+    while sent < 64 * 1024 * 1024 {
+        match stream.write(&junk) {
+            Ok(n) => sent += n,
+            Err(_) => break,
+        }
+    }
+    assert!(sent < 32 * 1024 * 1024, "{} bytes buffered", sent);
+
+    gate.open();
+    assert!(read_response(&mut stream).ends_with("unblocked"));
+    // The junk is a request line too long to be one
+    let response = read_response(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 414 "), "{}", response);
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn answers_panicking_handlers_with_500() {
+    let (server, _started) = start(
+        ServerBuilder::new("127.0.0.1:0").handler_threads(1),
+        &Gate::default(),
+    );
+    let mut stream = server.connect();
+
+    let response = get(&mut stream, "/panic");
+    assert!(
+        response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"),
+        "{}",
+        response
+    );
+
+    // The thread survived the panic
+    stream
+        .write_all(b"POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 2\r\n\r\nok")
+        .unwrap();
+    assert!(read_response(&mut stream).ends_with("\r\n\r\nok"));
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn answers_pipelined_requests_in_order() {
+    let (server, _started) = start(
+        ServerBuilder::new("127.0.0.1:0").handler_threads(4),
+        &Gate::default(),
+    );
+    let mut stream = server.connect();
+
+    let mut requests = String::new();
+    for i in 0..10 {
+        requests += &format!(
+            "POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 1\r\n\r\n{}",
+            i
+        );
+    }
+    stream.write_all(requests.as_bytes()).unwrap();
+
+    for i in 0..10 {
+        assert!(read_response(&mut stream).ends_with(&format!("\r\n\r\n{}", i)));
+    }
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn answers_clients_that_shut_their_side_down_while_waiting() {
+    let gate = Gate::default();
+    let (server, started) = start(ServerBuilder::new("127.0.0.1:0").handler_threads(1), &gate);
+
+    let mut stream = server.connect();
+    stream
+        .write_all(b"GET /blocked HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+    started.recv().unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    // Lets the worker notice the end of the input before the response is ready
+    std::thread::sleep(Duration::from_millis(50));
+
+    gate.open();
+    assert!(read_response(&mut stream).ends_with("unblocked"));
+    assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+
+    server.stop();
+}
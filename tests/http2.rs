@@ -0,0 +1,417 @@
+mod common;
+
+use common::RunningServer;
+use ducta::http::{Request, Response};
+use ducta::{Router, ServerBuilder};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const SETTINGS: u8 = 0x4;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+
+/// `:status: 200` and `:status: 404`, as indexed in the HPACK static table
+const STATUS_200: u8 = 0x88;
+const STATUS_404: u8 = 0x8d;
+
+fn start() -> RunningServer {
+    let router = Router::new()
+        .get("/", |req: Request| {
+            let host = req.get_header("Host").unwrap_or_default();
+            Response::new(200).with_body(format!(
+                "version {}, host {}",
+                req.version,
+                String::from_utf8_lossy(host)
+            ))
+        })
+        .get("/big", |_req: Request| {
+            Response::new(200).with_body(vec![b'x'; 100])
+        })
+        .post("/echo", |req: Request| {
+            Response::new(200).with_body(req.body.to_vec())
+        })
+        .get("/upgrade", |_req: Request| {
+            Response::new(101).with_header("Upgrade", "websocket")
+        });
+
+    RunningServer::start(ServerBuilder::new("127.0.0.1:0"), router)
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+/// A bare HTTP/2 client, sending header blocks made of literal fields only
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    /// Connects with prior knowledge, sending the preface and `settings`, then reads the server's
+    /// SETTINGS frame and the acknowledgement of ours
+    fn connect(addr: SocketAddr, settings: &[(u16, u32)]) -> Client {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut client = Client { stream };
+
+        client.stream.write_all(PREFACE).unwrap();
+        client.send_settings(settings);
+        client.handshake();
+        client
+    }
+
+    fn handshake(&mut self) {
+        let frame = self.read_frame();
+        assert_eq!((frame.kind, frame.flags), (SETTINGS, 0));
+        self.send_frame(SETTINGS, ACK, 0, &[]);
+        let frame = self.read_frame();
+        assert_eq!((frame.kind, frame.flags), (SETTINGS, ACK));
+    }
+
+    fn send_settings(&mut self, settings: &[(u16, u32)]) {
+        let mut payload = Vec::new();
+        for &(id, value) in settings {
+            payload.extend_from_slice(&id.to_be_bytes());
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        self.send_frame(SETTINGS, 0, 0, &payload);
+    }
+
+    fn send_frame(&mut self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.push(kind);
+        frame.push(flags);
+        frame.extend_from_slice(&stream_id.to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame).unwrap();
+    }
+
+    fn send_request(&mut self, stream_id: u32, method: &str, path: &str, end_stream: bool) {
+        let mut block = Vec::new();
+        for (name, value) in [
+            (":method", method),
+            (":scheme", "http"),
+            (":path", path),
+            (":authority", "example.test"),
+        ] {
+            // Literal field without indexing, with a literal name
+            block.push(0x00);
+            block.push(name.len() as u8);
+            block.extend_from_slice(name.as_bytes());
+            block.push(value.len() as u8);
+            block.extend_from_slice(value.as_bytes());
+        }
+
+        let flags = if end_stream {
+            END_HEADERS | END_STREAM
+        } else {
+            END_HEADERS
+        };
+        self.send_frame(HEADERS, flags, stream_id, &block);
+    }
+
+    fn read_frame(&mut self) -> Frame {
+        let mut header = [0; 9];
+        self.stream.read_exact(&mut header).unwrap();
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let mut payload = vec![0; len];
+        self.stream.read_exact(&mut payload).unwrap();
+
+        Frame {
+            kind: header[3],
+            flags: header[4],
+            stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]]),
+            payload,
+        }
+    }
+
+    /// Reads the response of `stream_id`, returning the first byte of its header block, which
+    /// holds the status, and its body
+    fn read_response(&mut self, stream_id: u32) -> (u8, String) {
+        let frame = self.read_frame();
+        assert_eq!((frame.kind, frame.stream_id), (HEADERS, stream_id));
+        assert!(frame.flags & END_HEADERS != 0);
+        let status = frame.payload[0];
+
+        let mut body = Vec::new();
+        let mut end_stream = frame.flags & END_STREAM != 0;
+        while !end_stream {
+            let frame = self.read_frame();
+            assert_eq!((frame.kind, frame.stream_id), (DATA, stream_id));
+            body.extend_from_slice(&frame.payload);
+            end_stream = frame.flags & END_STREAM != 0;
+        }
+
+        (status, String::from_utf8(body).unwrap())
+    }
+}
+
+#[test]
+fn serves_requests_with_prior_knowledge() {
+    let server = start();
+    let mut client = Client::connect(server.addr, &[]);
+
+    client.send_request(1, "GET", "/", true);
+    assert_eq!(
+        client.read_response(1),
+        (STATUS_200, "version 2, host example.test".to_string())
+    );
+
+    client.send_request(3, "GET", "/missing", true);
+    assert_eq!(client.read_response(3).0, STATUS_404);
+
+    drop(client);
+    server.stop();
+}
+
+#[test]
+fn multiplexes_streams() {
+    let server = start();
+    let mut client = Client::connect(server.addr, &[]);
+
+    // The first request is still being sent while the second one is answered
+    client.send_request(1, "POST", "/echo", false);
+    client.send_frame(DATA, 0, 1, b"hello ");
+    client.send_request(3, "GET", "/big", true);
+    assert_eq!(client.read_response(3), (STATUS_200, "x".repeat(100)));
+
+    client.send_frame(DATA, END_STREAM, 1, b"world");
+    assert_eq!(
+        client.read_response(1),
+        (STATUS_200, "hello world".to_string())
+    );
+
+    drop(client);
+    server.stop();
+}
+
+#[test]
+fn refuses_protocol_switches() {
+    let server = start();
+    let mut client = Client::connect(server.addr, &[]);
+
+    client.send_request(1, "GET", "/upgrade", true);
+    let frame = client.read_frame();
+    assert_eq!((frame.kind, frame.stream_id), (HEADERS, 1));
+    // :status literal, as 501 isn't in the static table, Huffman coded
+    assert_eq!(frame.payload[..4], [0x08, 0x82, 0x6c, 0x01]);
+
+    // The connection is still usable
+    client.send_request(3, "GET", "/", true);
+    loop {
+        let frame = client.read_frame();
+        if frame.stream_id == 3 {
+            assert_eq!((frame.kind, frame.payload[0]), (HEADERS, STATUS_200));
+            break;
+        }
+    }
+
+    drop(client);
+    server.stop();
+}
+
+#[test]
+fn answers_pings() {
+    let server = start();
+    let mut client = Client::connect(server.addr, &[]);
+
+    client.send_frame(PING, 0, 0, b"12345678");
+    let frame = client.read_frame();
+    assert_eq!((frame.kind, frame.flags), (PING, ACK));
+    assert_eq!(frame.payload, b"12345678");
+
+    drop(client);
+    server.stop();
+}
+
+#[test]
+fn stops_reading_while_replies_pile_up() {
+    let server = start();
+    let mut client = Client::connect(server.addr, &[]);
+
+    // Acknowledgements are queued no faster than the client reads them, so writes stall once the
+    // socket buffers are full
+    client
+        .stream
+        .set_write_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let ping = [&[0, 0, 8, PING, 0, 0, 0, 0, 0][..], b"12345678"].concat();
+    let pings = ping.repeat(4096);
+    let mut sent = 0;
+    while sent < 64 * 1024 * 1024 {
+        match client.stream.write(&pings[sent % pings.len()..]) {
+            Ok(n) => sent += n,
+            Err(_) => break,
+        }
+    }
+    assert!(sent < 32 * 1024 * 1024, "{} bytes buffered", sent);
+
+    // Every ping is answered once the client reads, before the request that follows them
+    let pinged = sent.div_ceil(ping.len());
+    let mut reader = Client {
+        stream: client.stream.try_clone().unwrap(),
+    };
+    let reader = std::thread::spawn(move || {
+        for _ in 0..pinged {
+            let frame = reader.read_frame();
+            assert_eq!((frame.kind, frame.flags), (PING, ACK));
+        }
+        reader.read_response(1).0
+    });
+
+    // Completes the ping cut short by the timeout, if any
+    client.stream.set_write_timeout(None).unwrap();
+    if sent % ping.len() != 0 {
+        client.stream.write_all(&ping[sent % ping.len()..]).unwrap();
+    }
+    client.send_request(1, "GET", "/", true);
+    assert_eq!(reader.join().unwrap(), STATUS_200);
+
+    drop(client);
+    server.stop();
+}
+
+#[test]
+fn respects_flow_control_windows() {
+    let server = start();
+    // SETTINGS_INITIAL_WINDOW_SIZE
+    let mut client = Client::connect(server.addr, &[(0x4, 30)]);
+
+    client.send_request(1, "GET", "/big", true);
+    let frame = client.read_frame();
+    assert_eq!((frame.kind, frame.payload[0]), (HEADERS, STATUS_200));
+
+    let mut received = 0;
+    while received < 30 {
+        let frame = client.read_frame();
+        assert_eq!((frame.kind, frame.flags), (DATA, 0));
+        received += frame.payload.len();
+    }
+    assert_eq!(received, 30);
+
+    // The window is exhausted, so the ping is answered before any more data
+    client.send_frame(PING, 0, 0, b"blocked?");
+    assert_eq!(client.read_frame().kind, PING);
+
+    client.send_frame(WINDOW_UPDATE, 0, 1, &70u32.to_be_bytes());
+    let mut end_stream = false;
+    while !end_stream {
+        let frame = client.read_frame();
+        assert_eq!(frame.kind, DATA);
+        received += frame.payload.len();
+        end_stream = frame.flags & END_STREAM != 0;
+    }
+    assert_eq!(received, 100);
+
+    drop(client);
+    server.stop();
+}
+
+#[test]
+fn upgrades_from_http1() {
+    let server = start();
+    let mut stream = server.connect();
+
+    // An empty SETTINGS payload
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: example.test\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+              Upgrade: h2c\r\nHTTP2-Settings: \r\n\r\n",
+        )
+        .unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).unwrap();
+    assert!(
+        head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+        "{}",
+        head
+    );
+    assert!(head.contains("Upgrade: h2c\r\n"), "{}", head);
+
+    // The upgrade request is answered on the first stream, right behind the server's SETTINGS
+    let mut client = Client { stream };
+    let frame = client.read_frame();
+    assert_eq!((frame.kind, frame.flags), (SETTINGS, 0));
+    assert_eq!(
+        client.read_response(1),
+        (STATUS_200, "version 1, host example.test".to_string())
+    );
+
+    client.stream.write_all(PREFACE).unwrap();
+    client.send_settings(&[]);
+    client.send_frame(SETTINGS, ACK, 0, &[]);
+    let frame = client.read_frame();
+    assert_eq!((frame.kind, frame.flags), (SETTINGS, ACK));
+
+    client.send_request(3, "GET", "/", true);
+    assert_eq!(
+        client.read_response(3),
+        (STATUS_200, "version 2, host example.test".to_string())
+    );
+
+    drop(client);
+    server.stop();
+}
+
+#[test]
+fn fails_the_connection_on_protocol_errors() {
+    let server = start();
+    let mut client = Client::connect(server.addr, &[]);
+
+    // DATA frames always belong to a stream
+    client.send_frame(DATA, 0, 0, b"oops");
+    let frame = client.read_frame();
+    assert_eq!(frame.kind, GOAWAY);
+    // PROTOCOL_ERROR
+    assert_eq!(&frame.payload[4..8], &1u32.to_be_bytes());
+
+    let mut rest = Vec::new();
+    client.stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    drop(client);
+    server.stop();
+}
+
+#[test]
+fn sends_goaway_on_shutdown() {
+    let server = start();
+    let mut client = Client::connect(server.addr, &[]);
+    client.send_request(1, "GET", "/", true);
+    client.read_response(1);
+
+    server.handle.shutdown();
+
+    let frame = client.read_frame();
+    assert_eq!(frame.kind, GOAWAY);
+    // The last stream handled, and NO_ERROR
+    assert_eq!(frame.payload, [0, 0, 0, 1, 0, 0, 0, 0]);
+
+    let mut rest = Vec::new();
+    client.stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    drop(client);
+    server.stop();
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use bytes::Bytes;
+use common::{is_closed, read_response, read_until_closed, RunningServer};
+use ducta::http::{Request, Response};
+use ducta::ServerBuilder;
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Answers `/close` asking to close the connection, `/stream` with a body of unknown length and
+/// anything else with a fixed body
+fn respond(req: Request) -> Response {
+    match req.path {
+        "/close" => Response::new(200)
+            .with_header("Connection", "close")
+            .with_body("bye"),
+        "/stream" => Response::new(200).with_stream(["str", "eam"].into_iter().map(Bytes::from)),
+        _ => Response::new(200).with_body("hello"),
+    }
+}
+
+fn start(builder: ServerBuilder) -> RunningServer {
+    let builder = builder
+        .keep_alive_timeout(Duration::from_secs(7))
+        // Closed connections linger until the client closes them too, which these tests don't
+        .linger_timeout(Duration::from_millis(100));
+    RunningServer::start(builder, respond)
+}
+
+/// Sends a request for `path` with the extra `headers`, returning the response head
+fn request(stream: &mut TcpStream, version: &str, path: &str, headers: &str) -> String {
+    write!(
+        stream,
+        "GET {} HTTP/{}\r\nHost: x\r\n{}\r\n",
+        path, version, headers
+    )
+    .unwrap();
+    let response = read_response(stream);
+    let (head, _) = response.split_once("\r\n\r\n").unwrap();
+    head.to_owned()
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+}
+
+#[test]
+fn keeps_http11_connections_unless_asked_to_close() {
+    let server = start(ServerBuilder::new("127.0.0.1:0"));
+    let mut stream = server.connect();
+
+    for _ in 0..3 {
+        let head = request(&mut stream, "1.1", "/", "");
+        assert_eq!(header(&head, "Connection"), None);
+        assert_eq!(header(&head, "Keep-Alive"), None);
+    }
+
+    // Option names are case insensitive, and may be listed along with others
+    let head = request(&mut stream, "1.1", "/", "Connection: TE, Close\r\n");
+    assert_eq!(header(&head, "Connection"), Some("close"));
+    assert!(is_closed(&mut stream));
+
+    server.stop();
+}
+
+#[test]
+fn closes_http11_connections_when_the_handler_asks_to() {
+    let server = start(ServerBuilder::new("127.0.0.1:0"));
+    let mut stream = server.connect();
+
+    write!(
+        stream,
+        "GET /close HTTP/1.1\r\nHost: x\r\n\r\nGET / HTTP/1.1\r\nHost: x\r\n\r\n"
+    )
+    .unwrap();
+    // The pipelined request is left unanswered
+    let response = read_until_closed(&mut stream);
+    assert!(
+        response.ends_with("\r\nConnection: close\r\n\r\nbye"),
+        "{}",
+        response
+    );
+
+    server.stop();
+}
+
+#[test]
+fn keeps_http10_connections_only_when_asked_to() {
+    let server = start(ServerBuilder::new("127.0.0.1:0"));
+
+    let mut stream = server.connect();
+    let head = request(&mut stream, "1.0", "/", "");
+    assert_eq!(header(&head, "Connection"), Some("close"));
+    assert!(is_closed(&mut stream));
+
+    let mut stream = server.connect();
+    for remaining in [999, 998] {
+        let head = request(&mut stream, "1.0", "/", "Connection: keep-alive\r\n");
+        assert_eq!(header(&head, "Connection"), Some("keep-alive"));
+        assert_eq!(
+            header(&head, "Keep-Alive"),
+            Some(format!("timeout=7, max={}", remaining).as_str())
+        );
+    }
+    let head = request(&mut stream, "1.0", "/", "");
+    assert_eq!(header(&head, "Connection"), Some("close"));
+    assert!(is_closed(&mut stream));
+
+    server.stop();
+}
+
+#[test]
+fn closes_http10_connections_after_bodies_of_unknown_length() {
+    let server = start(ServerBuilder::new("127.0.0.1:0"));
+    let mut stream = server.connect();
+
+    // Without chunked encoding, only closing the connection tells where the body ends
+    write!(
+        stream,
+        "GET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"
+    )
+    .unwrap();
+    let response = read_until_closed(&mut stream);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert_eq!(header(head, "Connection"), Some("close"));
+    assert_eq!(header(head, "Transfer-Encoding"), None);
+    assert_eq!(body, "stream");
+
+    server.stop();
+}
+
+#[test]
+fn closes_connections_past_the_request_limit() {
+    let server = start(ServerBuilder::new("127.0.0.1:0").max_requests_per_connection(3));
+
+    let mut stream = server.connect();
+    assert_eq!(
+        header(&request(&mut stream, "1.1", "/", ""), "Connection"),
+        None
+    );
+    assert_eq!(
+        header(&request(&mut stream, "1.1", "/", ""), "Connection"),
+        None
+    );
+    let head = request(&mut stream, "1.1", "/", "");
+    assert_eq!(header(&head, "Connection"), Some("close"));
+    assert!(is_closed(&mut stream));
+
+    let mut stream = server.connect();
+    let head = request(&mut stream, "1.0", "/", "Connection: keep-alive\r\n");
+    assert_eq!(header(&head, "Keep-Alive"), Some("timeout=7, max=2"));
+    request(&mut stream, "1.0", "/", "Connection: keep-alive\r\n");
+    let head = request(&mut stream, "1.0", "/", "Connection: keep-alive\r\n");
+    assert_eq!(header(&head, "Connection"), Some("close"));
+    assert_eq!(header(&head, "Keep-Alive"), None);
+    assert!(is_closed(&mut stream));
+
+    server.stop();
+}
@@ -0,0 +1,218 @@
+mod common;
+
+use common::{read_response, RunningServer};
+use ducta::handler;
+use ducta::http::{Request, Response};
+use ducta::middleware::{BearerAuth, Layered, Next, SetHeader};
+use ducta::{rt, Router, ServerBuilder};
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Sends a request for `path` with the extra `headers`, returning the whole response
+fn request(stream: &mut TcpStream, path: &str, headers: &str) -> String {
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: x\r\n{}\r\n",
+        path, headers
+    )
+    .unwrap();
+    read_response(stream)
+}
+
+/// Values of every `name` header of `response`, in order
+fn headers<'a>(response: &'a str, name: &str) -> Vec<&'a str> {
+    let (head, _) = response.split_once("\r\n\r\n").unwrap();
+    head.lines()
+        .filter_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+        .collect()
+}
+
+/// A layer recording in `trail` when it sees the request, and tagging the response with `name`
+fn traced(
+    name: &'static str,
+    trail: &Arc<Mutex<Vec<&'static str>>>,
+) -> impl Fn(Request, Next) -> Response {
+    let trail = trail.clone();
+    move |req, next: Next| {
+        trail.lock().unwrap().push(name);
+        next.run(req)
+            .map(move |resp| resp.with_header("X-Layer", name))
+    }
+}
+
+#[test]
+fn runs_layers_in_the_order_they_were_added() {
+    let trail = Arc::new(Mutex::new(Vec::new()));
+    let handler_trail = trail.clone();
+    let handler = Layered::new(move |_req: Request| {
+        handler_trail.lock().unwrap().push("handler");
+        Response::new(200)
+    })
+    .layer(traced("outer", &trail))
+    .layer(traced("inner", &trail));
+    let server = RunningServer::start(ServerBuilder::new("127.0.0.1:0"), handler);
+
+    let response = request(&mut server.connect(), "/", "");
+    // The first layer sees the request first, and the response last
+    assert_eq!(*trail.lock().unwrap(), ["outer", "inner", "handler"]);
+    assert_eq!(headers(&response, "X-Layer"), ["inner", "outer"]);
+
+    server.stop();
+}
+
+#[test]
+fn lets_layers_answer_on_their_own() {
+    let handler = Layered::new(|_req: Request| -> Response { panic!("handler reached") })
+        .layer(SetHeader::new("X-Frame-Options", "DENY"))
+        .layer(|req: Request, next: Next| {
+            if req.path == "/maintenance" {
+                return Response::new(503).with_body("later");
+            }
+            next.run(req)
+        });
+    let server = RunningServer::start(ServerBuilder::new("127.0.0.1:0"), handler);
+
+    let response = request(&mut server.connect(), "/maintenance", "");
+    assert!(response.starts_with("HTTP/1.1 503 "), "{}", response);
+    assert!(response.ends_with("\r\n\r\nlater"));
+    // Layers above the one that answered still run
+    assert_eq!(headers(&response, "X-Frame-Options"), ["DENY"]);
+
+    server.stop();
+}
+
+#[test]
+fn applies_router_layers_to_their_own_routes() {
+    let trail = Arc::new(Mutex::new(Vec::new()));
+    let admin = Router::new()
+        .get("/stats", |_req: Request| Response::new(200))
+        .layer(traced("admin", &trail));
+    let router = Router::new()
+        .get("/", |_req: Request| Response::new(200))
+        .nest("/admin", admin)
+        .layer(traced("app", &trail));
+    let server = RunningServer::start(ServerBuilder::new("127.0.0.1:0"), router);
+    let mut stream = server.connect();
+
+    assert_eq!(headers(&request(&mut stream, "/", ""), "X-Layer"), ["app"]);
+    assert_eq!(
+        headers(&request(&mut stream, "/admin/stats", ""), "X-Layer"),
+        ["admin", "app"]
+    );
+    // Including the responses of the router itself
+    let response = request(&mut stream, "/missing", "");
+    assert!(response.starts_with("HTTP/1.1 404 "), "{}", response);
+    assert_eq!(headers(&response, "X-Layer"), ["app"]);
+    assert_eq!(*trail.lock().unwrap(), ["app", "app", "admin", "app"]);
+
+    server.stop();
+}
+
+#[test]
+fn accepts_only_the_configured_bearer_tokens() {
+    let handler = Layered::new(|_req: Request| Response::new(200).with_body("secret"))
+        .layer(BearerAuth::new("first-token").with_token("second-token"));
+    let server = RunningServer::start(ServerBuilder::new("127.0.0.1:0"), handler);
+    let mut stream = server.connect();
+
+    for authorization in [
+        "Bearer first-token",
+        "Bearer second-token",
+        // The scheme is case insensitive, surrounding spaces don't count
+        "bearer   first-token ",
+    ] {
+        let header = format!("Authorization: {}\r\n", authorization);
+        let response = request(&mut stream, "/", &header);
+        assert!(response.ends_with("\r\n\r\nsecret"), "{}", authorization);
+    }
+
+    for header in [
+        "",
+        "Authorization: Bearer\r\n",
+        "Authorization: Bearer \r\n",
+        "Authorization: Bearerfirst-token\r\n",
+        "Authorization: Basic first-token\r\n",
+        "Authorization: Bearer FIRST-TOKEN\r\n",
+        "Authorization: Bearer first-toke\r\n",
+        "Authorization: Bearer first-tokens\r\n",
+        "Authorization: Bearer first-token second-token\r\n",
+    ] {
+        let response = request(&mut stream, "/", header);
+        assert!(response.starts_with("HTTP/1.1 401 "), "{:?}", header);
+        assert_eq!(headers(&response, "WWW-Authenticate"), ["Bearer"]);
+        assert!(response.ends_with("\r\n\r\nUnauthorized"));
+    }
+
+    server.stop();
+}
+
+#[test]
+fn sets_headers_the_handler_left_out() {
+    let handler = Layered::new(|req: Request| match req.path {
+        "/custom" => Response::new(200).with_header("cache-control", "no-store"),
+        _ => Response::new(200),
+    })
+    .layer(SetHeader::new("Cache-Control", "max-age=60"));
+    let server = RunningServer::start(ServerBuilder::new("127.0.0.1:0"), handler);
+    let mut stream = server.connect();
+
+    let response = request(&mut stream, "/", "");
+    assert_eq!(headers(&response, "Cache-Control"), ["max-age=60"]);
+    // Header names are case insensitive
+    let response = request(&mut stream, "/custom", "");
+    assert_eq!(headers(&response, "Cache-Control"), Vec::<&str>::new());
+    assert_eq!(headers(&response, "cache-control"), ["no-store"]);
+
+    server.stop();
+}
+
+#[test]
+fn post_processes_deferred_and_async_responses_once_ready() {
+    let trail = Arc::new(Mutex::new(Vec::new()));
+    let statuses = Arc::new(Mutex::new(Vec::new()));
+    let seen = statuses.clone();
+    let router = Router::new()
+        .get("/deferred", |_req: Request| {
+            let (resp, responder) = handler::defer();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                responder.respond(Response::new(201).with_header("Cache-Control", "no-store"));
+            });
+            resp
+        })
+        .get(
+            "/async",
+            handler::from_async(|_req: Request| async {
+                rt::sleep(Duration::from_millis(50)).await;
+                Response::new(202)
+            }),
+        )
+        .layer(traced("app", &trail))
+        .layer(SetHeader::new("Cache-Control", "max-age=60"))
+        .layer(move |req: Request, next: Next| {
+            let seen = seen.clone();
+            next.run(req).map(move |resp| {
+                seen.lock().unwrap().push(resp.status);
+                resp
+            })
+        });
+    let server = RunningServer::start(ServerBuilder::new("127.0.0.1:0"), router);
+    let mut stream = server.connect();
+
+    // Layers see the responses sent, not the placeholders standing for them
+    let response = request(&mut stream, "/deferred", "");
+    assert!(response.starts_with("HTTP/1.1 201 "), "{}", response);
+    assert_eq!(headers(&response, "X-Layer"), ["app"]);
+    assert_eq!(headers(&response, "Cache-Control"), ["no-store"]);
+
+    let response = request(&mut stream, "/async", "");
+    assert!(response.starts_with("HTTP/1.1 202 "), "{}", response);
+    assert_eq!(headers(&response, "X-Layer"), ["app"]);
+    assert_eq!(headers(&response, "Cache-Control"), ["max-age=60"]);
+
+    assert_eq!(*statuses.lock().unwrap(), [201, 202]);
+
+    server.stop();
+}
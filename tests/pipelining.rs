@@ -0,0 +1,185 @@
+use bytes::Bytes;
+use ducta::http::{Request, Response};
+use ducta::Server;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Starts a single server shared by every test of this file, echoing the path and body back
+fn server_addr() -> SocketAddr {
+    static ADDR: OnceLock<SocketAddr> = OnceLock::new();
+
+    *ADDR.get_or_init(|| {
+        let mut server = Server::new("127.0.0.1:0", |req: Request| {
+            if req.path.starts_with("/stream/") {
+                let path = req.path.to_owned();
+                let chunks = (0..3).map(move |i| Bytes::from(format!("{}#{};", path, i)));
+                return Response::new(200).with_stream(chunks);
+            }
+
+            let mut body = req.path.as_bytes().to_vec();
+            body.push(b':');
+            body.extend_from_slice(req.body);
+            Response::new(200).with_body(body)
+        })
+        .unwrap();
+
+        let addr = server.local_addr();
+        std::thread::spawn(move || server.run());
+        addr
+    })
+}
+
+/// Sends `requests` in a single write and reads back `count` responses
+fn pipeline(requests: &[u8], count: usize) -> Vec<String> {
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(requests).unwrap();
+
+    let mut received = Vec::new();
+    let mut bodies = Vec::new();
+    let mut buf = [0; 65536];
+
+    while bodies.len() < count {
+        let n = stream.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed after {} responses", bodies.len());
+        received.extend_from_slice(&buf[..n]);
+
+        while let Some(body) = take_response(&mut received) {
+            bodies.push(body);
+        }
+    }
+
+    bodies
+}
+
+/// Removes the first complete response from `buf` and returns its body
+fn take_response(buf: &mut Vec<u8>) -> Option<String> {
+    let head_end = buf.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_lowercase();
+    assert!(head.starts_with("http/1.1 200 ok"), "unexpected response: {}", head);
+
+    if let Some(line) = head.lines().find(|l| l.starts_with("content-length:")) {
+        let len: usize = line["content-length:".len()..].trim().parse().unwrap();
+        if buf.len() < head_end + len {
+            return None;
+        }
+
+        let body = String::from_utf8_lossy(&buf[head_end..head_end + len]).into_owned();
+        buf.drain(..head_end + len);
+        return Some(body);
+    }
+
+    // Chunked response
+    let mut body = String::new();
+    let mut pos = head_end;
+    loop {
+        let line_end = buf[pos..].windows(2).position(|w| w == b"\r\n")? + pos;
+        let size = usize::from_str_radix(std::str::from_utf8(&buf[pos..line_end]).unwrap(), 16)
+            .unwrap();
+        let data_start = line_end + 2;
+        if buf.len() < data_start + size + 2 {
+            return None;
+        }
+
+        body.push_str(&String::from_utf8_lossy(&buf[data_start..data_start + size]));
+        pos = data_start + size + 2;
+        if size == 0 {
+            buf.drain(..pos);
+            return Some(body);
+        }
+    }
+}
+
+#[test]
+fn pipelined_gets_are_answered_in_order() {
+    let requests: Vec<u8> = (0..50)
+        .flat_map(|i| format!("GET /get/{} HTTP/1.1\r\nHost: test\r\n\r\n", i).into_bytes())
+        .collect();
+
+    let bodies = pipeline(&requests, 50);
+    for (i, body) in bodies.iter().enumerate() {
+        assert_eq!(body, &format!("/get/{}:", i));
+    }
+}
+
+#[test]
+fn pipelined_bodies_are_framed_correctly() {
+    let mut requests = Vec::new();
+    for i in 0..40 {
+        let body = format!("payload-{}", i);
+        if i % 2 == 0 {
+            requests.extend_from_slice(
+                format!(
+                    "POST /post/{} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                    i,
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            );
+        } else {
+            requests.extend_from_slice(
+                format!(
+                    "POST /post/{} HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                    i,
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            );
+        }
+    }
+
+    let bodies = pipeline(&requests, 40);
+    for (i, body) in bodies.iter().enumerate() {
+        assert_eq!(body, &format!("/post/{}:payload-{}", i, i));
+    }
+}
+
+#[test]
+fn pipelined_streaming_responses_are_not_interleaved() {
+    let requests: Vec<u8> = (0..30)
+        .flat_map(|i| format!("GET /stream/{} HTTP/1.1\r\n\r\n", i).into_bytes())
+        .collect();
+
+    let bodies = pipeline(&requests, 30);
+    for (i, body) in bodies.iter().enumerate() {
+        assert_eq!(body, &format!("/stream/{0}#0;/stream/{0}#1;/stream/{0}#2;", i));
+    }
+}
+
+#[test]
+fn pipelined_requests_larger_than_the_read_limit() {
+    // Well past the default 8KB header limit, so the requests are read in several rounds
+    let padding = "x".repeat(1000);
+    let requests: Vec<u8> = (0..60)
+        .flat_map(|i| format!("GET /big/{} HTTP/1.1\r\nX-Pad: {}\r\n\r\n", i, padding).into_bytes())
+        .collect();
+
+    let bodies = pipeline(&requests, 60);
+    for (i, body) in bodies.iter().enumerate() {
+        assert_eq!(body, &format!("/big/{}:", i));
+    }
+}
+
+#[test]
+fn answers_requests_sent_before_a_half_close() {
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream
+        .write_all(
+            b"GET /first HTTP/1.1\r\nHost: test\r\n\r\n\
+              POST /second HTTP/1.1\r\nHost: test\r\nContent-Length: 2\r\n\r\nhi",
+        )
+        .unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    // Both are answered before the server closes its side as well
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(take_response(&mut received).as_deref(), Some("/first:"));
+    assert_eq!(take_response(&mut received).as_deref(), Some("/second:hi"));
+    assert!(received.is_empty());
+}
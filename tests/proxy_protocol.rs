@@ -0,0 +1,212 @@
+mod common;
+
+use common::{is_closed, read_body, RunningServer};
+use ducta::http::{Request, Response};
+use ducta::proxy::{ProxyProtocol, TLV_AUTHORITY};
+use ducta::ServerBuilder;
+use std::io::Write;
+use std::time::Duration;
+
+/// Answers with the peer and local addresses of the connection, the version of its PROXY
+/// protocol header and the authority TLV it carried
+fn describe(req: Request) -> Response {
+    let info = req.connection;
+    let (version, authority) = match info.proxy.as_deref() {
+        Some(header) => (
+            header.version.to_string(),
+            header
+                .tlv(TLV_AUTHORITY)
+                .map(|value| String::from_utf8_lossy(value).into_owned()),
+        ),
+        None => ("none".to_owned(), None),
+    };
+
+    Response::new(200).with_body(format!(
+        "{} {} {} {}",
+        info.peer_addr,
+        info.local_addr,
+        version,
+        authority.as_deref().unwrap_or("-")
+    ))
+}
+
+fn start(mode: ProxyProtocol) -> RunningServer {
+    RunningServer::start(
+        ServerBuilder::new("127.0.0.1:0").proxy_protocol(mode),
+        describe,
+    )
+}
+
+/// Builds a version 2 header for a TCP over IPv6 connection from `[2001:db8::1]:1234` to
+/// `[2001:db8::2]:443`, or one opened by the proxy itself if `local`, carrying an authority TLV
+fn v2_header(local: bool) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(
+        &"2001:db8::1"
+            .parse::<std::net::Ipv6Addr>()
+            .unwrap()
+            .octets(),
+    );
+    payload.extend_from_slice(
+        &"2001:db8::2"
+            .parse::<std::net::Ipv6Addr>()
+            .unwrap()
+            .octets(),
+    );
+    payload.extend_from_slice(&1234u16.to_be_bytes());
+    payload.extend_from_slice(&443u16.to_be_bytes());
+    payload.extend_from_slice(&[TLV_AUTHORITY, 0, 11]);
+    payload.extend_from_slice(b"example.com");
+
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    header.push(if local { 0x20 } else { 0x21 });
+    header.push(0x21);
+    header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    header.extend_from_slice(&payload);
+    header
+}
+
+#[test]
+fn uses_addresses_from_v1_headers() {
+    let server = start(ProxyProtocol::Required);
+    let mut stream = server.connect();
+
+    stream
+        .write_all(
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\nHost: x\r\n\r\n",
+        )
+        .unwrap();
+    assert_eq!(
+        read_body(&mut stream),
+        "192.0.2.1:56324 198.51.100.1:443 1 -"
+    );
+
+    // Only the first bytes of the connection are a header
+    write!(stream, "GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    assert_eq!(
+        read_body(&mut stream),
+        "192.0.2.1:56324 198.51.100.1:443 1 -"
+    );
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn uses_addresses_and_tlvs_from_v2_headers() {
+    let server = start(ProxyProtocol::Required);
+    let mut stream = server.connect();
+
+    // Split across reads, the header is waited for
+    let header = v2_header(false);
+    stream.write_all(&header[..20]).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    stream.write_all(&header[20..]).unwrap();
+    write!(stream, "GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+
+    assert_eq!(
+        read_body(&mut stream),
+        "[2001:db8::1]:1234 [2001:db8::2]:443 2 example.com"
+    );
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn keeps_the_socket_addresses_of_local_connections() {
+    let server = start(ProxyProtocol::Required);
+    let mut stream = server.connect();
+
+    stream.write_all(&v2_header(true)).unwrap();
+    write!(stream, "GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+
+    let expected = format!(
+        "{} {} 2 example.com",
+        stream.local_addr().unwrap(),
+        server.addr
+    );
+    assert_eq!(read_body(&mut stream), expected);
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn closes_connections_without_a_valid_header() {
+    let server = start(ProxyProtocol::Required);
+
+    let mut missing = server.connect();
+    write!(missing, "GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    assert!(is_closed(&mut missing));
+
+    let mut invalid = server.connect();
+    write!(invalid, "PROXY TCP4 192.0.2.1 nowhere 1 2\r\n").unwrap();
+    assert!(is_closed(&mut invalid));
+
+    let mut unterminated = server.connect();
+    write!(unterminated, "PROXY TCP4 {}", "1".repeat(120)).unwrap();
+    assert!(is_closed(&mut unterminated));
+
+    drop((missing, invalid, unterminated));
+    server.stop();
+}
+
+#[test]
+fn accepts_connections_without_header_when_optional() {
+    let server = start(ProxyProtocol::Optional);
+
+    let mut plain = server.connect();
+    write!(plain, "GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    let expected = format!("{} {} none -", plain.local_addr().unwrap(), server.addr);
+    assert_eq!(read_body(&mut plain), expected);
+
+    let mut proxied = server.connect();
+    write!(
+        proxied,
+        "PROXY UNKNOWN\r\nGET / HTTP/1.1\r\nHost: x\r\n\r\n"
+    )
+    .unwrap();
+    let expected = format!("{} {} 1 -", proxied.local_addr().unwrap(), server.addr);
+    assert_eq!(read_body(&mut proxied), expected);
+
+    drop((plain, proxied));
+    server.stop();
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn reads_headers_before_the_tls_handshake() {
+    use ducta::TlsConfig;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConnection, StreamOwned};
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let tls = TlsConfig::new()
+        .with_cert(
+            cert.cert.pem().as_bytes(),
+            cert.key_pair.serialize_pem().as_bytes(),
+        )
+        .unwrap();
+    let builder = ServerBuilder::new("127.0.0.1:0")
+        .tls(tls)
+        .proxy_protocol(ProxyProtocol::Required);
+    let server = RunningServer::start(builder, describe);
+
+    let config = common::tls_client(cert.cert.der(), &[]);
+    let name = ServerName::try_from("localhost").unwrap();
+    let session = ClientConnection::new(config, name).unwrap();
+
+    let mut socket = server.connect();
+    socket.write_all(&v2_header(false)).unwrap();
+    let mut stream = StreamOwned::new(session, socket);
+
+    write!(stream, "GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    assert_eq!(
+        read_body(&mut stream),
+        "[2001:db8::1]:1234 [2001:db8::2]:443 2 example.com"
+    );
+
+    drop(stream);
+    server.stop();
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use common::{get, read_body, read_response, RunningServer};
+use ducta::http::{Request, Response};
+use ducta::ServerBuilder;
+use std::io::Write;
+use std::time::Duration;
+
+/// Starts a server answering with the method, path and body of every request
+fn start(builder: ServerBuilder) -> RunningServer {
+    RunningServer::start(builder, |req: Request| {
+        let mut body = format!("{} {} ", req.method, req.path).into_bytes();
+        body.extend_from_slice(req.body);
+        Response::new(200).with_body(body)
+    })
+}
+
+#[test]
+fn waits_for_bodies_split_across_reads() {
+    let server = start(ServerBuilder::new("127.0.0.1:0"));
+    let mut stream = server.connect();
+
+    stream
+        .write_all(b"POST /upload HTTP/1.1\r\nHost: x\r\nContent-Length: 11\r\n\r\n")
+        .unwrap();
+    for piece in ["hel", "lo ", "world"] {
+        std::thread::sleep(Duration::from_millis(30));
+        stream.write_all(piece.as_bytes()).unwrap();
+    }
+    assert_eq!(read_body(&mut stream), "POST /upload hello world");
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn frames_requests_following_a_body() {
+    let server = start(ServerBuilder::new("127.0.0.1:0"));
+    let mut stream = server.connect();
+
+    // The body looks like a request of its own, and must not be parsed as one
+    let smuggled = "GET /smuggled HTTP/1.1\r\nHost: x\r\n\r\n";
+    let request = format!(
+        "POST /a HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\n\r\n{}GET /b HTTP/1.1\r\nHost: x\r\n\r\n",
+        smuggled.len(),
+        smuggled
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+
+    assert_eq!(read_body(&mut stream), format!("POST /a {}", smuggled));
+    assert_eq!(read_body(&mut stream), "GET /b ");
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn exposes_empty_bodies_without_content_length() {
+    let server = start(ServerBuilder::new("127.0.0.1:0"));
+    let mut stream = server.connect();
+
+    assert!(get(&mut stream, "/").ends_with("\r\n\r\nGET / "));
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 0\r\n\r\n"
+    )
+    .unwrap();
+    assert_eq!(read_body(&mut stream), "POST / ");
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn limits_headers_and_bodies_separately() {
+    let builder = ServerBuilder::new("127.0.0.1:0")
+        .max_header_size(512)
+        .max_uri_length(256)
+        .max_body_size(64 * 1024);
+    let server = start(builder);
+    let mut stream = server.connect();
+
+    // Far larger than the headers may be, and larger than the read buffers
+    let body = "b".repeat(64 * 1024);
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .unwrap();
+    assert_eq!(read_body(&mut stream), format!("POST / {}", body));
+
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\n\r\n",
+        body.len() + 1
+    )
+    .unwrap();
+    let response = read_response(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+
+    drop(stream);
+    server.stop();
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use common::{read_until_closed, RunningServer};
+use ducta::http::{Request, Response};
+use ducta::ServerBuilder;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// Starts a server echoing request bodies back on its own thread
+fn start(builder: ServerBuilder) -> RunningServer {
+    RunningServer::start(builder, |req: Request| {
+        Response::new(200).with_body(req.body.to_vec())
+    })
+}
+
+/// Sends a request on a new connection and returns the full response, read until the server
+/// closes the connection
+fn request_once(server: &RunningServer, body: &str) -> String {
+    let mut stream = server.connect();
+    let request = format!(
+        "POST / HTTP/1.1\r\nHost: x\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn starts_and_stops_servers_repeatedly() {
+    for i in 0..20 {
+        let server = start(ServerBuilder::new("127.0.0.1:0").workers(2));
+
+        let body = format!("server {}", i);
+        assert!(request_once(&server, &body).ends_with(&body));
+
+        server.stop();
+    }
+}
+
+#[test]
+fn runs_servers_side_by_side() {
+    // Every server shares the process-wide signal handler
+    let servers: Vec<_> = (0..8)
+        .map(|_| start(ServerBuilder::new("127.0.0.1:0").shutdown_on_signals(true)))
+        .collect();
+
+    for (i, server) in servers.iter().enumerate() {
+        let body = format!("server {}", i);
+        assert!(request_once(server, &body).ends_with(&body));
+    }
+
+    for server in servers {
+        server.stop();
+    }
+}
+
+#[test]
+fn returns_right_away_when_shut_down_before_running() {
+    let mut server = ServerBuilder::new("127.0.0.1:0")
+        .build(|_req: Request| Response::new(200))
+        .unwrap();
+
+    let handle = server.shutdown_handle();
+    handle.shutdown();
+    assert!(handle.is_shutdown());
+
+    server.run().unwrap();
+}
+
+#[test]
+fn lets_requests_in_flight_finish() {
+    let server = start(ServerBuilder::new("127.0.0.1:0"));
+
+    // Idle between two requests
+    let mut idle = server.connect();
+    idle.write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    let mut buf = [0; 1024];
+    assert!(idle.read(&mut buf).unwrap() > 0);
+
+    // In the middle of sending its body
+    let mut busy = server.connect();
+    busy
+        .write_all(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 10\r\n\r\nhello")
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+
+    server.handle.shutdown();
+
+    assert_eq!(read_until_closed(&mut idle), "");
+
+    busy.write_all(b"world").unwrap();
+    let response = read_until_closed(&mut busy);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert!(response.ends_with("helloworld"), "{}", response);
+
+    server.stop();
+}
+
+#[test]
+fn closes_connections_left_after_the_drain_timeout() {
+    let builder = ServerBuilder::new("127.0.0.1:0").drain_timeout(Duration::from_millis(200));
+    let server = start(builder);
+
+    // Never completes its request
+    let mut stuck = server.connect();
+    stuck
+        .write_all(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 10\r\n\r\nhello")
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+
+    let start = Instant::now();
+    server.stop();
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    assert_eq!(read_until_closed(&mut stuck), "");
+}
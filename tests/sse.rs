@@ -0,0 +1,168 @@
+mod common;
+
+use common::{read_until, RunningServer};
+use ducta::http::Request;
+use ducta::sse::{self, Event, EventSender};
+use ducta::{Router, ServerBuilder};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct EventServer {
+    server: RunningServer,
+    /// Senders of the streams opened on `/idle`
+    senders: Receiver<EventSender>,
+}
+
+/// Starts a server streaming a few events on `/events`, and handing the senders of the streams
+/// opened on `/idle` over to the test
+fn start() -> EventServer {
+    let (tx, senders) = mpsc::channel();
+    let tx = Arc::new(Mutex::new(tx));
+
+    let router = Router::new()
+        .get("/events", |_req: Request| {
+            let (resp, events) = sse::stream();
+            std::thread::spawn(move || {
+                events.send(Event::new("first")).unwrap();
+                let event = Event::new("two\nlines")
+                    .with_event("update")
+                    .with_id("2")
+                    .with_retry(Duration::from_millis(1500));
+                events.send(event).unwrap();
+                events.send(Event::new("last").with_id("3")).unwrap();
+            });
+            resp
+        })
+        .get("/idle", move |_req: Request| {
+            let (resp, events) = sse::stream();
+            tx.lock().unwrap().send(events).unwrap();
+            resp
+        });
+
+    let builder = ServerBuilder::new("127.0.0.1:0").heartbeat_interval(Duration::from_millis(50));
+    EventServer {
+        server: RunningServer::start(builder, router),
+        senders,
+    }
+}
+
+impl EventServer {
+    /// Opens a stream on `path`, returning the connection once the response head was read
+    fn open(&self, path: &str) -> TcpStream {
+        let mut stream = self.server.connect();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: x\r\n\r\n", path).unwrap();
+
+        let head = read_until(&mut stream, "\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains("Content-Type: text/event-stream\r\n"), "{}", head);
+        assert!(head.contains("Cache-Control: no-cache\r\n"), "{}", head);
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{}", head);
+        stream
+    }
+
+    fn sender(&self) -> EventSender {
+        self.senders.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    fn stop(self) {
+        self.server.stop();
+    }
+}
+
+/// Strips the chunked framing off a body
+fn dechunk(mut body: &str) -> String {
+    let mut data = String::new();
+    loop {
+        let (size, rest) = body.split_once("\r\n").unwrap();
+        let size = usize::from_str_radix(size, 16).unwrap();
+        if size == 0 {
+            return data;
+        }
+        data.push_str(&rest[..size]);
+        body = &rest[size + 2..];
+    }
+}
+
+#[test]
+fn streams_events_until_the_sender_is_dropped() {
+    let server = start();
+    let mut stream = server.open("/events");
+
+    let body = read_until(&mut stream, "0\r\n\r\n");
+    assert_eq!(
+        dechunk(&body),
+        "data: first\n\n\
+         event: update\nid: 2\nretry: 1500\ndata: two\ndata: lines\n\n\
+         id: 3\ndata: last\n\n"
+    );
+
+    // The connection is kept for the next request
+    stream.write_all(b"GET /events HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    let head = read_until(&mut stream, "\r\n\r\n");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn sends_heartbeats_while_idle() {
+    let server = start();
+    let mut stream = server.open("/idle");
+    let events = server.sender();
+
+    assert_eq!(read_until(&mut stream, "\r\n"), "3\r\n");
+    assert_eq!(read_until(&mut stream, "\r\n"), ":\n\n\r\n");
+
+    // Events still get through between heartbeats
+    events.send(Event::new("hello")).unwrap();
+    let received = read_until(&mut stream, "data: hello\n\n\r\n");
+    assert!(received.ends_with("d\r\ndata: hello\n\n\r\n"), "{:?}", received);
+
+    events.close();
+    read_until(&mut stream, "0\r\n\r\n");
+    assert!(events.send(Event::new("late")).is_err());
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn notices_clients_leaving() {
+    let server = start();
+    let stream = server.open("/idle");
+    let events = server.sender();
+    assert!(!events.is_closed());
+
+    drop(stream);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !events.is_closed() {
+        assert!(Instant::now() < deadline, "stream still open");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(events.send(Event::new("gone")).is_err());
+
+    server.stop();
+}
+
+#[test]
+fn ends_streams_on_shutdown() {
+    let server = start();
+    let mut stream = server.open("/idle");
+    let events = server.sender();
+    events.send(Event::new("before shutdown")).unwrap();
+
+    server.server.handle.shutdown();
+
+    let mut rest = String::new();
+    stream.read_to_string(&mut rest).unwrap();
+    assert!(rest.contains("data: before shutdown\n\n"), "{:?}", rest);
+    assert!(rest.ends_with("0\r\n\r\n"), "{:?}", rest);
+    assert!(events.is_closed());
+
+    drop(stream);
+    server.stop();
+}
@@ -0,0 +1,260 @@
+mod common;
+
+use common::{temp_dir, RunningServer};
+use ducta::{ServerBuilder, StaticFiles};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A server of a temporary directory, stopped and removed once dropped
+struct FileServer {
+    server: Option<RunningServer>,
+    root: PathBuf,
+}
+
+impl Drop for FileServer {
+    fn drop(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.stop();
+        }
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Serves a fresh directory, filled by `setup`, under `/static`
+fn start(name: &str, setup: impl FnOnce(&Path)) -> FileServer {
+    start_in(name, "", setup)
+}
+
+/// Like `start`, but only serves the `served` subdirectory of the one filled by `setup`
+fn start_in(name: &str, served: &str, setup: impl FnOnce(&Path)) -> FileServer {
+    let root = temp_dir(&format!("static-{}", name));
+    setup(&root);
+
+    let files = StaticFiles::new("/static", root.join(served));
+    FileServer {
+        server: Some(RunningServer::start(ServerBuilder::new("127.0.0.1:0"), files)),
+        root,
+    }
+}
+
+struct Reply {
+    head: String,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn status(&self) -> u16 {
+        self.head[9..12].parse().unwrap()
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().skip(1).find_map(|line| {
+            let (n, v) = line.split_once(": ")?;
+            n.eq_ignore_ascii_case(name).then_some(v)
+        })
+    }
+}
+
+/// Sends a request with the extra `headers` and reads the response until the server closes
+fn request(server: &FileServer, method: &str, path: &str, headers: &str) -> Reply {
+    let mut stream = server.server.as_ref().unwrap().connect();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: x\r\nConnection: close\r\n{}\r\n",
+        method, path, headers
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    let split = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+
+    Reply {
+        head: String::from_utf8(received[..split].to_vec()).unwrap(),
+        body: received[split + 4..].to_vec(),
+    }
+}
+
+fn get(server: &FileServer, path: &str, headers: &str) -> Reply {
+    request(server, "GET", path, headers)
+}
+
+#[test]
+fn serves_files_with_their_metadata() {
+    let server = start("files", |root| {
+        std::fs::write(root.join("app.js"), "console.log(1);").unwrap();
+    });
+
+    let reply = get(&server, "/static/app.js", "");
+    assert_eq!(reply.status(), 200);
+    assert_eq!(reply.body, b"console.log(1);");
+    assert_eq!(reply.header("Content-Type"), Some("text/javascript; charset=utf-8"));
+    assert_eq!(reply.header("Content-Length"), Some("15"));
+    assert_eq!(reply.header("Accept-Ranges"), Some("bytes"));
+    assert!(reply.header("ETag").is_some());
+    assert!(reply.header("Last-Modified").unwrap().ends_with(" GMT"));
+
+    let head = request(&server, "HEAD", "/static/app.js", "");
+    assert_eq!(head.status(), 200);
+    assert_eq!(head.header("Content-Length"), Some("15"));
+    assert!(head.body.is_empty());
+
+    assert_eq!(get(&server, "/static/missing.js", "").status(), 404);
+    assert_eq!(get(&server, "/other/app.js", "").status(), 404);
+    assert_eq!(get(&server, "/staticapp.js", "").status(), 404);
+
+    let post = request(&server, "POST", "/static/app.js", "Content-Length: 0\r\n");
+    assert_eq!(post.status(), 405);
+    assert_eq!(post.header("Allow"), Some("GET, HEAD"));
+}
+
+#[test]
+fn sends_large_files_intact() {
+    let content: Vec<u8> = (0..5_000_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let server = start("large", |root| {
+        std::fs::write(root.join("big.bin"), &content).unwrap();
+    });
+
+    let reply = get(&server, "/static/big.bin", "");
+    assert_eq!(reply.status(), 200);
+    assert_eq!(reply.header("Content-Type"), Some("application/octet-stream"));
+    assert!(reply.body == content);
+}
+
+#[test]
+fn answers_conditional_requests_with_not_modified() {
+    let server = start("conditional", |root| {
+        std::fs::write(root.join("style.css"), "body {}").unwrap();
+    });
+
+    let reply = get(&server, "/static/style.css", "");
+    let etag = reply.header("ETag").unwrap();
+    let last_modified = reply.header("Last-Modified").unwrap();
+
+    let cached = get(&server, "/static/style.css", &format!("If-None-Match: {}\r\n", etag));
+    assert_eq!(cached.status(), 304);
+    assert_eq!(cached.header("ETag"), Some(etag));
+    assert!(cached.body.is_empty());
+
+    let weak = format!("If-None-Match: \"other\", W/{}\r\n", etag);
+    assert_eq!(get(&server, "/static/style.css", &weak).status(), 304);
+
+    let stale = get(&server, "/static/style.css", "If-None-Match: \"other\"\r\n");
+    assert_eq!(stale.status(), 200);
+
+    let since = format!("If-Modified-Since: {}\r\n", last_modified);
+    assert_eq!(get(&server, "/static/style.css", &since).status(), 304);
+
+    let old = "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n";
+    assert_eq!(get(&server, "/static/style.css", old).status(), 200);
+
+    // Dates past what a SystemTime holds are ignored like any invalid date
+    for date in ["Sun, 06 Nov 500000000000 08:49:37 GMT", "Fri, 01 Jan 10000 00:00:00 GMT"] {
+        let far = format!("If-Modified-Since: {}\r\nIf-Range: {}\r\nRange: bytes=0-3\r\n", date, date);
+        let reply = get(&server, "/static/style.css", &far);
+        assert_eq!(reply.status(), 200);
+        assert_eq!(reply.body, b"body {}");
+    }
+}
+
+#[test]
+fn serves_byte_ranges() {
+    let server = start("ranges", |root| {
+        std::fs::write(root.join("digits.txt"), "0123456789").unwrap();
+    });
+
+    let single = get(&server, "/static/digits.txt", "Range: bytes=2-5\r\n");
+    assert_eq!(single.status(), 206);
+    assert_eq!(single.header("Content-Range"), Some("bytes 2-5/10"));
+    assert_eq!(single.body, b"2345");
+
+    let suffix = get(&server, "/static/digits.txt", "Range: bytes=-3\r\n");
+    assert_eq!(suffix.header("Content-Range"), Some("bytes 7-9/10"));
+    assert_eq!(suffix.body, b"789");
+
+    let open = get(&server, "/static/digits.txt", "Range: bytes=8-\r\n");
+    assert_eq!(open.body, b"89");
+
+    let multi = get(&server, "/static/digits.txt", "Range: bytes=0-1, 6-7\r\n");
+    assert_eq!(multi.status(), 206);
+    let content_type = multi.header("Content-Type").unwrap();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap();
+    let expected = format!(
+        "--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+         \r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 6-7/10\r\n\r\n67\
+         \r\n--{b}--\r\n",
+        b = boundary
+    );
+    assert_eq!(String::from_utf8(multi.body.clone()).unwrap(), expected);
+    assert_eq!(
+        multi.header("Content-Length").unwrap(),
+        expected.len().to_string()
+    );
+
+    // Overlapping ranges are merged into one
+    let merged = get(&server, "/static/digits.txt", "Range: bytes=1-4, 3-6\r\n");
+    assert_eq!(merged.header("Content-Range"), Some("bytes 1-6/10"));
+
+    let unsatisfiable = get(&server, "/static/digits.txt", "Range: bytes=20-30\r\n");
+    assert_eq!(unsatisfiable.status(), 416);
+    assert_eq!(unsatisfiable.header("Content-Range"), Some("bytes */10"));
+
+    let invalid = get(&server, "/static/digits.txt", "Range: lines=1-2\r\n");
+    assert_eq!(invalid.status(), 200);
+    assert_eq!(invalid.body, b"0123456789");
+
+    // A stale If-Range turns the request into a full one
+    let etag = single.header("ETag").unwrap();
+    let fresh = format!("Range: bytes=0-0\r\nIf-Range: {}\r\n", etag);
+    assert_eq!(get(&server, "/static/digits.txt", &fresh).status(), 206);
+    let stale = "Range: bytes=0-0\r\nIf-Range: \"other\"\r\n";
+    assert_eq!(get(&server, "/static/digits.txt", stale).status(), 200);
+}
+
+#[test]
+fn rejects_paths_escaping_the_root() {
+    // The secret sits right outside of the served directory
+    let server = start_in("traversal", "public", |root| {
+        std::fs::create_dir(root.join("public")).unwrap();
+        std::fs::write(root.join("secret.txt"), "secret").unwrap();
+        std::fs::write(root.join("public").join("ok.txt"), "ok").unwrap();
+        std::os::unix::fs::symlink(root.join("secret.txt"), root.join("public").join("link.txt"))
+            .unwrap();
+    });
+
+    assert_eq!(get(&server, "/static/ok.txt", "").body, b"ok");
+    for path in [
+        "/static/../secret.txt",
+        "/static/%2e%2e/secret.txt",
+        "/static/%2E%2E%2Fsecret.txt",
+        "/static/ok.txt%00",
+        "/static/%zz",
+    ] {
+        let reply = get(&server, path, "");
+        assert!(reply.status() == 400 || reply.status() == 404, "{}: {}", path, reply.head);
+        assert_ne!(reply.body, b"secret");
+    }
+
+    assert_eq!(get(&server, "/static/link.txt", "").status(), 404);
+}
+
+#[test]
+fn serves_index_files_of_directories() {
+    let server = start("index", |root| {
+        std::fs::create_dir(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs").join("index.html"), "<h1>docs</h1>").unwrap();
+        std::fs::create_dir(root.join("empty")).unwrap();
+    });
+
+    let redirect = get(&server, "/static/docs?page=2", "");
+    assert_eq!(redirect.status(), 301);
+    assert_eq!(redirect.header("Location"), Some("/static/docs/?page=2"));
+
+    let index = get(&server, "/static/docs/", "");
+    assert_eq!(index.status(), 200);
+    assert_eq!(index.header("Content-Type"), Some("text/html; charset=utf-8"));
+    assert_eq!(index.body, b"<h1>docs</h1>");
+
+    assert_eq!(get(&server, "/static/empty/", "").status(), 404);
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::{get, is_closed, read_response, read_until_closed, RunningServer};
+use ducta::http::{Request, Response};
+use ducta::ServerBuilder;
+use std::io::Write;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_millis(300);
+
+fn start(builder: ServerBuilder) -> RunningServer {
+    // Connections linger after a 408 until the client closes them, which these tests don't
+    let builder = builder.linger_timeout(TIMEOUT);
+    RunningServer::start(builder, |_req: Request| {
+        Response::new(200).with_body("done")
+    })
+}
+
+#[test]
+fn answers_incomplete_heads_with_request_timeout() {
+    let server = start(ServerBuilder::new("127.0.0.1:0").header_read_timeout(TIMEOUT));
+    let mut stream = server.connect();
+
+    let started = Instant::now();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: x\r\n").unwrap();
+    let response = read_until_closed(&mut stream);
+    assert!(started.elapsed() >= TIMEOUT);
+    assert!(response.starts_with("HTTP/1.1 408 "), "{}", response);
+    assert!(response.contains("\r\nConnection: close\r\n"));
+
+    server.stop();
+}
+
+#[test]
+fn does_not_extend_the_header_deadline_for_trickling_clients() {
+    let server = start(ServerBuilder::new("127.0.0.1:0").header_read_timeout(TIMEOUT));
+    let mut stream = server.connect();
+
+    let started = Instant::now();
+    let mut response = String::new();
+    for byte in b"GET / HTTP/1.1\r\nHost: x\r\nX-Slow: ".iter().cycle() {
+        if stream.write_all(&[*byte]).is_err() {
+            break;
+        }
+        sleep(Duration::from_millis(20));
+        if started.elapsed() > TIMEOUT * 2 {
+            response = read_until_closed(&mut stream);
+            break;
+        }
+    }
+    assert!(response.starts_with("HTTP/1.1 408 "), "{}", response);
+
+    server.stop();
+}
+
+#[test]
+fn answers_stalled_bodies_with_request_timeout() {
+    let server = start(
+        ServerBuilder::new("127.0.0.1:0")
+            .header_read_timeout(Duration::from_secs(10))
+            .body_read_timeout(TIMEOUT),
+    );
+    let mut stream = server.connect();
+
+    stream
+        .write_all(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 10\r\n\r\nhalf")
+        .unwrap();
+    let response = read_until_closed(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 408 "), "{}", response);
+
+    server.stop();
+}
+
+#[test]
+fn closes_idle_connections_without_answering() {
+    let server = start(
+        ServerBuilder::new("127.0.0.1:0")
+            .header_read_timeout(TIMEOUT)
+            .keep_alive_timeout(TIMEOUT),
+    );
+
+    // Never sent a byte
+    let mut stream = server.connect();
+    let started = Instant::now();
+    assert_eq!(read_until_closed(&mut stream), "");
+    assert!(started.elapsed() >= TIMEOUT);
+
+    // Done with its request
+    let mut stream = server.connect();
+    assert!(get(&mut stream, "/").starts_with("HTTP/1.1 200 "));
+    assert_eq!(read_until_closed(&mut stream), "");
+
+    server.stop();
+}
+
+#[test]
+fn replaces_the_deadline_of_the_previous_phase() {
+    let server = start(
+        ServerBuilder::new("127.0.0.1:0")
+            .header_read_timeout(TIMEOUT)
+            .keep_alive_timeout(TIMEOUT * 3),
+    );
+    let mut stream = server.connect();
+
+    // The header deadline set when the connection opened no longer applies once the request
+    // is in, the connection now waits for the keep-alive one
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+    assert!(read_response(&mut stream).starts_with("HTTP/1.1 200 "));
+    sleep(TIMEOUT * 2);
+    assert!(get(&mut stream, "/").starts_with("HTTP/1.1 200 "));
+
+    // And is closed once idle for longer than that
+    sleep(TIMEOUT * 4);
+    assert!(is_closed(&mut stream));
+
+    server.stop();
+}
@@ -0,0 +1,237 @@
+#![cfg(feature = "tls")]
+
+mod common;
+
+use common::RunningServer;
+use ducta::http::{Request, Response};
+use ducta::{ServerBuilder, TlsConfig};
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConnection, StreamOwned};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::OnceLock;
+
+struct TestServer {
+    /// Never stopped, shared until the test binary exits
+    server: RunningServer,
+    /// Default certificate, issued for `localhost`
+    default_cert: CertificateDer<'static>,
+    /// Certificate selected through SNI for `example.test`
+    sni_cert: CertificateDer<'static>,
+}
+
+/// Starts a single HTTPS server shared by every test of this file, echoing the path and body
+/// back, with certificates generated on the fly
+fn server() -> &'static TestServer {
+    static SERVER: OnceLock<TestServer> = OnceLock::new();
+
+    SERVER.get_or_init(|| {
+        let default = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let sni = rcgen::generate_simple_self_signed(vec!["example.test".into()]).unwrap();
+
+        let tls = TlsConfig::new()
+            .with_cert(
+                default.cert.pem().as_bytes(),
+                default.key_pair.serialize_pem().as_bytes(),
+            )
+            .unwrap()
+            .with_sni_cert(
+                "example.test",
+                sni.cert.pem().as_bytes(),
+                sni.key_pair.serialize_pem().as_bytes(),
+            )
+            .unwrap();
+
+        let server = RunningServer::start(
+            ServerBuilder::new("127.0.0.1:0").tls(tls),
+            |req: Request| {
+                let mut body = req.path.as_bytes().to_vec();
+                body.push(b':');
+                body.extend_from_slice(req.body);
+                Response::new(200).with_body(body)
+            },
+        );
+
+        TestServer {
+            server,
+            default_cert: default.cert.der().clone(),
+            sni_cert: sni.cert.der().clone(),
+        }
+    })
+}
+
+/// Opens a TLS connection for `server_name`, trusting only `cert` and offering `alpn`
+fn connect(
+    server_name: &str,
+    cert: &CertificateDer<'static>,
+    alpn: &[&[u8]],
+) -> StreamOwned<ClientConnection, TcpStream> {
+    let config = common::tls_client(cert, alpn);
+    let name = ServerName::try_from(server_name.to_owned()).unwrap();
+    let session = ClientConnection::new(config, name).unwrap();
+    StreamOwned::new(session, server().server.connect())
+}
+
+/// Reads a single `Content-Length` framed response and returns its body, `received` keeps
+/// whatever was read past it
+fn read_response(stream: &mut impl Read, received: &mut Vec<u8>) -> String {
+    let mut buf = [0; 65536];
+
+    loop {
+        if let Some(head_end) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&received[..head_end]).to_lowercase();
+            assert!(head.starts_with("http/1.1 200 ok"), "unexpected response: {}", head);
+
+            let line = head.lines().find(|l| l.starts_with("content-length:")).unwrap();
+            let len: usize = line["content-length:".len()..].trim().parse().unwrap();
+            let body_start = head_end + 4;
+            if received.len() >= body_start + len {
+                let body = received[body_start..body_start + len].to_vec();
+                received.drain(..body_start + len);
+                return String::from_utf8(body).unwrap();
+            }
+        }
+
+        let n = stream.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed before the response was complete");
+        received.extend_from_slice(&buf[..n]);
+    }
+}
+
+#[test]
+fn serves_requests_over_tls() {
+    let server = server();
+    let mut stream = connect("localhost", &server.default_cert, &[b"http/1.1"]);
+    let mut received = Vec::new();
+
+    stream
+        .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    assert_eq!(read_response(&mut stream, &mut received), "/hello:");
+    assert_eq!(stream.conn.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+    // The connection stays usable for the next request
+    stream
+        .write_all(b"POST /again HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello")
+        .unwrap();
+    assert_eq!(read_response(&mut stream, &mut received), "/again:hello");
+}
+
+#[test]
+fn selects_certificate_by_sni() {
+    let server = server();
+
+    // Each handshake only succeeds if the server presents the certificate the client trusts
+    for (name, cert) in [
+        ("example.test", &server.sni_cert),
+        ("localhost", &server.default_cert),
+    ] {
+        let mut stream = connect(name, cert, &[]);
+        let mut received = Vec::new();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        assert_eq!(read_response(&mut stream, &mut received), "/:");
+
+        let presented = stream.conn.peer_certificates().unwrap();
+        assert_eq!(&presented[0], cert);
+    }
+}
+
+#[test]
+fn refuses_clients_without_a_common_alpn_protocol() {
+    let server = server();
+    let mut stream = connect("localhost", &server.default_cert, &[b"spdy/3.1"]);
+
+    let result = stream.write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n");
+    let mut buf = [0; 1024];
+    assert!(result.is_err() || stream.read(&mut buf).is_err());
+}
+
+#[test]
+fn transfers_large_bodies() {
+    let server = server();
+    let mut stream = connect("localhost", &server.default_cert, &[]);
+    let mut received = Vec::new();
+
+    // Larger than the TLS session buffers on both sides, so records back up in either direction
+    let body: String = (0..600_000).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+    let request = format!(
+        "POST /big HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+
+    assert_eq!(read_response(&mut stream, &mut received), format!("/big:{}", body));
+}
+
+#[test]
+fn answers_pipelined_requests() {
+    let server = server();
+    let mut stream = connect("localhost", &server.default_cert, &[]);
+
+    let mut requests = Vec::new();
+    for i in 0..40 {
+        let body = "x".repeat(1000);
+        requests.extend_from_slice(
+            format!(
+                "POST /{} HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\n\r\n{}",
+                i,
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        );
+    }
+    stream.write_all(&requests).unwrap();
+
+    let mut received = Vec::new();
+    for i in 0..40 {
+        let body = read_response(&mut stream, &mut received);
+        assert_eq!(body, format!("/{}:{}", i, "x".repeat(1000)));
+    }
+}
+
+#[test]
+fn closes_plaintext_connections() {
+    let mut socket = server().server.connect();
+    socket
+        .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+        .unwrap();
+
+    // No HTTP response is ever sent, at most a TLS alert before the connection is closed
+    let mut received = Vec::new();
+    let _ = socket.read_to_end(&mut received);
+    assert!(!received.starts_with(b"HTTP/"));
+}
+
+#[test]
+fn negotiates_http2_through_alpn() {
+    let server = server();
+    let mut stream = connect("localhost", &server.default_cert, &[b"h2", b"http/1.1"]);
+
+    // Preface, empty SETTINGS, then a GET on stream 1 made of literal fields
+    let mut request = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0".to_vec();
+    let mut block = Vec::new();
+    for (name, value) in [(":method", "GET"), (":scheme", "https"), (":path", "/h2")] {
+        block.extend_from_slice(&[0x00, name.len() as u8]);
+        block.extend_from_slice(name.as_bytes());
+        block.push(value.len() as u8);
+        block.extend_from_slice(value.as_bytes());
+    }
+    request.extend_from_slice(&[0, 0, block.len() as u8, 0x1, 0x5, 0, 0, 0, 1]);
+    request.extend_from_slice(&block);
+    stream.write_all(&request).unwrap();
+
+    // The server's SETTINGS, its acknowledgement of ours, then the response
+    let mut received = Vec::new();
+    let mut buf = [0; 4096];
+    while !received.ends_with(b"/h2:") {
+        let n = stream.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed after {:?}", received);
+        received.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(stream.conn.alpn_protocol(), Some(&b"h2"[..]));
+    assert!(received.starts_with(&[0, 0, 12, 0x4, 0]), "{:?}", received);
+}
@@ -0,0 +1,176 @@
+mod common;
+
+use common::{read_body, temp_dir, RunningServer};
+use ducta::http::{Request, Response};
+use ducta::ServerBuilder;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// Answers with whether the connection came through a Unix domain socket, its peer address and
+/// the client address of the request
+fn describe(req: Request) -> Response {
+    let info = req.connection;
+    Response::new(200).with_body(format!(
+        "{} {} {}",
+        info.unix_socket,
+        info.peer_addr,
+        req.client_addr()
+    ))
+}
+
+fn start(builder: ServerBuilder) -> RunningServer {
+    RunningServer::start(builder, describe)
+}
+
+/// Sends a request with the extra `headers`, returning the body of the response
+fn get(stream: &mut UnixStream, headers: &str) -> String {
+    write!(stream, "GET / HTTP/1.1\r\nHost: x\r\n{}\r\n", headers).unwrap();
+    read_body(stream)
+}
+
+#[test]
+fn serves_connections_on_a_socket_file() {
+    let dir = temp_dir("serve");
+    let path = dir.join("app.sock");
+
+    let server = start(ServerBuilder::new(&format!("unix:{}", path.display())).workers(2));
+    assert_eq!(
+        server.unix_addr.as_ref().unwrap().as_pathname(),
+        Some(path.as_path())
+    );
+
+    let mut streams: Vec<_> = (0..4).map(|_| server.connect_unix()).collect();
+    for stream in &mut streams {
+        assert_eq!(get(stream, ""), "true 0.0.0.0:0 0.0.0.0");
+        // Connections are kept alive as over TCP
+        assert_eq!(get(stream, ""), "true 0.0.0.0:0 0.0.0.0");
+    }
+
+    drop(streams);
+    server.stop();
+    assert!(!path.exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn sets_the_permissions_and_ownership_of_the_socket_file() {
+    let dir = temp_dir("permissions");
+    let path = dir.join("app.sock");
+    // Giving the file to its current owner needs no privileges
+    let owner = std::fs::metadata(&dir).unwrap();
+
+    let server = start(
+        ServerBuilder::new(&format!("unix:{}", path.display()))
+            .unix_socket_mode(0o600)
+            .unix_socket_owner(Some(owner.uid()), Some(owner.gid())),
+    );
+
+    let metadata = std::fs::metadata(&path).unwrap();
+    assert!(metadata.file_type().is_socket());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    assert_eq!((metadata.uid(), metadata.gid()), (owner.uid(), owner.gid()));
+
+    server.stop();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn replaces_stale_sockets_only() {
+    let dir = temp_dir("stale");
+    let path = dir.join("app.sock");
+    let addr = format!("unix:{}", path.display());
+
+    // Left behind by a listener that is gone
+    drop(UnixListener::bind(&path).unwrap());
+    let server = start(ServerBuilder::new(&addr));
+    assert_eq!(
+        get(&mut server.connect_unix(), ""),
+        "true 0.0.0.0:0 0.0.0.0"
+    );
+
+    // Still listened on by the running server
+    let error = ServerBuilder::new(&addr).build(describe).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::AddrInUse);
+    assert_eq!(
+        get(&mut server.connect_unix(), ""),
+        "true 0.0.0.0:0 0.0.0.0"
+    );
+    server.stop();
+
+    // Not a socket at all
+    let file = dir.join("file");
+    std::fs::write(&file, "data").unwrap();
+    let error = ServerBuilder::new(&format!("unix:{}", file.display()))
+        .build(describe)
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "data");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn listens_in_the_abstract_namespace() {
+    use std::os::linux::net::SocketAddrExt;
+
+    let name = format!("ducta-test-{}", std::process::id());
+    let server = start(ServerBuilder::new(&format!("unix:@{}", name)));
+    assert_eq!(
+        server.unix_addr.as_ref().unwrap().as_abstract_name(),
+        Some(name.as_bytes())
+    );
+
+    assert_eq!(
+        get(&mut server.connect_unix(), ""),
+        "true 0.0.0.0:0 0.0.0.0"
+    );
+    server.stop();
+}
+
+#[test]
+fn trusts_forwarding_headers_from_local_proxies() {
+    let dir = temp_dir("forwarded");
+    let path = dir.join("app.sock");
+    let server = start(
+        ServerBuilder::new(&format!("unix:{}", path.display())).trust_unix_socket_peers(true),
+    );
+
+    let mut stream = server.connect_unix();
+    assert_eq!(
+        get(&mut stream, "X-Forwarded-For: 192.0.2.1\r\n"),
+        "true 0.0.0.0:0 192.0.2.1"
+    );
+    // Untrusted hops still stop the walk
+    assert_eq!(
+        get(&mut stream, "X-Forwarded-For: 192.0.2.1, 198.51.100.1\r\n"),
+        "true 0.0.0.0:0 198.51.100.1"
+    );
+
+    drop(stream);
+    server.stop();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn ignores_forwarding_headers_from_local_peers_by_default() {
+    let dir = temp_dir("untrusted");
+    let path = dir.join("app.sock");
+    let server = start(ServerBuilder::new(&format!("unix:{}", path.display())));
+
+    let mut stream = server.connect_unix();
+    assert_eq!(
+        get(&mut stream, "X-Forwarded-For: 192.0.2.1\r\n"),
+        "true 0.0.0.0:0 0.0.0.0"
+    );
+    assert_eq!(
+        get(&mut stream, "Forwarded: for=192.0.2.1\r\n"),
+        "true 0.0.0.0:0 0.0.0.0"
+    );
+
+    drop(stream);
+    server.stop();
+    std::fs::remove_dir_all(dir).unwrap();
+}
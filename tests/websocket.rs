@@ -0,0 +1,342 @@
+mod common;
+
+use common::{read_head, RunningServer};
+use ducta::http::Request;
+use ducta::websocket::{self, close_code, Message, WebSocket, WebSocketHandler};
+use ducta::{Router, ServerBuilder};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// Sends every message back, and reports how the connection closed
+struct Echo {
+    closed: Arc<Mutex<Option<Sender<u16>>>>,
+}
+
+impl WebSocketHandler for Echo {
+    fn on_message(&mut self, ws: &mut WebSocket, message: Message) {
+        match message {
+            Message::Text(text) if text == "bye" => ws.close(close_code::NORMAL, "see you"),
+            message => ws.send(message),
+        }
+    }
+
+    fn on_close(&mut self, code: u16, _reason: &str) {
+        if let Some(closed) = self.closed.lock().unwrap().as_ref() {
+            let _ = closed.send(code);
+        }
+    }
+}
+
+/// Pushes messages from another thread as soon as the connection opens
+struct Ticker;
+
+impl WebSocketHandler for Ticker {
+    fn on_open(&mut self, ws: &mut WebSocket) {
+        let sender = ws.sender();
+        std::thread::spawn(move || {
+            for i in 0..3 {
+                std::thread::sleep(Duration::from_millis(20));
+                sender.send(format!("tick {}", i)).unwrap();
+            }
+            sender.close(close_code::NORMAL, "done").unwrap();
+        });
+    }
+
+    fn on_message(&mut self, _ws: &mut WebSocket, _message: Message) {}
+}
+
+struct EchoServer {
+    server: RunningServer,
+    closed: Arc<Mutex<Option<Sender<u16>>>>,
+}
+
+fn start() -> EchoServer {
+    let closed = Arc::new(Mutex::new(None));
+    let echo_closed = closed.clone();
+    let router = Router::new()
+        .get("/echo", move |req: Request| {
+            let closed = echo_closed.clone();
+            websocket::upgrade(&req, Echo { closed })
+        })
+        .get("/ticker", |req: Request| websocket::upgrade(&req, Ticker));
+
+    EchoServer {
+        server: RunningServer::start(ServerBuilder::new("127.0.0.1:0"), router),
+        closed,
+    }
+}
+
+impl EchoServer {
+    fn stop(self) {
+        self.server.stop();
+    }
+
+    /// Reports the close codes seen by `Echo` handlers to the returned receiver
+    fn watch_closes(&self) -> mpsc::Receiver<u16> {
+        let (tx, rx) = mpsc::channel();
+        *self.closed.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    /// Opens a WebSocket connection to `path`
+    fn open(&self, path: &str) -> TcpStream {
+        let mut stream = self.server.connect();
+        stream.write_all(handshake(path).as_bytes()).unwrap();
+
+        let head = read_head(&mut stream);
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "{}", head);
+        // Example from RFC 6455
+        let accept = "Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n";
+        assert!(head.contains(accept), "{}", head);
+        stream
+    }
+}
+
+fn handshake(path: &str) -> String {
+    format!(
+        "GET {} HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        path
+    )
+}
+
+/// A masked frame, as clients send them
+fn frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![(fin as u8) << 7 | opcode];
+
+    match payload.len() {
+        len if len < 126 => frame.push(0x80 | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+fn send(stream: &mut TcpStream, opcode: u8, payload: &[u8]) {
+    stream.write_all(&frame(true, opcode, payload)).unwrap();
+}
+
+/// Reads an unmasked frame, returning its opcode and payload
+fn receive(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).unwrap();
+    assert_eq!(head[0] & 0x80, 0x80, "fragmented frame");
+    assert_eq!(head[1] & 0x80, 0, "masked frame");
+
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).unwrap();
+            u16::from_be_bytes(len) as usize
+        }
+        127 => {
+            let mut len = [0; 8];
+            stream.read_exact(&mut len).unwrap();
+            u64::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).unwrap();
+    (head[0] & 0x0F, payload)
+}
+
+fn close_code_of(payload: &[u8]) -> u16 {
+    u16::from_be_bytes([payload[0], payload[1]])
+}
+
+/// Waits for the server to close the connection, then closes the client side too so the server
+/// doesn't linger on it
+fn assert_closed(mut stream: TcpStream) {
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty(), "{:?}", rest);
+}
+
+#[test]
+fn echoes_messages() {
+    let server = start();
+    let mut ws = server.open("/echo");
+
+    send(&mut ws, TEXT, "hello".as_bytes());
+    assert_eq!(receive(&mut ws), (TEXT, b"hello".to_vec()));
+
+    let large: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+    send(&mut ws, BINARY, &large);
+    assert_eq!(receive(&mut ws), (BINARY, large));
+
+    // Fragmented, with a ping in between
+    ws.write_all(&frame(false, TEXT, b"frag")).unwrap();
+    ws.write_all(&frame(true, PING, b"still there?")).unwrap();
+    ws.write_all(&frame(false, 0x0, b"men")).unwrap();
+    ws.write_all(&frame(true, 0x0, "té".as_bytes())).unwrap();
+    assert_eq!(receive(&mut ws), (PONG, b"still there?".to_vec()));
+    assert_eq!(receive(&mut ws), (TEXT, "fragmenté".as_bytes().to_vec()));
+
+    drop(ws);
+    server.stop();
+}
+
+#[test]
+fn handles_frames_sent_with_the_handshake() {
+    let server = start();
+    let mut stream = server.server.connect();
+
+    let mut request = handshake("/echo").into_bytes();
+    request.extend(frame(true, TEXT, b"early"));
+    stream.write_all(&request).unwrap();
+
+    assert!(read_head(&mut stream).starts_with("HTTP/1.1 101"));
+    assert_eq!(receive(&mut stream), (TEXT, b"early".to_vec()));
+
+    drop(stream);
+    server.stop();
+}
+
+#[test]
+fn completes_the_closing_handshake() {
+    let server = start();
+    let closes = server.watch_closes();
+
+    // Started by the client
+    let mut ws = server.open("/echo");
+    let mut payload = 4000u16.to_be_bytes().to_vec();
+    payload.extend_from_slice(b"leaving");
+    send(&mut ws, CLOSE, &payload);
+    let (opcode, payload) = receive(&mut ws);
+    assert_eq!(opcode, CLOSE);
+    assert_eq!(close_code_of(&payload), 4000);
+    assert_closed(ws);
+    assert_eq!(closes.recv_timeout(Duration::from_secs(5)), Ok(4000));
+
+    // Started by the server
+    let mut ws = server.open("/echo");
+    send(&mut ws, TEXT, b"bye");
+    let (opcode, payload) = receive(&mut ws);
+    assert_eq!(opcode, CLOSE);
+    assert_eq!(close_code_of(&payload), close_code::NORMAL);
+    assert_eq!(&payload[2..], b"see you");
+    send(&mut ws, CLOSE, &close_code::NORMAL.to_be_bytes());
+    assert_closed(ws);
+    assert_eq!(closes.recv_timeout(Duration::from_secs(5)), Ok(close_code::NORMAL));
+
+    // Dropped without a closing handshake
+    drop(server.open("/echo"));
+    assert_eq!(closes.recv_timeout(Duration::from_secs(5)), Ok(close_code::ABNORMAL));
+
+    server.stop();
+}
+
+#[test]
+fn fails_connections_breaking_the_protocol() {
+    let server = start();
+
+    let cases: [(Vec<u8>, u16); 5] = [
+        // Unmasked frame
+        (vec![0x81, 0x02, b'h', b'i'], close_code::PROTOCOL_ERROR),
+        // Invalid UTF-8 in a text message
+        (frame(true, TEXT, &[0xC3, 0x28]), close_code::INVALID_DATA),
+        // Continuation without a message to continue
+        (frame(true, 0x0, b"orphan"), close_code::PROTOCOL_ERROR),
+        // Fragmented control frame
+        (frame(false, PING, b""), close_code::PROTOCOL_ERROR),
+        // Larger than the 1MB message limit
+        (frame(true, BINARY, &vec![0; 1024 * 1024 + 1]), close_code::MESSAGE_TOO_BIG),
+    ];
+
+    for (bytes, code) in cases {
+        let mut ws = server.open("/echo");
+        // The server may fail the connection before everything was sent
+        let _ = ws.write_all(&bytes);
+
+        let (opcode, payload) = receive(&mut ws);
+        assert_eq!(opcode, CLOSE);
+        assert_eq!(close_code_of(&payload), code);
+    }
+
+    server.stop();
+}
+
+#[test]
+fn pushes_messages_from_other_threads() {
+    let server = start();
+    let mut ws = server.open("/ticker");
+
+    for i in 0..3 {
+        assert_eq!(receive(&mut ws), (TEXT, format!("tick {}", i).into_bytes()));
+    }
+
+    let (opcode, payload) = receive(&mut ws);
+    assert_eq!(opcode, CLOSE);
+    assert_eq!(close_code_of(&payload), close_code::NORMAL);
+    send(&mut ws, CLOSE, &payload[..2]);
+    assert_closed(ws);
+
+    server.stop();
+}
+
+#[test]
+fn rejects_invalid_handshakes() {
+    let server = start();
+
+    let cases = [
+        ("GET /echo HTTP/1.1\r\nHost: x\r\n\r\n", "426"),
+        (
+            "GET /echo HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n",
+            "426",
+        ),
+        (
+            "GET /echo HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: short\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            "400",
+        ),
+    ];
+
+    for (request, status) in cases {
+        let mut stream = server.server.connect();
+        stream.write_all(request.as_bytes()).unwrap();
+        let head = read_head(&mut stream);
+        assert!(head.starts_with(&format!("HTTP/1.1 {}", status)), "{}", head);
+    }
+
+    server.stop();
+}
+
+#[test]
+fn closes_connections_on_shutdown() {
+    let server = start();
+    let mut ws = server.open("/echo");
+    send(&mut ws, TEXT, b"hi");
+    assert_eq!(receive(&mut ws), (TEXT, b"hi".to_vec()));
+
+    server.server.handle.shutdown();
+
+    let (opcode, payload) = receive(&mut ws);
+    assert_eq!(opcode, CLOSE);
+    assert_eq!(close_code_of(&payload), close_code::GOING_AWAY);
+    send(&mut ws, CLOSE, &payload);
+    assert_closed(ws);
+
+    server.stop();
+}